@@ -180,6 +180,7 @@ export async function POST(request: NextRequest) {
         `smtpd_sasl_security_options = noanonymous, noplaintext`,
         `smtpd_sasl_tls_security_options = noanonymous`,
         `smtpd_sasl_local_domain = $myhostname`,
+        `smtpd_sender_login_maps = mysql:/etc/postfix/mysql-sender-login-maps.cf`,
         ``,
         `# ── Virtual mailbox ──`,
         `virtual_transport = lmtp:unix:private/dovecot-lmtp`,
@@ -216,6 +217,7 @@ export async function POST(request: NextRequest) {
         ``,
         `# ── Recipient restrictions (anti-spam) ──`,
         `smtpd_recipient_restrictions =`,
+        `    reject_authenticated_sender_login_mismatch,`,
         `    permit_mynetworks,`,
         `    permit_sasl_authenticated,`,
         `    reject_unauth_destination,`,
@@ -295,6 +297,18 @@ export async function POST(request: NextRequest) {
       ].join("\n"),
     });
 
+    configs.push({
+      name: "postfix/mysql-sender-login-maps.cf",
+      path: "/etc/postfix/mysql-sender-login-maps.cf",
+      content: [
+        `user = ${dbUser}`,
+        `password = ${dbPassword}`,
+        `hosts = ${dbHost}`,
+        `dbname = ceymail`,
+        `query = SELECT email FROM virtual_users WHERE email='%s' UNION SELECT destination FROM virtual_aliases WHERE source='%s' UNION SELECT virtual_users.email FROM sender_grants INNER JOIN virtual_users ON sender_grants.user_id = virtual_users.id WHERE sender_grants.sender='%s'`,
+      ].join("\n"),
+    });
+
     // 3. Postfix master.cf (enables submission port 587 and SMTPS port 465)
     configs.push({
       name: "postfix/master.cf",
@@ -543,7 +557,7 @@ export async function POST(request: NextRequest) {
 
       for (const cfg of configs) {
         // Write sensitive files (containing DB password) with mode 640 from the start
-        const isSensitive = cfg.name.includes("mysql-virtual") || cfg.name === "dovecot/dovecot-sql.conf.ext";
+        const isSensitive = cfg.name.startsWith("postfix/mysql-") || cfg.name === "dovecot/dovecot-sql.conf.ext";
         const writeResult = sudoWriteFile(cfg.path, cfg.content, isSensitive ? "640" : "644");
         if (writeResult.success) {
           results.push({ name: cfg.name, status: "written" });
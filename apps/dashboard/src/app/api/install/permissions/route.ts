@@ -40,6 +40,7 @@ const PERMISSION_MANIFEST = [
       { cmd: "chmod", args: ["640", "/etc/postfix/mysql-virtual-mailbox-domains.cf"] },
       { cmd: "chmod", args: ["640", "/etc/postfix/mysql-virtual-mailbox-maps.cf"] },
       { cmd: "chmod", args: ["640", "/etc/postfix/mysql-virtual-alias-maps.cf"] },
+      { cmd: "chmod", args: ["640", "/etc/postfix/mysql-sender-login-maps.cf"] },
     ],
     check: "/etc/postfix",
   },
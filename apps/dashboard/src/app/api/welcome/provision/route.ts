@@ -232,10 +232,65 @@ export async function POST(request: NextRequest) {
         ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
       `);
 
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS password_history (
+          id INT AUTO_INCREMENT PRIMARY KEY,
+          user_id INT NOT NULL,
+          password_hash VARCHAR(255) NOT NULL,
+          created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+          FOREIGN KEY (user_id) REFERENCES virtual_users(id) ON DELETE CASCADE,
+          KEY idx_password_history_user (user_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS sender_grants (
+          id INT AUTO_INCREMENT PRIMARY KEY,
+          user_id INT NOT NULL,
+          sender VARCHAR(255) NOT NULL,
+          created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+          FOREIGN KEY (user_id) REFERENCES virtual_users(id) ON DELETE CASCADE,
+          UNIQUE KEY unique_sender_grant (user_id, sender),
+          KEY idx_sender_grants_sender (sender)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS mailbox_acl (
+          id INT AUTO_INCREMENT PRIMARY KEY,
+          owner_id INT NOT NULL,
+          mailbox VARCHAR(255) NOT NULL DEFAULT 'INBOX',
+          grantee_id INT NOT NULL,
+          rights VARCHAR(64) NOT NULL,
+          created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+          updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+          FOREIGN KEY (owner_id) REFERENCES virtual_users(id) ON DELETE CASCADE,
+          FOREIGN KEY (grantee_id) REFERENCES virtual_users(id) ON DELETE CASCADE,
+          UNIQUE KEY unique_mailbox_grant (owner_id, mailbox, grantee_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS acl_user_shares (
+          from_user VARCHAR(255) NOT NULL,
+          to_user VARCHAR(255) NOT NULL,
+          dummy CHAR(1) NOT NULL DEFAULT '1',
+          PRIMARY KEY (from_user, to_user)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS acl_anyone_shares (
+          from_user VARCHAR(255) NOT NULL,
+          dummy CHAR(1) NOT NULL DEFAULT '1',
+          PRIMARY KEY (from_user)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
       steps.push({
         step: "Create mail tables",
         status: "done",
-        detail: "virtual_domains, virtual_users, virtual_aliases",
+        detail: "virtual_domains, virtual_users, virtual_aliases, password_history, sender_grants, mailbox_acl, acl_user_shares, acl_anyone_shares",
       });
 
       // 7. Create dashboard tables
@@ -290,10 +345,42 @@ export async function POST(request: NextRequest) {
         ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
       `);
 
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS dmarc_reports (
+          id INT AUTO_INCREMENT PRIMARY KEY,
+          org_name VARCHAR(255) NOT NULL,
+          report_id VARCHAR(255) NOT NULL,
+          domain VARCHAR(255) NOT NULL,
+          policy VARCHAR(16) NOT NULL,
+          date_begin DATETIME NOT NULL,
+          date_end DATETIME NOT NULL,
+          received_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+          UNIQUE KEY unique_dmarc_report (org_name, report_id),
+          KEY idx_dmarc_reports_domain (domain, date_begin)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
+      await connection.query(`
+        CREATE TABLE IF NOT EXISTS dmarc_report_records (
+          id INT AUTO_INCREMENT PRIMARY KEY,
+          report_id INT NOT NULL,
+          source_ip VARCHAR(45) NOT NULL,
+          header_from VARCHAR(255) NOT NULL,
+          message_count BIGINT NOT NULL DEFAULT 0,
+          disposition VARCHAR(16) NOT NULL,
+          dkim_aligned BOOLEAN NOT NULL DEFAULT FALSE,
+          spf_aligned BOOLEAN NOT NULL DEFAULT FALSE,
+          dkim_domain VARCHAR(255),
+          spf_domain VARCHAR(255),
+          FOREIGN KEY (report_id) REFERENCES dmarc_reports(id) ON DELETE CASCADE,
+          KEY idx_dmarc_report_records_source (source_ip)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4
+      `);
+
       steps.push({
         step: "Create dashboard tables",
         status: "done",
-        detail: "dashboard_users, audit_logs, install_state, health_snapshots",
+        detail: "dashboard_users, audit_logs, install_state, health_snapshots, dmarc_reports, dmarc_report_records",
       });
 
       // 8. Save config (with ceymail creds, NOT root creds)
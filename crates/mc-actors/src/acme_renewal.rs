@@ -0,0 +1,93 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use mc_core::acme::store::{CertificateStore, ManagedCertificate, ACME_DIR, RENEW_BEFORE_DAYS};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Periodically checks which managed certificates are close to expiry.
+/// Subscribers renew them through `AcmeService::renew_due`.
+pub struct AcmeRenewalScheduler {
+    sender: broadcast::Sender<Vec<ManagedCertificate>>,
+    handle: Option<JoinHandle<()>>,
+    store_dir: PathBuf,
+    renew_before: ChronoDuration,
+}
+
+impl AcmeRenewalScheduler {
+    pub fn new(buffer_size: usize) -> (Self, broadcast::Receiver<Vec<ManagedCertificate>>) {
+        let (sender, receiver) = broadcast::channel(buffer_size);
+        (
+            Self {
+                sender,
+                handle: None,
+                store_dir: PathBuf::from(ACME_DIR),
+                renew_before: ChronoDuration::days(RENEW_BEFORE_DAYS),
+            },
+            receiver,
+        )
+    }
+
+    /// Read certificates from somewhere other than the default store.
+    pub fn with_store_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.store_dir = dir.into();
+        self
+    }
+
+    /// Renew this long before expiry instead of [`RENEW_BEFORE_DAYS`].
+    pub fn with_renew_before(mut self, renew_before: ChronoDuration) -> Self {
+        self.renew_before = renew_before;
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<ManagedCertificate>> {
+        self.sender.subscribe()
+    }
+
+    pub fn start(&mut self, interval: Duration) {
+        let sender = self.sender.clone();
+        let store_dir = self.store_dir.clone();
+        let renew_before = self.renew_before;
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let dir = store_dir.clone();
+                match tokio::task::spawn_blocking(move || Self::check_once(&dir, renew_before)).await {
+                    Ok(due) if !due.is_empty() => {
+                        let _ = sender.send(due);
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Certificate renewal check panicked: {}", e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        self.handle = Some(handle);
+        info!("Certificate renewal scheduler started with {:?} interval", interval);
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            info!("Certificate renewal scheduler stopped");
+        }
+    }
+
+    /// One-off check. An unreadable store counts as nothing due.
+    pub fn check_once(store_dir: &Path, renew_before: ChronoDuration) -> Vec<ManagedCertificate> {
+        match CertificateStore::with_dir(store_dir).due(Utc::now(), renew_before) {
+            Ok(due) => {
+                for certificate in &due {
+                    info!("Certificate for {} is due for renewal", certificate.domains.join(", "));
+                }
+                due
+            }
+            Err(e) => {
+                warn!("Skipping certificate renewal check: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use chrono::Utc;
+use mc_core::config::certificates::{self, CertificateReport, CertificateSources, ExpiryThresholds, Severity};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Periodically inventories the certificates Postfix, Dovecot, Apache and
+/// the daemon serve, warning about those close to expiry or misconfigured.
+pub struct CertificateMonitor {
+    sender: broadcast::Sender<CertificateReport>,
+    handle: Option<JoinHandle<()>>,
+    sources: CertificateSources,
+    thresholds: ExpiryThresholds,
+}
+
+impl CertificateMonitor {
+    pub fn new(buffer_size: usize) -> (Self, broadcast::Receiver<CertificateReport>) {
+        let (sender, receiver) = broadcast::channel(buffer_size);
+        (
+            Self {
+                sender,
+                handle: None,
+                sources: CertificateSources::default(),
+                thresholds: ExpiryThresholds::default(),
+            },
+            receiver,
+        )
+    }
+
+    /// Look for configs somewhere other than the default paths.
+    pub fn with_sources(mut self, sources: CertificateSources) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Warn this far ahead of expiry instead of the defaults.
+    pub fn with_thresholds(mut self, thresholds: ExpiryThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CertificateReport> {
+        self.sender.subscribe()
+    }
+
+    pub fn start(&mut self, interval: Duration) {
+        let sender = self.sender.clone();
+        let sources = self.sources.clone();
+        let thresholds = self.thresholds;
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let sources = sources.clone();
+                match tokio::task::spawn_blocking(move || Self::check_once(&sources, &thresholds)).await {
+                    Ok(report) => {
+                        let _ = sender.send(report);
+                    }
+                    Err(e) => warn!("Certificate check panicked: {}", e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        self.handle = Some(handle);
+        info!("Certificate monitor started with {:?} interval", interval);
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            info!("Certificate monitor stopped");
+        }
+    }
+
+    /// One-off check. Always returns a report, even an empty one, so a
+    /// certificate that went away clears its warnings.
+    pub fn check_once(sources: &CertificateSources, thresholds: &ExpiryThresholds) -> CertificateReport {
+        let report = certificates::check(sources, thresholds, Utc::now());
+        for (_, warning) in report.warnings() {
+            match warning.severity {
+                Severity::Critical => warn!("Certificate: {}", warning.message),
+                Severity::Warning => info!("Certificate: {}", warning.message),
+            }
+        }
+        report
+    }
+}
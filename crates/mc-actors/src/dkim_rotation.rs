@@ -0,0 +1,120 @@
+use chrono::Utc;
+use mc_core::config::opendkim::{DkimDomainEntry, OpendkimConfig};
+use mc_core::mail::dkim_rotation::{Rotation, RotationPolicy, RotationStore, ROTATION_STATE_PATH};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// DKIM rotation work found by one check.
+#[derive(Debug, Clone)]
+pub struct RotationSchedule {
+    /// Signing keys due for a new rotation under the policy.
+    pub due: Vec<DkimDomainEntry>,
+    /// Rotations under way, waiting on DNS or the grace period.
+    pub in_progress: Vec<Rotation>,
+}
+
+impl RotationSchedule {
+    pub fn has_work(&self) -> bool {
+        !self.due.is_empty() || !self.in_progress.is_empty()
+    }
+}
+
+/// Periodically checks which domains are due for DKIM rotation and which
+/// rotations are waiting to advance. Subscribers run the work through
+/// `DkimService::run_scheduled_rotations`.
+pub struct DkimRotationScheduler {
+    sender: broadcast::Sender<RotationSchedule>,
+    handle: Option<JoinHandle<()>>,
+    state_path: PathBuf,
+    policy: RotationPolicy,
+}
+
+impl DkimRotationScheduler {
+    pub fn new(buffer_size: usize, policy: RotationPolicy) -> (Self, broadcast::Receiver<RotationSchedule>) {
+        let (sender, receiver) = broadcast::channel(buffer_size);
+        (
+            Self {
+                sender,
+                handle: None,
+                state_path: PathBuf::from(ROTATION_STATE_PATH),
+                policy,
+            },
+            receiver,
+        )
+    }
+
+    /// Read rotation state from somewhere other than the default path.
+    pub fn with_state_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_path = path.into();
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RotationSchedule> {
+        self.sender.subscribe()
+    }
+
+    pub fn start(&mut self, interval: Duration) {
+        let sender = self.sender.clone();
+        let state_path = self.state_path.clone();
+        let policy = self.policy.clone();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let path = state_path.clone();
+                let policy = policy.clone();
+                match tokio::task::spawn_blocking(move || Self::check_once(&path, &policy)).await {
+                    Ok(Some(schedule)) if schedule.has_work() => {
+                        let _ = sender.send(schedule);
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("DKIM rotation check panicked: {}", e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        self.handle = Some(handle);
+        info!("DKIM rotation scheduler started with {:?} interval", interval);
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            info!("DKIM rotation scheduler stopped");
+        }
+    }
+
+    /// One-off check. Returns `None` when the rotation state or OpenDKIM
+    /// config cannot be read.
+    pub fn check_once(state_path: &Path, policy: &RotationPolicy) -> Option<RotationSchedule> {
+        let store = match RotationStore::load(state_path) {
+            Ok(store) => store,
+            Err(e) => {
+                warn!("Skipping DKIM rotation check: {}", e);
+                return None;
+            }
+        };
+        let config = match OpendkimConfig::load() {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Skipping DKIM rotation check: {}", e);
+                return None;
+            }
+        };
+        let due = store.due(&config, policy, Utc::now());
+        for entry in &due {
+            info!(
+                "DKIM {} key for {} is due for rotation",
+                entry.algorithm.key_type(),
+                entry.domain
+            );
+        }
+        Some(RotationSchedule {
+            due,
+            in_progress: store.rotations().iter().filter(|r| r.is_active()).cloned().collect(),
+        })
+    }
+}
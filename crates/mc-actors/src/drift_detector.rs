@@ -0,0 +1,89 @@
+use mc_core::config::drift::{self, DriftReport};
+use mc_core::config::history::{ConfigHistory, HISTORY_DIR};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Periodically compares the live config files against the last revision
+/// mission-control wrote to each, as kept in the config history.
+pub struct DriftDetector {
+    sender: broadcast::Sender<DriftReport>,
+    handle: Option<JoinHandle<()>>,
+    history_root: PathBuf,
+}
+
+impl DriftDetector {
+    pub fn new(buffer_size: usize) -> (Self, broadcast::Receiver<DriftReport>) {
+        let (sender, receiver) = broadcast::channel(buffer_size);
+        (
+            Self {
+                sender,
+                handle: None,
+                history_root: PathBuf::from(HISTORY_DIR),
+            },
+            receiver,
+        )
+    }
+
+    /// Read the config history from somewhere other than the default root.
+    pub fn with_history_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.history_root = root.into();
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DriftReport> {
+        self.sender.subscribe()
+    }
+
+    pub fn start(&mut self, interval: Duration) {
+        let sender = self.sender.clone();
+        let history_root = self.history_root.clone();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let root = history_root.clone();
+                match tokio::task::spawn_blocking(move || Self::check_once(&root)).await {
+                    Ok(Some(report)) => {
+                        let _ = sender.send(report);
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Drift check panicked: {}", e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        self.handle = Some(handle);
+        info!("Drift detector started with {:?} interval", interval);
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            info!("Drift detector stopped");
+        }
+    }
+
+    /// One-off drift check. Returns `None` when the config history cannot
+    /// be read, since there is no baseline to compare against.
+    pub fn check_once(history_root: &Path) -> Option<DriftReport> {
+        let expected = match drift::baseline(&ConfigHistory::with_root(history_root)) {
+            Ok(expected) => expected,
+            Err(e) => {
+                warn!("Skipping drift check: {}", e);
+                return None;
+            }
+        };
+        let report = drift::detect(&expected);
+        for file in report.drifted() {
+            warn!(
+                "Config drift in {}: {} parameter(s) differ",
+                file.path.display(),
+                file.differences.len()
+            );
+        }
+        Some(report)
+    }
+}
@@ -2,3 +2,7 @@ pub mod log_watcher;
 pub mod stats_collector;
 pub mod queue_monitor;
 pub mod state_manager;
+pub mod drift_detector;
+pub mod dkim_rotation;
+pub mod acme_renewal;
+pub mod cert_monitor;
@@ -5,6 +5,9 @@ use std::sync::Arc;
 use tokio::sync::{broadcast, RwLock};
 use tracing::info;
 
+use mc_core::config::certificates::{CertificateReport, CertificateUser, Severity, WarningKind};
+use mc_core::config::drift::{DriftReport, FileStatus, ParameterDrift};
+
 use crate::log_watcher::LogEntry;
 use crate::stats_collector::SystemSnapshot;
 use crate::queue_monitor::QueueSnapshot;
@@ -20,6 +23,29 @@ pub struct ServiceState {
     pub uptime_seconds: Option<u64>,
 }
 
+/// An open config drift alert for one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftAlert {
+    pub file: String,
+    pub status: FileStatus,
+    pub differences: Vec<ParameterDrift>,
+    pub message: String,
+    /// When drift in this file was first seen; kept while it persists
+    pub first_detected: chrono::DateTime<Utc>,
+}
+
+/// An open warning about one certificate a service uses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateAlert {
+    pub user: CertificateUser,
+    pub cert_path: String,
+    pub kind: WarningKind,
+    pub severity: Severity,
+    pub message: String,
+    /// When this warning was first raised; kept while it persists
+    pub first_detected: chrono::DateTime<Utc>,
+}
+
 /// The complete aggregated state of the CeyMail system
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AggregatedState {
@@ -27,6 +53,10 @@ pub struct AggregatedState {
     pub latest_stats: Option<SystemSnapshot>,
     pub latest_queue: Option<QueueSnapshot>,
     pub recent_logs: Vec<LogEntry>,
+    pub latest_drift: Option<DriftReport>,
+    pub drift_alerts: Vec<DriftAlert>,
+    pub latest_certificates: Option<CertificateReport>,
+    pub certificate_alerts: Vec<CertificateAlert>,
     pub last_updated: chrono::DateTime<Utc>,
 }
 
@@ -37,6 +67,10 @@ impl Default for AggregatedState {
             latest_stats: None,
             latest_queue: None,
             recent_logs: Vec::new(),
+            latest_drift: None,
+            drift_alerts: Vec::new(),
+            latest_certificates: None,
+            certificate_alerts: Vec::new(),
             last_updated: Utc::now(),
         }
     }
@@ -83,6 +117,78 @@ impl StateManager {
         let _ = self.change_sender.send(state.clone());
     }
 
+    /// Record a drift check, raising an alert per drifted file. Alerts for
+    /// files that are back in sync are cleared.
+    pub async fn update_drift(&self, report: DriftReport) {
+        let mut state = self.state.write().await;
+        let previous: HashMap<String, chrono::DateTime<Utc>> = state
+            .drift_alerts
+            .iter()
+            .map(|a| (a.file.clone(), a.first_detected))
+            .collect();
+        state.drift_alerts = report
+            .drifted()
+            .map(|f| {
+                let file = f.path.display().to_string();
+                let message = match &f.status {
+                    FileStatus::Missing => format!("{} is missing", file),
+                    FileStatus::Unreadable { reason } => format!("{} unreadable: {}", file, reason),
+                    _ => format!(
+                        "{} differs from the last version mission-control wrote in {} parameter(s)",
+                        file,
+                        f.differences.len()
+                    ),
+                };
+                DriftAlert {
+                    first_detected: previous.get(&file).copied().unwrap_or(report.checked_at),
+                    file,
+                    status: f.status.clone(),
+                    differences: f.differences.clone(),
+                    message,
+                }
+            })
+            .collect();
+        if !state.drift_alerts.is_empty() {
+            info!("{} config file(s) drifted", state.drift_alerts.len());
+        }
+        state.latest_drift = Some(report);
+        state.last_updated = Utc::now();
+        let _ = self.change_sender.send(state.clone());
+    }
+
+    /// Record a certificate inventory, raising an alert per warning.
+    /// Alerts whose warning is gone are cleared; one that only changed
+    /// wording (days left) or severity keeps its first detection time.
+    pub async fn update_certificates(&self, report: CertificateReport) {
+        let mut state = self.state.write().await;
+        let previous: HashMap<(CertificateUser, String, WarningKind), chrono::DateTime<Utc>> = state
+            .certificate_alerts
+            .iter()
+            .map(|a| ((a.user, a.cert_path.clone(), a.kind), a.first_detected))
+            .collect();
+        state.certificate_alerts = report
+            .warnings()
+            .map(|(entry, warning)| {
+                let cert_path = entry.reference.cert_path.display().to_string();
+                let key = (entry.reference.user, cert_path.clone(), warning.kind);
+                CertificateAlert {
+                    first_detected: previous.get(&key).copied().unwrap_or(report.checked_at),
+                    user: entry.reference.user,
+                    cert_path,
+                    kind: warning.kind,
+                    severity: warning.severity,
+                    message: warning.message.clone(),
+                }
+            })
+            .collect();
+        if !state.certificate_alerts.is_empty() {
+            info!("{} certificate warning(s)", state.certificate_alerts.len());
+        }
+        state.latest_certificates = Some(report);
+        state.last_updated = Utc::now();
+        let _ = self.change_sender.send(state.clone());
+    }
+
     /// Add a log entry (keeps last 1000)
     pub async fn add_log(&self, entry: LogEntry) {
         let mut state = self.state.write().await;
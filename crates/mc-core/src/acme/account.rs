@@ -0,0 +1,138 @@
+//! The ACME account key and the JWS (RFC 7515) signing of requests.
+//!
+//! Accounts use an ECDSA P-256 key and ES256 signatures. Until the account
+//! exists, requests carry the public key as a JWK; after that they name the
+//! account URL as `kid`.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+use rand::rngs::OsRng;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use super::AcmeError;
+
+/// Base64url without padding, as JWS and ACME use it throughout.
+pub fn b64(data: &[u8]) -> String {
+    BASE64URL.encode(data)
+}
+
+pub struct AccountKey {
+    key: SigningKey,
+}
+
+impl AccountKey {
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::random(&mut OsRng),
+        }
+    }
+
+    /// Load a PKCS#8 PEM key.
+    pub fn from_pem(pem: &str) -> Result<Self, AcmeError> {
+        SigningKey::from_pkcs8_pem(pem)
+            .map(|key| Self { key })
+            .map_err(|e| AcmeError::Key(e.to_string()))
+    }
+
+    /// The key as PKCS#8 PEM.
+    pub fn to_pem(&self) -> Result<String, AcmeError> {
+        self.key
+            .to_pkcs8_pem(LineEnding::LF)
+            .map(|pem| pem.to_string())
+            .map_err(|e| AcmeError::Key(e.to_string()))
+    }
+
+    /// The public key as a JWK, with its members in the lexicographic order
+    /// the thumbprint requires.
+    pub fn jwk(&self) -> Value {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        // An uncompressed point always has both coordinates
+        let x = point.x().map(|x| b64(x)).unwrap_or_default();
+        let y = point.y().map(|y| b64(y)).unwrap_or_default();
+        json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y })
+    }
+
+    /// The JWK thumbprint (RFC 7638).
+    pub fn thumbprint(&self) -> String {
+        b64(&Sha256::digest(self.jwk().to_string().as_bytes()))
+    }
+
+    /// The key authorization for a challenge token: what HTTP-01 serves,
+    /// and what DNS-01 publishes a digest of.
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{}.{}", token, self.thumbprint())
+    }
+
+    /// A flattened JWS for a POST to `url`. `kid` is the account URL, or
+    /// `None` to embed the JWK; a `None` payload makes a POST-as-GET.
+    pub fn sign(&self, url: &str, nonce: &str, kid: Option<&str>, payload: Option<&Value>) -> Value {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = b64(protected.to_string().as_bytes());
+        let payload = payload.map(|p| b64(p.to_string().as_bytes())).unwrap_or_default();
+        let signature: Signature = self.key.sign(format!("{}.{}", protected, payload).as_bytes());
+        json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(&signature.to_bytes()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+    use p256::ecdsa::VerifyingKey;
+
+    fn decode(s: &str) -> Vec<u8> {
+        BASE64URL.decode(s).unwrap()
+    }
+
+    #[test]
+    fn test_jws_verifies() {
+        let key = AccountKey::generate();
+        let payload = json!({ "termsOfServiceAgreed": true });
+        let jws = key.sign("https://acme.test/new-acct", "nonce-1", None, Some(&payload));
+
+        let protected: Value = serde_json::from_slice(&decode(jws["protected"].as_str().unwrap())).unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "nonce-1");
+        assert_eq!(protected["jwk"], key.jwk());
+        assert!(protected.get("kid").is_none());
+        let body: Value = serde_json::from_slice(&decode(jws["payload"].as_str().unwrap())).unwrap();
+        assert_eq!(body, payload);
+
+        let signing_input = format!("{}.{}", jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap());
+        let signature = Signature::from_slice(&decode(jws["signature"].as_str().unwrap())).unwrap();
+        let verifying = VerifyingKey::from(&key.key);
+        assert!(verifying.verify(signing_input.as_bytes(), &signature).is_ok());
+
+        // POST-as-GET has an empty payload and names the account
+        let jws = key.sign("https://acme.test/order/1", "nonce-2", Some("https://acme.test/acct/1"), None);
+        assert_eq!(jws["payload"], "");
+        let protected: Value = serde_json::from_slice(&decode(jws["protected"].as_str().unwrap())).unwrap();
+        assert_eq!(protected["kid"], "https://acme.test/acct/1");
+        assert!(protected.get("jwk").is_none());
+    }
+
+    #[test]
+    fn test_key_round_trip_and_thumbprint() {
+        let key = AccountKey::generate();
+        let loaded = AccountKey::from_pem(&key.to_pem().unwrap()).unwrap();
+        assert_eq!(loaded.jwk(), key.jwk());
+        assert_eq!(loaded.thumbprint(), key.thumbprint());
+        // 32-byte digest, base64url without padding
+        assert_eq!(key.thumbprint().len(), 43);
+        assert!(key.jwk().to_string().starts_with("{\"crv\":\"P-256\",\"kty\":\"EC\",\"x\":"));
+        assert_eq!(key.key_authorization("tok"), format!("tok.{}", key.thumbprint()));
+        assert!(AccountKey::from_pem("not a key").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Challenge solvers: how the server is shown that we control a name.
+//!
+//! HTTP-01 drops the key authorization into the Apache webroot, which every
+//! generated vhost serves `/.well-known/acme-challenge/` from. DNS-01
+//! publishes a TXT record through a [`DnsProvider`], and is the only choice
+//! for names Apache does not answer for.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use super::account::b64;
+use super::AcmeError;
+use crate::fs::atomic::atomic_write;
+use crate::system::dns::TxtResolver;
+
+/// The directory HTTP-01 responses are served from.
+pub const ACME_WEBROOT: &str = "/var/www/html";
+
+/// The URL path HTTP-01 responses live under.
+pub const HTTP01_PATH: &str = "/.well-known/acme-challenge/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChallengeType {
+    Http01,
+    Dns01,
+}
+
+impl ChallengeType {
+    /// The challenge's name in the protocol.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChallengeType::Http01 => "http-01",
+            ChallengeType::Dns01 => "dns-01",
+        }
+    }
+}
+
+/// Proves control of a domain for one challenge type.
+pub trait ChallengeSolver: Send + Sync {
+    fn challenge_type(&self) -> ChallengeType;
+
+    /// Make the response visible, returning once the server can see it.
+    fn present(&self, domain: &str, token: &str, key_authorization: &str) -> Result<(), AcmeError>;
+
+    /// Remove what [`ChallengeSolver::present`] set up.
+    fn cleanup(&self, domain: &str, token: &str, key_authorization: &str) -> Result<(), AcmeError>;
+}
+
+/// HTTP-01 through a directory the web server serves.
+pub struct Http01Webroot {
+    webroot: PathBuf,
+}
+
+impl Http01Webroot {
+    pub fn new(webroot: &Path) -> Self {
+        Self {
+            webroot: webroot.to_path_buf(),
+        }
+    }
+
+    /// Where the response to `token` is written.
+    pub fn path(&self, token: &str) -> Result<PathBuf, AcmeError> {
+        // Tokens are base64url; anything else could escape the directory
+        if token.is_empty() || !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(AcmeError::InvalidInput(format!("invalid challenge token: {}", token)));
+        }
+        Ok(self.webroot.join(HTTP01_PATH.trim_matches('/')).join(token))
+    }
+}
+
+impl Default for Http01Webroot {
+    fn default() -> Self {
+        Self::new(Path::new(ACME_WEBROOT))
+    }
+}
+
+impl ChallengeSolver for Http01Webroot {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Http01
+    }
+
+    fn present(&self, domain: &str, token: &str, key_authorization: &str) -> Result<(), AcmeError> {
+        let path = self.path(token)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        atomic_write(&path, key_authorization.as_bytes(), Some(0o644))
+            .map_err(|e| AcmeError::Io(std::io::Error::other(e.to_string())))?;
+        debug!("HTTP-01 response for {} written to {}", domain, path.display());
+        Ok(())
+    }
+
+    fn cleanup(&self, _domain: &str, token: &str, _key_authorization: &str) -> Result<(), AcmeError> {
+        match std::fs::remove_file(self.path(token)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The record DNS-01 is validated against for `domain`.
+pub fn dns01_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*.").trim_end_matches('.'))
+}
+
+/// The TXT value DNS-01 expects: the digest of the key authorization.
+pub fn dns01_value(key_authorization: &str) -> String {
+    b64(&Sha256::digest(key_authorization.as_bytes()))
+}
+
+/// Adds and removes TXT records at a DNS host, for DNS-01.
+pub trait DnsProvider: Send + Sync {
+    /// Add a TXT record, leaving any others at `name` in place: a wildcard
+    /// and its base domain need two values at once.
+    fn set_txt(&self, name: &str, value: &str) -> Result<(), AcmeError>;
+
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), AcmeError>;
+}
+
+/// A [`DnsProvider`] that hands the change to an executable, run as
+/// `<program> add|remove <name> <value>`, for DNS hosts without built-in
+/// support.
+pub struct ScriptDnsProvider {
+    program: PathBuf,
+}
+
+impl ScriptDnsProvider {
+    pub fn new(program: &Path) -> Self {
+        Self {
+            program: program.to_path_buf(),
+        }
+    }
+
+    fn run(&self, action: &str, name: &str, value: &str) -> Result<(), AcmeError> {
+        let output = Command::new(&self.program)
+            .arg(action)
+            .arg(name)
+            .arg(value)
+            .output()
+            .map_err(|e| AcmeError::DnsProvider(format!("{}: {}", self.program.display(), e)))?;
+        if !output.status.success() {
+            return Err(AcmeError::DnsProvider(format!(
+                "{} {} {} failed: {}",
+                self.program.display(),
+                action,
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl DnsProvider for ScriptDnsProvider {
+    fn set_txt(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.run("add", name, value)
+    }
+
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.run("remove", name, value)
+    }
+}
+
+/// DNS-01 through a [`DnsProvider`].
+pub struct Dns01 {
+    provider: Box<dyn DnsProvider>,
+    /// Waits for the record to be served before the server is asked to
+    /// look, when set.
+    propagation: Option<(Box<dyn TxtResolver>, Duration)>,
+}
+
+impl Dns01 {
+    pub fn new(provider: Box<dyn DnsProvider>) -> Self {
+        Self {
+            provider,
+            propagation: None,
+        }
+    }
+
+    /// Wait up to `timeout` for `resolver` to serve each record before
+    /// responding to the challenge.
+    pub fn with_propagation_check(mut self, resolver: Box<dyn TxtResolver>, timeout: Duration) -> Self {
+        self.propagation = Some((resolver, timeout));
+        self
+    }
+
+    fn wait_for(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        let Some((resolver, timeout)) = &self.propagation else {
+            return Ok(());
+        };
+        let deadline = Instant::now() + *timeout;
+        loop {
+            match resolver.lookup_txt(name) {
+                Ok(values) if values.iter().any(|v| v == value) => return Ok(()),
+                Ok(_) => {}
+                Err(e) => debug!("TXT lookup for {} failed: {}", name, e),
+            }
+            if Instant::now() >= deadline {
+                return Err(AcmeError::Timeout(format!("{} to be served", name)));
+            }
+            thread::sleep(Duration::from_secs(5).min(*timeout));
+        }
+    }
+}
+
+impl ChallengeSolver for Dns01 {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present(&self, domain: &str, _token: &str, key_authorization: &str) -> Result<(), AcmeError> {
+        let name = dns01_name(domain);
+        let value = dns01_value(key_authorization);
+        self.provider.set_txt(&name, &value)?;
+        info!("DNS-01 record published at {}", name);
+        self.wait_for(&name, &value)
+    }
+
+    fn cleanup(&self, domain: &str, _token: &str, key_authorization: &str) -> Result<(), AcmeError> {
+        let name = dns01_name(domain);
+        if let Err(e) = self.provider.remove_txt(&name, &dns01_value(key_authorization)) {
+            warn!("Leaving DNS-01 record at {}: {}", name, e);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_http01_webroot() {
+        let dir = tempfile::tempdir().unwrap();
+        let solver = Http01Webroot::new(dir.path());
+        solver.present("mail.example.com", "tok-en_1", "tok-en_1.thumb").unwrap();
+        let path = dir.path().join(".well-known/acme-challenge/tok-en_1");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "tok-en_1.thumb");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o644);
+
+        solver.cleanup("mail.example.com", "tok-en_1", "tok-en_1.thumb").unwrap();
+        assert!(!path.exists());
+        // Already gone is fine
+        solver.cleanup("mail.example.com", "tok-en_1", "tok-en_1.thumb").unwrap();
+
+        assert!(solver.present("mail.example.com", "../../etc/passwd", "x").is_err());
+        assert!(solver.path("").is_err());
+    }
+
+    #[test]
+    fn test_dns01_values() {
+        assert_eq!(dns01_name("example.com"), "_acme-challenge.example.com");
+        assert_eq!(dns01_name("*.example.com."), "_acme-challenge.example.com");
+        // RFC 8555 section 8.4: base64url(SHA-256(key authorization))
+        assert_eq!(dns01_value("abc"), "ungWv48Bz-pBQUDeXa4iI7ADYaOWF3qctBD_YfIAFa0");
+    }
+
+    #[test]
+    fn test_script_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let script = dir.path().join("hook.sh");
+        std::fs::write(&script, format!("#!/bin/sh\necho \"$@\" >> {}\n[ \"$2\" != bad ]\n", log.display())).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let solver = Dns01::new(Box::new(ScriptDnsProvider::new(&script)));
+        solver.present("example.com", "tok", "tok.thumb").unwrap();
+        solver.cleanup("example.com", "tok", "tok.thumb").unwrap();
+        let value = dns01_value("tok.thumb");
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            format!("add _acme-challenge.example.com {v}\nremove _acme-challenge.example.com {v}\n", v = value)
+        );
+
+        let provider = ScriptDnsProvider::new(&script);
+        assert!(matches!(provider.set_txt("bad", "x"), Err(AcmeError::DnsProvider(_))));
+    }
+}
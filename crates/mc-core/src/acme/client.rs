@@ -0,0 +1,427 @@
+//! A blocking ACME client: account registration and the order flow.
+//!
+//! Callers in async code run it under `spawn_blocking`; an order spends
+//! most of its time waiting on the server to validate challenges.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use super::account::{b64, AccountKey};
+use super::challenge::ChallengeSolver;
+use super::csr::CertificateKey;
+use super::AcmeError;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    identifier: Identifier,
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+    error: Option<Problem>,
+}
+
+/// An RFC 7807 problem document.
+#[derive(Debug, Default, Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+impl From<Problem> for AcmeError {
+    fn from(problem: Problem) -> Self {
+        AcmeError::Problem {
+            kind: problem.kind,
+            detail: problem.detail,
+        }
+    }
+}
+
+/// What a POST returned.
+struct Reply {
+    location: Option<String>,
+    body: String,
+}
+
+impl Reply {
+    fn json<T: for<'de> Deserialize<'de>>(&self) -> Result<T, AcmeError> {
+        serde_json::from_str(&self.body).map_err(|e| AcmeError::Protocol(e.to_string()))
+    }
+}
+
+pub struct AcmeClient {
+    agent: ureq::Agent,
+    directory: Directory,
+    account: AccountKey,
+    /// The account URL, once registered.
+    kid: Option<String>,
+    nonce: Option<String>,
+    poll_interval: Duration,
+    poll_timeout: Duration,
+}
+
+impl AcmeClient {
+    /// Connect to the server at `directory_url`, acting as `account`.
+    pub fn new(directory_url: &str, account: AccountKey) -> Result<Self, AcmeError> {
+        let agent = ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).build();
+        let directory = agent
+            .get(directory_url)
+            .call()
+            .map_err(http_error)?
+            .into_json()
+            .map_err(|e| AcmeError::Protocol(format!("directory: {}", e)))?;
+        Ok(Self {
+            agent,
+            directory,
+            account,
+            kid: None,
+            nonce: None,
+            poll_interval: Duration::from_secs(2),
+            poll_timeout: Duration::from_secs(120),
+        })
+    }
+
+    /// How often to check on pending authorizations and orders, and how
+    /// long to wait for them.
+    pub fn with_polling(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.poll_interval = interval;
+        self.poll_timeout = timeout;
+        self
+    }
+
+    /// Register the account, agreeing to the terms of service, or look it
+    /// up if the key is already registered. Returns the account URL.
+    pub fn register(&mut self, contact: Option<&str>) -> Result<String, AcmeError> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = contact {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.directory.new_account.clone();
+        let reply = self.post(&url, Some(&payload))?;
+        let kid = reply
+            .location
+            .ok_or_else(|| AcmeError::Protocol("account has no Location".to_string()))?;
+        debug!("ACME account {}", kid);
+        self.kid = Some(kid.clone());
+        Ok(kid)
+    }
+
+    /// Order a certificate for `domains` with `key`, proving control of
+    /// each through `solver`. Returns the PEM chain, leaf first.
+    pub fn issue(
+        &mut self,
+        domains: &[String],
+        key: &CertificateKey,
+        solver: &dyn ChallengeSolver,
+    ) -> Result<String, AcmeError> {
+        if self.kid.is_none() {
+            return Err(AcmeError::InvalidInput("register the account first".to_string()));
+        }
+        let csr = key.csr_der(domains)?;
+
+        let identifiers: Vec<Value> = domains.iter().map(|d| json!({ "type": "dns", "value": d })).collect();
+        let url = self.directory.new_order.clone();
+        let reply = self.post(&url, Some(&json!({ "identifiers": identifiers })))?;
+        let order_url = reply
+            .location
+            .clone()
+            .ok_or_else(|| AcmeError::Protocol("order has no Location".to_string()))?;
+        let order: Order = reply.json()?;
+        info!("ACME order for {}", domains.join(", "));
+
+        for authz in &order.authorizations {
+            self.authorize(authz, solver)?;
+        }
+
+        self.post(&order.finalize, Some(&json!({ "csr": b64(&csr) })))?;
+        let order = self.poll(&order_url, "the certificate", |order: &Order| match order.status.as_str() {
+            "valid" => Some(true),
+            "invalid" => Some(false),
+            _ => None,
+        })?;
+        let certificate = match (order.status.as_str(), order.certificate) {
+            ("valid", Some(url)) => url,
+            _ => return Err(order.error.map(AcmeError::from).unwrap_or_else(|| {
+                AcmeError::Protocol(format!("order ended {}", order.status))
+            })),
+        };
+        let chain = self.post(&certificate, None)?.body;
+        info!("Certificate issued for {}", domains.join(", "));
+        Ok(chain)
+    }
+
+    /// Satisfy one authorization, unless the server already holds a valid
+    /// one for the name.
+    fn authorize(&mut self, url: &str, solver: &dyn ChallengeSolver) -> Result<(), AcmeError> {
+        let authz: Authorization = self.post(url, None)?.json()?;
+        let domain = authz.identifier.value;
+        if authz.status == "valid" {
+            debug!("{} is already authorized", domain);
+            return Ok(());
+        }
+        let challenge = authz
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == solver.challenge_type().as_str())
+            .ok_or_else(|| AcmeError::Challenge {
+                domain: domain.clone(),
+                reason: format!("server does not offer {}", solver.challenge_type().as_str()),
+            })?;
+        let token = challenge
+            .token
+            .ok_or_else(|| AcmeError::Protocol(format!("{} challenge has no token", challenge.kind)))?;
+        let key_authorization = self.account.key_authorization(&token);
+
+        solver.present(&domain, &token, &key_authorization)?;
+        let result = self.respond(url, &challenge.url);
+        if let Err(e) = solver.cleanup(&domain, &token, &key_authorization) {
+            warn!("Challenge cleanup for {} failed: {}", domain, e);
+        }
+
+        let authz = result?;
+        if authz.status == "valid" {
+            return Ok(());
+        }
+        let reason = authz
+            .challenges
+            .into_iter()
+            .find_map(|c| c.error)
+            .map(|p| p.detail)
+            .unwrap_or_else(|| format!("authorization is {}", authz.status));
+        Err(AcmeError::Challenge { domain, reason })
+    }
+
+    /// Tell the server the challenge is ready and wait for its verdict.
+    fn respond(&mut self, authz_url: &str, challenge_url: &str) -> Result<Authorization, AcmeError> {
+        self.post(challenge_url, Some(&json!({})))?;
+        self.poll(authz_url, "the authorization", |authz: &Authorization| {
+            match authz.status.as_str() {
+                "pending" | "processing" => None,
+                status => Some(status == "valid"),
+            }
+        })
+    }
+
+    /// POST-as-GET `url` until `done` decides on it, returning the last
+    /// response either way.
+    fn poll<T: for<'de> Deserialize<'de>>(
+        &mut self,
+        url: &str,
+        what: &str,
+        done: impl Fn(&T) -> Option<bool>,
+    ) -> Result<T, AcmeError> {
+        let deadline = Instant::now() + self.poll_timeout;
+        loop {
+            let value: T = self.post(url, None)?.json()?;
+            if done(&value).is_some() {
+                return Ok(value);
+            }
+            if Instant::now() >= deadline {
+                return Err(AcmeError::Timeout(what.to_string()));
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// A signed POST, retried once with a fresh nonce if the server
+    /// rejects the one used.
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Reply, AcmeError> {
+        match self.post_once(url, payload) {
+            Err(e) if e.is_bad_nonce() => {
+                debug!("Retrying {} with a fresh nonce", url);
+                self.post_once(url, payload)
+            }
+            reply => reply,
+        }
+    }
+
+    fn post_once(&mut self, url: &str, payload: Option<&Value>) -> Result<Reply, AcmeError> {
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            None => self.new_nonce()?,
+        };
+        let body = self.account.sign(url, &nonce, self.kid.as_deref(), payload);
+        let result = self
+            .agent
+            .post(url)
+            .set("Content-Type", "application/jose+json")
+            .send_string(&body.to_string());
+
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                self.nonce = response.header("Replay-Nonce").map(str::to_string);
+                let body = response.into_string().unwrap_or_default();
+                let problem: Problem = serde_json::from_str(&body).unwrap_or_else(|_| Problem {
+                    kind: format!("HTTP {}", status),
+                    detail: body,
+                });
+                return Err(problem.into());
+            }
+            Err(e) => return Err(http_error(e)),
+        };
+        self.nonce = response.header("Replay-Nonce").map(str::to_string);
+        let location = response.header("Location").map(str::to_string);
+        let body = response.into_string()?;
+        Ok(Reply { location, body })
+    }
+
+    fn new_nonce(&self) -> Result<String, AcmeError> {
+        self.agent
+            .head(&self.directory.new_nonce)
+            .call()
+            .map_err(http_error)?
+            .header("Replay-Nonce")
+            .map(str::to_string)
+            .ok_or_else(|| AcmeError::Protocol("no Replay-Nonce from newNonce".to_string()))
+    }
+}
+
+fn http_error(e: ureq::Error) -> AcmeError {
+    AcmeError::Http(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::challenge::{ChallengeType, Dns01, Http01Webroot};
+    use crate::acme::csr::KeyType;
+    use crate::acme::test_server::{MemoryDns, TestServer};
+    use x509_cert::der::Encode;
+    use x509_cert::Certificate;
+
+    fn client(server: &TestServer) -> AcmeClient {
+        let mut client = AcmeClient::new(&server.directory_url(), AccountKey::generate())
+            .unwrap()
+            .with_polling(Duration::from_millis(10), Duration::from_secs(5));
+        client.register(Some("admin@example.com")).unwrap();
+        client
+    }
+
+    fn leaf(chain: &str) -> Certificate {
+        Certificate::load_pem_chain(chain.as_bytes()).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_issue_http01() {
+        let webroot = tempfile::tempdir().unwrap();
+        let server = TestServer::start().with_webroot(webroot.path());
+        let mut client = client(&server);
+
+        let domains = vec!["mail.example.com".to_string(), "webmail.example.com".to_string()];
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        let chain = client.issue(&domains, &key, &Http01Webroot::new(webroot.path())).unwrap();
+
+        let chain_certs = Certificate::load_pem_chain(chain.as_bytes()).unwrap();
+        assert_eq!(chain_certs.len(), 2);
+        let cert = leaf(&chain);
+        assert_eq!(cert.tbs_certificate.subject_public_key_info.to_der().unwrap(), key.spki_der().unwrap());
+        assert_eq!(server.validated(), vec![
+            (ChallengeType::Http01, "mail.example.com".to_string()),
+            (ChallengeType::Http01, "webmail.example.com".to_string()),
+        ]);
+        // Responses are removed once validated
+        assert_eq!(std::fs::read_dir(webroot.path().join(".well-known/acme-challenge")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_issue_dns01() {
+        let dns = MemoryDns::default();
+        let server = TestServer::start().with_dns(dns.clone());
+        let mut client = client(&server);
+
+        let domains = vec!["mail.example.com".to_string()];
+        let key = CertificateKey::generate(KeyType::Rsa).unwrap();
+        let solver = Dns01::new(Box::new(dns.clone()))
+            .with_propagation_check(Box::new(dns.clone()), Duration::from_secs(1));
+        let chain = client.issue(&domains, &key, &solver).unwrap();
+
+        assert_eq!(
+            leaf(&chain).tbs_certificate.subject_public_key_info.to_der().unwrap(),
+            key.spki_der().unwrap()
+        );
+        assert_eq!(server.validated(), vec![(ChallengeType::Dns01, "mail.example.com".to_string())]);
+        assert!(dns.is_empty());
+    }
+
+    #[test]
+    fn test_failed_challenge_is_reported_and_cleaned_up() {
+        let webroot = tempfile::tempdir().unwrap();
+        // The server looks somewhere other than where the response goes
+        let elsewhere = tempfile::tempdir().unwrap();
+        let server = TestServer::start().with_webroot(elsewhere.path());
+        let mut client = client(&server);
+
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        let err = client
+            .issue(&["mail.example.com".to_string()], &key, &Http01Webroot::new(webroot.path()))
+            .unwrap_err();
+        assert!(matches!(err, AcmeError::Challenge { ref domain, .. } if domain == "mail.example.com"), "{}", err);
+        assert_eq!(std::fs::read_dir(webroot.path().join(".well-known/acme-challenge")).unwrap().count(), 0);
+
+        // A challenge type the server does not offer
+        let err = client
+            .issue(&["mail.example.com".to_string()], &key, &Dns01::new(Box::new(MemoryDns::default())))
+            .unwrap_err();
+        assert!(err.to_string().contains("dns-01"), "{}", err);
+    }
+
+    #[test]
+    fn test_bad_nonce_is_retried() {
+        let webroot = tempfile::tempdir().unwrap();
+        let server = TestServer::start().with_webroot(webroot.path());
+        let mut client = client(&server);
+        client.nonce = Some("stale".to_string());
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        client
+            .issue(&["mail.example.com".to_string()], &key, &Http01Webroot::new(webroot.path()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_issue_requires_registration() {
+        let server = TestServer::start();
+        let mut client = AcmeClient::new(&server.directory_url(), AccountKey::generate()).unwrap();
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        assert!(matches!(
+            client.issue(&["mail.example.com".to_string()], &key, &Http01Webroot::default()),
+            Err(AcmeError::InvalidInput(_))
+        ));
+    }
+}
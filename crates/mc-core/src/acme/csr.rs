@@ -0,0 +1,156 @@
+//! Certificate keys and the signing requests sent to finalize an order.
+
+use std::str::FromStr;
+
+use p256::ecdsa::DerSignature;
+use rand::rngs::OsRng;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x509_cert::builder::{Builder, RequestBuilder};
+use x509_cert::der::asn1::Ia5String;
+use x509_cert::der::Encode;
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::name::Name;
+
+use super::AcmeError;
+
+const RSA_BITS: usize = 2048;
+
+/// The kind of key a certificate is issued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    /// RSA 2048, which every SMTP client can use, and what DANE next keys
+    /// are generated as.
+    Rsa,
+    /// ECDSA P-256, smaller and faster for HTTPS.
+    EcdsaP256,
+}
+
+/// The private key of a certificate.
+pub enum CertificateKey {
+    Rsa(Box<RsaPrivateKey>),
+    EcdsaP256(p256::ecdsa::SigningKey),
+}
+
+impl CertificateKey {
+    pub fn generate(key_type: KeyType) -> Result<Self, AcmeError> {
+        Ok(match key_type {
+            KeyType::Rsa => Self::Rsa(Box::new(
+                RsaPrivateKey::new(&mut OsRng, RSA_BITS).map_err(|e| AcmeError::Key(e.to_string()))?,
+            )),
+            KeyType::EcdsaP256 => Self::EcdsaP256(p256::ecdsa::SigningKey::random(&mut OsRng)),
+        })
+    }
+
+    /// Load a PKCS#8 key of either type, or a PKCS#1 RSA key.
+    pub fn from_pem(pem: &str) -> Result<Self, AcmeError> {
+        if let Ok(key) = p256::ecdsa::SigningKey::from_pkcs8_pem(pem) {
+            return Ok(Self::EcdsaP256(key));
+        }
+        RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .map(|key| Self::Rsa(Box::new(key)))
+            .map_err(|_| AcmeError::Key("not an RSA or P-256 private key".to_string()))
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            Self::Rsa(_) => KeyType::Rsa,
+            Self::EcdsaP256(_) => KeyType::EcdsaP256,
+        }
+    }
+
+    /// The key as PKCS#8 PEM.
+    pub fn to_pem(&self) -> Result<String, AcmeError> {
+        let pem = match self {
+            Self::Rsa(key) => key.to_pkcs8_pem(LineEnding::LF),
+            Self::EcdsaP256(key) => key.to_pkcs8_pem(LineEnding::LF),
+        };
+        pem.map(|pem| pem.to_string()).map_err(|e| AcmeError::Key(e.to_string()))
+    }
+
+    /// The DER SubjectPublicKeyInfo, for comparing with a certificate.
+    pub fn spki_der(&self) -> Result<Vec<u8>, AcmeError> {
+        let der = match self {
+            Self::Rsa(key) => key.to_public_key().to_public_key_der(),
+            Self::EcdsaP256(key) => key.verifying_key().to_public_key_der(),
+        };
+        der.map(|der| der.as_bytes().to_vec()).map_err(|e| AcmeError::Key(e.to_string()))
+    }
+
+    /// A DER certificate signing request for `domains`, the first of which
+    /// becomes the common name. Every domain is listed as a DNS SAN.
+    pub fn csr_der(&self, domains: &[String]) -> Result<Vec<u8>, AcmeError> {
+        let first = domains
+            .first()
+            .ok_or_else(|| AcmeError::InvalidInput("a certificate needs at least one domain".to_string()))?;
+        let subject = Name::from_str(&format!("CN={}", first)).map_err(csr_error)?;
+        let san = SubjectAltName(
+            domains
+                .iter()
+                .map(|d| Ia5String::new(d).map(GeneralName::DnsName))
+                .collect::<Result<_, _>>()
+                .map_err(csr_error)?,
+        );
+
+        let csr = match self {
+            Self::Rsa(key) => {
+                let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new(key.as_ref().clone());
+                let mut builder = RequestBuilder::new(subject, &signer).map_err(csr_error)?;
+                builder.add_extension(&san).map_err(csr_error)?;
+                builder.build::<rsa::pkcs1v15::Signature>().map_err(csr_error)?
+            }
+            Self::EcdsaP256(key) => {
+                let mut builder = RequestBuilder::new(subject, key).map_err(csr_error)?;
+                builder.add_extension(&san).map_err(csr_error)?;
+                builder.build::<DerSignature>().map_err(csr_error)?
+            }
+        };
+        csr.to_der().map_err(csr_error)
+    }
+}
+
+fn csr_error(e: impl std::fmt::Display) -> AcmeError {
+    AcmeError::Key(format!("building the CSR: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_cert::der::Decode;
+    use x509_cert::request::CertReq;
+
+    #[test]
+    fn test_csr_carries_key_and_names() {
+        let domains = vec!["mail.example.com".to_string(), "webmail.example.com".to_string()];
+        for key_type in [KeyType::EcdsaP256, KeyType::Rsa] {
+            let key = CertificateKey::generate(key_type).unwrap();
+            let csr = CertReq::from_der(&key.csr_der(&domains).unwrap()).unwrap();
+            assert_eq!(csr.info.subject.to_string(), "CN=mail.example.com");
+            assert_eq!(csr.info.public_key.to_der().unwrap(), key.spki_der().unwrap());
+            let attrs = csr.info.attributes.to_der().unwrap();
+            for domain in &domains {
+                assert!(attrs.windows(domain.len()).any(|w| w == domain.as_bytes()));
+            }
+
+            let loaded = CertificateKey::from_pem(&key.to_pem().unwrap()).unwrap();
+            assert_eq!(loaded.key_type(), key_type);
+            assert_eq!(loaded.spki_der().unwrap(), key.spki_der().unwrap());
+        }
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        assert!(key.csr_der(&[]).is_err());
+    }
+
+    #[test]
+    fn test_loads_dane_next_key() {
+        let pem = crate::mail::dane::generate_next_key().unwrap();
+        let key = CertificateKey::from_pem(&pem).unwrap();
+        assert_eq!(key.key_type(), KeyType::Rsa);
+        assert_eq!(key.spki_der().unwrap(), crate::mail::dane::spki_from_key_pem(&pem).unwrap());
+    }
+}
@@ -0,0 +1,135 @@
+//! ACME (RFC 8555) certificate issuance and renewal, replacing certbot.
+//!
+//! - [`account`]: the account key and the JWS requests are signed with
+//! - [`client`]: the order flow, from new order to certificate download
+//! - [`challenge`]: proving control of a name, over HTTP-01 from the
+//!   Apache webroot or DNS-01 through a [`challenge::DnsProvider`]
+//! - [`store`]: where certificates, their keys and renewal settings live
+//!
+//! Installing a certificate into Postfix, Dovecot and Apache is left to
+//! the caller, which knows how to apply and reload config safely.
+
+pub mod account;
+pub mod challenge;
+pub mod client;
+pub mod csr;
+pub mod store;
+
+#[cfg(test)]
+pub(crate) mod test_server;
+
+use thiserror::Error;
+use tracing::info;
+
+use self::challenge::ChallengeSolver;
+use self::client::AcmeClient;
+use self::csr::CertificateKey;
+use self::store::{CertificateStore, ManagedCertificate};
+
+/// Let's Encrypt's production directory.
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Let's Encrypt's staging directory, for trying the setup out without
+/// running into production rate limits.
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+#[derive(Debug, Error)]
+pub enum AcmeError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("HTTP request failed: {0}")]
+    Http(String),
+    /// A problem document (RFC 7807) returned by the server.
+    #[error("ACME server error ({kind}): {detail}")]
+    Problem { kind: String, detail: String },
+    #[error("Unexpected ACME response: {0}")]
+    Protocol(String),
+    #[error("Challenge failed for {domain}: {reason}")]
+    Challenge { domain: String, reason: String },
+    #[error("Timed out waiting for {0}")]
+    Timeout(String),
+    #[error("Key error: {0}")]
+    Key(String),
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("DNS provider error: {0}")]
+    DnsProvider(String),
+}
+
+impl AcmeError {
+    /// Whether the server rejected the request's nonce, in which case the
+    /// request can be retried with a fresh one.
+    pub fn is_bad_nonce(&self) -> bool {
+        matches!(self, AcmeError::Problem { kind, .. } if kind == "urn:ietf:params:acme:error:badNonce")
+    }
+}
+
+/// Issue `cert` from the server at `directory_url` and save it to `store`,
+/// registering the store's account first (`contact` is its email address).
+///
+/// `key` is used when given, for a planned key change; otherwise the
+/// stored key is kept, or a new one generated for a first issue.
+pub fn obtain(
+    directory_url: &str,
+    store: &CertificateStore,
+    cert: &ManagedCertificate,
+    contact: Option<&str>,
+    solver: &dyn ChallengeSolver,
+    key: Option<CertificateKey>,
+) -> Result<(), AcmeError> {
+    let key = match key {
+        Some(key) => key,
+        None => match store.key(cert.name())? {
+            Some(key) => key,
+            None => CertificateKey::generate(cert.key_type)?,
+        },
+    };
+    let account = store.account_key()?;
+    let mut client = AcmeClient::new(directory_url, account)?;
+    client.register(contact)?;
+    let chain = client.issue(&cert.domains, &key, solver)?;
+    store.save(cert, &chain, &key)?;
+    info!("Stored certificate for {} in {}", cert.domains.join(", "), store.live_dir(cert.name())?.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::challenge::{ChallengeType, Http01Webroot};
+    use crate::acme::store::CertificatePurpose;
+    use crate::acme::test_server::TestServer;
+
+    #[test]
+    fn test_obtain_keeps_key_across_renewals() {
+        let webroot = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start().with_webroot(webroot.path());
+        let store = CertificateStore::with_dir(dir.path());
+        let solver = Http01Webroot::new(webroot.path());
+        let cert = ManagedCertificate::new(
+            vec!["mail.example.com".to_string()],
+            CertificatePurpose::Mail,
+            ChallengeType::Http01,
+        );
+
+        obtain(&server.directory_url(), &store, &cert, Some("admin@example.com"), &solver, None).unwrap();
+        let key = store.key("mail.example.com").unwrap().unwrap().spki_der().unwrap();
+        let first = std::fs::read_to_string(store.cert_path("mail.example.com").unwrap()).unwrap();
+        assert_eq!(store.list().unwrap(), vec![cert.clone()]);
+
+        // Renewal keeps the key, so DANE records stay valid
+        obtain(&server.directory_url(), &store, &cert, None, &solver, None).unwrap();
+        let renewed = std::fs::read_to_string(store.cert_path("mail.example.com").unwrap()).unwrap();
+        assert_ne!(renewed, first);
+        assert_eq!(store.key("mail.example.com").unwrap().unwrap().spki_der().unwrap(), key);
+
+        // Unless a new key is handed over
+        let next = CertificateKey::generate(cert.key_type).unwrap();
+        let next_spki = next.spki_der().unwrap();
+        obtain(&server.directory_url(), &store, &cert, None, &solver, Some(next)).unwrap();
+        assert_eq!(store.key("mail.example.com").unwrap().unwrap().spki_der().unwrap(), next_spki);
+        let chain = std::fs::read_to_string(store.cert_path("mail.example.com").unwrap()).unwrap();
+        assert_eq!(crate::mail::dane::spki_from_cert_pem(&chain).unwrap(), next_spki);
+    }
+}
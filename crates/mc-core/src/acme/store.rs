@@ -0,0 +1,291 @@
+//! Where issued certificates live, and what is needed to renew them.
+//!
+//! ```text
+//! /etc/ceymail-mc/acme/
+//!   account.pem                 ACME account key (0600)
+//!   live/<name>/fullchain.pem   leaf first, then intermediates (0644)
+//!   live/<name>/privkey.pem     certificate key (0600)
+//!   live/<name>/renewal.json    domains, purpose and challenge
+//! ```
+//!
+//! `<name>` is the certificate's first domain. The paths stay the same
+//! across renewals, so configs pointing at them never need rewriting.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use x509_cert::Certificate;
+
+use super::account::AccountKey;
+use super::challenge::{ChallengeSolver, ChallengeType, Dns01, Http01Webroot, ScriptDnsProvider};
+use super::csr::{CertificateKey, KeyType};
+use super::AcmeError;
+use crate::fs::atomic::{atomic_write_config, atomic_write_secret, AtomicWriteError};
+
+pub const ACME_DIR: &str = "/etc/ceymail-mc/acme";
+
+/// Renew once a certificate has this many days left. Let's Encrypt
+/// certificates last 90.
+pub const RENEW_BEFORE_DAYS: i64 = 30;
+
+/// The certificate file for `name` in the default store.
+pub fn cert_path(name: &str) -> String {
+    format!("{}/live/{}/fullchain.pem", ACME_DIR, name)
+}
+
+/// The key file for `name` in the default store.
+pub fn key_path(name: &str) -> String {
+    format!("{}/live/{}/privkey.pem", ACME_DIR, name)
+}
+
+/// What a certificate is installed into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificatePurpose {
+    /// Postfix and Dovecot, as well as any vhost for its names, such as
+    /// the dashboard on the mail hostname.
+    Mail,
+    /// Only the Apache vhosts for its names: webmail or MTA-STS.
+    Vhost,
+}
+
+/// A certificate the store renews.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManagedCertificate {
+    pub domains: Vec<String>,
+    pub purpose: CertificatePurpose,
+    pub challenge: ChallengeType,
+    pub key_type: KeyType,
+    /// The [`super::challenge::ScriptDnsProvider`] hook, for DNS-01.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_hook: Option<PathBuf>,
+}
+
+impl ManagedCertificate {
+    pub fn new(domains: Vec<String>, purpose: CertificatePurpose, challenge: ChallengeType) -> Self {
+        let key_type = match purpose {
+            CertificatePurpose::Mail => KeyType::Rsa,
+            CertificatePurpose::Vhost => KeyType::EcdsaP256,
+        };
+        Self {
+            domains,
+            purpose,
+            challenge,
+            key_type,
+            dns_hook: None,
+        }
+    }
+
+    /// The solver for its challenge type: the Apache webroot, or the DNS
+    /// hook.
+    pub fn solver(&self) -> Result<Box<dyn ChallengeSolver>, AcmeError> {
+        match (self.challenge, &self.dns_hook) {
+            (ChallengeType::Http01, _) => Ok(Box::new(Http01Webroot::default())),
+            (ChallengeType::Dns01, Some(hook)) => Ok(Box::new(Dns01::new(Box::new(ScriptDnsProvider::new(hook))))),
+            (ChallengeType::Dns01, None) => Err(AcmeError::InvalidInput(format!(
+                "{} uses DNS-01 but has no DNS hook",
+                self.name()
+            ))),
+        }
+    }
+
+    /// The name it is stored under: its first domain.
+    pub fn name(&self) -> &str {
+        self.domains.first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+pub struct CertificateStore {
+    dir: PathBuf,
+}
+
+impl CertificateStore {
+    pub fn new() -> Self {
+        Self::with_dir(Path::new(ACME_DIR))
+    }
+
+    pub fn with_dir(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf() }
+    }
+
+    pub(crate) fn live_dir(&self, name: &str) -> Result<PathBuf, AcmeError> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            return Err(AcmeError::InvalidInput(format!("invalid certificate name: {}", name)));
+        }
+        Ok(self.dir.join("live").join(name))
+    }
+
+    pub fn cert_path(&self, name: &str) -> Result<PathBuf, AcmeError> {
+        Ok(self.live_dir(name)?.join("fullchain.pem"))
+    }
+
+    pub fn key_path(&self, name: &str) -> Result<PathBuf, AcmeError> {
+        Ok(self.live_dir(name)?.join("privkey.pem"))
+    }
+
+    /// The account key, generated and saved on first use.
+    pub fn account_key(&self) -> Result<AccountKey, AcmeError> {
+        let path = self.dir.join("account.pem");
+        match std::fs::read_to_string(&path) {
+            Ok(pem) => AccountKey::from_pem(&pem),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = AccountKey::generate();
+                std::fs::create_dir_all(&self.dir)?;
+                atomic_write_secret(&path, &key.to_pem()?).map_err(write_error)?;
+                Ok(key)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The key of a stored certificate, if there is one.
+    pub fn key(&self, name: &str) -> Result<Option<CertificateKey>, AcmeError> {
+        match std::fs::read_to_string(self.key_path(name)?) {
+            Ok(pem) => CertificateKey::from_pem(&pem).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a newly issued certificate. The key goes first, so a crash
+    /// in between leaves a mismatched pair only until the next renewal,
+    /// never a certificate without its key.
+    pub fn save(&self, cert: &ManagedCertificate, chain: &str, key: &CertificateKey) -> Result<(), AcmeError> {
+        let dir = self.live_dir(cert.name())?;
+        std::fs::create_dir_all(&dir)?;
+        atomic_write_secret(&dir.join("privkey.pem"), &key.to_pem()?).map_err(write_error)?;
+        atomic_write_config(&dir.join("fullchain.pem"), chain).map_err(write_error)?;
+        let record = serde_json::to_string_pretty(cert).map_err(|e| AcmeError::InvalidInput(e.to_string()))?;
+        atomic_write_config(&dir.join("renewal.json"), &record).map_err(write_error)?;
+        Ok(())
+    }
+
+    /// Every certificate the store manages.
+    pub fn list(&self) -> Result<Vec<ManagedCertificate>, AcmeError> {
+        let entries = match std::fs::read_dir(self.dir.join("live")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut certs = Vec::new();
+        for entry in entries.flatten() {
+            let Ok(record) = std::fs::read_to_string(entry.path().join("renewal.json")) else {
+                continue;
+            };
+            match serde_json::from_str(&record) {
+                Ok(cert) => certs.push(cert),
+                Err(e) => tracing::warn!("Skipping {}: {}", entry.path().display(), e),
+            }
+        }
+        certs.sort_by(|a: &ManagedCertificate, b| a.name().cmp(b.name()));
+        Ok(certs)
+    }
+
+    /// When the stored certificate for `name` expires.
+    pub fn not_after(&self, name: &str) -> Result<DateTime<Utc>, AcmeError> {
+        not_after(&std::fs::read_to_string(self.cert_path(name)?)?)
+    }
+
+    /// The certificates that expire within `before` of `now`, or are
+    /// missing altogether.
+    pub fn due(&self, now: DateTime<Utc>, before: Duration) -> Result<Vec<ManagedCertificate>, AcmeError> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|cert| match self.not_after(cert.name()) {
+                Ok(expiry) => expiry - now <= before,
+                Err(_) => true,
+            })
+            .collect())
+    }
+}
+
+impl Default for CertificateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// When the leaf of a PEM chain expires.
+pub fn not_after(chain: &str) -> Result<DateTime<Utc>, AcmeError> {
+    let certs = Certificate::load_pem_chain(chain.as_bytes())
+        .map_err(|e| AcmeError::InvalidInput(format!("certificate: {}", e)))?;
+    let leaf = certs
+        .first()
+        .ok_or_else(|| AcmeError::InvalidInput("no certificate in the chain".to_string()))?;
+    let secs = leaf.tbs_certificate.validity.not_after.to_unix_duration().as_secs();
+    DateTime::from_timestamp(secs as i64, 0)
+        .ok_or_else(|| AcmeError::InvalidInput("certificate expiry out of range".to_string()))
+}
+
+fn write_error(e: AtomicWriteError) -> AcmeError {
+    AcmeError::Io(std::io::Error::other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::test_server::TestCa;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn stored(store: &CertificateStore, ca: &TestCa, domain: &str, days: i64) -> ManagedCertificate {
+        let cert = ManagedCertificate::new(vec![domain.to_string()], CertificatePurpose::Vhost, ChallengeType::Http01);
+        let key = CertificateKey::generate(cert.key_type).unwrap();
+        let chain = ca.issue_for_days(&cert.domains, &key.spki_der().unwrap(), days);
+        store.save(&cert, &chain, &key).unwrap();
+        cert
+    }
+
+    #[test]
+    fn test_save_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CertificateStore::with_dir(dir.path());
+        let ca = TestCa::new();
+        assert!(store.list().unwrap().is_empty());
+
+        let cert = stored(&store, &ca, "webmail.example.com", 90);
+        assert_eq!(store.list().unwrap(), vec![cert.clone()]);
+        let mode = |p: PathBuf| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(store.key_path("webmail.example.com").unwrap()), 0o600);
+        assert_eq!(mode(store.cert_path("webmail.example.com").unwrap()), 0o644);
+
+        let key = store.key("webmail.example.com").unwrap().unwrap();
+        assert_eq!(key.key_type(), KeyType::EcdsaP256);
+        assert!(store.key("mail.example.com").unwrap().is_none());
+        assert!(store.cert_path("../etc").is_err());
+        assert_eq!(cert_path("mail.example.com"), "/etc/ceymail-mc/acme/live/mail.example.com/fullchain.pem");
+    }
+
+    #[test]
+    fn test_due_for_renewal() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CertificateStore::with_dir(dir.path());
+        let ca = TestCa::new();
+        stored(&store, &ca, "fresh.example.com", 80);
+        stored(&store, &ca, "expiring.example.com", 10);
+        stored(&store, &ca, "expired.example.com", -1);
+
+        let now = Utc::now();
+        let expiry = store.not_after("fresh.example.com").unwrap();
+        assert!((expiry - now - Duration::days(80)).num_minutes().abs() < 5);
+
+        let due: Vec<String> = store
+            .due(now, Duration::days(RENEW_BEFORE_DAYS))
+            .unwrap()
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect();
+        assert_eq!(due, vec!["expired.example.com", "expiring.example.com"]);
+    }
+
+    #[test]
+    fn test_account_key_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CertificateStore::with_dir(dir.path());
+        let first = store.account_key().unwrap();
+        assert_eq!(store.account_key().unwrap().thumbprint(), first.thumbprint());
+        let mode = std::fs::metadata(dir.path().join("account.pem")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
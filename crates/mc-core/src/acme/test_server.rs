@@ -0,0 +1,639 @@
+//! A local ACME server in the spirit of Pebble, for testing the client and
+//! what is built on it without network access.
+//!
+//! It checks every request's JWS, nonce and URL, and validates challenges
+//! for real: HTTP-01 by reading the response from a webroot directory, and
+//! DNS-01 by looking it up in a [`MemoryDns`]. Orders are issued by a
+//! throwaway [`TestCa`].
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{DerSignature, Signature, SigningKey, VerifyingKey};
+use p256::pkcs8::EncodePublicKey;
+use rand::rngs::OsRng;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+use x509_cert::der::asn1::Ia5String;
+use x509_cert::der::{Decode, EncodePem};
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::name::Name;
+use x509_cert::request::CertReq;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::{Time, Validity};
+use x509_cert::Certificate;
+
+use super::account::b64;
+use super::challenge::{dns01_name, dns01_value, ChallengeType, DnsProvider};
+use super::AcmeError;
+use crate::system::dns::{DnsError, TxtResolver};
+
+/// TXT records held in memory: the DNS host a [`DnsProvider`] changes,
+/// and the resolver the server validates against.
+#[derive(Clone, Default)]
+pub(crate) struct MemoryDns {
+    records: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+}
+
+impl MemoryDns {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.records.lock().unwrap().values().all(Vec::is_empty)
+    }
+}
+
+impl DnsProvider for MemoryDns {
+    fn set_txt(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.records.lock().unwrap().entry(name.to_string()).or_default().push(value.to_string());
+        Ok(())
+    }
+
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        if let Some(values) = self.records.lock().unwrap().get_mut(name) {
+            values.retain(|v| v != value);
+        }
+        Ok(())
+    }
+}
+
+impl TxtResolver for MemoryDns {
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        Ok(self.records.lock().unwrap().get(name).cloned().unwrap_or_default())
+    }
+}
+
+/// A P-256 CA issuing leaf certificates.
+pub(crate) struct TestCa {
+    key: SigningKey,
+    cert: Certificate,
+}
+
+impl TestCa {
+    pub(crate) fn new() -> Self {
+        let key = SigningKey::random(&mut OsRng);
+        let spki = SubjectPublicKeyInfoOwned::from_der(key.verifying_key().to_public_key_der().unwrap().as_bytes())
+            .unwrap();
+        let cert = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(1u32),
+            // Backdated, so a test's `now` taken before the CA exists
+            // still falls inside its validity
+            Validity {
+                not_before: Time::try_from(SystemTime::now() - Duration::from_secs(86400)).unwrap(),
+                not_after: Time::try_from(SystemTime::now() + Duration::from_secs(10 * 365 * 86400)).unwrap(),
+            },
+            Name::from_str("CN=Test ACME CA").unwrap(),
+            spki,
+            &key,
+        )
+        .unwrap()
+        .build::<DerSignature>()
+        .unwrap();
+        Self { key, cert }
+    }
+
+    /// The CA certificate as PEM.
+    pub(crate) fn pem(&self) -> String {
+        self.cert.to_pem(Default::default()).unwrap()
+    }
+
+    /// A certificate for `domains` and the key in `spki_der`, valid from
+    /// `not_before` to `not_after`, as a PEM chain ending with the CA.
+    pub(crate) fn issue(
+        &self,
+        domains: &[String],
+        spki_der: &[u8],
+        not_before: SystemTime,
+        not_after: SystemTime,
+    ) -> String {
+        let validity = Validity {
+            not_before: Time::try_from(not_before).unwrap(),
+            not_after: Time::try_from(not_after).unwrap(),
+        };
+        let serial = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos() as u64;
+        let mut builder = CertificateBuilder::new(
+            Profile::Leaf {
+                issuer: self.cert.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: false,
+            },
+            SerialNumber::from(serial),
+            validity,
+            Name::from_str(&format!("CN={}", domains[0])).unwrap(),
+            SubjectPublicKeyInfoOwned::from_der(spki_der).unwrap(),
+            &self.key,
+        )
+        .unwrap();
+        let san = domains.iter().map(|d| GeneralName::DnsName(Ia5String::new(d).unwrap())).collect();
+        builder.add_extension(&SubjectAltName(san)).unwrap();
+        let leaf = builder.build::<DerSignature>().unwrap();
+        format!("{}{}", leaf.to_pem(Default::default()).unwrap(), self.pem())
+    }
+
+    /// A certificate valid for `days` from now.
+    pub(crate) fn issue_for_days(&self, domains: &[String], spki_der: &[u8], days: i64) -> String {
+        let now = SystemTime::now();
+        let offset = Duration::from_secs(days.unsigned_abs() * 86400);
+        let not_after = if days >= 0 { now + offset } else { now - offset };
+        let not_before = now.min(not_after) - Duration::from_secs(86400);
+        self.issue(domains, spki_der, not_before, not_after)
+    }
+}
+
+struct Authz {
+    domain: String,
+    token: String,
+    thumbprint: String,
+    status: &'static str,
+    error: Option<String>,
+}
+
+struct Order {
+    authzs: Vec<usize>,
+    domains: Vec<String>,
+    status: &'static str,
+    certificate: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    next_nonce: u64,
+    nonces: Vec<String>,
+    accounts: Vec<VerifyingKey>,
+    authzs: Vec<Authz>,
+    orders: Vec<Order>,
+    validated: Vec<(ChallengeType, String)>,
+}
+
+struct Server {
+    base: String,
+    ca: TestCa,
+    webroot: Mutex<Option<PathBuf>>,
+    dns: Mutex<Option<MemoryDns>>,
+    state: Mutex<State>,
+}
+
+/// A running test server; it lives until the test process exits.
+pub(crate) struct TestServer {
+    server: Arc<Server>,
+}
+
+impl TestServer {
+    /// Start listening on a free localhost port.
+    pub(crate) fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let server = Arc::new(Server {
+            base: format!("http://{}", addr),
+            ca: TestCa::new(),
+            webroot: Mutex::new(None),
+            dns: Mutex::new(None),
+            state: Mutex::new(State::default()),
+        });
+        let handler = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = handler.clone();
+                thread::spawn(move || handler.serve(stream));
+            }
+        });
+        Self { server }
+    }
+
+    /// Offer HTTP-01, validated by reading responses from `webroot`.
+    pub(crate) fn with_webroot(self, webroot: &Path) -> Self {
+        *self.server.webroot.lock().unwrap() = Some(webroot.to_path_buf());
+        self
+    }
+
+    /// Offer DNS-01, validated by looking records up in `dns`.
+    pub(crate) fn with_dns(self, dns: MemoryDns) -> Self {
+        *self.server.dns.lock().unwrap() = Some(dns);
+        self
+    }
+
+    pub(crate) fn directory_url(&self) -> String {
+        format!("{}/dir", self.server.base)
+    }
+
+    /// The challenges validated so far, in order.
+    pub(crate) fn validated(&self) -> Vec<(ChallengeType, String)> {
+        self.server.state.lock().unwrap().validated.clone()
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    fn problem(status: u16, kind: &str, detail: &str) -> Self {
+        let mut response = Self::json(
+            status,
+            json!({ "type": format!("urn:ietf:params:acme:error:{}", kind), "detail": detail }),
+        );
+        response.headers[0].1 = "application/problem+json".to_string();
+        response
+    }
+
+    fn header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request { method, path, body })
+}
+
+fn decode(s: &str) -> Option<Vec<u8>> {
+    BASE64URL.decode(s).ok()
+}
+
+/// The RFC 7638 thumbprint of an EC JWK.
+fn thumbprint(jwk: &Value) -> String {
+    let canonical = json!({ "crv": jwk["crv"], "kty": jwk["kty"], "x": jwk["x"], "y": jwk["y"] });
+    b64(&Sha256::digest(canonical.to_string().as_bytes()))
+}
+
+fn jwk_key(jwk: &Value) -> Option<VerifyingKey> {
+    let mut point = vec![4u8];
+    point.extend(decode(jwk["x"].as_str()?)?);
+    point.extend(decode(jwk["y"].as_str()?)?);
+    VerifyingKey::from_sec1_bytes(&point).ok()
+}
+
+impl Server {
+    fn serve(&self, mut stream: TcpStream) {
+        let Some(request) = read_request(&mut stream) else {
+            return;
+        };
+        let response = self.handle(&request);
+        let mut head = format!("HTTP/1.1 {} X\r\nConnection: close\r\nContent-Length: {}\r\n", response.status, response.body.len());
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let _ = stream.write_all(head.as_bytes());
+        if request.method != "HEAD" {
+            let _ = stream.write_all(response.body.as_bytes());
+        }
+    }
+
+    fn nonce(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.next_nonce += 1;
+        let nonce = format!("nonce-{}", state.next_nonce);
+        state.nonces.push(nonce.clone());
+        nonce
+    }
+
+    fn url(&self, path: &str, id: usize) -> String {
+        format!("{}/{}/{}", self.base, path, id)
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let response = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/dir") => Response::json(
+                200,
+                json!({
+                    "newNonce": format!("{}/nonce", self.base),
+                    "newAccount": format!("{}/new-acct", self.base),
+                    "newOrder": format!("{}/new-order", self.base),
+                }),
+            ),
+            ("HEAD", "/nonce") => Response::json(200, json!({})),
+            ("POST", path) => self.post(path, &request.body),
+            _ => Response::problem(404, "malformed", "not found"),
+        };
+        response.header("Replay-Nonce", self.nonce())
+    }
+
+    fn post(&self, path: &str, body: &[u8]) -> Response {
+        let (account, thumbprint, payload) = match self.verify(path, body) {
+            Ok(verified) => verified,
+            Err(response) => return response,
+        };
+        let mut segments = path.trim_start_matches('/').splitn(2, '/');
+        let resource = segments.next().unwrap_or_default();
+        let id: Option<usize> = segments.next().and_then(|id| id.parse().ok());
+
+        match (resource, id) {
+            ("new-acct", None) => Response::json(201, json!({ "status": "valid" }))
+                .header("Location", self.url("acct", account)),
+            ("new-order", None) => self.new_order(&thumbprint, &payload),
+            ("authz", Some(id)) => self.authz(id),
+            ("chall", Some(id)) => self.challenge(id),
+            ("finalize", Some(id)) => self.finalize(id, &payload),
+            ("order", Some(id)) => self.order(id),
+            ("cert", Some(id)) => {
+                let state = self.state.lock().unwrap();
+                match state.orders.get(id).and_then(|o| o.certificate.clone()) {
+                    Some(pem) => Response {
+                        status: 200,
+                        headers: vec![("Content-Type", "application/pem-certificate-chain".to_string())],
+                        body: pem,
+                    },
+                    None => Response::problem(404, "malformed", "no such certificate"),
+                }
+            }
+            _ => Response::problem(404, "malformed", "not found"),
+        }
+    }
+
+    /// Check the JWS, returning the account index, its key thumbprint and
+    /// the decoded payload (`Null` for POST-as-GET).
+    fn verify(&self, path: &str, body: &[u8]) -> Result<(usize, String, Value), Response> {
+        let malformed = |detail: &str| Response::problem(400, "malformed", detail);
+        let jws: Value = serde_json::from_slice(body).map_err(|_| malformed("body is not JSON"))?;
+        let field = |name: &str| jws[name].as_str().map(str::to_string).ok_or_else(|| malformed(name));
+        let (protected_b64, payload_b64, signature) = (field("protected")?, field("payload")?, field("signature")?);
+        let protected: Value = decode(&protected_b64)
+            .and_then(|p| serde_json::from_slice(&p).ok())
+            .ok_or_else(|| malformed("bad protected header"))?;
+
+        if protected["alg"] != "ES256" {
+            return Err(Response::problem(400, "badSignatureAlgorithm", "ES256 only"));
+        }
+        if protected["url"] != format!("{}{}", self.base, path) {
+            return Err(malformed("url does not match the request"));
+        }
+        let nonce = protected["nonce"].as_str().unwrap_or_default();
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.nonces.iter().position(|n| n == nonce) {
+                Some(i) => {
+                    state.nonces.remove(i);
+                }
+                None => return Err(Response::problem(400, "badNonce", "unknown nonce")),
+            }
+        }
+
+        let (account, key) = if path == "/new-acct" {
+            let key = jwk_key(&protected["jwk"]).ok_or_else(|| malformed("bad jwk"))?;
+            let mut state = self.state.lock().unwrap();
+            let index = match state.accounts.iter().position(|k| *k == key) {
+                Some(index) => index,
+                None => {
+                    state.accounts.push(key);
+                    state.accounts.len() - 1
+                }
+            };
+            (index, key)
+        } else {
+            let kid = protected["kid"].as_str().ok_or_else(|| malformed("kid required"))?;
+            let state = self.state.lock().unwrap();
+            let index = kid
+                .strip_prefix(&format!("{}/acct/", self.base))
+                .and_then(|id| id.parse::<usize>().ok())
+                .filter(|&id| id < state.accounts.len())
+                .ok_or_else(|| Response::problem(400, "accountDoesNotExist", kid))?;
+            (index, state.accounts[index])
+        };
+
+        let signature = decode(&signature)
+            .and_then(|s| Signature::from_slice(&s).ok())
+            .ok_or_else(|| malformed("bad signature"))?;
+        let signing_input = format!("{}.{}", protected_b64, payload_b64);
+        if key.verify(signing_input.as_bytes(), &signature).is_err() {
+            return Err(Response::problem(403, "unauthorized", "signature does not verify"));
+        }
+
+        let point = key.to_encoded_point(false);
+        let jwk = json!({ "crv": "P-256", "kty": "EC", "x": b64(point.x().unwrap()), "y": b64(point.y().unwrap()) });
+        let payload = if payload_b64.is_empty() {
+            Value::Null
+        } else {
+            decode(&payload_b64)
+                .and_then(|p| serde_json::from_slice(&p).ok())
+                .ok_or_else(|| malformed("bad payload"))?
+        };
+        Ok((account, thumbprint(&jwk), payload))
+    }
+
+    fn new_order(&self, thumbprint: &str, payload: &Value) -> Response {
+        let domains: Vec<String> = payload["identifiers"]
+            .as_array()
+            .map(|ids| ids.iter().filter_map(|id| id["value"].as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+        if domains.is_empty() {
+            return Response::problem(400, "malformed", "no identifiers");
+        }
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            let mut authzs = Vec::new();
+            for domain in &domains {
+                state.authzs.push(Authz {
+                    domain: domain.clone(),
+                    token: b64(&rand::random::<[u8; 16]>()),
+                    thumbprint: thumbprint.to_string(),
+                    status: "pending",
+                    error: None,
+                });
+                authzs.push(state.authzs.len() - 1);
+            }
+            state.orders.push(Order {
+                authzs,
+                domains,
+                status: "pending",
+                certificate: None,
+            });
+            state.orders.len() - 1
+        };
+        let mut response = self.order(id);
+        response.status = 201;
+        response.header("Location", self.url("order", id))
+    }
+
+    fn offered(&self) -> Vec<ChallengeType> {
+        let mut offered = Vec::new();
+        if self.webroot.lock().unwrap().is_some() {
+            offered.push(ChallengeType::Http01);
+        }
+        if self.dns.lock().unwrap().is_some() {
+            offered.push(ChallengeType::Dns01);
+        }
+        offered
+    }
+
+    fn authz(&self, id: usize) -> Response {
+        let offered = self.offered();
+        let state = self.state.lock().unwrap();
+        let Some(authz) = state.authzs.get(id) else {
+            return Response::problem(404, "malformed", "no such authorization");
+        };
+        let challenges: Vec<Value> = offered
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| {
+                let mut challenge = json!({
+                    "type": kind.as_str(),
+                    "url": self.url("chall", id * 2 + i),
+                    "token": authz.token,
+                    "status": authz.status,
+                });
+                if let Some(error) = &authz.error {
+                    challenge["error"] = json!({ "type": "urn:ietf:params:acme:error:incorrectResponse", "detail": error });
+                }
+                challenge
+            })
+            .collect();
+        Response::json(
+            200,
+            json!({
+                "identifier": { "type": "dns", "value": authz.domain },
+                "status": authz.status,
+                "challenges": challenges,
+            }),
+        )
+    }
+
+    /// Validate a challenge on the spot.
+    fn challenge(&self, id: usize) -> Response {
+        let (authz_id, offered) = (id / 2, self.offered());
+        let Some(&kind) = offered.get(id % 2) else {
+            return Response::problem(404, "malformed", "no such challenge");
+        };
+        let (domain, token, key_authorization) = {
+            let state = self.state.lock().unwrap();
+            let Some(authz) = state.authzs.get(authz_id) else {
+                return Response::problem(404, "malformed", "no such challenge");
+            };
+            (authz.domain.clone(), authz.token.clone(), format!("{}.{}", authz.token, authz.thumbprint))
+        };
+
+        let result = match kind {
+            ChallengeType::Http01 => {
+                let webroot = self.webroot.lock().unwrap().clone().unwrap_or_default();
+                match std::fs::read_to_string(webroot.join(".well-known/acme-challenge").join(&token)) {
+                    Ok(served) if served.trim_end() == key_authorization => Ok(()),
+                    Ok(_) => Err("wrong key authorization served".to_string()),
+                    Err(e) => Err(format!("fetching the response: {}", e)),
+                }
+            }
+            ChallengeType::Dns01 => {
+                let dns = self.dns.lock().unwrap().clone().unwrap_or_default();
+                let values = dns.lookup_txt(&dns01_name(&domain)).unwrap_or_default();
+                if values.contains(&dns01_value(&key_authorization)) {
+                    Ok(())
+                } else {
+                    Err(format!("no matching TXT record at {}", dns01_name(&domain)))
+                }
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let authz = &mut state.authzs[authz_id];
+        match result {
+            Ok(()) => authz.status = "valid",
+            Err(error) => {
+                authz.status = "invalid";
+                authz.error = Some(error);
+            }
+        }
+        let status = authz.status;
+        if status == "valid" {
+            state.validated.push((kind, domain));
+        }
+        Response::json(200, json!({ "type": kind.as_str(), "status": status, "token": token }))
+    }
+
+    fn finalize(&self, id: usize, payload: &Value) -> Response {
+        let csr = payload["csr"]
+            .as_str()
+            .and_then(decode)
+            .and_then(|der| CertReq::from_der(&der).ok());
+        let Some(csr) = csr else {
+            return Response::problem(400, "badCSR", "unparseable CSR");
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            let Some(order) = state.orders.get(id) else {
+                return Response::problem(404, "malformed", "no such order");
+            };
+            if !order.authzs.iter().all(|&a| state.authzs[a].status == "valid") {
+                return Response::problem(403, "orderNotReady", "authorizations are not valid");
+            }
+            let spki = x509_cert::der::Encode::to_der(&csr.info.public_key).unwrap();
+            let now = SystemTime::now();
+            let pem = self.ca.issue(&order.domains, &spki, now, now + Duration::from_secs(90 * 86400));
+            let order = &mut state.orders[id];
+            order.status = "valid";
+            order.certificate = Some(pem);
+        }
+        self.order(id)
+    }
+
+    fn order(&self, id: usize) -> Response {
+        let state = self.state.lock().unwrap();
+        let Some(order) = state.orders.get(id) else {
+            return Response::problem(404, "malformed", "no such order");
+        };
+        let status = if order.status == "pending" && order.authzs.iter().all(|&a| state.authzs[a].status == "valid") {
+            "ready"
+        } else if order.authzs.iter().any(|&a| state.authzs[a].status == "invalid") {
+            "invalid"
+        } else {
+            order.status
+        };
+        let mut body = json!({
+            "status": status,
+            "authorizations": order.authzs.iter().map(|&a| self.url("authz", a)).collect::<Vec<_>>(),
+            "finalize": self.url("finalize", id),
+        });
+        if order.certificate.is_some() {
+            body["certificate"] = json!(self.url("cert", id));
+        }
+        Response::json(200, body)
+    }
+}
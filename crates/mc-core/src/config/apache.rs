@@ -1,4 +1,9 @@
 use std::fmt;
+use std::path::Path;
+
+use crate::acme;
+use crate::acme::challenge::{ACME_WEBROOT, HTTP01_PATH};
+use crate::mail::mta_sts::{self, MTA_STS_ROOT};
 
 /// Configuration for an Apache virtual host.
 #[derive(Debug, Clone)]
@@ -69,8 +74,15 @@ pub fn generate_vhost(config: &VhostConfig) -> Result<String, ApacheConfigError>
 {aliases}\
     ServerAdmin {server_admin}
 
-    # Redirect all HTTP traffic to HTTPS
+    # ACME HTTP-01 challenges are answered here, for certificate renewal
+    Alias {challenge_path} {webroot}{challenge_path}
+    <Directory {webroot}{challenge_path}>
+        Require all granted
+    </Directory>
+
+    # Redirect all other HTTP traffic to HTTPS
     RewriteEngine On
+    RewriteCond %{{REQUEST_URI}} !^{challenge_pattern}
     RewriteCond %{{HTTPS}} off
     RewriteRule ^ https://%{{HTTP_HOST}}%{{REQUEST_URI}} [L,R=301]
 </VirtualHost>
@@ -79,6 +91,9 @@ pub fn generate_vhost(config: &VhostConfig) -> Result<String, ApacheConfigError>
             server_name = config.server_name,
             server_admin = config.server_admin,
             aliases = format_aliases(&config.server_aliases, "    "),
+            challenge_path = HTTP01_PATH,
+            challenge_pattern = HTTP01_PATH.replace('.', "\\."),
+            webroot = ACME_WEBROOT,
         ));
     }
 
@@ -165,14 +180,8 @@ pub fn generate_webmail_vhost(
         server_admin: admin_email.to_string(),
         document_root: "/var/lib/roundcube/public_html".to_string(),
         server_aliases: Vec::new(),
-        ssl_cert: Some(format!(
-            "/etc/letsencrypt/live/{}/fullchain.pem",
-            hostname
-        )),
-        ssl_key: Some(format!(
-            "/etc/letsencrypt/live/{}/privkey.pem",
-            hostname
-        )),
+        ssl_cert: Some(acme::store::cert_path(&hostname)),
+        ssl_key: Some(acme::store::key_path(&hostname)),
         redirect_http_to_https: true,
         extra_directives: vec![
             format!("# {} webmail", site_name),
@@ -186,6 +195,90 @@ pub fn generate_webmail_vhost(
     generate_vhost(&config)
 }
 
+/// Generate the `mta-sts.<domain>` virtual host, which serves only the
+/// MTA-STS policy file written by the daemon. RFC 8461 requires HTTPS
+/// with a valid certificate and no redirects for the policy fetch, so the
+/// vhost is only written once `mta-sts.<domain>` has a certificate.
+pub fn generate_mta_sts_vhost(domain: &str, admin_email: &str) -> Result<String, ApacheConfigError> {
+    let hostname = mta_sts::policy_host(domain);
+    let config = VhostConfig {
+        server_name: hostname.clone(),
+        server_admin: admin_email.to_string(),
+        document_root: mta_sts::policy_dir(Path::new(MTA_STS_ROOT), domain)
+            .display()
+            .to_string(),
+        server_aliases: Vec::new(),
+        ssl_cert: Some(acme::store::cert_path(&hostname)),
+        ssl_key: Some(acme::store::key_path(&hostname)),
+        redirect_http_to_https: true,
+        extra_directives: vec![
+            "# MTA-STS policy (RFC 8461); nothing else is served".to_string(),
+            "RewriteEngine On".to_string(),
+            format!(
+                "RewriteRule !^/{}$ - [R=404,L]",
+                mta_sts::POLICY_PATH.replace('.', "\\.")
+            ),
+            "<Files \"mta-sts.txt\">".to_string(),
+            "    ForceType text/plain".to_string(),
+            "    Header set Cache-Control \"max-age=300\"".to_string(),
+            "</Files>".to_string(),
+        ],
+    };
+
+    generate_vhost(&config)
+}
+
+/// Point the TLS vhost for `server_name` in `content` at `cert` and `key`,
+/// keeping everything else as it is. Returns the new content, or `None`
+/// when no TLS vhost answers for the name or it already uses these files.
+pub fn set_vhost_ssl(content: &str, server_name: &str, cert: &str, key: &str) -> Option<String> {
+    let mut out = String::with_capacity(content.len());
+    let mut block: Vec<&str> = Vec::new();
+    let mut changed = false;
+
+    for line in content.split_inclusive('\n') {
+        let directive = line.trim_start();
+        if block.is_empty() && !starts_with_ci(directive, "<VirtualHost") {
+            out.push_str(line);
+            continue;
+        }
+        block.push(line);
+        if !starts_with_ci(directive, "</VirtualHost") {
+            continue;
+        }
+
+        let serves = block.iter().any(|l| {
+            let mut words = l.split_whitespace();
+            matches!(words.next(), Some(d) if d.eq_ignore_ascii_case("ServerName") || d.eq_ignore_ascii_case("ServerAlias"))
+                && words.any(|name| name.eq_ignore_ascii_case(server_name))
+        });
+        for l in block.drain(..) {
+            let replacement = match l.split_whitespace().next() {
+                Some(d) if serves && d.eq_ignore_ascii_case("SSLCertificateFile") => Some(("SSLCertificateFile", cert)),
+                Some(d) if serves && d.eq_ignore_ascii_case("SSLCertificateKeyFile") => Some(("SSLCertificateKeyFile", key)),
+                _ => None,
+            };
+            match replacement {
+                Some((directive, path)) => {
+                    let indent = &l[..l.len() - l.trim_start().len()];
+                    let ending = if l.ends_with('\n') { "\n" } else { "" };
+                    let new = format!("{}{} {}{}", indent, directive, path, ending);
+                    changed |= new != l;
+                    out.push_str(&new);
+                }
+                None => out.push_str(l),
+            }
+        }
+    }
+    // An unterminated block is left alone
+    out.extend(block);
+    changed.then_some(out)
+}
+
+fn starts_with_ci(s: &str, prefix: &str) -> bool {
+    s.get(..prefix.len()).is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+}
+
 /// Helper to format ServerAlias lines.
 fn format_aliases(aliases: &[String], indent: &str) -> String {
     let mut out = String::new();
@@ -279,6 +372,36 @@ mod tests {
         assert!(out.contains("R=301"));
     }
 
+    #[test]
+    fn test_redirect_leaves_acme_challenges() {
+        let out = generate_vhost(&ssl_config()).unwrap();
+        let http = &out[..out.find("<VirtualHost *:443>").unwrap()];
+        assert!(http.contains("Alias /.well-known/acme-challenge/ /var/www/html/.well-known/acme-challenge/"));
+        assert!(http.contains(r"RewriteCond %{REQUEST_URI} !^/\.well-known/acme-challenge/"));
+    }
+
+    #[test]
+    fn test_set_vhost_ssl() {
+        let out = format!(
+            "{}\n{}",
+            generate_webmail_vhost("example.com", "CeyMail", "admin@example.com").unwrap(),
+            generate_mta_sts_vhost("example.com", "admin@example.com").unwrap()
+        );
+        let updated = set_vhost_ssl(&out, "mta-sts.example.com", "/new/cert.pem", "/new/key.pem").unwrap();
+        // Only the matching host changes
+        let split = updated.find("ServerName mta-sts.example.com").unwrap();
+        assert!(updated[..split].contains("SSLCertificateFile /etc/ceymail-mc/acme/live/webmail.example.com/fullchain.pem"));
+        assert!(updated[split..].contains("    SSLCertificateFile /new/cert.pem\n"));
+        assert!(updated[split..].contains("    SSLCertificateKeyFile /new/key.pem\n"));
+        assert_eq!(updated.lines().count(), out.lines().count());
+
+        assert!(set_vhost_ssl(&updated, "mta-sts.example.com", "/new/cert.pem", "/new/key.pem").is_none());
+        assert!(set_vhost_ssl(&out, "other.example.com", "/new/cert.pem", "/new/key.pem").is_none());
+        // Plain HTTP vhosts have nothing to point
+        let plain = generate_vhost(&basic_config()).unwrap();
+        assert!(set_vhost_ssl(&plain, "www.example.com", "/c", "/k").is_none());
+    }
+
     #[test]
     fn test_no_redirect_without_ssl() {
         let config = basic_config();
@@ -305,6 +428,19 @@ mod tests {
         assert!(out.contains("X-Frame-Options"));
     }
 
+    #[test]
+    fn test_mta_sts_vhost() {
+        let out = generate_mta_sts_vhost("example.com", "admin@example.com").unwrap();
+        assert!(out.contains("<VirtualHost *:443>"));
+        assert!(out.contains("ServerName mta-sts.example.com"));
+        assert!(out.contains("DocumentRoot /var/www/mta-sts/example.com"));
+        assert!(out.contains("SSLCertificateFile /etc/ceymail-mc/acme/live/mta-sts.example.com/fullchain.pem"));
+        assert!(out.contains(r"RewriteRule !^/\.well-known/mta-sts\.txt$ - [R=404,L]"));
+        assert!(out.contains("ForceType text/plain"));
+        assert!(out.contains("AllowOverride None"));
+        assert!(!out.contains("php-fpm"));
+    }
+
     #[test]
     fn test_extra_directives() {
         let mut config = basic_config();
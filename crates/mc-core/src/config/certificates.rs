@@ -0,0 +1,797 @@
+//! Inventory of the TLS certificates the stack serves.
+//!
+//! Certificates are found through the configs that use them rather than
+//! through the ACME store, so a hand-edited path or a leftover certbot
+//! certificate is checked too:
+//!
+//! - Postfix: `smtpd_tls_cert_file` and `smtpd_tls_eccert_file` in main.cf
+//! - Dovecot: `ssl_cert` in dovecot.conf and its includes
+//! - Apache: `SSLCertificateFile` in each enabled `<VirtualHost>`
+//! - the daemon's own gRPC certificate
+//!
+//! Each is parsed for its expiry, names and key, and checked against the
+//! key file next to it, the rest of its chain, and the hostnames it is
+//! served for. [`check`] turns that into warnings by [`ExpiryThresholds`].
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use p256::ecdsa::{DerSignature, VerifyingKey as EcdsaVerifyingKey};
+use rsa::pkcs1v15::{Signature as RsaSignature, VerifyingKey as RsaVerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x509_cert::der::asn1::ObjectIdentifier;
+use x509_cert::der::Encode;
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::time::Time;
+use x509_cert::Certificate;
+
+use super::dovecot_conf::{DovecotConf, EffectiveConfig};
+use super::parser::parse_config;
+use crate::acme::csr::CertificateKey;
+
+/// The daemon's gRPC certificate and key.
+pub const DAEMON_CERT_PATH: &str = "/etc/ceymail-mc/certs/server.pem";
+pub const DAEMON_KEY_PATH: &str = "/etc/ceymail-mc/certs/server-key.pem";
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const SECP384R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.34");
+const SECP521R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.35");
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+
+/// What serves a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateUser {
+    Postfix,
+    Dovecot,
+    Apache,
+    Daemon,
+}
+
+/// A certificate a config points at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateReference {
+    pub user: CertificateUser,
+    /// The config file naming it.
+    pub config: PathBuf,
+    pub cert_path: PathBuf,
+    pub key_path: Option<PathBuf>,
+    /// Names clients reach it by, which it has to cover. Empty when they
+    /// are not known, as for the daemon.
+    pub hostnames: Vec<String>,
+}
+
+/// Where to look for certificate references.
+#[derive(Debug, Clone)]
+pub struct CertificateSources {
+    pub postfix_main: PathBuf,
+    pub dovecot_conf: PathBuf,
+    /// Every `*.conf` in here is read.
+    pub apache_sites: PathBuf,
+    pub daemon_cert: PathBuf,
+    pub daemon_key: PathBuf,
+}
+
+impl Default for CertificateSources {
+    fn default() -> Self {
+        Self {
+            postfix_main: PathBuf::from("/etc/postfix/main.cf"),
+            dovecot_conf: PathBuf::from("/etc/dovecot/dovecot.conf"),
+            apache_sites: PathBuf::from("/etc/apache2/sites-enabled"),
+            daemon_cert: PathBuf::from(DAEMON_CERT_PATH),
+            daemon_key: PathBuf::from(DAEMON_KEY_PATH),
+        }
+    }
+}
+
+/// Find every certificate the configs under `sources` refer to. Configs
+/// that are missing or cannot be parsed contribute nothing.
+pub fn discover(sources: &CertificateSources) -> Vec<CertificateReference> {
+    let mut references = Vec::new();
+
+    let mut mail_hostname = None;
+    if let Ok(content) = std::fs::read_to_string(&sources.postfix_main) {
+        let postfix = postfix_references(&sources.postfix_main, &content);
+        mail_hostname = postfix.first().and_then(|r| r.hostnames.first().cloned());
+        references.extend(postfix);
+    }
+
+    if sources.dovecot_conf.exists() {
+        match DovecotConf::load_effective(&sources.dovecot_conf) {
+            // Dovecot does not name itself; clients use the mail hostname
+            Ok(effective) => references.extend(dovecot_references(&effective, mail_hostname.as_deref())),
+            Err(e) => tracing::warn!("Cannot read {}: {}", sources.dovecot_conf.display(), e),
+        }
+    }
+
+    if let Ok(entries) = std::fs::read_dir(&sources.apache_sites) {
+        let mut sites: Vec<PathBuf> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "conf"))
+            .collect();
+        sites.sort();
+        for site in sites {
+            if let Ok(content) = std::fs::read_to_string(&site) {
+                references.extend(apache_references(&site, &content));
+            }
+        }
+    }
+
+    if sources.daemon_cert.exists() {
+        references.push(CertificateReference {
+            user: CertificateUser::Daemon,
+            config: sources.daemon_cert.clone(),
+            cert_path: sources.daemon_cert.clone(),
+            key_path: Some(sources.daemon_key.clone()),
+            hostnames: Vec::new(),
+        });
+    }
+
+    references
+}
+
+/// The certificates in a main.cf, served for `myhostname`.
+pub fn postfix_references(path: &Path, content: &str) -> Vec<CertificateReference> {
+    let Ok(config) = parse_config(content) else {
+        return Vec::new();
+    };
+    let value = |key: &str| {
+        config
+            .get(key)
+            .map(|v| config.expand(v))
+            .filter(|v| !v.trim().is_empty())
+    };
+    let hostnames: Vec<String> = value("myhostname").into_iter().collect();
+
+    [
+        ("smtpd_tls_cert_file", "smtpd_tls_key_file"),
+        ("smtpd_tls_eccert_file", "smtpd_tls_eckey_file"),
+    ]
+    .into_iter()
+    .filter_map(|(cert, key)| {
+        let cert_path = value(cert)?;
+        // The key defaults to the certificate file
+        let key_path = value(key).unwrap_or_else(|| cert_path.clone());
+        Some(CertificateReference {
+            user: CertificateUser::Postfix,
+            config: path.to_path_buf(),
+            cert_path: PathBuf::from(cert_path),
+            key_path: Some(PathBuf::from(key_path)),
+            hostnames: hostnames.clone(),
+        })
+    })
+    .collect()
+}
+
+/// The certificate in an effective Dovecot config, unless TLS is off.
+pub fn dovecot_references(effective: &EffectiveConfig, hostname: Option<&str>) -> Vec<CertificateReference> {
+    if effective.get("ssl") == Some("no") {
+        return Vec::new();
+    }
+    // The "<" makes Dovecot read the file rather than take the value as PEM
+    let file = |key: &str| {
+        effective
+            .get(key)
+            .and_then(|v| v.strip_prefix('<'))
+            .map(|v| PathBuf::from(v.trim()))
+    };
+    let Some(cert_path) = file("ssl_cert") else {
+        return Vec::new();
+    };
+    vec![CertificateReference {
+        user: CertificateUser::Dovecot,
+        config: effective
+            .origin("ssl_cert")
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+        cert_path,
+        key_path: file("ssl_key"),
+        hostnames: hostname.map(str::to_string).into_iter().collect(),
+    }]
+}
+
+/// The certificate of each TLS `<VirtualHost>` in an Apache site, served
+/// for its `ServerName` and `ServerAlias`es.
+pub fn apache_references(path: &Path, content: &str) -> Vec<CertificateReference> {
+    let mut references = Vec::new();
+    let mut vhost: Option<(Vec<String>, Option<String>, Option<String>)> = None;
+
+    for line in content.lines() {
+        let mut words = line.split_whitespace();
+        let Some(directive) = words.next() else {
+            continue;
+        };
+        let directive = directive.to_ascii_lowercase();
+        if directive.starts_with("<virtualhost") {
+            vhost = Some((Vec::new(), None, None));
+            continue;
+        }
+        let Some((names, cert, key)) = vhost.as_mut() else {
+            continue;
+        };
+        match directive.as_str() {
+            "</virtualhost>" => {
+                if let Some((names, Some(cert), key)) = vhost.take() {
+                    references.push(CertificateReference {
+                        user: CertificateUser::Apache,
+                        config: path.to_path_buf(),
+                        cert_path: PathBuf::from(cert),
+                        key_path: key.map(PathBuf::from),
+                        hostnames: names,
+                    });
+                }
+            }
+            "servername" | "serveralias" => names.extend(words.map(str::to_string)),
+            "sslcertificatefile" => *cert = words.next().map(unquote),
+            "sslcertificatekeyfile" => *key = words.next().map(unquote),
+            _ => {}
+        }
+    }
+    references
+}
+
+fn unquote(s: &str) -> String {
+    s.trim_matches('"').to_string()
+}
+
+// ── inspection ─────────────────────────────────────────────────────
+
+/// What a certificate file holds, from its first certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateDetails {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// DNS names and IP addresses from the subjectAltName extension.
+    pub names: Vec<String>,
+    /// e.g. "RSA 2048" or "ECDSA P-256".
+    pub key_type: String,
+    /// Certificates in the file, the leaf included.
+    pub chain_length: usize,
+}
+
+/// Parse a PEM chain. Along with the details, returns what is wrong with
+/// the chain: certificates out of order or not issued by the next one,
+/// and intermediates that have expired.
+///
+/// Signatures are verified where the issuer key is RSA or P-256 with
+/// SHA-256; others are only matched by name.
+pub fn inspect_chain(pem: &str, now: DateTime<Utc>) -> Result<(CertificateDetails, Vec<String>), String> {
+    let chain = Certificate::load_pem_chain(pem.as_bytes()).map_err(|e| format!("not a PEM certificate: {}", e))?;
+    let leaf = chain.first().ok_or("no certificate in the file")?;
+    let tbs = &leaf.tbs_certificate;
+
+    let names = match tbs.get::<SubjectAltName>() {
+        Ok(Some((_, san))) => san.0.iter().filter_map(general_name).collect(),
+        Ok(None) => Vec::new(),
+        Err(e) => return Err(format!("invalid subjectAltName: {}", e)),
+    };
+    let details = CertificateDetails {
+        subject: tbs.subject.to_string(),
+        issuer: tbs.issuer.to_string(),
+        not_before: time(&tbs.validity.not_before),
+        not_after: time(&tbs.validity.not_after),
+        names,
+        key_type: key_type(leaf),
+        chain_length: chain.len(),
+    };
+
+    let mut problems = Vec::new();
+    for (i, pair) in chain.windows(2).enumerate() {
+        let (cert, issuer) = (&pair[0], &pair[1]);
+        if cert.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+            problems.push(format!(
+                "certificate {} is issued by {}, not by the next one in the file ({})",
+                i + 1,
+                cert.tbs_certificate.issuer,
+                issuer.tbs_certificate.subject
+            ));
+        } else if verify_signature(cert, issuer) == Some(false) {
+            problems.push(format!("certificate {} has an invalid signature", i + 1));
+        }
+    }
+    for cert in chain.iter().skip(1) {
+        let validity = &cert.tbs_certificate.validity;
+        if time(&validity.not_after) < now {
+            problems.push(format!("intermediate {} has expired", cert.tbs_certificate.subject));
+        } else if time(&validity.not_before) > now {
+            problems.push(format!("intermediate {} is not valid yet", cert.tbs_certificate.subject));
+        }
+    }
+    Ok((details, problems))
+}
+
+/// Whether `names` cover `hostname`, matching a wildcard against one
+/// label only.
+pub fn covers(names: &[String], hostname: &str) -> bool {
+    let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+    names.iter().any(|name| {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        match name.strip_prefix("*.") {
+            Some(parent) => hostname
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && rest == parent),
+            None => name == hostname,
+        }
+    })
+}
+
+fn general_name(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DnsName(dns) => Some(dns.to_string()),
+        GeneralName::IpAddress(ip) => match ip.as_bytes().len() {
+            4 => <[u8; 4]>::try_from(ip.as_bytes()).ok().map(|b| Ipv4Addr::from(b).to_string()),
+            16 => <[u8; 16]>::try_from(ip.as_bytes()).ok().map(|b| Ipv6Addr::from(b).to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn time(t: &Time) -> DateTime<Utc> {
+    DateTime::from_timestamp(t.to_unix_duration().as_secs() as i64, 0).unwrap_or_default()
+}
+
+fn key_type(cert: &Certificate) -> String {
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    match spki.algorithm.oid {
+        RSA_ENCRYPTION => {
+            let bits = spki
+                .to_der()
+                .ok()
+                .and_then(|der| RsaPublicKey::from_public_key_der(&der).ok())
+                .map(|key| key.size() * 8);
+            match bits {
+                Some(bits) => format!("RSA {}", bits),
+                None => "RSA".to_string(),
+            }
+        }
+        EC_PUBLIC_KEY => match spki.algorithm.parameters.as_ref().map(|p| p.decode_as::<ObjectIdentifier>()) {
+            Some(Ok(SECP256R1)) => "ECDSA P-256".to_string(),
+            Some(Ok(SECP384R1)) => "ECDSA P-384".to_string(),
+            Some(Ok(SECP521R1)) => "ECDSA P-521".to_string(),
+            Some(Ok(curve)) => format!("ECDSA {}", curve),
+            _ => "ECDSA".to_string(),
+        },
+        ED25519 => "Ed25519".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Whether `issuer`'s key signed `cert`, or `None` for algorithms that
+/// are not checked.
+fn verify_signature(cert: &Certificate, issuer: &Certificate) -> Option<bool> {
+    let tbs = cert.tbs_certificate.to_der().ok()?;
+    let signature = cert.signature.as_bytes()?;
+    let issuer_spki = issuer.tbs_certificate.subject_public_key_info.to_der().ok()?;
+    match cert.signature_algorithm.oid {
+        SHA256_WITH_RSA => {
+            let key = RsaPublicKey::from_public_key_der(&issuer_spki).ok()?;
+            let signature = RsaSignature::try_from(signature).ok()?;
+            Some(RsaVerifyingKey::<Sha256>::new(key).verify(&tbs, &signature).is_ok())
+        }
+        ECDSA_WITH_SHA256 => {
+            // Only P-256 keys are checked; a P-384 issuer can sign with SHA-256 too
+            let key = EcdsaVerifyingKey::from_public_key_der(&issuer_spki).ok()?;
+            let signature = DerSignature::try_from(signature).ok()?;
+            Some(key.verify(&tbs, &signature).is_ok())
+        }
+        _ => None,
+    }
+}
+
+// ── report ─────────────────────────────────────────────────────────
+
+/// How far ahead of expiry to warn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiryThresholds {
+    pub warning: Duration,
+    pub critical: Duration,
+}
+
+impl Default for ExpiryThresholds {
+    /// ACME renewals start 30 days out, so a certificate still this close
+    /// to expiry means renewal is failing.
+    fn default() -> Self {
+        Self {
+            warning: Duration::days(21),
+            critical: Duration::days(7),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Expires within the thresholds, or already has.
+    Expiry,
+    NotYetValid,
+    /// The certificate or key file cannot be read or parsed.
+    Unreadable,
+    KeyMismatch,
+    Chain,
+    /// A hostname it is served for is not among its names.
+    Hostname,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateWarning {
+    pub kind: WarningKind,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl CertificateWarning {
+    fn critical(kind: WarningKind, message: String) -> Self {
+        Self {
+            kind,
+            severity: Severity::Critical,
+            message,
+        }
+    }
+}
+
+/// One certificate reference and what was found checking it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateEntry {
+    #[serde(flatten)]
+    pub reference: CertificateReference,
+    /// `None` when the file could not be parsed.
+    pub details: Option<CertificateDetails>,
+    pub warnings: Vec<CertificateWarning>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateReport {
+    pub checked_at: DateTime<Utc>,
+    pub certificates: Vec<CertificateEntry>,
+}
+
+impl CertificateReport {
+    /// Every warning, with the certificate it is about.
+    pub fn warnings(&self) -> impl Iterator<Item = (&CertificateEntry, &CertificateWarning)> {
+        self.certificates
+            .iter()
+            .flat_map(|entry| entry.warnings.iter().map(move |w| (entry, w)))
+    }
+}
+
+/// Discover and inspect every certificate under `sources`.
+pub fn check(sources: &CertificateSources, thresholds: &ExpiryThresholds, now: DateTime<Utc>) -> CertificateReport {
+    CertificateReport {
+        checked_at: now,
+        certificates: discover(sources)
+            .into_iter()
+            .map(|reference| inspect(reference, thresholds, now))
+            .collect(),
+    }
+}
+
+/// Read and check the certificate `reference` points at.
+pub fn inspect(reference: CertificateReference, thresholds: &ExpiryThresholds, now: DateTime<Utc>) -> CertificateEntry {
+    let mut warnings = Vec::new();
+    let cert_path = reference.cert_path.display().to_string();
+
+    let parsed = std::fs::read_to_string(&reference.cert_path)
+        .map_err(|e| e.to_string())
+        .and_then(|pem| Ok((inspect_chain(&pem, now)?, pem)));
+    let details = match parsed {
+        Ok(((details, problems), pem)) => {
+            warnings.extend(expiry_warning(&details, thresholds, now, &cert_path));
+            for problem in problems {
+                warnings.push(CertificateWarning::critical(
+                    WarningKind::Chain,
+                    format!("{}: {}", cert_path, problem),
+                ));
+            }
+            for hostname in &reference.hostnames {
+                if !hostname.contains('*') && !covers(&details.names, hostname) {
+                    warnings.push(CertificateWarning::critical(
+                        WarningKind::Hostname,
+                        format!("{} does not cover {}", cert_path, hostname),
+                    ));
+                }
+            }
+            if let Some(key_path) = &reference.key_path {
+                warnings.extend(key_warning(&pem, key_path));
+            }
+            Some(details)
+        }
+        Err(e) => {
+            warnings.push(CertificateWarning::critical(
+                WarningKind::Unreadable,
+                format!("Cannot read {}: {}", cert_path, e),
+            ));
+            None
+        }
+    };
+
+    CertificateEntry {
+        reference,
+        details,
+        warnings,
+    }
+}
+
+fn expiry_warning(
+    details: &CertificateDetails,
+    thresholds: &ExpiryThresholds,
+    now: DateTime<Utc>,
+    cert_path: &str,
+) -> Option<CertificateWarning> {
+    if details.not_before > now {
+        return Some(CertificateWarning::critical(
+            WarningKind::NotYetValid,
+            format!("{} is not valid until {}", cert_path, details.not_before.format("%Y-%m-%d %H:%M UTC")),
+        ));
+    }
+    let left = details.not_after - now;
+    let (severity, message) = if left <= Duration::zero() {
+        (Severity::Critical, format!("{} expired on {}", cert_path, details.not_after.format("%Y-%m-%d")))
+    } else if left <= thresholds.warning {
+        let severity = if left <= thresholds.critical {
+            Severity::Critical
+        } else {
+            Severity::Warning
+        };
+        let message = format!(
+            "{} expires in {} day(s), on {}",
+            cert_path,
+            left.num_days(),
+            details.not_after.format("%Y-%m-%d")
+        );
+        (severity, message)
+    } else {
+        return None;
+    };
+    Some(CertificateWarning {
+        kind: WarningKind::Expiry,
+        severity,
+        message,
+    })
+}
+
+/// Whether the key file holds the certificate's key. Keys of types other
+/// than RSA and P-256 are not compared.
+fn key_warning(pem: &str, key_path: &Path) -> Option<CertificateWarning> {
+    let key = match std::fs::read_to_string(key_path) {
+        Ok(key) => key,
+        Err(e) => {
+            return Some(CertificateWarning::critical(
+                WarningKind::Unreadable,
+                format!("Cannot read {}: {}", key_path.display(), e),
+            ));
+        }
+    };
+    let key_spki = CertificateKey::from_pem(&key).ok()?.spki_der().ok()?;
+    let cert_spki = crate::mail::dane::spki_from_cert_pem(pem).ok()?;
+    (key_spki != cert_spki).then(|| {
+        CertificateWarning::critical(
+            WarningKind::KeyMismatch,
+            format!("{} does not hold the key for its certificate", key_path.display()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::csr::KeyType;
+    use crate::acme::test_server::TestCa;
+
+    fn write_cert(dir: &Path, name: &str, domains: &[&str], days: i64) -> (PathBuf, PathBuf) {
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        let domains: Vec<String> = domains.iter().map(|d| d.to_string()).collect();
+        let chain = TestCa::new().issue_for_days(&domains, &key.spki_der().unwrap(), days);
+        let cert_path = dir.join(format!("{}.pem", name));
+        let key_path = dir.join(format!("{}.key", name));
+        std::fs::write(&cert_path, chain).unwrap();
+        std::fs::write(&key_path, key.to_pem().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn reference(cert_path: &Path, key_path: &Path, hostname: &str) -> CertificateReference {
+        CertificateReference {
+            user: CertificateUser::Apache,
+            config: PathBuf::from("/etc/apache2/sites-enabled/site.conf"),
+            cert_path: cert_path.to_path_buf(),
+            key_path: Some(key_path.to_path_buf()),
+            hostnames: vec![hostname.to_string()],
+        }
+    }
+
+    fn kinds(entry: &CertificateEntry) -> Vec<(WarningKind, Severity)> {
+        entry.warnings.iter().map(|w| (w.kind, w.severity)).collect()
+    }
+
+    #[test]
+    fn test_postfix_references() {
+        let main_cf = "myhostname = mail.example.com\n\
+                       smtpd_tls_cert_file = /etc/ceymail-mc/acme/live/$myhostname/fullchain.pem\n\
+                       smtpd_tls_key_file = /etc/ceymail-mc/acme/live/$myhostname/privkey.pem\n";
+        let refs = postfix_references(Path::new("/etc/postfix/main.cf"), main_cf);
+        assert_eq!(refs.len(), 1);
+        assert_eq!(
+            refs[0].cert_path,
+            Path::new("/etc/ceymail-mc/acme/live/mail.example.com/fullchain.pem")
+        );
+        assert_eq!(
+            refs[0].key_path.as_deref(),
+            Some(Path::new("/etc/ceymail-mc/acme/live/mail.example.com/privkey.pem"))
+        );
+        assert_eq!(refs[0].hostnames, vec!["mail.example.com"]);
+
+        // No TLS configured
+        assert!(postfix_references(Path::new("main.cf"), "myhostname = mail.example.com\n").is_empty());
+    }
+
+    #[test]
+    fn test_dovecot_references_follow_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("conf.d")).unwrap();
+        std::fs::write(dir.path().join("dovecot.conf"), "!include conf.d/*.conf\n").unwrap();
+        std::fs::write(
+            dir.path().join("conf.d/10-ssl.conf"),
+            "ssl = required\nssl_cert = </etc/ssl/mail.pem\nssl_key = </etc/ssl/mail.key\n",
+        )
+        .unwrap();
+
+        let effective = DovecotConf::load_effective(&dir.path().join("dovecot.conf")).unwrap();
+        let refs = dovecot_references(&effective, Some("mail.example.com"));
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].config, dir.path().join("conf.d/10-ssl.conf"));
+        assert_eq!(refs[0].cert_path, Path::new("/etc/ssl/mail.pem"));
+        assert_eq!(refs[0].key_path.as_deref(), Some(Path::new("/etc/ssl/mail.key")));
+        assert_eq!(refs[0].hostnames, vec!["mail.example.com"]);
+    }
+
+    #[test]
+    fn test_apache_references_per_vhost() {
+        let site = "<VirtualHost *:80>\n    ServerName webmail.example.com\n</VirtualHost>\n\
+                    <VirtualHost *:443>\n    ServerName webmail.example.com\n    ServerAlias mail.example.com\n\
+                    \x20   SSLEngine on\n    SSLCertificateFile \"/etc/ssl/webmail.pem\"\n\
+                    \x20   SSLCertificateKeyFile /etc/ssl/webmail.key\n</VirtualHost>\n";
+        let refs = apache_references(Path::new("/etc/apache2/sites-enabled/webmail.conf"), site);
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].cert_path, Path::new("/etc/ssl/webmail.pem"));
+        assert_eq!(refs[0].hostnames, vec!["webmail.example.com", "mail.example.com"]);
+    }
+
+    #[test]
+    fn test_discover_and_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = write_cert(dir.path(), "mail", &["mail.example.com"], 60);
+        let main_cf = dir.path().join("main.cf");
+        std::fs::write(
+            &main_cf,
+            format!(
+                "myhostname = mail.example.com\nsmtpd_tls_cert_file = {}\nsmtpd_tls_key_file = {}\n",
+                cert.display(),
+                key.display()
+            ),
+        )
+        .unwrap();
+        let dovecot_conf = dir.path().join("dovecot.conf");
+        std::fs::write(&dovecot_conf, format!("ssl_cert = <{}\nssl_key = <{}\n", cert.display(), key.display())).unwrap();
+        let sources = CertificateSources {
+            postfix_main: main_cf,
+            dovecot_conf,
+            apache_sites: dir.path().join("sites-enabled"),
+            daemon_cert: dir.path().join("server.pem"),
+            daemon_key: dir.path().join("server-key.pem"),
+        };
+
+        let report = check(&sources, &ExpiryThresholds::default(), Utc::now());
+        let users: Vec<CertificateUser> = report.certificates.iter().map(|e| e.reference.user).collect();
+        assert_eq!(users, vec![CertificateUser::Postfix, CertificateUser::Dovecot]);
+        assert_eq!(report.warnings().count(), 0);
+
+        let details = report.certificates[1].details.as_ref().unwrap();
+        assert_eq!(details.names, vec!["mail.example.com"]);
+        assert_eq!(details.key_type, "ECDSA P-256");
+        assert_eq!(details.issuer, "CN=Test ACME CA");
+        assert_eq!(details.chain_length, 2);
+        assert_eq!(report.certificates[1].reference.hostnames, vec!["mail.example.com"]);
+    }
+
+    #[test]
+    fn test_expiry_thresholds() {
+        let dir = tempfile::tempdir().unwrap();
+        let thresholds = ExpiryThresholds::default();
+        let now = Utc::now();
+        let check = |days: i64| {
+            let (cert, key) = write_cert(dir.path(), &format!("d{}", days), &["a.example.com"], days);
+            kinds(&inspect(reference(&cert, &key, "a.example.com"), &thresholds, now))
+        };
+        assert!(check(60).is_empty());
+        assert_eq!(check(14), vec![(WarningKind::Expiry, Severity::Warning)]);
+        assert_eq!(check(3), vec![(WarningKind::Expiry, Severity::Critical)]);
+        assert_eq!(check(-2), vec![(WarningKind::Expiry, Severity::Critical)]);
+
+        let strict = ExpiryThresholds {
+            warning: Duration::days(90),
+            critical: Duration::days(70),
+        };
+        let (cert, key) = write_cert(dir.path(), "strict", &["a.example.com"], 60);
+        // Taken after issuing, so a little under 60 days are left
+        let entry = inspect(reference(&cert, &key, "a.example.com"), &strict, Utc::now());
+        assert_eq!(kinds(&entry), vec![(WarningKind::Expiry, Severity::Critical)]);
+        assert!(entry.warnings[0].message.contains("expires in 59 day(s)"));
+    }
+
+    #[test]
+    fn test_hostname_coverage() {
+        let names = vec!["*.example.com".to_string(), "example.com".to_string()];
+        assert!(covers(&names, "mail.example.com"));
+        assert!(covers(&names, "Example.COM."));
+        assert!(!covers(&names, "a.b.example.com"));
+        assert!(!covers(&names, "example.org"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = write_cert(dir.path(), "web", &["webmail.example.com"], 60);
+        let entry = inspect(reference(&cert, &key, "mail.example.com"), &ExpiryThresholds::default(), Utc::now());
+        assert_eq!(kinds(&entry), vec![(WarningKind::Hostname, Severity::Critical)]);
+    }
+
+    #[test]
+    fn test_key_mismatch_and_unreadable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, _) = write_cert(dir.path(), "a", &["a.example.com"], 60);
+        let (_, other_key) = write_cert(dir.path(), "b", &["a.example.com"], 60);
+        let thresholds = ExpiryThresholds::default();
+
+        let entry = inspect(reference(&cert, &other_key, "a.example.com"), &thresholds, Utc::now());
+        assert_eq!(kinds(&entry), vec![(WarningKind::KeyMismatch, Severity::Critical)]);
+
+        let missing = dir.path().join("missing.pem");
+        let entry = inspect(reference(&missing, &other_key, "a.example.com"), &thresholds, Utc::now());
+        assert!(entry.details.is_none());
+        assert_eq!(kinds(&entry), vec![(WarningKind::Unreadable, Severity::Critical)]);
+    }
+
+    #[test]
+    fn test_chain_problems() {
+        let now = Utc::now();
+        let key = CertificateKey::generate(KeyType::EcdsaP256).unwrap();
+        let domains = vec!["a.example.com".to_string()];
+        let chain = TestCa::new().issue_for_days(&domains, &key.spki_der().unwrap(), 60);
+        let (_, problems) = inspect_chain(&chain, now).unwrap();
+        assert!(problems.is_empty());
+
+        // The leaf followed by another CA of the same name: names match,
+        // the signature does not
+        let leaf_end = chain.find("-----END CERTIFICATE-----\n").unwrap() + 26;
+        let wrong = format!("{}{}", &chain[..leaf_end], TestCa::new().pem());
+        let (_, problems) = inspect_chain(&wrong, now).unwrap();
+        assert_eq!(problems, vec!["certificate 1 has an invalid signature"]);
+
+        // Intermediates in the wrong order
+        let reversed = format!("{}{}", &chain[leaf_end..], &chain[..leaf_end]);
+        let (details, problems) = inspect_chain(&reversed, now).unwrap();
+        assert_eq!(details.subject, "CN=Test ACME CA");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("not by the next one"));
+
+        assert!(inspect_chain("not a certificate", now).is_err());
+    }
+}
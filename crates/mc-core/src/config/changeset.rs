@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+use super::roundcube;
+use crate::fs::atomic;
+
+/// Mode for staged files that do not exist yet.
+const DEFAULT_MODE: u32 = 0o644;
+
+#[derive(Debug)]
+pub enum ChangesetError {
+    Io { path: PathBuf, source: std::io::Error },
+    Write { path: PathBuf, message: String },
+}
+
+impl fmt::Display for ChangesetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Self::Write { path, message } => {
+                write!(f, "Failed to write {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChangesetError {}
+
+/// The check that covers a config file, and the unit that reads it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConfigCheck {
+    Postfix,
+    Dovecot,
+    Opendkim,
+    SpamAssassin,
+    Apache,
+    Roundcube,
+}
+
+impl ConfigCheck {
+    pub fn for_path(path: &Path) -> Option<Self> {
+        let path = path.to_str()?;
+        [
+            ("/etc/postfix/", Self::Postfix),
+            ("/etc/dovecot/", Self::Dovecot),
+            ("/etc/opendkim", Self::Opendkim),
+            ("/etc/spamassassin/", Self::SpamAssassin),
+            ("/etc/apache2/", Self::Apache),
+            ("/etc/roundcube/", Self::Roundcube),
+        ]
+        .into_iter()
+        .find(|(prefix, _)| path.starts_with(prefix))
+        .map(|(_, check)| check)
+    }
+
+    /// The systemd unit to reload after the files change.
+    pub fn unit(self) -> &'static str {
+        match self {
+            Self::Postfix => "postfix",
+            Self::Dovecot => "dovecot",
+            Self::Opendkim => "opendkim",
+            Self::SpamAssassin => "spamassassin",
+            // Roundcube is served by mod_php; a reload clears the opcache
+            Self::Apache | Self::Roundcube => "apache2",
+        }
+    }
+
+    /// Commands that check the installed config in place. Each is a
+    /// program followed by its arguments; all must exit successfully.
+    pub fn commands(self) -> &'static [&'static [&'static str]] {
+        match self {
+            Self::Postfix => &[&["postconf", "-n"], &["postconf", "-M"]],
+            Self::Dovecot => &[&["doveconf", "-n"]],
+            Self::Opendkim => &[&["opendkim", "-n", "-x", "/etc/opendkim.conf"]],
+            Self::SpamAssassin => &[&["spamassassin", "--lint"]],
+            Self::Apache => &[&["apachectl", "configtest"]],
+            Self::Roundcube => &[&["php", "-l", "/etc/roundcube/config.inc.php"]],
+        }
+    }
+}
+
+/// What writing one file would change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    /// True when the file does not exist yet.
+    pub created: bool,
+    /// Unified diff from the current content to the new one.
+    pub diff: String,
+}
+
+impl FileDiff {
+    /// Diff `previous` (`None` for a new file) against `content`, or
+    /// `None` when nothing would change.
+    ///
+    /// Roundcube's config holds the database password, so its secrets are
+    /// masked on both sides before diffing.
+    pub fn new(path: &Path, previous: Option<&str>, content: &str) -> Option<Self> {
+        if previous == Some(content) {
+            return None;
+        }
+        let (old, new) = if ConfigCheck::for_path(path) == Some(ConfigCheck::Roundcube) {
+            (roundcube::mask_secrets(previous.unwrap_or_default()), roundcube::mask_secrets(content))
+        } else {
+            (previous.unwrap_or_default().to_string(), content.to_string())
+        };
+        let name = path.display();
+        let old_header = match previous {
+            Some(_) => format!("a{}", name),
+            None => "/dev/null".to_string(),
+        };
+        let diff = TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .context_radius(3)
+            .header(&old_header, &format!("b{}", name))
+            .to_string();
+        Some(Self {
+            path: path.to_path_buf(),
+            created: previous.is_none(),
+            diff,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StagedFile {
+    pub path: PathBuf,
+    pub content: String,
+    /// Mode for a new file; an existing file keeps its mode.
+    pub mode: u32,
+    /// `(uid, gid)` for a new file, if not the writer's; an existing file
+    /// keeps its owner.
+    pub owner: Option<(u32, u32)>,
+}
+
+/// A set of config file writes applied together.
+///
+/// Files are staged in memory, then [`Changeset::write_all`] writes them one
+/// by one (each atomically) and returns an [`AppliedChangeset`] holding the
+/// previous contents, so the whole set can be undone if a later step such
+/// as validation or a service reload fails.
+#[derive(Debug, Clone, Default)]
+pub struct Changeset {
+    files: BTreeMap<PathBuf, StagedFile>,
+}
+
+impl Changeset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stage `content` for `path`, replacing anything staged for it before.
+    pub fn stage(&mut self, path: impl Into<PathBuf>, content: impl Into<String>) -> &mut Self {
+        self.stage_with_mode(path, content, DEFAULT_MODE)
+    }
+
+    pub fn stage_with_mode(
+        &mut self,
+        path: impl Into<PathBuf>,
+        content: impl Into<String>,
+        mode: u32,
+    ) -> &mut Self {
+        self.stage_owned(path, content, mode, None)
+    }
+
+    /// Stage a file that, if it has to be created, gets `owner` as
+    /// `(uid, gid)`, e.g. a secret readable by one service's group.
+    pub fn stage_owned(
+        &mut self,
+        path: impl Into<PathBuf>,
+        content: impl Into<String>,
+        mode: u32,
+        owner: Option<(u32, u32)>,
+    ) -> &mut Self {
+        let path = path.into();
+        self.files.insert(
+            path.clone(),
+            StagedFile {
+                path,
+                content: content.into(),
+                mode,
+                owner,
+            },
+        );
+        self
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&StagedFile> {
+        self.files.get(path)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &StagedFile> {
+        self.files.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Checks (and so units) affected by the staged files.
+    pub fn checks(&self) -> BTreeSet<ConfigCheck> {
+        self.files
+            .keys()
+            .filter_map(|p| ConfigCheck::for_path(p))
+            .collect()
+    }
+
+    /// Diff every staged file against what is on disk, without writing
+    /// anything. Unchanged files are left out.
+    pub fn preview(&self) -> Result<Vec<FileDiff>, ChangesetError> {
+        let mut diffs = Vec::new();
+        for file in self.files.values() {
+            let previous = match std::fs::read(&file.path) {
+                Ok(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(source) => {
+                    return Err(ChangesetError::Io {
+                        path: file.path.clone(),
+                        source,
+                    })
+                }
+            };
+            diffs.extend(FileDiff::new(&file.path, previous.as_deref(), &file.content));
+        }
+        Ok(diffs)
+    }
+
+    /// Write every staged file whose content differs from disk. If a write
+    /// fails, the files already written are restored before returning.
+    pub fn write_all(&self) -> Result<AppliedChangeset, ChangesetError> {
+        let mut applied = AppliedChangeset::default();
+        for file in self.files.values() {
+            let original = match std::fs::read(&file.path) {
+                Ok(bytes) => match std::fs::metadata(&file.path) {
+                    Ok(meta) => Some(Original {
+                        bytes,
+                        mode: meta.permissions().mode() & 0o7777,
+                        owner: Some((meta.uid(), meta.gid())),
+                    }),
+                    Err(_) => Some(Original {
+                        bytes,
+                        mode: file.mode,
+                        owner: None,
+                    }),
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(source) => {
+                    applied.restore();
+                    return Err(ChangesetError::Io {
+                        path: file.path.clone(),
+                        source,
+                    });
+                }
+            };
+            if original.as_ref().is_some_and(|o| o.bytes == file.content.as_bytes()) {
+                continue;
+            }
+
+            let (mode, owner) = match &original {
+                Some(o) => (o.mode, o.owner),
+                None => (file.mode, file.owner),
+            };
+            if let Err(e) =
+                atomic::atomic_write_owned(&file.path, file.content.as_bytes(), Some(mode), owner)
+            {
+                applied.restore();
+                return Err(ChangesetError::Write {
+                    path: file.path.clone(),
+                    message: e.to_string(),
+                });
+            }
+            applied.written.push(WrittenFile {
+                path: file.path.clone(),
+                original,
+                content: file.content.clone(),
+            });
+        }
+        Ok(applied)
+    }
+}
+
+/// A file as it was before a changeset replaced it.
+#[derive(Debug, Clone)]
+struct Original {
+    bytes: Vec<u8>,
+    mode: u32,
+    /// `(uid, gid)`, if the file's metadata could be read.
+    owner: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone)]
+struct WrittenFile {
+    path: PathBuf,
+    original: Option<Original>,
+    content: String,
+}
+
+/// The files a changeset actually changed, with what they held before.
+#[derive(Debug, Clone, Default)]
+pub struct AppliedChangeset {
+    written: Vec<WrittenFile>,
+}
+
+impl AppliedChangeset {
+    pub fn is_empty(&self) -> bool {
+        self.written.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.written.iter().map(|w| w.path.as_path())
+    }
+
+    /// Each changed file as `(path, previous, new)`; `previous` is `None`
+    /// for files the changeset created (or that were not UTF-8).
+    pub fn changes(&self) -> impl Iterator<Item = (&Path, Option<&str>, &str)> {
+        self.written.iter().map(|w| {
+            let previous = w
+                .original
+                .as_ref()
+                .and_then(|o| std::str::from_utf8(&o.bytes).ok());
+            (w.path.as_path(), previous, w.content.as_str())
+        })
+    }
+
+    /// Put every changed file back the way it was, removing files the
+    /// changeset created. Returns the files that could not be restored.
+    pub fn restore(&self) -> Vec<ChangesetError> {
+        let mut failures = Vec::new();
+        for file in self.written.iter().rev() {
+            let result = match &file.original {
+                Some(o) => atomic::atomic_write_owned(&file.path, &o.bytes, Some(o.mode), o.owner)
+                    .map_err(|e| ChangesetError::Write {
+                        path: file.path.clone(),
+                        message: e.to_string(),
+                    }),
+                None => std::fs::remove_file(&file.path).map_err(|source| ChangesetError::Io {
+                    path: file.path.clone(),
+                    source,
+                }),
+            };
+            if let Err(e) = result {
+                failures.push(e);
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_from_paths() {
+        let mut cs = Changeset::new();
+        cs.stage("/etc/opendkim.conf", "")
+            .stage("/etc/opendkim/key.table", "")
+            .stage("/etc/postfix/main.cf", "")
+            .stage("/etc/hosts", "");
+        let checks: Vec<_> = cs.checks().into_iter().collect();
+        assert_eq!(checks, vec![ConfigCheck::Postfix, ConfigCheck::Opendkim]);
+        assert_eq!(ConfigCheck::Apache.unit(), "apache2");
+    }
+
+    #[test]
+    fn test_write_all_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("main.cf");
+        let unchanged = dir.path().join("master.cf");
+        let created = dir.path().join("key.table");
+        std::fs::write(&existing, "a = 1\n").unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o640)).unwrap();
+        std::fs::write(&unchanged, "smtp inet n - y - - smtpd\n").unwrap();
+
+        let mut cs = Changeset::new();
+        cs.stage(&existing, "a = 2\n")
+            .stage(&unchanged, "smtp inet n - y - - smtpd\n")
+            .stage(&created, "k d:s:/key\n");
+        let applied = cs.write_all().unwrap();
+
+        let paths: Vec<_> = applied.paths().collect();
+        assert_eq!(paths, vec![created.as_path(), existing.as_path()]);
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "a = 2\n");
+        let mode = std::fs::metadata(&existing).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o640);
+        let changes: Vec<_> = applied.changes().collect();
+        assert_eq!(changes[1], (existing.as_path(), Some("a = 1\n"), "a = 2\n"));
+
+        assert!(applied.restore().is_empty());
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "a = 1\n");
+        assert!(!created.exists());
+    }
+
+    #[test]
+    fn test_write_all_keeps_owner() {
+        // Only root can own files for someone else
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("opendkim.conf");
+        std::fs::write(&path, "Mode s\n").unwrap();
+        std::os::unix::fs::chown(&path, Some(1234), Some(5678)).unwrap();
+
+        let mut cs = Changeset::new();
+        cs.stage(&path, "Mode sv\n");
+        let applied = cs.write_all().unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (1234, 5678));
+
+        assert!(applied.restore().is_empty());
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (1234, 5678));
+    }
+
+    #[test]
+    fn test_preview_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("main.cf");
+        let created = dir.path().join("key.table");
+        std::fs::write(&existing, "a = 1\nb = 2\n").unwrap();
+
+        let mut cs = Changeset::new();
+        cs.stage(&existing, "a = 1\nb = 3\n").stage(&created, "k d:s:/key\n");
+        let diffs = cs.preview().unwrap();
+
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].created);
+        assert!(diffs[0].diff.starts_with("--- /dev/null\n"));
+        assert!(!diffs[1].created);
+        assert!(diffs[1].diff.contains("-b = 2\n+b = 3\n"));
+        assert!(!created.exists());
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "a = 1\nb = 2\n");
+
+        cs.stage(&existing, "a = 1\nb = 2\n");
+        assert_eq!(cs.preview().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_roundcube_diff_masks_secrets() {
+        let path = Path::new("/etc/roundcube/config.inc.php");
+        let old = "$config['des_key'] = 'old-key';\n$config['skin'] = 'larry';\n";
+        let new = "$config['des_key'] = 'new-key';\n$config['skin'] = 'elastic';\n";
+        let diff = FileDiff::new(path, Some(old), new).unwrap().diff;
+        assert!(!diff.contains("old-key") && !diff.contains("new-key"));
+        assert!(diff.contains("+$config['skin'] = 'elastic';"));
+    }
+
+    #[test]
+    fn test_failed_write_restores_earlier_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("a.cf");
+        std::fs::write(&first, "old\n").unwrap();
+
+        let mut cs = Changeset::new();
+        cs.stage(&first, "new\n")
+            .stage(dir.path().join("missing-dir/b.cf"), "x\n");
+        assert!(cs.write_all().is_err());
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "old\n");
+    }
+}
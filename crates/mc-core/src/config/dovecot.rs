@@ -1,5 +1,8 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
+use crate::acme;
+
 /// Dovecot configuration manager.
 ///
 /// Dovecot uses a nested brace-delimited config format rather than simple
@@ -32,6 +35,25 @@ pub struct DovecotConfig {
     pub log_path: String,
     /// Info log path. Empty string means same as log_path.
     pub info_log_path: String,
+    /// Where Dovecot records which users share mailboxes with whom.
+    pub shared_dict: SharedDictBackend,
+}
+
+/// Backend for `acl_shared_dict`, the index Dovecot uses to list other
+/// users' mailboxes under the `shared/` namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SharedDictBackend {
+    /// A local file dict. The directory must be writable by vmail.
+    File(String),
+    /// The `acl_user_shares` / `acl_anyone_shares` tables in the mail DB,
+    /// reached through Dovecot's dict proxy.
+    Sql,
+}
+
+impl Default for SharedDictBackend {
+    fn default() -> Self {
+        Self::File("/var/lib/dovecot/db/shared-mailboxes.db".to_string())
+    }
 }
 
 #[derive(Debug)]
@@ -56,8 +78,8 @@ impl DovecotConfig {
         Self {
             domain: domain.to_string(),
             hostname: hostname.clone(),
-            ssl_cert: format!("/etc/letsencrypt/live/{}/fullchain.pem", hostname),
-            ssl_key: format!("/etc/letsencrypt/live/{}/privkey.pem", hostname),
+            ssl_cert: acme::store::cert_path(&hostname),
+            ssl_key: acme::store::key_path(&hostname),
             mail_home_base: "/var/mail/vhosts".to_string(),
             mail_location: "maildir:/var/mail/vhosts/%d/%n/Maildir".to_string(),
             db_name: "mailserver".to_string(),
@@ -66,6 +88,7 @@ impl DovecotConfig {
             db_host: "127.0.0.1".to_string(),
             log_path: String::new(),
             info_log_path: String::new(),
+            shared_dict: SharedDictBackend::default(),
         }
     }
 
@@ -90,9 +113,11 @@ auth_mechanisms = plain login
 ## generated by mission-control
 mail_location = {}
 mail_privileged_group = mail
+mail_plugins = $mail_plugins acl
 
 namespace inbox {{
   inbox = yes
+  separator = /
 
   mailbox Drafts {{
     auto = subscribe
@@ -114,6 +139,17 @@ namespace inbox {{
     special_use = \\Trash
   }}
 }}
+
+# Mailboxes other users have shared via IMAP ACLs, as shared/<owner>/<folder>.
+# Private flags (\\Seen etc.) are kept per reader in INDEXPVT.
+namespace shared {{
+  type = shared
+  separator = /
+  prefix = shared/%%u/
+  location = maildir:%%h/Maildir:INDEXPVT=~/Maildir/shared/%%u
+  subscriptions = no
+  list = children
+}}
 ",
             self.mail_location
         )
@@ -144,6 +180,16 @@ service pop3-login {
   }
 }
 
+service managesieve-login {
+  inet_listener sieve {
+    port = 4190
+  }
+}
+
+service managesieve {
+  process_limit = 256
+}
+
 service lmtp {
   unix_listener /var/spool/postfix/private/dovecot-lmtp {
     mode = 0600
@@ -174,6 +220,139 @@ service auth-worker {
         .to_string()
     }
 
+    /// Generate 20-imap.conf
+    pub fn generate_20_imap(&self) -> String {
+        "\
+## generated by mission-control
+protocol imap {
+  mail_plugins = $mail_plugins imap_acl
+}
+"
+        .to_string()
+    }
+
+    /// Generate 20-lmtp.conf
+    ///
+    /// Sieve runs at delivery time, so the plugin belongs to LMTP.
+    pub fn generate_20_lmtp(&self) -> String {
+        format!(
+            "\
+## generated by mission-control
+protocol lmtp {{
+  postmaster_address = postmaster@{}
+  mail_plugins = $mail_plugins sieve
+}}
+",
+            self.domain
+        )
+    }
+
+    /// Generate 20-managesieve.conf
+    pub fn generate_20_managesieve(&self) -> String {
+        "\
+## generated by mission-control
+protocols = $protocols sieve
+
+protocol sieve {
+  managesieve_max_line_length = 65536
+}
+"
+        .to_string()
+    }
+
+    /// Generate 90-sieve.conf
+    ///
+    /// Users' own scripts live in `~/sieve` with `~/.dovecot.sieve` marking the
+    /// active one (the ManageSieve layout). `~/sieve-after` holds the vacation
+    /// responder so it runs regardless of which user script is active.
+    pub fn generate_90_sieve(&self) -> String {
+        "\
+## generated by mission-control
+plugin {
+  sieve = file:~/sieve;active=~/.dovecot.sieve
+  sieve_after = file:~/sieve-after
+  sieve_max_script_size = 1M
+  sieve_vacation_min_period = 1d
+  sieve_vacation_max_period = 60d
+}
+"
+        .to_string()
+    }
+
+    /// Generate 90-acl.conf
+    ///
+    /// ACLs live in per-mailbox `dovecot-acl` files (`vfile`); the shared
+    /// dict only indexes who can see whose mailboxes.
+    pub fn generate_90_acl(&self) -> String {
+        match &self.shared_dict {
+            SharedDictBackend::File(path) => format!(
+                "\
+## generated by mission-control
+plugin {{
+  acl = vfile
+  acl_shared_dict = file:{}
+}}
+",
+                path
+            ),
+            SharedDictBackend::Sql => "\
+## generated by mission-control
+plugin {
+  acl = vfile
+  acl_shared_dict = proxy::acl
+}
+
+dict {
+  acl = mysql:/etc/dovecot/dovecot-dict-sql.conf.ext
+}
+
+service dict {
+  unix_listener dict {
+    mode = 0660
+    user = vmail
+    group = vmail
+  }
+}
+"
+            .to_string(),
+        }
+    }
+
+    /// Generate dovecot-dict-sql.conf.ext for the SQL shared dict.
+    pub fn generate_dict_sql_ext(&self) -> String {
+        format!(
+            "\
+## generated by mission-control
+connect = host={db_host} dbname={db_name} user={db_user} password={db_password}
+
+map {{
+  pattern = shared/shared-boxes/user/$to/$from
+  table = acl_user_shares
+  value_field = dummy
+
+  fields {{
+    from_user = $from
+    to_user = $to
+  }}
+}}
+
+map {{
+  pattern = shared/shared-boxes/anyone/$from
+  table = acl_anyone_shares
+  value_field = dummy
+
+  fields {{
+    from_user = $from
+  }}
+}}
+",
+            db_host = self.db_host,
+            db_name = self.db_name,
+            db_user = self.db_user,
+            db_password = self.db_password,
+        )
+    }
+
     /// Generate 10-ssl.conf
     pub fn generate_10_ssl(&self) -> String {
         format!(
@@ -278,6 +457,15 @@ iterate_query = SELECT email AS user FROM virtual_users
         self.ssl_key = path.to_string();
     }
 
+    /// The `ssl_cert` and `ssl_key` settings as Dovecot reads them, with
+    /// the `<` that makes it load the file, for changing them in place.
+    pub fn ssl_settings(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("ssl_cert".to_string(), format!("<{}", self.ssl_cert)),
+            ("ssl_key".to_string(), format!("<{}", self.ssl_key)),
+        ])
+    }
+
     pub fn set_log_path(&mut self, path: &str) {
         self.log_path = path.to_string();
     }
@@ -338,6 +526,24 @@ mod tests {
         assert!(out.contains("user = vmail"));
         assert!(out.contains("port = 993"));
         assert!(out.contains("port = 995"));
+        assert!(out.contains("service managesieve-login"));
+        assert!(out.contains("port = 4190"));
+    }
+
+    #[test]
+    fn test_sieve_plugin() {
+        let cfg = DovecotConfig::generate_default("example.com");
+        let lmtp = cfg.generate_20_lmtp();
+        assert!(lmtp.contains("protocol lmtp {"));
+        assert!(lmtp.contains("mail_plugins = $mail_plugins sieve"));
+        assert!(lmtp.contains("postmaster_address = postmaster@example.com"));
+
+        let managesieve = cfg.generate_20_managesieve();
+        assert!(managesieve.contains("protocols = $protocols sieve"));
+
+        let sieve = cfg.generate_90_sieve();
+        assert!(sieve.contains("sieve = file:~/sieve;active=~/.dovecot.sieve"));
+        assert!(sieve.contains("sieve_after = file:~/sieve-after"));
     }
 
     #[test]
@@ -404,6 +610,50 @@ mod tests {
         assert!(out.contains("password=mypass"));
     }
 
+    #[test]
+    fn test_10_mail_shared_namespace() {
+        let cfg = DovecotConfig::generate_default("example.com");
+        let out = cfg.generate_10_mail();
+        assert!(out.contains("mail_plugins = $mail_plugins acl"));
+        assert!(out.contains("namespace shared {"));
+        assert!(out.contains("type = shared"));
+        assert!(out.contains("prefix = shared/%%u/"));
+        assert!(out.contains("INDEXPVT="));
+    }
+
+    #[test]
+    fn test_20_imap_acl_plugin() {
+        let cfg = DovecotConfig::generate_default("example.com");
+        let out = cfg.generate_20_imap();
+        assert!(out.contains("protocol imap {"));
+        assert!(out.contains("imap_acl"));
+    }
+
+    #[test]
+    fn test_90_acl_file_dict() {
+        let cfg = DovecotConfig::generate_default("example.com");
+        let out = cfg.generate_90_acl();
+        assert!(out.contains("acl = vfile"));
+        assert!(out.contains("acl_shared_dict = file:/var/lib/dovecot/db/shared-mailboxes.db"));
+        assert!(!out.contains("service dict"));
+    }
+
+    #[test]
+    fn test_90_acl_sql_dict() {
+        let mut cfg = DovecotConfig::generate_default("example.com");
+        cfg.shared_dict = SharedDictBackend::Sql;
+        let out = cfg.generate_90_acl();
+        assert!(out.contains("acl_shared_dict = proxy::acl"));
+        assert!(out.contains("dovecot-dict-sql.conf.ext"));
+        assert!(out.contains("service dict"));
+
+        let dict = cfg.generate_dict_sql_ext();
+        assert!(dict.contains("dbname=mailserver"));
+        assert!(dict.contains("table = acl_user_shares"));
+        assert!(dict.contains("table = acl_anyone_shares"));
+        assert!(dict.contains("pattern = shared/shared-boxes/user/$to/$from"));
+    }
+
     #[test]
     fn test_set_mail_location() {
         let mut cfg = DovecotConfig::generate_default("example.com");
@@ -420,6 +670,8 @@ mod tests {
         let out = cfg.generate_10_ssl();
         assert!(out.contains("ssl_cert = </custom/cert.pem"));
         assert!(out.contains("ssl_key = </custom/key.pem"));
+        assert_eq!(cfg.ssl_settings()["ssl_cert"], "</custom/cert.pem");
+        assert_eq!(cfg.ssl_settings()["ssl_key"], "</custom/key.pem");
     }
 
     #[test]
@@ -0,0 +1,860 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till1, take_while1},
+    character::complete::{char, space0, space1},
+    combinator::{eof, map, opt, rest},
+    sequence::{terminated, tuple},
+    IResult,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Includes nest at most this deep; deeper almost certainly means a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A parsed dovecot.conf (or conf.d fragment) as a tree.
+///
+/// Unlike the flat `key = value` parser this understands `name {` ... `}`
+/// sections and `!include` / `!include_try`. Lines that are not edited are
+/// written back verbatim, so comments, ordering and indentation survive a
+/// round trip.
+///
+/// Nested settings are addressed the way they are written, with `{`
+/// between levels: `service auth { unix_listener auth-userdb { mode`.
+#[derive(Debug, Clone, Default)]
+pub struct DovecotConf {
+    pub nodes: Vec<DovecotNode>,
+    trailing_newline: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum DovecotNode {
+    Setting(DovecotSetting),
+    Section(DovecotSection),
+    Include(DovecotInclude),
+    /// A comment line, verbatim.
+    Comment(String),
+    /// An empty or whitespace-only line, verbatim.
+    Blank(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DovecotSetting {
+    pub key: String,
+    pub value: String,
+    /// Original line and the value it held.
+    raw: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DovecotSection {
+    /// Section type, e.g. `service`, `protocol`, `namespace`, `plugin`.
+    pub kind: String,
+    /// Section name as written (quotes kept), e.g. `auth`, `"Sent Messages"`.
+    pub name: Option<String>,
+    pub children: Vec<DovecotNode>,
+    /// Original opening and closing lines, and the label they were for.
+    raw: Option<(String, String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DovecotInclude {
+    pub path: String,
+    /// `!include_try`: a missing file is not an error.
+    pub optional: bool,
+    /// Original line.
+    raw: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum DovecotConfError {
+    ParseError { line: usize, message: String },
+    IncludeError { path: String, message: String },
+    InvalidPath(String),
+}
+
+impl fmt::Display for DovecotConfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParseError { line, message } => {
+                write!(f, "Dovecot config parse error at line {}: {}", line, message)
+            }
+            Self::IncludeError { path, message } => {
+                write!(f, "Dovecot include {} failed: {}", path, message)
+            }
+            Self::InvalidPath(p) => write!(f, "Invalid Dovecot setting path: {}", p),
+        }
+    }
+}
+
+impl std::error::Error for DovecotConfError {}
+
+impl DovecotSection {
+    pub fn new(kind: &str, name: Option<&str>) -> Self {
+        Self {
+            kind: kind.to_string(),
+            name: name.map(str::to_string),
+            children: Vec::new(),
+            raw: None,
+        }
+    }
+
+    /// The section name without surrounding quotes.
+    pub fn unquoted_name(&self) -> Option<&str> {
+        self.name.as_deref().map(unquote)
+    }
+
+    fn matches(&self, id: &SectionId) -> bool {
+        self.kind == id.kind && self.unquoted_name() == id.name.as_deref()
+    }
+
+    /// `kind name` (or just `kind`) as used in setting paths.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} {}", self.kind, name),
+            None => self.kind.clone(),
+        }
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// One `kind [name]` step of a setting path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SectionId {
+    kind: String,
+    name: Option<String>,
+}
+
+impl SectionId {
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} {}", self.kind, quote_if_needed(name)),
+            None => self.kind.clone(),
+        }
+    }
+}
+
+fn quote_if_needed(name: &str) -> String {
+    if name.contains(char::is_whitespace) {
+        format!("\"{}\"", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Split `service auth { unix_listener auth-userdb { mode` into section
+/// steps and the setting key.
+fn parse_setting_path(path: &str) -> Result<(Vec<SectionId>, String), DovecotConfError> {
+    let invalid = || DovecotConfError::InvalidPath(path.to_string());
+    let mut parts: Vec<&str> = path.split('{').map(str::trim).collect();
+    let key = parts.pop().ok_or_else(invalid)?;
+    if !is_valid_key(key) {
+        return Err(invalid());
+    }
+    let mut sections = Vec::new();
+    for part in parts {
+        let (kind, name) = match part.split_once(char::is_whitespace) {
+            Some((kind, name)) => (kind, Some(unquote(name.trim()).to_string())),
+            None => (part, None),
+        };
+        if !is_valid_key(kind) || part.contains(['}', '\n', '\r']) {
+            return Err(invalid());
+        }
+        sections.push(SectionId {
+            kind: kind.to_string(),
+            name,
+        });
+    }
+    Ok((sections, key.to_string()))
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn join_path(sections: &[String], key: &str) -> String {
+    let mut out = String::new();
+    for section in sections {
+        out.push_str(section);
+        out.push_str(" { ");
+    }
+    out.push_str(key);
+    out
+}
+
+impl DovecotConf {
+    /// Parse dovecot.conf syntax.
+    pub fn parse(input: &str) -> Result<Self, DovecotConfError> {
+        let trailing_newline = input.is_empty() || input.ends_with('\n');
+        let mut stack: Vec<(DovecotSection, usize)> = Vec::new();
+        let mut top: Vec<DovecotNode> = Vec::new();
+
+        for (index, text) in input.lines().enumerate() {
+            let line = index + 1;
+            let parsed = match dovecot_line(text.trim()) {
+                Ok((_, parsed)) => parsed,
+                Err(_) => {
+                    return Err(DovecotConfError::ParseError {
+                        line,
+                        message: format!("unrecognized line '{}'", text.trim()),
+                    })
+                }
+            };
+            let node = match parsed {
+                Line::Blank => DovecotNode::Blank(text.to_string()),
+                Line::Comment => DovecotNode::Comment(text.to_string()),
+                Line::Include { optional, path } => DovecotNode::Include(DovecotInclude {
+                    path: path.to_string(),
+                    optional,
+                    raw: Some(text.to_string()),
+                }),
+                Line::Setting { key, value } => DovecotNode::Setting(DovecotSetting {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    raw: Some((text.to_string(), value.to_string())),
+                }),
+                Line::Open { kind, name } => {
+                    let mut section = DovecotSection::new(kind, name);
+                    section.raw = Some((text.to_string(), String::new(), section.label()));
+                    stack.push((section, line));
+                    continue;
+                }
+                Line::Close => {
+                    let (mut section, _) = stack.pop().ok_or(DovecotConfError::ParseError {
+                        line,
+                        message: "unexpected '}'".to_string(),
+                    })?;
+                    if let Some((_, close, _)) = section.raw.as_mut() {
+                        *close = text.to_string();
+                    }
+                    DovecotNode::Section(section)
+                }
+            };
+            match stack.last_mut() {
+                Some((parent, _)) => parent.children.push(node),
+                None => top.push(node),
+            }
+        }
+
+        if let Some((section, line)) = stack.pop() {
+            return Err(DovecotConfError::ParseError {
+                line,
+                message: format!("section '{}' is never closed", section.label()),
+            });
+        }
+
+        Ok(Self {
+            nodes: top,
+            trailing_newline,
+        })
+    }
+
+    /// Serialize back to dovecot.conf syntax.
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        write_nodes(&self.nodes, 0, &mut out);
+        if !self.trailing_newline {
+            out.pop();
+        }
+        out
+    }
+
+    /// Value of a (possibly nested) setting in this file, last one wins.
+    pub fn get(&self, path: &str) -> Result<Option<&str>, DovecotConfError> {
+        let (sections, key) = parse_setting_path(path)?;
+        let mut nodes = &self.nodes;
+        for id in &sections {
+            match find_section(nodes, id) {
+                Some(section) => nodes = &section.children,
+                None => return Ok(None),
+            }
+        }
+        Ok(nodes
+            .iter()
+            .rev()
+            .find_map(|n| match n {
+                DovecotNode::Setting(s) if s.key == key => Some(s.value.as_str()),
+                _ => None,
+            }))
+    }
+
+    /// Set a (possibly nested) setting, creating sections as needed. An
+    /// existing setting is changed in place; a new one is appended to its
+    /// section.
+    pub fn set(&mut self, path: &str, value: &str) -> Result<(), DovecotConfError> {
+        if value.contains(['\n', '\r']) {
+            return Err(DovecotConfError::InvalidPath(format!(
+                "{}: value must be a single line",
+                path
+            )));
+        }
+        let (sections, key) = parse_setting_path(path)?;
+        let mut nodes = &mut self.nodes;
+        for id in &sections {
+            let position = nodes.iter().rposition(|n| {
+                matches!(n, DovecotNode::Section(s) if s.matches(id))
+            });
+            let index = match position {
+                Some(index) => index,
+                None => {
+                    let name = id.name.as_deref().map(quote_if_needed);
+                    let section = DovecotSection::new(&id.kind, name.as_deref());
+                    nodes.push(DovecotNode::Section(section));
+                    nodes.len() - 1
+                }
+            };
+            nodes = match &mut nodes[index] {
+                DovecotNode::Section(section) => &mut section.children,
+                _ => unreachable!("index points at a section"),
+            };
+        }
+
+        let existing = nodes.iter_mut().rev().find_map(|n| match n {
+            DovecotNode::Setting(s) if s.key == key => Some(s),
+            _ => None,
+        });
+        match existing {
+            Some(setting) => setting.value = value.to_string(),
+            None => nodes.push(DovecotNode::Setting(DovecotSetting {
+                key,
+                value: value.to_string(),
+                raw: None,
+            })),
+        }
+        Ok(())
+    }
+
+    /// Remove every occurrence of a setting. Returns whether any existed.
+    pub fn remove(&mut self, path: &str) -> Result<bool, DovecotConfError> {
+        let (sections, key) = parse_setting_path(path)?;
+        let mut nodes = &mut self.nodes;
+        for id in &sections {
+            let found = nodes.iter_mut().rev().find_map(|n| match n {
+                DovecotNode::Section(s) if s.matches(id) => Some(s),
+                _ => None,
+            });
+            match found {
+                Some(section) => nodes = &mut section.children,
+                None => return Ok(false),
+            }
+        }
+        let before = nodes.len();
+        nodes.retain(|n| !matches!(n, DovecotNode::Setting(s) if s.key == key));
+        Ok(nodes.len() != before)
+    }
+
+    /// Settings defined in this file (includes not followed), keyed by
+    /// setting path.
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        collect_settings(&self.nodes, &mut Vec::new(), &mut |path, value| {
+            map.insert(path, value.to_string());
+        });
+        map
+    }
+
+    /// Load `path` and everything it includes, and compute the settings
+    /// Dovecot would actually use.
+    pub fn load_effective(path: &Path) -> Result<EffectiveConfig, DovecotConfError> {
+        let mut effective = EffectiveConfig::default();
+        let mut stack = Vec::new();
+        effective.load_file(path, &[], &mut stack, 0)?;
+        Ok(effective)
+    }
+}
+
+fn find_section<'a>(nodes: &'a [DovecotNode], id: &SectionId) -> Option<&'a DovecotSection> {
+    nodes.iter().rev().find_map(|n| match n {
+        DovecotNode::Section(s) if s.matches(id) => Some(s),
+        _ => None,
+    })
+}
+
+fn collect_settings(
+    nodes: &[DovecotNode],
+    scope: &mut Vec<String>,
+    emit: &mut dyn FnMut(String, &str),
+) {
+    for node in nodes {
+        match node {
+            DovecotNode::Setting(s) => emit(join_path(scope, &s.key), &s.value),
+            DovecotNode::Section(section) => {
+                scope.push(section_label(section));
+                collect_settings(&section.children, scope, emit);
+                scope.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Canonical path label: names unquoted, re-quoted only if they need it.
+fn section_label(section: &DovecotSection) -> String {
+    SectionId {
+        kind: section.kind.clone(),
+        name: section.unquoted_name().map(str::to_string),
+    }
+    .label()
+}
+
+fn write_nodes(nodes: &[DovecotNode], depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match node {
+            DovecotNode::Comment(text) | DovecotNode::Blank(text) => {
+                out.push_str(text);
+                out.push('\n');
+            }
+            DovecotNode::Include(include) => {
+                let directive = if include.optional { "!include_try" } else { "!include" };
+                match &include.raw {
+                    // Still describes the same include
+                    Some(raw) if raw.split_whitespace().eq([directive, include.path.as_str()]) => {
+                        out.push_str(raw)
+                    }
+                    _ => {
+                        out.push_str(&format!("{}{} {}", indent, directive, include.path));
+                    }
+                }
+                out.push('\n');
+            }
+            DovecotNode::Setting(setting) => {
+                match &setting.raw {
+                    Some((raw, value)) if *value == setting.value => out.push_str(raw),
+                    _ if setting.value.is_empty() => {
+                        out.push_str(&format!("{}{} =", indent, setting.key))
+                    }
+                    _ => out.push_str(&format!("{}{} = {}", indent, setting.key, setting.value)),
+                }
+                out.push('\n');
+            }
+            DovecotNode::Section(section) => {
+                let (open, close) = match &section.raw {
+                    Some((open, close, label)) if *label == section.label() => {
+                        (open.clone(), close.clone())
+                    }
+                    _ => (format!("{}{} {{", indent, section.label()), format!("{}}}", indent)),
+                };
+                out.push_str(&open);
+                out.push('\n');
+                write_nodes(&section.children, depth + 1, out);
+                out.push_str(&close);
+                out.push('\n');
+            }
+        }
+    }
+}
+
+// ── effective config ───────────────────────────────────────────────
+
+/// A setting's effective value and the file that set it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveSetting {
+    pub value: String,
+    pub file: PathBuf,
+}
+
+/// The merged result of a config file and its includes.
+#[derive(Debug, Clone, Default)]
+pub struct EffectiveConfig {
+    pub settings: BTreeMap<String, EffectiveSetting>,
+    /// Every file that was read, in include order.
+    pub files: Vec<PathBuf>,
+}
+
+impl EffectiveConfig {
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.settings.get(path).map(|s| s.value.as_str())
+    }
+
+    /// The file an edit to `path` should go to: the one whose value wins.
+    pub fn origin(&self, path: &str) -> Option<&Path> {
+        self.settings.get(path).map(|s| s.file.as_path())
+    }
+
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.settings
+            .iter()
+            .map(|(k, v)| (k.clone(), v.value.clone()))
+            .collect()
+    }
+
+    fn load_file(
+        &mut self,
+        path: &Path,
+        scope: &[String],
+        stack: &mut Vec<PathBuf>,
+        depth: usize,
+    ) -> Result<(), DovecotConfError> {
+        let include_error = |message: String| DovecotConfError::IncludeError {
+            path: path.display().to_string(),
+            message,
+        };
+        if depth > MAX_INCLUDE_DEPTH || stack.iter().any(|p| p == path) {
+            return Err(include_error("include loop".to_string()));
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| include_error(e.to_string()))?;
+        let conf = DovecotConf::parse(&content).map_err(|e| include_error(e.to_string()))?;
+
+        self.files.push(path.to_path_buf());
+        stack.push(path.to_path_buf());
+        // Included settings land in the scope of the `!include` directive
+        let mut scope = scope.to_vec();
+        let result = self.apply_nodes(&conf.nodes, path, &mut scope, stack, depth);
+        stack.pop();
+        result
+    }
+
+    fn apply_nodes(
+        &mut self,
+        nodes: &[DovecotNode],
+        file: &Path,
+        scope: &mut Vec<String>,
+        stack: &mut Vec<PathBuf>,
+        depth: usize,
+    ) -> Result<(), DovecotConfError> {
+        for node in nodes {
+            match node {
+                DovecotNode::Setting(s) => {
+                    let value = self.expand(scope, &s.key, &s.value);
+                    self.settings.insert(
+                        join_path(scope, &s.key),
+                        EffectiveSetting {
+                            value,
+                            file: file.to_path_buf(),
+                        },
+                    );
+                }
+                DovecotNode::Section(section) => {
+                    scope.push(section_label(section));
+                    self.apply_nodes(&section.children, file, scope, stack, depth)?;
+                    scope.pop();
+                }
+                DovecotNode::Include(include) => {
+                    let base = file.parent().unwrap_or(Path::new("/"));
+                    let files = expand_include(base, &include.path).map_err(|message| {
+                        DovecotConfError::IncludeError {
+                            path: include.path.clone(),
+                            message,
+                        }
+                    })?;
+                    // A wildcard matching nothing is fine, as in Dovecot
+                    if files.is_empty() && !include.optional && !include.path.contains('*') {
+                        return Err(DovecotConfError::IncludeError {
+                            path: include.path.clone(),
+                            message: "file not found".to_string(),
+                        });
+                    }
+                    for included in files {
+                        self.load_file(&included, scope, stack, depth + 1)?;
+                    }
+                }
+                DovecotNode::Comment(_) | DovecotNode::Blank(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand `$name` references to the value `name` has so far, looking
+    /// in the current section first and then outwards. Unknown names
+    /// (e.g. `$to` in dict maps) are left alone.
+    fn expand(&self, scope: &[String], key: &str, value: &str) -> String {
+        if !value.contains('$') {
+            return value.to_string();
+        }
+        let lookup = |name: &str| {
+            (0..=scope.len())
+                .rev()
+                .find_map(|len| self.settings.get(&join_path(&scope[..len], name)))
+                .map(|s| s.value.clone())
+        };
+        let mut out = String::new();
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            let name_len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            let name = &after[..name_len];
+            match (name.is_empty(), lookup(name)) {
+                (false, Some(previous)) => out.push_str(&previous),
+                // A setting referring to itself before it has a value
+                (false, None) if name == key => {}
+                _ => {
+                    out.push('$');
+                    out.push_str(name);
+                }
+            }
+            rest = &after[name_len..];
+        }
+        out.push_str(rest);
+        out.trim().to_string()
+    }
+}
+
+/// Resolve an include path relative to the including file's directory,
+/// expanding a `*` wildcard in the file name.
+fn expand_include(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let full = if Path::new(pattern).is_absolute() {
+        PathBuf::from(pattern)
+    } else {
+        base.join(pattern)
+    };
+    let file_name = full
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| "invalid include path".to_string())?
+        .to_string();
+    if !file_name.contains('*') {
+        return Ok(if full.exists() { vec![full] } else { Vec::new() });
+    }
+    let dir = full.parent().unwrap_or(Path::new("/"));
+    let (prefix, suffix) = file_name.split_once('*').unwrap_or((&file_name, ""));
+    let mut matches = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if name.len() >= prefix.len() + suffix.len()
+            && name.starts_with(prefix)
+            && name.ends_with(suffix)
+            && !suffix.contains('*')
+        {
+            matches.push(entry.path());
+        }
+    }
+    // Dovecot reads wildcard matches in sorted order (10-*, 20-*, ...)
+    matches.sort();
+    Ok(matches)
+}
+
+// nom parsers
+
+enum Line<'a> {
+    Blank,
+    Comment,
+    Include { optional: bool, path: &'a str },
+    Setting { key: &'a str, value: &'a str },
+    Open { kind: &'a str, name: Option<&'a str> },
+    Close,
+}
+
+fn key_token(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))(input)
+}
+
+fn include_line(input: &str) -> IResult<&str, Line<'_>> {
+    let (input, directive) = alt((tag("!include_try"), tag("!include")))(input)?;
+    let (input, _) = space1(input)?;
+    let (input, path) = take_till1(|c: char| c.is_whitespace())(input)?;
+    let (input, _) = tuple((space0, eof))(input)?;
+    Ok((
+        input,
+        Line::Include {
+            optional: directive == "!include_try",
+            path,
+        },
+    ))
+}
+
+fn setting_line(input: &str) -> IResult<&str, Line<'_>> {
+    let (input, (key, _, _, _, value)) =
+        tuple((key_token, space0, char('='), space0, rest))(input)?;
+    Ok((input, Line::Setting { key, value: value.trim_end() }))
+}
+
+fn open_line(input: &str) -> IResult<&str, Line<'_>> {
+    let (input, kind) = key_token(input)?;
+    let (input, name) = opt(tuple((space1, take_till1(|c: char| c == '{'))))(input)?;
+    let (input, _) = terminated(tuple((space0, char('{'))), tuple((space0, eof)))(input)?;
+    let name = name.map(|(_, n)| n.trim()).filter(|n| !n.is_empty());
+    Ok((input, Line::Open { kind, name }))
+}
+
+fn dovecot_line(input: &str) -> IResult<&str, Line<'_>> {
+    alt((
+        map(eof, |_| Line::Blank),
+        map(tuple((char('#'), rest)), |_| Line::Comment),
+        map(tuple((char('}'), space0, eof)), |_| Line::Close),
+        include_line,
+        open_line,
+        setting_line,
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::dovecot::{DovecotConfig, SharedDictBackend};
+    use std::fs;
+
+    #[test]
+    fn test_generated_fragments_roundtrip() {
+        let mut cfg = DovecotConfig::generate_default("example.com");
+        let mut fragments = vec![
+            cfg.generate_10_auth(),
+            cfg.generate_10_mail(),
+            cfg.generate_10_master(),
+            cfg.generate_20_imap(),
+            cfg.generate_20_lmtp(),
+            cfg.generate_90_acl(),
+            cfg.generate_90_sieve(),
+            cfg.generate_dict_sql_ext(),
+        ];
+        cfg.shared_dict = SharedDictBackend::Sql;
+        fragments.push(cfg.generate_90_acl());
+        for fragment in fragments {
+            let conf = DovecotConf::parse(&fragment).unwrap();
+            assert_eq!(conf.serialize(), fragment);
+        }
+    }
+
+    #[test]
+    fn test_nested_get_and_set() {
+        let cfg = DovecotConfig::generate_default("example.com");
+        let input = cfg.generate_10_master();
+        let mut conf = DovecotConf::parse(&input).unwrap();
+
+        let path = "service auth { unix_listener /var/spool/postfix/private/auth { mode";
+        assert_eq!(conf.get(path).unwrap(), Some("0660"));
+        assert_eq!(
+            conf.get("service imap-login { inet_listener imaps { port").unwrap(),
+            Some("993")
+        );
+
+        conf.set(path, "0666").unwrap();
+        let out = conf.serialize();
+        // Only the edited line changes
+        let changed: Vec<_> = input
+            .lines()
+            .zip(out.lines())
+            .filter(|(a, b)| a != b)
+            .collect();
+        assert_eq!(changed, vec![("    mode = 0660", "    mode = 0666")]);
+        // The sibling listener is untouched
+        assert_eq!(
+            conf.get("service auth { unix_listener auth-userdb { mode").unwrap(),
+            Some("0600")
+        );
+    }
+
+    #[test]
+    fn test_set_creates_sections() {
+        let mut conf = DovecotConf::parse("protocols = imap\n").unwrap();
+        conf.set("service stats { unix_listener stats-writer { mode", "0660")
+            .unwrap();
+        conf.set("namespace inbox { mailbox Sent Messages { special_use", "\\Sent")
+            .unwrap();
+        assert_eq!(
+            conf.serialize(),
+            "protocols = imap\n\
+             service stats {\n  unix_listener stats-writer {\n    mode = 0660\n  }\n}\n\
+             namespace inbox {\n  mailbox \"Sent Messages\" {\n    special_use = \\Sent\n  }\n}\n"
+        );
+        let reparsed = DovecotConf::parse(&conf.serialize()).unwrap();
+        assert_eq!(
+            reparsed
+                .get("namespace inbox { mailbox \"Sent Messages\" { special_use")
+                .unwrap(),
+            Some("\\Sent")
+        );
+    }
+
+    #[test]
+    fn test_remove_and_map() {
+        let input = "plugin {\n  sieve = file:~/sieve\n  sieve_after = file:~/sieve-after\n}\n";
+        let mut conf = DovecotConf::parse(input).unwrap();
+        let map = conf.to_map();
+        assert_eq!(map.get("plugin { sieve").map(String::as_str), Some("file:~/sieve"));
+        assert!(conf.remove("plugin { sieve_after").unwrap());
+        assert!(!conf.remove("plugin { sieve_after").unwrap());
+        assert_eq!(conf.serialize(), "plugin {\n  sieve = file:~/sieve\n}\n");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = DovecotConf::parse("service auth {\n  user = root\n").unwrap_err();
+        assert!(matches!(err, DovecotConfError::ParseError { line: 1, .. }));
+
+        let err = DovecotConf::parse("ssl = yes\n}\n").unwrap_err();
+        assert!(matches!(err, DovecotConfError::ParseError { line: 2, .. }));
+
+        let err = DovecotConf::parse("this is not dovecot\n").unwrap_err();
+        assert!(matches!(err, DovecotConfError::ParseError { line: 1, .. }));
+
+        assert!(DovecotConf::parse("").unwrap().set("a { }", "x").is_err());
+    }
+
+    #[test]
+    fn test_effective_config_follows_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf_d = dir.path().join("conf.d");
+        fs::create_dir(&conf_d).unwrap();
+        fs::write(
+            dir.path().join("dovecot.conf"),
+            "protocols = imap lmtp\nmail_plugins = quota\n!include conf.d/*.conf\n!include_try local.conf\n",
+        )
+        .unwrap();
+        fs::write(
+            conf_d.join("10-mail.conf"),
+            "mail_plugins = $mail_plugins acl\n",
+        )
+        .unwrap();
+        fs::write(
+            conf_d.join("20-imap.conf"),
+            "protocol imap {\n  mail_plugins = $mail_plugins imap_acl\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            conf_d.join("10-master.conf"),
+            "service auth {\n  unix_listener auth-userdb {\n    mode = 0600\n  }\n}\n",
+        )
+        .unwrap();
+        fs::write(conf_d.join("README"), "not a config file").unwrap();
+
+        let effective = DovecotConf::load_effective(&dir.path().join("dovecot.conf")).unwrap();
+        assert_eq!(effective.get("mail_plugins"), Some("quota acl"));
+        assert_eq!(
+            effective.get("protocol imap { mail_plugins"),
+            Some("quota acl imap_acl")
+        );
+        assert_eq!(
+            effective.origin("service auth { unix_listener auth-userdb { mode"),
+            Some(conf_d.join("10-master.conf").as_path())
+        );
+        // 10-mail before 10-master before 20-imap; local.conf is optional
+        assert_eq!(effective.files.len(), 4);
+        assert!(effective.files[1].ends_with("10-mail.conf"));
+    }
+
+    #[test]
+    fn test_effective_config_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("dovecot.conf");
+        fs::write(&main, "!include missing.conf\n").unwrap();
+        assert!(matches!(
+            DovecotConf::load_effective(&main),
+            Err(DovecotConfError::IncludeError { .. })
+        ));
+
+        fs::write(&main, "!include dovecot.conf\n").unwrap();
+        assert!(DovecotConf::load_effective(&main)
+            .unwrap_err()
+            .to_string()
+            .contains("include loop"));
+    }
+}
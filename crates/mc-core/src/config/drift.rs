@@ -0,0 +1,612 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::dovecot_conf::DovecotConf;
+use super::history::{ConfigHistory, HistoryError};
+use super::parser::{parse_config, split_list};
+use super::postfix_master::MasterCf;
+
+/// Roundcube keys that hold secrets or per-install random values.
+const ROUNDCUBE_IGNORED: &[&str] = &["db_dsnw", "des_key"];
+
+/// How a file is reduced to comparable `parameter -> value` pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigFormat {
+    /// `key = value` with continuation lines (main.cf).
+    PostfixMain,
+    /// master.cf service entries keyed by `name/type`.
+    PostfixMaster,
+    /// Dovecot sections and settings keyed by nested path.
+    Dovecot,
+    /// `Directive args...` lines (local.cf). `case_insensitive` folds
+    /// directive names, as OpenDKIM does.
+    Directives { case_insensitive: bool },
+    /// Apache directives inside `<Section>` blocks, case-insensitive.
+    Apache,
+    /// Roundcube `$config['key'] = value;` statements.
+    RoundcubePhp,
+}
+
+impl ConfigFormat {
+    /// The format of a file mission-control manages, by path. `None` for
+    /// files drift detection does not compare (key tables, certificates).
+    pub fn for_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let dir = path.parent()?;
+        if dir == Path::new("/etc/postfix") {
+            return match name {
+                "main.cf" => Some(Self::PostfixMain),
+                "master.cf" => Some(Self::PostfixMaster),
+                _ => None,
+            };
+        }
+        if path == Path::new("/etc/opendkim.conf") {
+            return Some(Self::Directives { case_insensitive: true });
+        }
+        if path == Path::new("/etc/roundcube/config.inc.php") {
+            return Some(Self::RoundcubePhp);
+        }
+        let under = |root: &str| path.starts_with(root);
+        if under("/etc/dovecot") && name.ends_with(".conf") {
+            Some(Self::Dovecot)
+        } else if under("/etc/spamassassin") && name.ends_with(".cf") {
+            Some(Self::Directives { case_insensitive: false })
+        } else if under("/etc/apache2") && name.ends_with(".conf") {
+            Some(Self::Apache)
+        } else {
+            None
+        }
+    }
+}
+
+/// A config file as mission-control last wrote it.
+#[derive(Debug, Clone)]
+pub struct ExpectedFile {
+    pub path: PathBuf,
+    pub format: ConfigFormat,
+    pub content: String,
+    /// Parameters never compared (secrets, per-install random values).
+    pub ignore: Vec<String>,
+}
+
+impl ExpectedFile {
+    pub fn new(path: impl Into<PathBuf>, format: ConfigFormat, content: String) -> Self {
+        Self {
+            path: path.into(),
+            format,
+            content,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// The baseline drift is measured against: for every file in `history`,
+/// the last revision mission-control wrote. Files in formats drift
+/// detection cannot compare, and files only ever edited externally, are
+/// left out.
+pub fn baseline(history: &ConfigHistory) -> Result<Vec<ExpectedFile>, HistoryError> {
+    let mut files = Vec::new();
+    for path in history.files()? {
+        let Some(format) = ConfigFormat::for_path(&path) else { continue };
+        let Some(revision) = history.latest_managed(&path)? else { continue };
+        let mut file = ExpectedFile::new(path, format, revision.content);
+        if format == ConfigFormat::RoundcubePhp {
+            file.ignore = ROUNDCUBE_IGNORED.iter().map(|s| s.to_string()).collect();
+        }
+        files.push(file);
+    }
+    Ok(files)
+}
+
+// ── report ─────────────────────────────────────────────────────────
+
+/// One parameter that differs between the expected and live file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParameterDrift {
+    pub parameter: String,
+    /// `None` when the live file sets something the baseline does not.
+    pub expected: Option<String>,
+    /// `None` when the live file lacks an expected parameter.
+    pub actual: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    Changed,
+    Missing,
+    Added,
+}
+
+impl ParameterDrift {
+    pub fn kind(&self) -> DriftKind {
+        match (&self.expected, &self.actual) {
+            (Some(_), Some(_)) => DriftKind::Changed,
+            (Some(_), None) => DriftKind::Missing,
+            _ => DriftKind::Added,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileStatus {
+    InSync,
+    Drifted,
+    /// The file does not exist.
+    Missing,
+    /// The file exists but could not be read or parsed.
+    Unreadable { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDrift {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    pub differences: Vec<ParameterDrift>,
+}
+
+impl FileDrift {
+    pub fn is_drifted(&self) -> bool {
+        self.status != FileStatus::InSync
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftReport {
+    pub checked_at: DateTime<Utc>,
+    pub files: Vec<FileDrift>,
+}
+
+impl DriftReport {
+    pub fn has_drift(&self) -> bool {
+        self.files.iter().any(FileDrift::is_drifted)
+    }
+
+    pub fn drifted(&self) -> impl Iterator<Item = &FileDrift> {
+        self.files.iter().filter(|f| f.is_drifted())
+    }
+}
+
+// ── detection ──────────────────────────────────────────────────────
+
+/// Compare every expected file with what is on disk.
+pub fn detect(expected: &[ExpectedFile]) -> DriftReport {
+    let files = expected
+        .iter()
+        .map(|file| match std::fs::read_to_string(&file.path) {
+            Ok(live) => compare(file, &live),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileDrift {
+                path: file.path.clone(),
+                status: FileStatus::Missing,
+                differences: Vec::new(),
+            },
+            Err(e) => FileDrift {
+                path: file.path.clone(),
+                status: FileStatus::Unreadable {
+                    reason: e.to_string(),
+                },
+                differences: Vec::new(),
+            },
+        })
+        .collect();
+    DriftReport {
+        checked_at: Utc::now(),
+        files,
+    }
+}
+
+/// Semantically compare one expected file with live content.
+pub fn compare(expected: &ExpectedFile, live: &str) -> FileDrift {
+    let unreadable = |reason: String| FileDrift {
+        path: expected.path.clone(),
+        status: FileStatus::Unreadable { reason },
+        differences: Vec::new(),
+    };
+    let want = match normalize(expected.format, &expected.content) {
+        Ok(map) => map,
+        Err(e) => return unreadable(format!("baseline: {}", e)),
+    };
+    let have = match normalize(expected.format, live) {
+        Ok(map) => map,
+        Err(e) => return unreadable(e),
+    };
+
+    let mut differences = Vec::new();
+    let keys: std::collections::BTreeSet<&String> = want.keys().chain(have.keys()).collect();
+    for key in keys {
+        if expected.ignore.iter().any(|i| i == key) {
+            continue;
+        }
+        let (w, h) = (want.get(key), have.get(key));
+        if w != h {
+            differences.push(ParameterDrift {
+                parameter: key.clone(),
+                expected: w.cloned(),
+                actual: h.cloned(),
+            });
+        }
+    }
+
+    FileDrift {
+        path: expected.path.clone(),
+        status: if differences.is_empty() {
+            FileStatus::InSync
+        } else {
+            FileStatus::Drifted
+        },
+        differences,
+    }
+}
+
+/// Reduce a file to `parameter -> value`, dropping comments, layout and
+/// parameter order. Order within a value (e.g. a restriction list) is kept,
+/// since Postfix and Dovecot evaluate it in order.
+pub fn normalize(format: ConfigFormat, content: &str) -> Result<BTreeMap<String, String>, String> {
+    match format {
+        ConfigFormat::PostfixMain => Ok(parse_config(content)?
+            .to_map()
+            .into_iter()
+            .map(|(k, v)| (k, split_list(&v).join(" ")))
+            .collect()),
+        ConfigFormat::PostfixMaster => Ok(MasterCf::parse(content)
+            .map_err(|e| e.to_string())?
+            .to_map()
+            .into_iter()
+            .map(|(k, v)| (k, collapse_whitespace(&v)))
+            .collect()),
+        ConfigFormat::Dovecot => Ok(DovecotConf::parse(content)
+            .map_err(|e| e.to_string())?
+            .to_map()
+            .into_iter()
+            .map(|(k, v)| (k, collapse_whitespace(&v)))
+            .collect()),
+        ConfigFormat::Directives { case_insensitive } => {
+            Ok(directive_map(content, case_insensitive, false))
+        }
+        ConfigFormat::Apache => Ok(directive_map(content, true, true)),
+        ConfigFormat::RoundcubePhp => Ok(php_config_map(content)),
+    }
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Key directive lines by name. A directive that appears more than once
+/// (`score`, `Header`, ...) is keyed by as many leading arguments as it
+/// takes to tell the occurrences apart, so reordering them is not drift.
+fn directive_map(content: &str, case_insensitive: bool, sections: bool) -> BTreeMap<String, String> {
+    let mut scope: Vec<String> = Vec::new();
+    let mut lines: Vec<(String, Vec<String>)> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if sections {
+            if let Some(close) = line.strip_prefix("</") {
+                if close.ends_with('>') {
+                    scope.pop();
+                    continue;
+                }
+            }
+            if let Some(open) = line.strip_prefix('<').and_then(|l| l.strip_suffix('>')) {
+                let open = collapse_whitespace(open);
+                scope.push(match open.split_once(' ') {
+                    Some((tag, args)) => format!("{} {}", tag.to_ascii_lowercase(), args),
+                    None => open.to_ascii_lowercase(),
+                });
+                continue;
+            }
+        }
+        let mut tokens = line.split_whitespace().map(str::to_string);
+        let Some(mut name) = tokens.next() else { continue };
+        if case_insensitive {
+            name = name.to_ascii_lowercase();
+        }
+        let mut prefix = scope.join(" > ");
+        if !prefix.is_empty() {
+            prefix.push_str(" > ");
+        }
+        lines.push((format!("{}{}", prefix, name), tokens.collect()));
+    }
+
+    let mut groups: BTreeMap<String, Vec<Vec<String>>> = BTreeMap::new();
+    for (key, args) in lines {
+        groups.entry(key).or_default().push(args);
+    }
+
+    let mut map = BTreeMap::new();
+    for (key, occurrences) in groups {
+        if occurrences.len() == 1 {
+            map.insert(key, occurrences[0].join(" "));
+            continue;
+        }
+        let longest = occurrences.iter().map(Vec::len).max().unwrap_or(0);
+        let width = (0..=longest)
+            .find(|&n| {
+                let mut seen = std::collections::HashSet::new();
+                occurrences
+                    .iter()
+                    .all(|args| seen.insert(&args[..n.min(args.len())]))
+            })
+            .unwrap_or(longest);
+        for (i, args) in occurrences.iter().enumerate() {
+            let n = width.min(args.len());
+            let mut sub = format!("{} {}", key, args[..n].join(" ")).trim_end().to_string();
+            if map.contains_key(&sub) {
+                // Identical lines: keep each occurrence
+                sub = format!("{} #{}", sub, i + 1);
+            }
+            map.insert(sub, args[n..].join(" "));
+        }
+    }
+    map
+}
+
+/// `$config['key'] = value;` statements, which may span lines.
+fn php_config_map(content: &str) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+    let mut current: Option<(String, String)> = None;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if current.is_none() {
+            let Some(rest) = trimmed.strip_prefix("$config[") else { continue };
+            let Some((key, rest)) = rest.split_once(']') else { continue };
+            let Some(value) = rest.trim_start().strip_prefix('=') else { continue };
+            let key = key.trim_matches(|c| c == '\'' || c == '"').to_string();
+            current = Some((key, value.to_string()));
+        } else if let Some((_, value)) = current.as_mut() {
+            value.push(' ');
+            value.push_str(trimmed);
+        }
+        let done = current
+            .as_ref()
+            .is_some_and(|(_, value)| value.trim_end().ends_with(';'));
+        if done {
+            let (key, value) = current.take().expect("statement in progress");
+            map.insert(key, normalize_php_value(&value));
+        }
+    }
+    map
+}
+
+fn normalize_php_value(value: &str) -> String {
+    let value = collapse_whitespace(value.trim().trim_end_matches(';'));
+    let mut out = value;
+    for punct in ["(", ")", ",", "=>"] {
+        out = out
+            .replace(&format!(" {}", punct), punct)
+            .replace(&format!("{} ", punct), punct);
+    }
+    // Trailing commas in arrays are optional
+    out.replace(",)", ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::apache;
+    use crate::config::dovecot::DovecotConfig;
+    use crate::config::history::ConfigChange;
+    use crate::config::opendkim::OpendkimConfig;
+    use crate::config::postfix::PostfixConfig;
+    use crate::config::roundcube::{self, RoundcubeConfig};
+    use crate::config::spamassassin::SpamAssassinConfig;
+
+    /// What the generators write for a fresh stack, as `(path, content)`.
+    fn generated() -> Vec<(PathBuf, String)> {
+        let mut dovecot = DovecotConfig::generate_default("example.com");
+        dovecot.hostname = "mail.example.com".to_string();
+        let roundcube_cfg = RoundcubeConfig::generate_default("example.com", "admin@example.com");
+        vec![
+            (
+                "/etc/postfix/main.cf".into(),
+                PostfixConfig::generate_default("mail.example.com", "example.com").to_string(),
+            ),
+            ("/etc/dovecot/conf.d/10-auth.conf".into(), dovecot.generate_10_auth()),
+            ("/etc/dovecot/conf.d/10-master.conf".into(), dovecot.generate_10_master()),
+            ("/etc/dovecot/conf.d/90-sieve.conf".into(), dovecot.generate_90_sieve()),
+            (
+                "/etc/opendkim.conf".into(),
+                OpendkimConfig::generate_default().generate_opendkim_conf(),
+            ),
+            (
+                "/etc/spamassassin/local.cf".into(),
+                SpamAssassinConfig::generate_default().generate_local_cf(),
+            ),
+            (
+                "/etc/roundcube/config.inc.php".into(),
+                roundcube::generate_config_inc_php(&roundcube_cfg).unwrap(),
+            ),
+            (
+                "/etc/apache2/sites-available/webmail.example.com.conf".into(),
+                apache::generate_webmail_vhost("example.com", "example", "admin@example.com").unwrap(),
+            ),
+            ("/etc/opendkim/key.table".into(), "example.com mail:/keys\n".to_string()),
+        ]
+    }
+
+    /// The baseline after mission-control wrote every generated file.
+    fn recorded_baseline() -> Vec<ExpectedFile> {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        for (path, content) in generated() {
+            history
+                .record(&path, None, &content, &ConfigChange::new("admin", "install"))
+                .unwrap();
+        }
+        baseline(&history).unwrap()
+    }
+
+    fn expected(path: &str) -> ExpectedFile {
+        recorded_baseline()
+            .into_iter()
+            .find(|f| f.path == Path::new(path))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_generated_files_are_in_sync_with_themselves() {
+        let files = recorded_baseline();
+        // Everything but the key table has a comparable format
+        assert_eq!(files.len(), generated().len() - 1);
+        for file in files {
+            let drift = compare(&file, &file.content);
+            assert_eq!(drift.status, FileStatus::InSync, "{}", file.path.display());
+        }
+    }
+
+    #[test]
+    fn test_baseline_skips_external_revisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let path = Path::new("/etc/spamassassin/local.cf");
+        history
+            .record(path, Some("required_score 9.0\n"), "required_score 5.0\n", &ConfigChange::new("admin", "tune"))
+            .unwrap();
+        // Only ever edited by hand: no baseline
+        history
+            .record(Path::new("/etc/postfix/main.cf"), Some("a = 1\n"), "a = 1\n", &ConfigChange::new("admin", "noop"))
+            .unwrap();
+
+        let files = baseline(&history).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].content, "required_score 5.0\n");
+        assert_eq!(files[0].format, ConfigFormat::Directives { case_insensitive: false });
+    }
+
+    #[test]
+    fn test_postfix_ignores_comments_order_and_layout() {
+        let file = expected("/etc/postfix/main.cf");
+        let mut lines: Vec<&str> = file
+            .content
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.is_empty())
+            .collect();
+        lines.reverse();
+        let mut live = lines.join("\n");
+        live = live.replace(
+            "smtpd_recipient_restrictions = ",
+            "# edited by hand\nsmtpd_recipient_restrictions =\n    ",
+        );
+        assert_eq!(compare(&file, &live).status, FileStatus::InSync);
+    }
+
+    #[test]
+    fn test_postfix_reports_each_parameter() {
+        let file = expected("/etc/postfix/main.cf");
+        let live = file
+            .content
+            .replace("smtpd_helo_required = yes", "smtpd_helo_required = no")
+            .replace("disable_vrfy_command = yes\n", "")
+            + "relayhost = [smtp.example.net]:587\n";
+        let drift = compare(&file, &live);
+        assert_eq!(drift.status, FileStatus::Drifted);
+        assert_eq!(
+            drift.differences,
+            vec![
+                ParameterDrift {
+                    parameter: "disable_vrfy_command".to_string(),
+                    expected: Some("yes".to_string()),
+                    actual: None,
+                },
+                ParameterDrift {
+                    parameter: "relayhost".to_string(),
+                    expected: None,
+                    actual: Some("[smtp.example.net]:587".to_string()),
+                },
+                ParameterDrift {
+                    parameter: "smtpd_helo_required".to_string(),
+                    expected: Some("yes".to_string()),
+                    actual: Some("no".to_string()),
+                },
+            ]
+        );
+        let kinds: Vec<_> = drift.differences.iter().map(ParameterDrift::kind).collect();
+        assert_eq!(kinds, vec![DriftKind::Missing, DriftKind::Added, DriftKind::Changed]);
+    }
+
+    #[test]
+    fn test_dovecot_nested_drift() {
+        let file = expected("/etc/dovecot/conf.d/10-master.conf");
+        let live = file.content.replace("    mode = 0660", "    mode = 0666");
+        let drift = compare(&file, &live);
+        assert_eq!(drift.differences.len(), 1);
+        assert_eq!(
+            drift.differences[0].parameter,
+            "service auth { unix_listener /var/spool/postfix/private/auth { mode"
+        );
+    }
+
+    #[test]
+    fn test_repeated_directives_are_order_insensitive() {
+        let file = ExpectedFile::new(
+            "/etc/spamassassin/local.cf",
+            ConfigFormat::Directives { case_insensitive: false },
+            "score RCVD_IN_A 2.0\nscore RCVD_IN_B 2.0\nrequired_score 5.0\n".to_string(),
+        );
+        let reordered = "required_score   5.0\nscore RCVD_IN_B 2.0\nscore RCVD_IN_A 2.0\n";
+        assert_eq!(compare(&file, reordered).status, FileStatus::InSync);
+
+        let changed = "required_score 5.0\nscore RCVD_IN_B 3.5\nscore RCVD_IN_A 2.0\n";
+        let drift = compare(&file, changed);
+        assert_eq!(drift.differences.len(), 1);
+        assert_eq!(drift.differences[0].parameter, "score RCVD_IN_B");
+    }
+
+    #[test]
+    fn test_roundcube_ignores_secrets() {
+        let file = expected("/etc/roundcube/config.inc.php");
+        let live = file
+            .content
+            .lines()
+            .map(|l| {
+                if l.starts_with("$config['des_key']") {
+                    "$config['des_key'] = 'abcdefghijklmnopqrstuvwx';"
+                } else {
+                    l
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(compare(&file, &live).status, FileStatus::InSync);
+
+        let live = live.replace("'verify_peer'       => true", "'verify_peer' => false");
+        let drift = compare(&file, &live);
+        assert_eq!(drift.status, FileStatus::Drifted);
+        assert!(drift
+            .differences
+            .iter()
+            .any(|d| d.parameter == "imap_conn_options"));
+    }
+
+    #[test]
+    fn test_detect_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = dir.path().join("local.cf");
+        std::fs::write(&present, "required_score 5.0\n").unwrap();
+        let files = vec![
+            ExpectedFile::new(
+                &present,
+                ConfigFormat::Directives { case_insensitive: false },
+                "required_score 5.0\n".to_string(),
+            ),
+            ExpectedFile::new(
+                dir.path().join("absent.cf"),
+                ConfigFormat::Directives { case_insensitive: false },
+                "required_score 5.0\n".to_string(),
+            ),
+        ];
+        let report = detect(&files);
+        assert!(report.has_drift());
+        assert_eq!(report.files[0].status, FileStatus::InSync);
+        assert_eq!(report.files[1].status, FileStatus::Missing);
+        assert_eq!(report.drifted().count(), 1);
+    }
+}
@@ -0,0 +1,615 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use super::import;
+use crate::fs::atomic;
+
+/// Root of the per-file revision store.
+pub const HISTORY_DIR: &str = "/var/lib/ceymail-mc/config-history";
+
+/// Oldest revisions beyond this are pruned, per file.
+const MAX_REVISIONS: usize = 200;
+
+/// Author recorded for edits made outside mission-control.
+const EXTERNAL_AUTHOR: &str = "(external)";
+
+/// Stored in place of a MySQL password. Those live in the credential
+/// store, never in history.
+pub const REDACTED: &str = "(redacted)";
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(std::io::Error),
+    Write(String),
+    Corrupt { path: PathBuf, message: String },
+    RevisionNotFound { path: PathBuf, id: u32 },
+    InvalidPath(String),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {}", e),
+            Self::Write(e) => write!(f, "Failed to write revision: {}", e),
+            Self::Corrupt { path, message } => {
+                write!(f, "Corrupt revision {}: {}", path.display(), message)
+            }
+            Self::RevisionNotFound { path, id } => {
+                write!(f, "Revision {} not found for {}", id, path.display())
+            }
+            Self::InvalidPath(p) => write!(f, "Invalid config path: {}", p),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<std::io::Error> for HistoryError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Who made a change and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub author: String,
+    pub reason: String,
+}
+
+impl ConfigChange {
+    pub fn new(author: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            author: author.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// One recorded version of a config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: u32,
+    pub path: PathBuf,
+    pub author: String,
+    pub reason: String,
+    pub timestamp: DateTime<Utc>,
+    /// Unified diff against the previous revision (empty for the first).
+    pub diff: String,
+    /// Set when this revision restored an earlier one.
+    #[serde(default)]
+    pub rollback_of: Option<u32>,
+    /// Full file content after the change.
+    pub content: String,
+}
+
+impl Revision {
+    /// Whether this revision records an edit made outside mission-control.
+    pub fn is_external(&self) -> bool {
+        self.author == EXTERNAL_AUTHOR
+    }
+
+    /// Whether a password was taken out of this revision, so writing it
+    /// back would break the file.
+    pub fn is_redacted(&self) -> bool {
+        self.content.contains(REDACTED)
+    }
+}
+
+/// A revision without its content, for listings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionSummary {
+    pub id: u32,
+    pub author: String,
+    pub reason: String,
+    pub timestamp: DateTime<Utc>,
+    pub rollback_of: Option<u32>,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+}
+
+impl From<&Revision> for RevisionSummary {
+    fn from(rev: &Revision) -> Self {
+        let (lines_added, lines_removed) = count_changes(&rev.diff);
+        Self {
+            id: rev.id,
+            author: rev.author.clone(),
+            reason: rev.reason.clone(),
+            timestamp: rev.timestamp,
+            rollback_of: rev.rollback_of,
+            lines_added,
+            lines_removed,
+        }
+    }
+}
+
+/// Revision store for config files.
+///
+/// Each file gets a directory mirroring its absolute path under the root
+/// (`<root>/etc/postfix/main.cf/000001.json`), holding one JSON document per
+/// revision with the full content, so any revision can be restored without
+/// replaying diffs.
+#[derive(Debug, Clone)]
+pub struct ConfigHistory {
+    root: PathBuf,
+}
+
+impl ConfigHistory {
+    pub fn new() -> Self {
+        Self::with_root(HISTORY_DIR)
+    }
+
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Record a write of `content` to `path`.
+    ///
+    /// `previous` is what the file held before the write. If it does not
+    /// match the latest revision (or there is no history yet), it is first
+    /// recorded as an external revision so it can be rolled back to.
+    /// Returns `None` when the content is unchanged.
+    pub fn record(
+        &self,
+        path: &Path,
+        previous: Option<&str>,
+        content: &str,
+        change: &ConfigChange,
+    ) -> Result<Option<Revision>, HistoryError> {
+        self.record_inner(path, previous, content, change, None)
+    }
+
+    /// Record `content`, already on disk, as mission-control's own version
+    /// of `path`, as when an existing server is adopted. Unlike
+    /// [`Self::record`] this is not an external edit, so it becomes the
+    /// baseline drift is measured against. Returns `None` when that is
+    /// already the latest managed content.
+    pub fn adopt(
+        &self,
+        path: &Path,
+        content: &str,
+        change: &ConfigChange,
+    ) -> Result<Option<Revision>, HistoryError> {
+        let dir = self.file_dir(path)?;
+        std::fs::create_dir_all(&dir)?;
+
+        let content = redact(content);
+        let content = content.as_str();
+        let latest = self.latest(path)?;
+        if latest
+            .as_ref()
+            .is_some_and(|r| !r.is_external() && r.content == content)
+        {
+            return Ok(None);
+        }
+        let rev = self.write_revision(path, latest.as_ref(), content, change, None)?;
+        self.prune(path)?;
+        Ok(Some(rev))
+    }
+
+    /// Record a rollback of `path` to revision `target`.
+    pub fn record_rollback(
+        &self,
+        path: &Path,
+        previous: Option<&str>,
+        target: u32,
+        change: &ConfigChange,
+    ) -> Result<Option<Revision>, HistoryError> {
+        let content = self.get(path, target)?.content;
+        self.record_inner(path, previous, &content, change, Some(target))
+    }
+
+    fn record_inner(
+        &self,
+        path: &Path,
+        previous: Option<&str>,
+        content: &str,
+        change: &ConfigChange,
+        rollback_of: Option<u32>,
+    ) -> Result<Option<Revision>, HistoryError> {
+        let dir = self.file_dir(path)?;
+        std::fs::create_dir_all(&dir)?;
+
+        let previous = previous.map(redact);
+        let previous = previous.as_deref();
+        let content = redact(content);
+        let content = content.as_str();
+        let mut latest = self.latest(path)?;
+        if let Some(previous) = previous {
+            if latest.as_ref().map(|r| r.content.as_str()) != Some(previous) {
+                let external = ConfigChange::new(
+                    EXTERNAL_AUTHOR,
+                    if latest.is_some() {
+                        "Edited outside mission-control"
+                    } else {
+                        "Initial version"
+                    },
+                );
+                latest = Some(self.write_revision(path, latest.as_ref(), previous, &external, None)?);
+            }
+        }
+
+        if latest.as_ref().map(|r| r.content.as_str()) == Some(content) {
+            return Ok(None);
+        }
+        let rev = self.write_revision(path, latest.as_ref(), content, change, rollback_of)?;
+        self.prune(path)?;
+        Ok(Some(rev))
+    }
+
+    fn write_revision(
+        &self,
+        path: &Path,
+        parent: Option<&Revision>,
+        content: &str,
+        change: &ConfigChange,
+        rollback_of: Option<u32>,
+    ) -> Result<Revision, HistoryError> {
+        let id = parent.map(|p| p.id + 1).unwrap_or(1);
+        let diff = match parent {
+            Some(p) => unified_diff(path, &p.content, content, p.id, id),
+            None => String::new(),
+        };
+        let rev = Revision {
+            id,
+            path: path.to_path_buf(),
+            author: change.author.clone(),
+            reason: change.reason.clone(),
+            timestamp: Utc::now(),
+            diff,
+            rollback_of,
+            content: content.to_string(),
+        };
+        let json = serde_json::to_vec_pretty(&rev).map_err(|e| HistoryError::Corrupt {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        atomic::atomic_write(&self.revision_path(path, id)?, &json, Some(0o600))
+            .map_err(|e| HistoryError::Write(e.to_string()))?;
+        Ok(rev)
+    }
+
+    /// Revisions of `path`, newest first.
+    pub fn list(&self, path: &Path) -> Result<Vec<RevisionSummary>, HistoryError> {
+        self.ids(path)?
+            .into_iter()
+            .rev()
+            .map(|id| self.get(path, id).map(|r| RevisionSummary::from(&r)))
+            .collect()
+    }
+
+    pub fn get(&self, path: &Path, id: u32) -> Result<Revision, HistoryError> {
+        let file = self.revision_path(path, id)?;
+        let json = match std::fs::read(&file) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(HistoryError::RevisionNotFound {
+                    path: path.to_path_buf(),
+                    id,
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&json).map_err(|e| HistoryError::Corrupt {
+            path: file,
+            message: e.to_string(),
+        })
+    }
+
+    pub fn latest(&self, path: &Path) -> Result<Option<Revision>, HistoryError> {
+        match self.ids(path)?.last() {
+            Some(&id) => self.get(path, id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The newest revision of `path` written by mission-control itself,
+    /// skipping edits made outside it.
+    pub fn latest_managed(&self, path: &Path) -> Result<Option<Revision>, HistoryError> {
+        for id in self.ids(path)?.into_iter().rev() {
+            let rev = self.get(path, id)?;
+            if !rev.is_external() {
+                return Ok(Some(rev));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Every file with at least one revision, sorted by path.
+    pub fn files(&self) -> Result<Vec<PathBuf>, HistoryError> {
+        let mut files = Vec::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let mut has_revisions = false;
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    pending.push(entry.path());
+                } else if is_revision_file(&entry.file_name()) {
+                    has_revisions = true;
+                }
+            }
+            if has_revisions && dir != self.root {
+                if let Ok(relative) = dir.strip_prefix(&self.root) {
+                    files.push(Path::new("/").join(relative));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Unified diff from revision `from` to revision `to`.
+    pub fn diff(&self, path: &Path, from: u32, to: u32) -> Result<String, HistoryError> {
+        let a = self.get(path, from)?;
+        let b = self.get(path, to)?;
+        Ok(unified_diff(path, &a.content, &b.content, from, to))
+    }
+
+    fn ids(&self, path: &Path) -> Result<Vec<u32>, HistoryError> {
+        let dir = self.file_dir(path)?;
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut ids: Vec<u32> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                e.file_name()
+                    .to_str()?
+                    .strip_suffix(".json")?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn prune(&self, path: &Path) -> Result<(), HistoryError> {
+        let ids = self.ids(path)?;
+        if ids.len() > MAX_REVISIONS {
+            for id in &ids[..ids.len() - MAX_REVISIONS] {
+                std::fs::remove_file(self.revision_path(path, *id)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn file_dir(&self, path: &Path) -> Result<PathBuf, HistoryError> {
+        let mut dir = self.root.clone();
+        let mut normal = 0;
+        for component in path.components() {
+            match component {
+                Component::RootDir => {}
+                Component::Normal(part) => {
+                    dir.push(part);
+                    normal += 1;
+                }
+                _ => return Err(HistoryError::InvalidPath(path.display().to_string())),
+            }
+        }
+        if !path.is_absolute() || normal == 0 {
+            return Err(HistoryError::InvalidPath(path.display().to_string()));
+        }
+        Ok(dir)
+    }
+
+    fn revision_path(&self, path: &Path, id: u32) -> Result<PathBuf, HistoryError> {
+        Ok(self.file_dir(path)?.join(format!("{:06}.json", id)))
+    }
+}
+
+impl Default for ConfigHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `NNNNNN.json`, as written by `revision_path`.
+fn is_revision_file(name: &std::ffi::OsStr) -> bool {
+    name.to_str()
+        .and_then(|n| n.strip_suffix(".json"))
+        .is_some_and(|id| id.len() == 6 && id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// `content` with any MySQL password replaced by [`REDACTED`].
+fn redact(content: &str) -> String {
+    import::replace_mysql_password(content, "password", REDACTED).unwrap_or_else(|| content.to_string())
+}
+
+fn unified_diff(path: &Path, old: &str, new: &str, from: u32, to: u32) -> String {
+    let name = path.display().to_string();
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("{}@{}", name, from), &format!("{}@{}", name, to))
+        .to_string()
+}
+
+fn count_changes(diff: &str) -> (usize, usize) {
+    let mut added = 0;
+    let mut removed = 0;
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            continue;
+        }
+        match line.chars().next() {
+            Some('+') => added += 1,
+            Some('-') => removed += 1,
+            _ => {}
+        }
+    }
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(reason: &str) -> ConfigChange {
+        ConfigChange::new("admin", reason)
+    }
+
+    #[test]
+    fn test_record_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let path = Path::new("/etc/postfix/main.cf");
+
+        let rev = history
+            .record(path, Some("a = 1\n"), "a = 2\n", &change("bump a"))
+            .unwrap()
+            .unwrap();
+        // The pre-existing content became revision 1
+        assert_eq!(rev.id, 2);
+        assert!(rev.diff.contains("-a = 1"));
+        assert!(rev.diff.contains("+a = 2"));
+
+        let list = history.list(path).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, 2);
+        assert_eq!(list[0].reason, "bump a");
+        assert_eq!((list[0].lines_added, list[0].lines_removed), (1, 1));
+        assert_eq!(list[1].author, EXTERNAL_AUTHOR);
+    }
+
+    #[test]
+    fn test_unchanged_write_is_not_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let path = Path::new("/etc/opendkim.conf");
+        history.record(path, None, "Mode sv\n", &change("init")).unwrap();
+        assert!(history
+            .record(path, Some("Mode sv\n"), "Mode sv\n", &change("noop"))
+            .unwrap()
+            .is_none());
+        assert_eq!(history.list(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_external_edit_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let path = Path::new("/etc/postfix/main.cf");
+        history.record(path, None, "a = 1\n", &change("one")).unwrap();
+        history
+            .record(path, Some("a = 1\nb = 9\n"), "a = 2\nb = 9\n", &change("two"))
+            .unwrap();
+
+        let list = history.list(path).unwrap();
+        let reasons: Vec<_> = list.iter().map(|r| r.reason.as_str()).collect();
+        assert_eq!(reasons, vec!["two", "Edited outside mission-control", "one"]);
+    }
+
+    #[test]
+    fn test_tracked_files_and_latest_managed() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let main_cf = Path::new("/etc/postfix/main.cf");
+        let key_table = Path::new("/etc/opendkim/key.table");
+        history.record(main_cf, Some("a = 1\n"), "a = 2\n", &change("one")).unwrap();
+        history.record(key_table, None, "k d:s:/k\n", &change("key")).unwrap();
+        assert_eq!(history.files().unwrap(), vec![key_table.to_path_buf(), main_cf.to_path_buf()]);
+
+        // An external revision on top does not replace the managed baseline
+        history
+            .record(main_cf, Some("a = 3\n"), "a = 3\n", &change("noop"))
+            .unwrap();
+        assert!(history.latest(main_cf).unwrap().unwrap().is_external());
+        let managed = history.latest_managed(main_cf).unwrap().unwrap();
+        assert_eq!(managed.content, "a = 2\n");
+    }
+
+    #[test]
+    fn test_adopt_makes_live_content_the_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let path = Path::new("/etc/spamassassin/local.cf");
+        let rev = history.adopt(path, "required_score 4.0\n", &change("import")).unwrap().unwrap();
+        assert_eq!(rev.id, 1);
+        assert!(!rev.is_external());
+        assert!(history.adopt(path, "required_score 4.0\n", &change("again")).unwrap().is_none());
+
+        // A later write sees no external edit in between
+        history
+            .record(path, Some("required_score 4.0\n"), "required_score 5.0\n", &change("tune"))
+            .unwrap();
+        assert_eq!(history.list(path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_diff_between_revisions_and_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let path = Path::new("/etc/dovecot/dovecot.conf");
+        history.record(path, None, "x = 1\n", &change("one")).unwrap();
+        history.record(path, Some("x = 1\n"), "x = 2\n", &change("two")).unwrap();
+        history.record(path, Some("x = 2\n"), "x = 3\n", &change("three")).unwrap();
+
+        let diff = history.diff(path, 1, 3).unwrap();
+        assert!(diff.contains("-x = 1"));
+        assert!(diff.contains("+x = 3"));
+
+        let rev = history
+            .record_rollback(path, Some("x = 3\n"), 1, &change("revert"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rev.id, 4);
+        assert_eq!(rev.rollback_of, Some(1));
+        assert_eq!(rev.content, "x = 1\n");
+    }
+
+    #[test]
+    fn test_rejects_relative_and_parent_paths() {
+        let history = ConfigHistory::with_root("/tmp/unused");
+        assert!(history.list(Path::new("etc/passwd")).is_err());
+        assert!(history.list(Path::new("/etc/../root/.ssh")).is_err());
+        assert!(matches!(
+            history.get(Path::new("/etc/x"), 1),
+            Err(HistoryError::RevisionNotFound { .. }) | Err(HistoryError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_mysql_passwords_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(dir.path());
+        let map = Path::new("/etc/postfix/mysql-virtual-alias-maps.cf");
+        let rev = history
+            .record(
+                map,
+                Some("user = mailuser\npassword = hunter2\n"),
+                "user = mailuser\npassword = hunter3\n",
+                &change("new password"),
+            )
+            .unwrap();
+        // Only the password changed, and that is not kept
+        assert!(rev.is_none());
+        let stored = history.latest(map).unwrap().unwrap();
+        assert_eq!(stored.content, "user = mailuser\npassword = (redacted)\n");
+        assert!(stored.is_redacted());
+
+        let sql = Path::new("/etc/dovecot/dovecot-sql.conf.ext");
+        let rev = history
+            .adopt(sql, "connect = host=127.0.0.1 user=mailuser password=hunter2\n", &change("adopt"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rev.content, "connect = host=127.0.0.1 user=mailuser password=(redacted)\n");
+        for path in history.files().unwrap() {
+            for summary in history.list(&path).unwrap() {
+                let revision = history.get(&path, summary.id).unwrap();
+                assert!(!revision.content.contains("hunter") && !revision.diff.contains("hunter"));
+            }
+        }
+    }
+}
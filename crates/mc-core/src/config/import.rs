@@ -0,0 +1,964 @@
+//! Adoption of mail servers that mission-control did not set up.
+//!
+//! Boxes provisioned by the legacy ceymail bash scripts, or by hand, run
+//! configs the generators know nothing about. [`scan`] reads the live
+//! Postfix, Dovecot, OpenDKIM and SpamAssassin files back into the config
+//! models, finds the DKIM keys and the MySQL credentials written into the
+//! lookup map files, and lists everything the models cannot hold, which is
+//! what regenerating the configs would lose.
+//!
+//! Adopting a server moves the MySQL passwords into the [`CredentialStore`]:
+//! the map files are rewritten to read them from a MySQL option file under
+//! [`MYSQL_OPTION_DIR`], rendered from the store, instead of carrying their
+//! own copy.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::changeset::Changeset;
+use super::dovecot::{DovecotConfig, SharedDictBackend};
+use super::dovecot_conf::DovecotConf;
+use super::drift::{self, ConfigFormat};
+use super::opendkim::{DkimDomainEntry, OpendkimConfig};
+use super::parser::{self, split_list};
+use super::postfix::PostfixConfig;
+use super::postfix_params;
+use super::spamassassin::SpamAssassinConfig;
+use crate::mail::dkim::{txt_record_value, DkimAlgorithm};
+use crate::security::credentials::{CredentialError, CredentialStore};
+use crate::security::input;
+
+const POSTFIX_MAIN_CF: &str = "/etc/postfix/main.cf";
+const DOVECOT_CONF: &str = "/etc/dovecot/dovecot.conf";
+const OPENDKIM_CONF: &str = "/etc/opendkim.conf";
+const SPAMASSASSIN_LOCAL_CF: &str = "/etc/spamassassin/local.cf";
+
+/// Where moved MySQL passwords are kept for Postfix and Dovecot to read.
+pub const MYSQL_OPTION_DIR: &str = "/etc/ceymail-mc/mysql";
+
+/// Where to look for the live configs.
+#[derive(Debug, Clone)]
+pub struct ImportPaths {
+    root: PathBuf,
+}
+
+impl Default for ImportPaths {
+    fn default() -> Self {
+        Self::with_root("/")
+    }
+}
+
+impl ImportPaths {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read everything, including the absolute paths the configs refer
+    /// to, below `root` instead of `/`.
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+/// Something in the live config that has no place in the models.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmappedItem {
+    /// The file it was found in.
+    pub source: PathBuf,
+    /// The setting, line or file concerned.
+    pub item: String,
+    pub reason: String,
+}
+
+/// A DKIM signing key referenced by the OpenDKIM key table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredKey {
+    pub domain: String,
+    pub selector: String,
+    pub key_path: PathBuf,
+    /// Whether the private key file is actually there.
+    pub exists: bool,
+}
+
+/// MySQL credentials found in a map or SQL config file.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DiscoveredCredential {
+    /// Name to store the password under in the [`CredentialStore`].
+    pub name: String,
+    /// The files holding the password, in the order they were found.
+    pub sources: Vec<PathBuf>,
+    pub user: String,
+    pub host: String,
+    pub database: String,
+    pub password: String,
+}
+
+impl fmt::Debug for DiscoveredCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiscoveredCredential")
+            .field("name", &self.name)
+            .field("sources", &self.sources)
+            .field("user", &self.user)
+            .field("host", &self.host)
+            .field("database", &self.database)
+            .field("password", &"********")
+            .finish()
+    }
+}
+
+impl DiscoveredCredential {
+    /// The MySQL option file the password is moved to.
+    pub fn option_file(&self) -> PathBuf {
+        Path::new(MYSQL_OPTION_DIR).join(format!("{}.cnf", self.name))
+    }
+}
+
+/// The live configs read back into the models.
+#[derive(Debug, Clone, Default)]
+pub struct ImportedStack {
+    pub hostname: Option<String>,
+    pub domain: Option<String>,
+    pub postfix: Option<PostfixConfig>,
+    pub dovecot: Option<DovecotConfig>,
+    pub opendkim: Option<OpendkimConfig>,
+    pub spamassassin: Option<SpamAssassinConfig>,
+    pub dkim_keys: Vec<DiscoveredKey>,
+    pub credentials: Vec<DiscoveredCredential>,
+    pub unmapped: Vec<UnmappedItem>,
+    /// Every config file the scan read, by live path, as it was read.
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+impl ImportedStack {
+    /// Move every discovered password into `store`, returning the names
+    /// stored and the changeset that completes the move: an option file
+    /// per password, readable by the group of the file it was first found
+    /// in, and each source rewritten to read it with `option_file` instead
+    /// of holding its own copy. Files are staged under `paths`.
+    pub fn move_credentials(
+        &self,
+        store: &CredentialStore,
+        paths: &ImportPaths,
+    ) -> Result<(Vec<String>, Changeset), CredentialError> {
+        let mut stored = Vec::new();
+        let mut changeset = Changeset::new();
+        if !self.credentials.is_empty() {
+            std::fs::create_dir_all(paths.resolve(MYSQL_OPTION_DIR))?;
+        }
+        for credential in &self.credentials {
+            store.store(&credential.name, &credential.password)?;
+            stored.push(credential.name.clone());
+
+            let option_file = credential.option_file();
+            let group = credential
+                .sources
+                .first()
+                .and_then(|source| std::fs::metadata(paths.resolve(source)).ok())
+                .map(|meta| std::os::unix::fs::MetadataExt::gid(&meta));
+            changeset.stage_owned(
+                paths.resolve(&option_file),
+                render_option_file(&credential.password),
+                0o640,
+                group.map(|gid| (0, gid)),
+            );
+            for source in &credential.sources {
+                let Some(content) = self.files.get(source) else { continue };
+                if let Some(rewritten) = point_at_option_file(content, &option_file) {
+                    changeset.stage(paths.resolve(source), rewritten);
+                }
+            }
+        }
+        Ok((stored, changeset))
+    }
+
+    fn unmapped(&mut self, source: &Path, item: impl Into<String>, reason: impl Into<String>) {
+        self.unmapped.push(UnmappedItem {
+            source: source.to_path_buf(),
+            item: item.into(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Record MySQL credentials, keeping the first of any that share a
+    /// store name.
+    fn add_credential(&mut self, credential: DiscoveredCredential) {
+        let source = credential.sources.first().cloned().unwrap_or_default();
+        if let Err(e) = input::validate_path_component(&credential.name) {
+            self.unmapped(&source, format!("user = {}", credential.user), format!("cannot be stored: {}", e));
+            return;
+        }
+        match self.credentials.iter_mut().find(|c| c.name == credential.name) {
+            Some(existing) if existing.password == credential.password => {
+                if !existing.sources.contains(&source) {
+                    existing.sources.push(source);
+                }
+            }
+            Some(existing) => {
+                let reason = format!(
+                    "password differs from the one in {}; only that one is stored",
+                    existing.sources[0].display()
+                );
+                self.unmapped(&source, format!("user = {}", credential.user), reason);
+            }
+            None => self.credentials.push(credential),
+        }
+    }
+}
+
+/// Read the live configs under `paths`. Missing or unreadable files are
+/// reported as unmapped rather than failing the scan.
+pub fn scan(paths: &ImportPaths) -> ImportedStack {
+    let mut stack = ImportedStack::default();
+    scan_postfix(paths, &mut stack);
+    scan_dovecot(paths, &mut stack);
+    scan_opendkim(paths, &mut stack);
+    scan_spamassassin(paths, &mut stack);
+    stack
+}
+
+fn read(paths: &ImportPaths, path: &str, stack: &mut ImportedStack) -> Option<String> {
+    let resolved = paths.resolve(path);
+    match std::fs::read_to_string(&resolved) {
+        Ok(content) => {
+            stack.files.insert(PathBuf::from(path), content.clone());
+            Some(content)
+        }
+        Err(e) => {
+            stack.unmapped(Path::new(path), "(file)", format!("cannot read: {}", e));
+            None
+        }
+    }
+}
+
+/// A MySQL option file holding `password` for the client.
+fn render_option_file(password: &str) -> String {
+    let escaped = password.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        "# Managed by ceymail-mc from its credential store\n[client]\npassword = \"{}\"\n",
+        escaped
+    )
+}
+
+/// Rewrite `content` to read its password from `option_file`. `None` if
+/// nothing in it holds one.
+fn point_at_option_file(content: &str, option_file: &Path) -> Option<String> {
+    replace_mysql_password(content, "option_file", &option_file.display().to_string())
+}
+
+/// Replace the MySQL password in `content` with `key` set to `value`: a
+/// `password = ...` line (Postfix mysql_table, MySQL option files) or the
+/// `password=` parameter of a `connect = ...` line (Dovecot). `None` if
+/// nothing in `content` holds a password.
+pub(crate) fn replace_mysql_password(content: &str, key: &str, value: &str) -> Option<String> {
+    let mut changed = false;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let setting = line.split_once('=').map(|(name, rest)| (name, name.trim(), rest.trim()));
+        match setting {
+            Some((_, "password", _)) => {
+                lines.push(format!("{} = {}", key, value));
+                changed = true;
+            }
+            Some((name, "connect", rest)) if rest.split_whitespace().any(|p| p.starts_with("password=")) => {
+                let quoted = rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"');
+                let inner = if quoted { &rest[1..rest.len() - 1] } else { rest };
+                let params: Vec<String> = inner
+                    .split_whitespace()
+                    .map(|pair| match pair.split_once('=') {
+                        Some(("password", _)) => format!("{}={}", key, value),
+                        _ => pair.to_string(),
+                    })
+                    .collect();
+                let params = params.join(" ");
+                let rest = if quoted { format!("\"{}\"", params) } else { params };
+                lines.push(format!("{}= {}", name, rest));
+                changed = true;
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    if !changed {
+        return None;
+    }
+    let mut rewritten = lines.join("\n");
+    if content.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    Some(rewritten)
+}
+
+// ── Postfix ────────────────────────────────────────────────────────
+
+fn scan_postfix(paths: &ImportPaths, stack: &mut ImportedStack) {
+    let source = Path::new(POSTFIX_MAIN_CF);
+    let Some(content) = read(paths, POSTFIX_MAIN_CF, stack) else { return };
+    let (config, entries) = match PostfixConfig::parse(&content)
+        .map_err(|e| e.to_string())
+        .and_then(|config| Ok((config, parser::parse_config(&content)?.to_map())))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            stack.unmapped(source, "(file)", format!("cannot parse: {}", e));
+            return;
+        }
+    };
+
+    for (key, value) in &entries {
+        if postfix_params::lookup(key).is_none() {
+            stack.unmapped(
+                source,
+                format!("{} = {}", key, value),
+                "not in the parameter catalogue; kept, but edited without type checks",
+            );
+        }
+        for table in split_list(value) {
+            if let Some(map) = table.strip_prefix("proxy:").unwrap_or(&table).strip_prefix("mysql:") {
+                scan_mysql_map(paths, map, stack);
+            }
+        }
+    }
+
+    stack.hostname = config.get_expanded("myhostname");
+    stack.domain = config.get_expanded("mydomain").or_else(|| {
+        stack
+            .hostname
+            .as_deref()
+            .and_then(|h| h.split_once('.'))
+            .map(|(_, domain)| domain.to_string())
+    });
+    stack.postfix = Some(config);
+}
+
+/// Pull the connection settings out of a Postfix `mysql:` map file.
+fn scan_mysql_map(paths: &ImportPaths, map: &str, stack: &mut ImportedStack) {
+    let Some(content) = read(paths, map, stack) else { return };
+    let settings = match parser::parse_config(&content) {
+        Ok(config) => config.to_map(),
+        Err(e) => {
+            stack.unmapped(Path::new(map), "(file)", format!("cannot parse: {}", e));
+            return;
+        }
+    };
+    let (Some(user), Some(password)) = (settings.get("user"), settings.get("password")) else {
+        stack.unmapped(Path::new(map), "(file)", "no user and password to import");
+        return;
+    };
+    let host = settings
+        .get("hosts")
+        .and_then(|hosts| split_list(hosts).into_iter().next())
+        .unwrap_or_else(|| "localhost".to_string());
+    stack.add_credential(DiscoveredCredential {
+        name: format!("mysql-{}", user),
+        sources: vec![PathBuf::from(map)],
+        user: user.clone(),
+        host,
+        database: settings.get("dbname").cloned().unwrap_or_default(),
+        password: password.clone(),
+    });
+}
+
+// ── Dovecot ────────────────────────────────────────────────────────
+
+fn scan_dovecot(paths: &ImportPaths, stack: &mut ImportedStack) {
+    let main = paths.resolve(DOVECOT_CONF);
+    let effective = match DovecotConf::load_effective(&main) {
+        Ok(effective) => effective,
+        Err(e) => {
+            stack.unmapped(Path::new(DOVECOT_CONF), "(file)", format!("cannot read: {}", e));
+            return;
+        }
+    };
+
+    let domain = stack.domain.clone().unwrap_or_default();
+    let mut config = DovecotConfig::generate_default(&domain);
+    if let Some(hostname) = &stack.hostname {
+        config.hostname = hostname.clone();
+    }
+    if let Some(location) = effective.get("mail_location") {
+        config.set_mail_location(location);
+        let base = location.split_once(':').map_or(location, |(_, path)| path);
+        if let Some((base, _)) = base.split_once("/%d") {
+            config.mail_home_base = base.to_string();
+        }
+    }
+    if let Some(cert) = effective.get("ssl_cert") {
+        config.set_ssl_cert(cert.trim_start_matches('<'));
+    }
+    if let Some(key) = effective.get("ssl_key") {
+        config.set_ssl_key(key.trim_start_matches('<'));
+    }
+    if let Some(path) = effective.get("log_path") {
+        config.set_log_path(path);
+    }
+    if let Some(path) = effective.get("info_log_path") {
+        config.set_info_log_path(path);
+    }
+    match effective.get("plugin { acl_shared_dict") {
+        Some(dict) if dict.starts_with("proxy:") => config.shared_dict = SharedDictBackend::Sql,
+        Some(dict) => {
+            if let Some(path) = dict.strip_prefix("file:") {
+                config.shared_dict = SharedDictBackend::File(path.to_string());
+            }
+        }
+        None => {}
+    }
+    if effective.get("passdb { driver") == Some("sql") {
+        if let Some(sql_conf) = effective.get("passdb { args") {
+            scan_dovecot_sql(paths, sql_conf, &mut config, stack);
+        }
+    }
+
+    // Whatever the generated fragments would not reproduce is lost on
+    // regeneration
+    let mut generated = BTreeMap::new();
+    for fragment in [
+        config.generate_10_auth(),
+        config.generate_10_mail(),
+        config.generate_10_master(),
+        config.generate_10_ssl(),
+        config.generate_10_logging(),
+        config.generate_20_imap(),
+        config.generate_20_lmtp(),
+        config.generate_20_managesieve(),
+        config.generate_90_acl(),
+        config.generate_90_sieve(),
+        config.generate_auth_sql_ext(),
+    ] {
+        if let Ok(map) = drift::normalize(ConfigFormat::Dovecot, &fragment) {
+            generated.extend(map);
+        }
+    }
+    for (key, setting) in &effective.settings {
+        let value = setting.value.split_whitespace().collect::<Vec<_>>().join(" ");
+        let reason = match generated.get(key) {
+            Some(expected) if *expected == value => continue,
+            Some(expected) => format!("the generated config sets `{}`", expected),
+            None => "not produced by the generated config".to_string(),
+        };
+        let source = setting.file.strip_prefix(&paths.root).unwrap_or(&setting.file);
+        stack.unmapped(&Path::new("/").join(source), format!("{} = {}", key, setting.value), reason);
+    }
+    for setting in effective.settings.values() {
+        let live = Path::new("/").join(setting.file.strip_prefix(&paths.root).unwrap_or(&setting.file));
+        if let std::collections::btree_map::Entry::Vacant(entry) = stack.files.entry(live) {
+            if let Ok(content) = std::fs::read_to_string(&setting.file) {
+                entry.insert(content);
+            }
+        }
+    }
+
+    stack.dovecot = Some(config);
+}
+
+/// Read the `connect` line of dovecot-sql.conf.ext into the model and
+/// report the queries, which the generator always rewrites.
+fn scan_dovecot_sql(paths: &ImportPaths, sql_conf: &str, config: &mut DovecotConfig, stack: &mut ImportedStack) {
+    let Some(content) = read(paths, sql_conf, stack) else { return };
+    let source = Path::new(sql_conf);
+    let live = match parser::parse_config(&content) {
+        Ok(parsed) => parsed.to_map(),
+        Err(e) => {
+            stack.unmapped(source, "(file)", format!("cannot parse: {}", e));
+            return;
+        }
+    };
+
+    if let Some(connect) = live.get("connect") {
+        let params: BTreeMap<&str, &str> = connect
+            .split_whitespace()
+            .filter_map(|pair| pair.split_once('='))
+            .collect();
+        let get = |key: &str| params.get(key).copied().unwrap_or_default();
+        config.set_db_credentials(get("user"), get("password"), get("dbname"), get("host"));
+        if !config.db_user.is_empty() && !config.db_password.is_empty() {
+            stack.add_credential(DiscoveredCredential {
+                name: format!("mysql-{}", config.db_user),
+                sources: vec![source.to_path_buf()],
+                user: config.db_user.clone(),
+                host: config.db_host.clone(),
+                database: config.db_name.clone(),
+                password: config.db_password.clone(),
+            });
+        }
+    }
+
+    let generated = parser::parse_config(&config.generate_dovecot_sql_ext())
+        .map(|parsed| parsed.to_map())
+        .unwrap_or_default();
+    for (key, value) in &live {
+        if key != "connect" && generated.get(key) != Some(value) {
+            stack.unmapped(source, format!("{} = {}", key, value), "not produced by the generated config");
+        }
+    }
+}
+
+// ── OpenDKIM ───────────────────────────────────────────────────────
+
+fn scan_opendkim(paths: &ImportPaths, stack: &mut ImportedStack) {
+    let source = Path::new(OPENDKIM_CONF);
+    let Some(content) = read(paths, OPENDKIM_CONF, stack) else { return };
+    let directives = drift::normalize(ConfigFormat::Directives { case_insensitive: true }, &content)
+        .unwrap_or_default();
+    let table = |name: &str| {
+        directives
+            .get(name)
+            .map(|value| value.strip_prefix("refile:").unwrap_or(value).to_string())
+    };
+
+    let mut config = OpendkimConfig::generate_default();
+    if let Some(socket) = directives.get("socket") {
+        config.socket = socket.clone();
+    }
+    if let Some(mode) = directives.get("mode") {
+        config.mode = mode.clone();
+    }
+    if let Some(canonicalization) = directives.get("canonicalization") {
+        config.canonicalization = canonicalization.clone();
+    }
+
+    if let Some(key_table) = table("keytable") {
+        if let Some(content) = read(paths, &key_table, stack) {
+            scan_key_table(paths, Path::new(&key_table), &content, &mut config, stack);
+        }
+    }
+    if let Some(signing_table) = table("signingtable") {
+        if let Some(content) = read(paths, &signing_table, stack) {
+            scan_signing_table(Path::new(&signing_table), &content, &mut config, stack);
+        }
+    }
+    // OpenDKIM would not sign for these; the generated table signs with
+    // their first key
+    for entry in config.key_table.clone() {
+        if config.signing_keys(&entry.domain).is_empty() {
+            config.signing_table.push(entry);
+        }
+    }
+    if let Some(trusted) = table("internalhosts").or_else(|| table("externalignorelist")) {
+        if let Some(content) = read(paths, &trusted, stack) {
+            config.trusted_hosts = content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(str::to_string)
+                .collect();
+        }
+    }
+
+    let generated = drift::normalize(
+        ConfigFormat::Directives { case_insensitive: true },
+        &config.generate_opendkim_conf(),
+    )
+    .unwrap_or_default();
+    for (key, value) in &directives {
+        let reason = match generated.get(key) {
+            Some(expected) if expected == value => continue,
+            Some(expected) => format!("the generated config sets `{}`", expected),
+            None => "not produced by the generated config".to_string(),
+        };
+        stack.unmapped(source, format!("{} {}", key, value), reason);
+    }
+
+    stack.opendkim = Some(config);
+}
+
+/// `selector._domainkey.domain domain:selector:/path/to/key` lines. The
+/// legacy scripts separate the columns with a tab.
+fn scan_key_table(
+    paths: &ImportPaths,
+    source: &Path,
+    content: &str,
+    config: &mut OpendkimConfig,
+    stack: &mut ImportedStack,
+) {
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let spec = line.split_whitespace().nth(1).unwrap_or_default();
+        let mut parts = spec.splitn(3, ':');
+        let (Some(domain), Some(selector), Some(key_path)) = (parts.next(), parts.next(), parts.next()) else {
+            stack.unmapped(source, line, "not a domain:selector:keyfile entry");
+            continue;
+        };
+        if config.selectors(domain).contains(&selector) {
+            stack.unmapped(source, line, "duplicate selector");
+            continue;
+        }
+        let algorithm = std::fs::read_to_string(paths.resolve(key_path).with_extension("txt"))
+            .ok()
+            .and_then(|record| DkimAlgorithm::from_txt_value(&txt_record_value(&record)))
+            .unwrap_or_default();
+        config.key_table.push(DkimDomainEntry::new(domain, selector, algorithm));
+        stack.dkim_keys.push(DiscoveredKey {
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            key_path: PathBuf::from(key_path),
+            exists: paths.resolve(key_path).is_file(),
+        });
+    }
+
+    // Keys live at <base>/<domain>/<anything>; the legacy scripts used
+    // /etc/mail/dkim-keys/<domain>/<domain>.private
+    let bases: Vec<&Path> = stack
+        .dkim_keys
+        .iter()
+        .filter_map(|k| {
+            let dir = k.key_path.parent()?;
+            (dir.file_name()? == k.domain.as_str()).then_some(dir.parent()?)
+        })
+        .collect();
+    if let Some(base) = bases.first() {
+        if bases.iter().all(|b| b == base) {
+            config.key_base_dir = base.display().to_string();
+        }
+    }
+
+    let moved: Vec<(String, String)> = stack
+        .dkim_keys
+        .iter()
+        .filter_map(|key| {
+            let expected = format!("{}/{}/{}.private", config.key_base_dir, key.domain, key.selector);
+            (Path::new(&expected) != key.key_path).then(|| (key.key_path.display().to_string(), expected))
+        })
+        .collect();
+    for (actual, expected) in moved {
+        stack.unmapped(
+            source,
+            actual,
+            format!("the generated key table expects {}; move the key there before regenerating", expected),
+        );
+    }
+}
+
+/// `*@domain selector._domainkey.domain` lines naming key-table keys; a
+/// domain may sign with one key of each type. Anything narrower than a
+/// whole domain cannot be expressed.
+fn scan_signing_table(source: &Path, content: &str, config: &mut OpendkimConfig, stack: &mut ImportedStack) {
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split_whitespace();
+        let (pattern, key) = (columns.next().unwrap_or_default(), columns.next().unwrap_or_default());
+        let Some(entry) = config
+            .key_table
+            .iter()
+            .find(|e| pattern == format!("*@{}", e.domain) && key == format!("{}._domainkey.{}", e.selector, e.domain))
+            .cloned()
+        else {
+            stack.unmapped(source, line, "only whole domains signed with a key-table key are modelled");
+            continue;
+        };
+        if config.signing_selector(&entry.domain, entry.algorithm).is_some() {
+            stack.unmapped(source, line, "the domain already signs with a key of this type");
+            continue;
+        }
+        config.signing_table.push(entry);
+    }
+}
+
+// ── SpamAssassin ───────────────────────────────────────────────────
+
+fn scan_spamassassin(paths: &ImportPaths, stack: &mut ImportedStack) {
+    let source = Path::new(SPAMASSASSIN_LOCAL_CF);
+    let Some(content) = read(paths, SPAMASSASSIN_LOCAL_CF, stack) else { return };
+
+    let mut config = SpamAssassinConfig::generate_default();
+    config.dnsbl_entries.clear();
+    config.use_pyzor = false;
+    config.use_razor2 = false;
+    config.use_dcc = false;
+
+    let flag = |value: &str| value == "1";
+    // DNSBL rules by name: (zone, score)
+    let mut rbl_rules: BTreeMap<String, (Option<String>, Option<f64>)> = BTreeMap::new();
+    let mut other = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        let directive = tokens.next().unwrap_or_default();
+        let args: Vec<&str> = tokens.collect();
+        let value = args.join(" ");
+        match (directive, args.as_slice()) {
+            ("required_score", [score]) if score.parse::<f64>().is_ok() => {
+                config.required_score = score.parse().unwrap_or(config.required_score);
+            }
+            ("rewrite_header", ["Subject", ..]) => {
+                config.rewrite_header_subject = args[1..].join(" ");
+            }
+            ("report_safe", [n]) if n.parse::<u8>().is_ok() => {
+                config.report_safe = n.parse().unwrap_or(config.report_safe);
+            }
+            ("use_bayes", _) => config.use_bayes = flag(&value),
+            ("bayes_auto_learn", _) => config.bayes_auto_learn = flag(&value),
+            ("skip_rbl_checks", _) => config.skip_rbl_checks = flag(&value),
+            ("use_pyzor", _) => config.use_pyzor = flag(&value),
+            ("use_razor2", _) => config.use_razor2 = flag(&value),
+            ("use_dcc", _) => config.use_dcc = flag(&value),
+            ("header", [rule, check, ..]) if check.starts_with("eval:check_rbl(") => {
+                let zone = check
+                    .trim_start_matches("eval:check_rbl(")
+                    .split(',')
+                    .next()
+                    .map(|z| z.trim_matches(|c| c == '\'' || c == '"').to_string());
+                rbl_rules.entry(rule.to_string()).or_default().0 = zone;
+            }
+            ("describe", [rule, ..]) if rbl_rules.contains_key(*rule) => {}
+            _ => other.push(line),
+        }
+    }
+
+    // Scores may come before or after the rule they belong to
+    for line in other {
+        let mut tokens = line.split_whitespace();
+        if let (Some("score"), Some(rule), Some(score)) = (tokens.next(), tokens.next(), tokens.next()) {
+            if let (Some(entry), Ok(score)) = (rbl_rules.get_mut(rule), score.parse::<f64>()) {
+                entry.1 = Some(score);
+                continue;
+            }
+        }
+        stack.unmapped(source, line, "not produced by the generated config");
+    }
+    for (rule, (zone, score)) in rbl_rules {
+        match (zone, score) {
+            (Some(zone), Some(score)) => config.dnsbl_entries.push((zone, score)),
+            _ => stack.unmapped(source, rule, "DNSBL rule without a zone or score"),
+        }
+    }
+
+    stack.spamassassin = Some(config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path.trim_start_matches('/'));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// A box as the legacy ceymail scripts left it.
+    fn legacy_server() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(
+            root,
+            "/etc/postfix/main.cf",
+            "myhostname = mail.example.com\n\
+             mydomain = example.com\n\
+             virtual_mailbox_domains = mysql:/etc/postfix/mysql-virtual-mailbox-domains.cf\n\
+             virtual_alias_maps = proxy:mysql:/etc/postfix/mysql-virtual-alias-maps.cf\n\
+             ceymail_custom = yes\n",
+        );
+        for map in ["mysql-virtual-mailbox-domains.cf", "mysql-virtual-alias-maps.cf"] {
+            write(
+                root,
+                &format!("/etc/postfix/{}", map),
+                "user = mailuser\npassword = hunter2\nhosts = 127.0.0.1\ndbname = mailserver\nquery = SELECT 1\n",
+            );
+        }
+        write(
+            root,
+            "/etc/opendkim.conf",
+            "Socket inet:12301@localhost\n\
+             KeyTable refile:/etc/opendkim/key.table\n\
+             SigningTable refile:/etc/opendkim/signing.table\n\
+             InternalHosts refile:/etc/opendkim/trusted.hosts\n",
+        );
+        write(
+            root,
+            "/etc/opendkim/key.table",
+            "mail._domainkey.example.com\texample.com:mail:/etc/mail/dkim-keys/example.com/example.com.private\n",
+        );
+        write(root, "/etc/opendkim/signing.table", "*@example.com  \t\tmail._domainkey.example.com\n");
+        write(root, "/etc/opendkim/trusted.hosts", "127.0.0.1\nexample.com\n");
+        write(root, "/etc/mail/dkim-keys/example.com/example.com.private", "key");
+        write(
+            root,
+            "/etc/spamassassin/local.cf",
+            "required_score 4.0\n\
+             rewrite_header Subject ***SPAM***\n\
+             use_bayes 1\n\
+             header RCVD_IN_ZEN eval:check_rbl('zen', 'zen.spamhaus.org.')\n\
+             score RCVD_IN_ZEN 3.5\n\
+             score URIBL_BLACK 4.0\n",
+        );
+        dir
+    }
+
+    #[test]
+    fn test_postfix_and_map_credentials() {
+        let dir = legacy_server();
+        let stack = scan(&ImportPaths::with_root(dir.path()));
+
+        assert_eq!(stack.hostname.as_deref(), Some("mail.example.com"));
+        assert_eq!(stack.domain.as_deref(), Some("example.com"));
+        assert!(stack.postfix.is_some());
+
+        // Both maps carry the same credentials: one entry
+        assert_eq!(stack.credentials.len(), 1);
+        let credential = &stack.credentials[0];
+        assert_eq!(credential.name, "mysql-mailuser");
+        assert_eq!(credential.database, "mailserver");
+        assert_eq!(credential.host, "127.0.0.1");
+        assert!(!format!("{:?}", credential).contains("hunter2"));
+        assert_eq!(
+            credential.sources,
+            vec![
+                PathBuf::from("/etc/postfix/mysql-virtual-alias-maps.cf"),
+                PathBuf::from("/etc/postfix/mysql-virtual-mailbox-domains.cf"),
+            ]
+        );
+        assert!(stack.files.contains_key(Path::new(POSTFIX_MAIN_CF)));
+        assert!(stack.files.keys().all(|path| !path.ends_with("example.com.private")));
+
+        assert!(stack
+            .unmapped
+            .iter()
+            .any(|u| u.item == "ceymail_custom = yes"));
+    }
+
+    #[test]
+    fn test_legacy_dkim_keys() {
+        let dir = legacy_server();
+        let stack = scan(&ImportPaths::with_root(dir.path()));
+
+        assert_eq!(stack.dkim_keys.len(), 1);
+        let key = &stack.dkim_keys[0];
+        assert_eq!((key.domain.as_str(), key.selector.as_str()), ("example.com", "mail"));
+        assert!(key.exists);
+
+        let opendkim = stack.opendkim.as_ref().unwrap();
+        assert_eq!(opendkim.socket, "inet:12301@localhost");
+        assert_eq!(opendkim.key_base_dir, "/etc/mail/dkim-keys");
+        assert_eq!(opendkim.trusted_hosts, vec!["127.0.0.1", "example.com"]);
+        // Legacy key file names are <domain>.private, not <selector>.private
+        assert!(stack.unmapped.iter().any(|u| {
+            u.item == "/etc/mail/dkim-keys/example.com/example.com.private"
+                && u.reason.contains("/etc/mail/dkim-keys/example.com/mail.private")
+        }));
+        // The signing table line is covered despite the tab separators
+        assert!(!stack.unmapped.iter().any(|u| u.source.ends_with("signing.table")));
+    }
+
+    #[test]
+    fn test_spamassassin_model() {
+        let dir = legacy_server();
+        let stack = scan(&ImportPaths::with_root(dir.path()));
+
+        let sa = stack.spamassassin.as_ref().unwrap();
+        assert!((sa.required_score - 4.0).abs() < f64::EPSILON);
+        assert_eq!(sa.rewrite_header_subject, "***SPAM***");
+        assert_eq!(sa.dnsbl_entries, vec![("zen".to_string(), 3.5)]);
+        assert!(stack
+            .unmapped
+            .iter()
+            .any(|u| u.item == "score URIBL_BLACK 4.0"));
+    }
+
+    #[test]
+    fn test_dovecot_sql_credentials() {
+        let dir = legacy_server();
+        let root = dir.path();
+        write(
+            root,
+            "/etc/dovecot/dovecot.conf",
+            "protocols = imap lmtp\n\
+             mail_location = maildir:/srv/mail/%d/%n\n\
+             ssl_cert = </etc/ssl/mail.pem\n\
+             passdb {\n  driver = sql\n  args = /etc/dovecot/dovecot-sql.conf.ext\n}\n",
+        );
+        write(
+            root,
+            "/etc/dovecot/dovecot-sql.conf.ext",
+            "driver = mysql\n\
+             connect = host=127.0.0.1 dbname=mailserver user=mailuser password=other\n\
+             default_pass_scheme = SHA512-CRYPT\n",
+        );
+        let stack = scan(&ImportPaths::with_root(root));
+
+        let dovecot = stack.dovecot.as_ref().unwrap();
+        assert_eq!(dovecot.mail_location, "maildir:/srv/mail/%d/%n");
+        assert_eq!(dovecot.mail_home_base, "/srv/mail");
+        assert_eq!(dovecot.ssl_cert, "/etc/ssl/mail.pem");
+        assert_eq!(dovecot.db_password, "other");
+        assert!(stack.unmapped.iter().any(|u| u.item == "protocols = imap lmtp"));
+        // Same user, different password from the Postfix maps
+        assert_eq!(stack.credentials.len(), 1);
+        assert!(stack
+            .unmapped
+            .iter()
+            .any(|u| u.source.ends_with("dovecot-sql.conf.ext") && u.reason.contains("password differs")));
+    }
+
+    #[test]
+    fn test_move_credentials_points_maps_at_option_file() {
+        let dir = legacy_server();
+        let paths = ImportPaths::with_root(dir.path());
+        let stack = scan(&paths);
+        let secrets = tempfile::tempdir().unwrap();
+        let store = CredentialStore::with_credentials_dir(
+            &secrets.path().join("key.txt"),
+            &secrets.path().join("credentials"),
+        )
+        .unwrap();
+
+        let (stored, changeset) = stack.move_credentials(&store, &paths).unwrap();
+        assert_eq!(stored, vec!["mysql-mailuser"]);
+        assert_eq!(store.retrieve("mysql-mailuser").unwrap(), "hunter2");
+
+        let option_file = changeset
+            .get(&dir.path().join("etc/ceymail-mc/mysql/mysql-mailuser.cnf"))
+            .unwrap();
+        assert!(option_file.content.contains("[client]\npassword = \"hunter2\"\n"));
+        assert_eq!(option_file.mode, 0o640);
+        let map = changeset
+            .get(&dir.path().join("etc/postfix/mysql-virtual-alias-maps.cf"))
+            .unwrap();
+        assert!(!map.content.contains("hunter2"));
+        assert!(map
+            .content
+            .contains("user = mailuser\noption_file = /etc/ceymail-mc/mysql/mysql-mailuser.cnf\nhosts"));
+    }
+
+    #[test]
+    fn test_connect_password_becomes_option_file() {
+        let option_file = Path::new("/etc/ceymail-mc/mysql/mysql-mailuser.cnf");
+        let rewritten = point_at_option_file(
+            "driver = mysql\nconnect = \"host=127.0.0.1 user=mailuser password=secret\"\n",
+            option_file,
+        )
+        .unwrap();
+        assert_eq!(
+            rewritten,
+            "driver = mysql\nconnect = \"host=127.0.0.1 user=mailuser \
+             option_file=/etc/ceymail-mc/mysql/mysql-mailuser.cnf\"\n"
+        );
+        assert!(point_at_option_file("driver = mysql\n", option_file).is_none());
+        assert_eq!(
+            render_option_file(r#"a"b\c"#),
+            "# Managed by ceymail-mc from its credential store\n[client]\npassword = \"a\\\"b\\\\c\"\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let stack = scan(&ImportPaths::with_root(dir.path()));
+        assert!(stack.postfix.is_none() && stack.opendkim.is_none());
+        assert!(stack
+            .unmapped
+            .iter()
+            .any(|u| u.source == Path::new(POSTFIX_MAIN_CF) && u.item == "(file)"));
+    }
+}
@@ -1,7 +1,16 @@
 pub mod parser;
 pub mod postfix;
+pub mod postfix_master;
 pub mod dovecot;
+pub mod dovecot_conf;
 pub mod opendkim;
 pub mod spamassassin;
 pub mod apache;
 pub mod roundcube;
+pub mod drift;
+pub mod certificates;
+pub mod history;
+pub mod changeset;
+pub mod validate;
+pub mod postfix_params;
+pub mod import;
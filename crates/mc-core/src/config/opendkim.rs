@@ -1,12 +1,29 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::changeset::Changeset;
+use crate::mail::dkim::DkimAlgorithm;
 
 /// A single domain entry in the DKIM configuration tables.
 #[derive(Debug, Clone, PartialEq)]
 pub struct DkimDomainEntry {
     pub domain: String,
     pub selector: String,
+    /// The key's type. The tables do not record it; it is read from the
+    /// selector's TXT record file when the config is loaded.
+    pub algorithm: DkimAlgorithm,
+}
+
+impl DkimDomainEntry {
+    pub fn new(domain: &str, selector: &str, algorithm: DkimAlgorithm) -> Self {
+        Self {
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            algorithm,
+        }
+    }
 }
 
 /// OpenDKIM configuration manager.
@@ -17,9 +34,11 @@ pub struct DkimDomainEntry {
 ///   - TrustedHosts
 #[derive(Debug, Clone)]
 pub struct OpendkimConfig {
-    /// Entries in the key table (one per domain).
+    /// Entries in the key table (one per domain and selector; a domain
+    /// has several while a key rotation overlaps).
     pub key_table: Vec<DkimDomainEntry>,
-    /// Entries in the signing table (one per domain).
+    /// Entries in the signing table: the selectors that sign, at most one
+    /// per domain and key type, so a domain can sign with RSA and Ed25519.
     pub signing_table: Vec<DkimDomainEntry>,
     /// List of trusted hosts/domains.
     pub trusted_hosts: Vec<String>,
@@ -38,6 +57,10 @@ pub enum OpendkimConfigError {
     InvalidDomain(String),
     DomainAlreadyExists(String),
     DomainNotFound(String),
+    SelectorAlreadyExists(String),
+    SelectorNotFound(String),
+    SelectorInUse(String),
+    KeyTypeAlreadySigning(String),
 }
 
 impl fmt::Display for OpendkimConfigError {
@@ -46,6 +69,10 @@ impl fmt::Display for OpendkimConfigError {
             Self::InvalidDomain(d) => write!(f, "Invalid domain name: {}", d),
             Self::DomainAlreadyExists(d) => write!(f, "Domain already exists: {}", d),
             Self::DomainNotFound(d) => write!(f, "Domain not found: {}", d),
+            Self::SelectorAlreadyExists(s) => write!(f, "Selector already exists: {}", s),
+            Self::SelectorNotFound(s) => write!(f, "Selector not found: {}", s),
+            Self::SelectorInUse(s) => write!(f, "Selector is still signing: {}", s),
+            Self::KeyTypeAlreadySigning(s) => write!(f, "Domain already signs with a key of this type: {}", s),
         }
     }
 }
@@ -113,11 +140,21 @@ impl OpendkimConfig {
         }
     }
 
-    /// Add a domain to all three tables.
+    /// Add a domain to all three tables, signing with an RSA key.
     pub fn add_domain(
         &mut self,
         domain: &str,
         selector: &str,
+    ) -> Result<(), OpendkimConfigError> {
+        self.add_domain_with_algorithm(domain, selector, DkimAlgorithm::default())
+    }
+
+    /// Add a domain to all three tables, signing with a key of `algorithm`.
+    pub fn add_domain_with_algorithm(
+        &mut self,
+        domain: &str,
+        selector: &str,
+        algorithm: DkimAlgorithm,
     ) -> Result<(), OpendkimConfigError> {
         validate_domain(domain)?;
         validate_selector(selector)?;
@@ -129,10 +166,7 @@ impl OpendkimConfig {
             ));
         }
 
-        let entry = DkimDomainEntry {
-            domain: domain.to_string(),
-            selector: selector.to_string(),
-        };
+        let entry = DkimDomainEntry::new(domain, selector, algorithm);
 
         self.key_table.push(entry.clone());
         self.signing_table.push(entry);
@@ -170,50 +204,216 @@ impl OpendkimConfig {
         Ok(())
     }
 
-    /// List all configured domains.
+    /// List all signing keys, one entry per domain and key type.
     pub fn list_domains(&self) -> Vec<DkimDomainEntry> {
-        self.key_table.clone()
+        self.signing_table.clone()
+    }
+
+    // ── selectors ──────────────────────────────────────────────────
+
+    /// Add a key for another selector of an existing domain. The domain
+    /// keeps signing with its current selectors until
+    /// [`set_signing_selector`](Self::set_signing_selector) is called.
+    pub fn add_selector(
+        &mut self,
+        domain: &str,
+        selector: &str,
+        algorithm: DkimAlgorithm,
+    ) -> Result<(), OpendkimConfigError> {
+        validate_domain(domain)?;
+        validate_selector(selector)?;
+
+        if !self.key_table.iter().any(|e| e.domain == domain) {
+            return Err(OpendkimConfigError::DomainNotFound(domain.to_string()));
+        }
+        if self.selectors(domain).contains(&selector) {
+            return Err(OpendkimConfigError::SelectorAlreadyExists(format!(
+                "{}._domainkey.{}",
+                selector, domain
+            )));
+        }
+
+        self.key_table.push(DkimDomainEntry::new(domain, selector, algorithm));
+        Ok(())
+    }
+
+    /// Add a key that signs alongside the domain's existing ones, e.g. an
+    /// Ed25519 key next to the RSA key receivers still rely on. A domain
+    /// signs with at most one key of each type.
+    pub fn add_signing_key(
+        &mut self,
+        domain: &str,
+        selector: &str,
+        algorithm: DkimAlgorithm,
+    ) -> Result<(), OpendkimConfigError> {
+        if let Some(existing) = self.signing_selector(domain, algorithm) {
+            return Err(OpendkimConfigError::KeyTypeAlreadySigning(format!(
+                "{}._domainkey.{}",
+                existing, domain
+            )));
+        }
+        self.add_selector(domain, selector, algorithm)?;
+        self.signing_table.push(DkimDomainEntry::new(domain, selector, algorithm));
+        Ok(())
+    }
+
+    /// Sign a domain's mail with another of its key-table selectors, in
+    /// place of the selector of the same key type that signs now.
+    pub fn set_signing_selector(&mut self, domain: &str, selector: &str) -> Result<(), OpendkimConfigError> {
+        let entry = self
+            .key_table
+            .iter()
+            .find(|e| e.domain == domain && e.selector == selector)
+            .cloned()
+            .ok_or_else(|| {
+                OpendkimConfigError::SelectorNotFound(format!("{}._domainkey.{}", selector, domain))
+            })?;
+        match self
+            .signing_table
+            .iter_mut()
+            .find(|e| e.domain == domain && e.algorithm.same_key_type(entry.algorithm))
+        {
+            Some(existing) => *existing = entry,
+            None => self.signing_table.push(entry),
+        }
+        Ok(())
+    }
+
+    /// Drop a selector that no longer signs from the key table.
+    pub fn remove_selector(&mut self, domain: &str, selector: &str) -> Result<(), OpendkimConfigError> {
+        let name = format!("{}._domainkey.{}", selector, domain);
+        if self
+            .signing_table
+            .iter()
+            .any(|e| e.domain == domain && e.selector == selector)
+        {
+            return Err(OpendkimConfigError::SelectorInUse(name));
+        }
+        let before = self.key_table.len();
+        self.key_table
+            .retain(|e| !(e.domain == domain && e.selector == selector));
+        if self.key_table.len() == before {
+            return Err(OpendkimConfigError::SelectorNotFound(name));
+        }
+        Ok(())
+    }
+
+    /// Every key-table selector of a domain.
+    pub fn selectors(&self, domain: &str) -> Vec<&str> {
+        self.key_table
+            .iter()
+            .filter(|e| e.domain == domain)
+            .map(|e| e.selector.as_str())
+            .collect()
+    }
+
+    /// The selector a domain signs with for keys of `algorithm`'s type.
+    pub fn signing_selector(&self, domain: &str, algorithm: DkimAlgorithm) -> Option<&str> {
+        self.signing_table
+            .iter()
+            .find(|e| e.domain == domain && e.algorithm.same_key_type(algorithm))
+            .map(|e| e.selector.as_str())
+    }
+
+    /// Every key a domain signs with.
+    pub fn signing_keys(&self, domain: &str) -> Vec<&DkimDomainEntry> {
+        self.signing_table.iter().filter(|e| e.domain == domain).collect()
+    }
+
+    /// Directory holding a domain's keys.
+    pub fn key_dir(&self, domain: &str) -> PathBuf {
+        Path::new(&self.key_base_dir).join(domain)
+    }
+
+    /// Private key file for a selector, as listed in the key table.
+    pub fn key_path(&self, domain: &str, selector: &str) -> PathBuf {
+        self.key_dir(domain).join(format!("{}.private", selector))
     }
 
     // ── config file generators ─────────────────────────────────────
 
     /// Load the OpenDKIM configuration from the standard paths.
     pub fn load() -> Result<Self, OpendkimConfigError> {
+        Self::load_from(Path::new("/etc/opendkim"))
+    }
+
+    /// Load the tables from `dir`. Columns may be separated by spaces or
+    /// tabs; the legacy install scripts used tabs.
+    pub fn load_from(dir: &Path) -> Result<Self, OpendkimConfigError> {
         let mut config = Self::generate_default();
 
-        // Parse key.table if it exists
-        let key_table_path = "/etc/opendkim/key.table";
-        if let Ok(content) = std::fs::read_to_string(key_table_path) {
+        // Format: selector._domainkey.domain domain:selector:keypath
+        let mut key_paths = Vec::new();
+        if let Ok(content) = std::fs::read_to_string(dir.join("key.table")) {
             for line in content.lines() {
                 let line = line.trim();
                 if line.is_empty() || line.starts_with('#') {
                     continue;
                 }
-                // Format: selector._domainkey.domain domain:selector:keypath
-                if let Some((_left, right)) = line.split_once(' ') {
-                    let parts: Vec<&str> = right.splitn(3, ':').collect();
-                    if parts.len() >= 2 {
-                        let domain = parts[0].to_string();
-                        let selector = parts[1].to_string();
-                        // Avoid duplicates
-                        if !config.key_table.iter().any(|e| e.domain == domain) {
-                            config.key_table.push(DkimDomainEntry {
-                                domain: domain.clone(),
-                                selector: selector.clone(),
-                            });
-                            config.signing_table.push(DkimDomainEntry {
-                                domain,
-                                selector,
-                            });
-                        }
+                let Some(right) = line.split_whitespace().nth(1) else { continue };
+                let parts: Vec<&str> = right.splitn(3, ':').collect();
+                if parts.len() < 2 {
+                    continue;
+                }
+                // The key type is in the TXT record written next to the key
+                let algorithm = parts
+                    .get(2)
+                    .and_then(|path| std::fs::read_to_string(Path::new(path).with_extension("txt")).ok())
+                    .and_then(|record| DkimAlgorithm::from_txt_value(&crate::mail::dkim::txt_record_value(&record)))
+                    .unwrap_or_default();
+                let entry = DkimDomainEntry::new(parts[0], parts[1], algorithm);
+                // Avoid duplicates
+                if !config.selectors(&entry.domain).contains(&entry.selector.as_str()) {
+                    if let Some(path) = parts.get(2) {
+                        key_paths.push((entry.clone(), PathBuf::from(path)));
                     }
+                    config.key_table.push(entry);
+                }
+            }
+        }
+
+        // Keys are at <base>/<domain>/<selector>.private; keep the default
+        // base when they are laid out some other way
+        if let Some(base) = key_paths.first().and_then(|(_, p)| p.parent()?.parent()) {
+            let mut inferred = config.clone();
+            inferred.key_base_dir = base.display().to_string();
+            if key_paths
+                .iter()
+                .all(|(e, p)| *p == inferred.key_path(&e.domain, &e.selector))
+            {
+                config.key_base_dir = inferred.key_base_dir;
+            }
+        }
+
+        // Format: *@domain selector._domainkey.domain
+        if let Ok(content) = std::fs::read_to_string(dir.join("signing.table")) {
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
                 }
+                let mut columns = line.split_whitespace();
+                let (Some(pattern), Some(key)) = (columns.next(), columns.next()) else { continue };
+                let Some(domain) = pattern.strip_prefix("*@") else { continue };
+                let Some(selector) = key.strip_suffix(&format!("._domainkey.{}", domain)) else { continue };
+                let _ = config.set_signing_selector(domain, selector);
+            }
+        }
+        // Domains missing from the signing table sign with their first key
+        let unsigned: Vec<DkimDomainEntry> = config
+            .key_table
+            .iter()
+            .filter(|e| config.signing_keys(&e.domain).is_empty())
+            .cloned()
+            .collect();
+        for entry in unsigned {
+            if config.signing_keys(&entry.domain).is_empty() {
+                config.signing_table.push(entry);
             }
         }
 
         // Parse trusted.hosts if it exists
-        let trusted_path = "/etc/opendkim/trusted.hosts";
-        if let Ok(content) = std::fs::read_to_string(trusted_path) {
+        if let Ok(content) = std::fs::read_to_string(dir.join("trusted.hosts")) {
             config.trusted_hosts.clear();
             for line in content.lines() {
                 let line = line.trim();
@@ -258,7 +458,7 @@ impl OpendkimConfig {
 
     /// Generate the main opendkim.conf content.
     pub fn generate_opendkim_conf(&self) -> String {
-        format!(
+        let mut conf = format!(
             "\
 ## generated by mission-control
 AutoRestart             Yes
@@ -289,7 +489,17 @@ InternalHosts           /etc/opendkim/trusted.hosts
             canonicalization = self.canonicalization,
             mode = self.mode,
             socket = self.socket,
-        )
+        );
+        // Without this OpenDKIM signs with the first matching
+        // signing-table entry only
+        let dual = self
+            .signing_table
+            .iter()
+            .any(|e| self.signing_keys(&e.domain).len() > 1);
+        if dual {
+            conf.push_str("MultipleSignatures      yes\n");
+        }
+        conf
     }
 
     /// Generate key.table content.
@@ -299,14 +509,12 @@ InternalHosts           /etc/opendkim/trusted.hosts
         let mut out = String::new();
         for entry in &self.key_table {
             out.push_str(&format!(
-                "{}._domainkey.{} {}:{}:{}/{}/{}.private\n",
+                "{}._domainkey.{} {}:{}:{}\n",
                 entry.selector,
                 entry.domain,
                 entry.domain,
                 entry.selector,
-                self.key_base_dir,
-                entry.domain,
-                entry.selector,
+                self.key_path(&entry.domain, &entry.selector).display(),
             ));
         }
         out
@@ -335,6 +543,31 @@ InternalHosts           /etc/opendkim/trusted.hosts
         }
         out
     }
+
+    /// Stage opendkim.conf and the three tables, so they are written
+    /// together with the Postfix milter settings that reference them.
+    pub fn stage(&self, changeset: &mut Changeset) {
+        changeset
+            .stage("/etc/opendkim.conf", self.generate_opendkim_conf())
+            .stage("/etc/opendkim/key.table", self.generate_key_table())
+            .stage("/etc/opendkim/signing.table", self.generate_signing_table())
+            .stage("/etc/opendkim/trusted.hosts", self.generate_trusted_hosts());
+    }
+
+    /// The `Socket` setting in Postfix milter syntax, e.g.
+    /// `inet:8891@localhost` becomes `inet:localhost:8891`.
+    pub fn postfix_milter(&self) -> String {
+        if let Some(inet) = self.socket.strip_prefix("inet:") {
+            if let Some((port, host)) = inet.split_once('@') {
+                return format!("inet:{}:{}", host, port);
+            }
+            return format!("inet:localhost:{}", inet);
+        }
+        if let Some(path) = self.socket.strip_prefix("local:") {
+            return format!("unix:{}", path);
+        }
+        self.socket.clone()
+    }
 }
 
 #[cfg(test)]
@@ -425,6 +658,14 @@ mod tests {
         assert!(out.contains("OversignHeaders         From"));
     }
 
+    #[test]
+    fn test_postfix_milter() {
+        let mut cfg = OpendkimConfig::generate_default();
+        assert_eq!(cfg.postfix_milter(), "inet:localhost:8891");
+        cfg.socket = "local:/run/opendkim/opendkim.sock".to_string();
+        assert_eq!(cfg.postfix_milter(), "unix:/run/opendkim/opendkim.sock");
+    }
+
     #[test]
     fn test_generate_key_table() {
         let mut cfg = OpendkimConfig::generate_default();
@@ -436,6 +677,97 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rotation_selectors() {
+        let mut cfg = OpendkimConfig::generate_default();
+        cfg.add_domain("example.com", "mail").unwrap();
+        cfg.add_selector("example.com", "s202610", DkimAlgorithm::Rsa2048).unwrap();
+        assert!(cfg.add_selector("example.com", "mail", DkimAlgorithm::Rsa2048).is_err());
+        assert!(cfg.add_selector("other.org", "mail", DkimAlgorithm::Rsa2048).is_err());
+
+        // Both keys are published in the key table; only one signs
+        assert_eq!(cfg.selectors("example.com"), vec!["mail", "s202610"]);
+        assert_eq!(cfg.generate_signing_table(), "*@example.com mail._domainkey.example.com\n");
+        assert_eq!(cfg.list_domains().len(), 1);
+
+        assert!(cfg.remove_selector("example.com", "mail").is_err());
+        cfg.set_signing_selector("example.com", "s202610").unwrap();
+        assert_eq!(cfg.signing_selector("example.com", DkimAlgorithm::Rsa2048), Some("s202610"));
+        cfg.remove_selector("example.com", "mail").unwrap();
+        assert_eq!(
+            cfg.generate_key_table(),
+            "s202610._domainkey.example.com example.com:s202610:/etc/opendkim/keys/example.com/s202610.private\n"
+        );
+        assert!(cfg.set_signing_selector("example.com", "mail").is_err());
+    }
+
+    #[test]
+    fn test_load_from_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("key.table"),
+            "mail._domainkey.example.com\texample.com:mail:/etc/mail/dkim-keys/example.com/mail.private\n\
+             s2026._domainkey.example.com\texample.com:s2026:/etc/mail/dkim-keys/example.com/s2026.private\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("signing.table"), "*@example.com\ts2026._domainkey.example.com\n").unwrap();
+
+        let cfg = OpendkimConfig::load_from(dir.path()).unwrap();
+        assert_eq!(cfg.selectors("example.com"), vec!["mail", "s2026"]);
+        assert_eq!(cfg.signing_selector("example.com", DkimAlgorithm::Rsa2048), Some("s2026"));
+        assert_eq!(cfg.key_base_dir, "/etc/mail/dkim-keys");
+        assert!(cfg.generate_key_table().contains("/etc/mail/dkim-keys/example.com/mail.private"));
+    }
+
+    #[test]
+    fn test_dual_algorithm_signing() {
+        let mut cfg = OpendkimConfig::generate_default();
+        cfg.add_domain("example.com", "rsa").unwrap();
+        assert!(!cfg.generate_opendkim_conf().contains("MultipleSignatures"));
+
+        cfg.add_signing_key("example.com", "ed", DkimAlgorithm::Ed25519).unwrap();
+        assert!(cfg.add_signing_key("example.com", "rsa2", DkimAlgorithm::Rsa4096).is_err());
+        assert_eq!(
+            cfg.generate_signing_table(),
+            "*@example.com rsa._domainkey.example.com\n*@example.com ed._domainkey.example.com\n"
+        );
+        assert!(cfg.generate_opendkim_conf().contains("MultipleSignatures      yes"));
+
+        // Rotating the Ed25519 key leaves the RSA signer alone
+        cfg.add_selector("example.com", "ed2", DkimAlgorithm::Ed25519).unwrap();
+        cfg.set_signing_selector("example.com", "ed2").unwrap();
+        assert_eq!(cfg.signing_selector("example.com", DkimAlgorithm::Rsa2048), Some("rsa"));
+        assert_eq!(cfg.signing_selector("example.com", DkimAlgorithm::Ed25519), Some("ed2"));
+        assert_eq!(cfg.signing_keys("example.com").len(), 2);
+    }
+
+    #[test]
+    fn test_load_detects_key_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = dir.path().join("keys/example.com");
+        std::fs::create_dir_all(&keys).unwrap();
+        std::fs::write(keys.join("ed.txt"), "ed._domainkey IN TXT ( \"v=DKIM1; k=ed25519; p=abc\" )").unwrap();
+        std::fs::write(
+            dir.path().join("key.table"),
+            format!(
+                "rsa._domainkey.example.com example.com:rsa:{0}/rsa.private\n\
+                 ed._domainkey.example.com example.com:ed:{0}/ed.private\n",
+                keys.display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("signing.table"),
+            "*@example.com rsa._domainkey.example.com\n*@example.com ed._domainkey.example.com\n",
+        )
+        .unwrap();
+
+        let cfg = OpendkimConfig::load_from(dir.path()).unwrap();
+        assert_eq!(cfg.key_table[1].algorithm, DkimAlgorithm::Ed25519);
+        assert_eq!(cfg.signing_selector("example.com", DkimAlgorithm::Rsa2048), Some("rsa"));
+        assert_eq!(cfg.signing_selector("example.com", DkimAlgorithm::Ed25519), Some("ed"));
+    }
+
     #[test]
     fn test_generate_signing_table() {
         let mut cfg = OpendkimConfig::generate_default();
@@ -1,13 +1,17 @@
 use nom::{
     branch::alt,
     bytes::complete::{take_while, take_while1},
-    character::complete::{char, line_ending, space0},
-    combinator::opt,
+    character::complete::{char, line_ending, not_line_ending, satisfy, space0, space1},
+    combinator::{eof, opt, peek, recognize},
     multi::many0,
+    sequence::{preceded, terminated, tuple},
     IResult,
 };
 use std::collections::BTreeMap;
 
+/// `$name` references nest at most this deep before expansion gives up.
+const MAX_EXPANSION_DEPTH: usize = 16;
+
 /// A parsed config file preserving comments and ordering
 #[derive(Debug, Clone)]
 pub struct ConfigFile {
@@ -16,8 +20,14 @@ pub struct ConfigFile {
 
 #[derive(Debug, Clone)]
 pub enum ConfigLine {
-    /// A key = value pair
-    KeyValue { key: String, value: String },
+    /// A key = value pair. `value` is the logical value: continuation lines
+    /// are joined with single spaces. `raw` keeps the original text of a
+    /// value that spanned several lines so it can be written back as-is.
+    KeyValue {
+        key: String,
+        value: String,
+        raw: Option<String>,
+    },
     /// A comment line (including the # prefix)
     Comment(String),
     /// An empty/blank line
@@ -27,7 +37,7 @@ pub enum ConfigLine {
 impl ConfigFile {
     pub fn get(&self, key: &str) -> Option<&str> {
         for line in &self.entries {
-            if let ConfigLine::KeyValue { key: k, value: v } = line {
+            if let ConfigLine::KeyValue { key: k, value: v, .. } = line {
                 if k == key {
                     return Some(v.as_str());
                 }
@@ -39,7 +49,7 @@ impl ConfigFile {
     pub fn set(&mut self, key: &str, new_value: &str) {
         let mut found = false;
         for line in &mut self.entries {
-            if let ConfigLine::KeyValue { key: k, value: v } = line {
+            if let ConfigLine::KeyValue { key: k, value: v, .. } = line {
                 if k == key {
                     *v = new_value.to_string();
                     found = true;
@@ -51,6 +61,7 @@ impl ConfigFile {
             self.entries.push(ConfigLine::KeyValue {
                 key: key.to_string(),
                 value: new_value.to_string(),
+                raw: None,
             });
         }
     }
@@ -69,8 +80,12 @@ impl ConfigFile {
         let mut output = String::new();
         for line in &self.entries {
             match line {
-                ConfigLine::KeyValue { key, value } => {
-                    output.push_str(&format!("{} = {}\n", key, value));
+                ConfigLine::KeyValue { key, value, raw } => {
+                    match raw {
+                        Some(raw) if join_continued(raw).1 == *value => output.push_str(raw),
+                        _ => output.push_str(&format!("{} = {}", key, value)),
+                    }
+                    output.push('\n');
                 }
                 ConfigLine::Comment(c) => {
                     output.push_str(c);
@@ -87,7 +102,7 @@ impl ConfigFile {
     pub fn to_map(&self) -> BTreeMap<String, String> {
         let mut map = BTreeMap::new();
         for line in &self.entries {
-            if let ConfigLine::KeyValue { key, value } = line {
+            if let ConfigLine::KeyValue { key, value, .. } = line {
                 map.insert(key.clone(), value.clone());
             }
         }
@@ -99,7 +114,7 @@ impl ConfigFile {
         self.entries
             .iter()
             .filter_map(|line| {
-                if let ConfigLine::KeyValue { key: k, value: v } = line {
+                if let ConfigLine::KeyValue { key: k, value: v, .. } = line {
                     if k == key {
                         return Some(v.as_str());
                     }
@@ -108,6 +123,153 @@ impl ConfigFile {
             })
             .collect()
     }
+
+    /// A value split into its comma/whitespace-separated elements, the way
+    /// Postfix reads list parameters.
+    pub fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).map(split_list)
+    }
+
+    /// Replace a list value, keeping the layout of the existing value:
+    /// one element per continuation line if it was written that way, and
+    /// comma separators if it used them (or is new).
+    ///
+    /// Elements may contain spaces, e.g. `reject_rbl_client zen.spamhaus.org`.
+    pub fn set_list<S: AsRef<str>>(&mut self, key: &str, items: &[S]) {
+        let items: Vec<&str> = items.iter().map(|s| s.as_ref().trim()).collect();
+        let existing = self.entries.iter_mut().find_map(|line| match line {
+            ConfigLine::KeyValue { key: k, value, raw } if k == key => Some((value, raw)),
+            _ => None,
+        });
+        let Some((value, raw)) = existing else {
+            self.set(key, &items.join(", "));
+            return;
+        };
+
+        let commas = value.contains(',') || value.trim().is_empty();
+        let separator = if commas { "," } else { "" };
+        match raw.as_deref().and_then(continuation_layout) {
+            Some((first_inline, indent)) => {
+                let mut text = format!("{} =", key);
+                for (i, item) in items.iter().enumerate() {
+                    let sep = if i + 1 < items.len() { separator } else { "" };
+                    if i == 0 && first_inline {
+                        text.push_str(&format!(" {}{}", item, sep));
+                    } else {
+                        text.push_str(&format!("\n{}{}{}", indent, item, sep));
+                    }
+                }
+                let (_, joined) = join_continued(&text);
+                *value = joined;
+                *raw = Some(text);
+            }
+            None => {
+                *value = items.join(if commas { ", " } else { " " });
+                *raw = None;
+            }
+        }
+    }
+
+    /// Expand `$name`, `${name}`, `${name?text}` and `${name:text}` using
+    /// the values in this file, for display. Parameters that are not set
+    /// here (Postfix built-in defaults) are left as written.
+    pub fn expand(&self, value: &str) -> String {
+        self.expand_depth(value, 0)
+    }
+
+    fn expand_depth(&self, value: &str, depth: usize) -> String {
+        if depth >= MAX_EXPANSION_DEPTH || !value.contains('$') {
+            return value.to_string();
+        }
+        let mut out = String::new();
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            if let Some(inner) = after.strip_prefix('{') {
+                let Some(end) = inner.find('}') else {
+                    out.push_str(&rest[pos..]);
+                    return out;
+                };
+                let body = &inner[..end];
+                let name_len = body
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(body.len());
+                let (name, op) = body.split_at(name_len);
+                let current = self.get(name);
+                let set = current.is_some_and(|v| !v.is_empty());
+                match (op.chars().next(), current) {
+                    (None, Some(v)) => out.push_str(&self.expand_depth(v, depth + 1)),
+                    (Some('?'), Some(_)) if set => {
+                        out.push_str(&self.expand_depth(&op[1..], depth + 1))
+                    }
+                    (Some(':'), _) if !set && current.is_some() => {
+                        out.push_str(&self.expand_depth(&op[1..], depth + 1))
+                    }
+                    (Some('?') | Some(':'), Some(_)) => {}
+                    _ => out.push_str(&rest[pos..pos + 2 + end + 1]),
+                }
+                rest = &inner[end + 1..];
+            } else {
+                let name_len = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                let name = &after[..name_len];
+                match self.get(name) {
+                    Some(v) if !name.is_empty() => out.push_str(&self.expand_depth(v, depth + 1)),
+                    _ => {
+                        out.push('$');
+                        out.push_str(name);
+                    }
+                }
+                rest = &after[name_len..];
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Split a Postfix list value on commas and whitespace.
+pub fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Join a (possibly continued) `key = value` text into `(key, value)`,
+/// skipping comment and blank lines between continuations.
+fn join_continued(raw: &str) -> (&str, String) {
+    let mut lines = raw.lines();
+    let first = lines.next().unwrap_or_default();
+    let (key, first_value) = first.split_once('=').unwrap_or((first, ""));
+    let mut parts: Vec<&str> = vec![first_value.trim()];
+    for line in lines {
+        let t = line.trim();
+        if !t.is_empty() && !t.starts_with('#') {
+            parts.push(t);
+        }
+    }
+    let parts: Vec<&str> = parts.into_iter().filter(|p| !p.is_empty()).collect();
+    (key.trim(), parts.join(" "))
+}
+
+/// For a continued value: whether the first element shares the key's line,
+/// and the indentation of the continuation lines.
+fn continuation_layout(raw: &str) -> Option<(bool, String)> {
+    let mut lines = raw.lines();
+    let first = lines.next()?;
+    let indent = lines
+        .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))?
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect();
+    let first_inline = first
+        .split_once('=')
+        .is_some_and(|(_, v)| !v.trim().is_empty());
+    Some((first_inline, indent))
 }
 
 // nom parsers
@@ -134,19 +296,55 @@ fn key_chars(input: &str) -> IResult<&str, &str> {
     take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')(input)
 }
 
+/// An indented, non-comment line continuing the previous value.
+fn continuation_line(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        space1,
+        peek(satisfy(|c| c != '#' && c != '\n' && c != '\r')),
+        not_line_ending,
+    )))(input)
+}
+
+/// Comment and blank lines Postfix skips between continuations.
+fn skipped_line(input: &str) -> IResult<&str, &str> {
+    terminated(
+        recognize(tuple((space0, opt(tuple((char('#'), not_line_ending)))))),
+        line_ending,
+    )(input)
+}
+
 fn key_value_line(input: &str) -> IResult<&str, ConfigLine> {
+    let start = input;
     let (input, _) = space0(input)?;
     let (input, key) = key_chars(input)?;
     let (input, _) = space0(input)?;
     let (input, _) = char('=')(input)?;
     let (input, _) = space0(input)?;
     let (input, val) = take_while(is_not_newline)(input)?;
-    let (input, _) = opt(line_ending)(input)?;
+    let (input, continued) = many0(preceded(
+        tuple((line_ending, many0(skipped_line))),
+        continuation_line,
+    ))(input)?;
+    let raw = &start[..start.len() - input.len()];
+    let (input, _) = alt((line_ending, eof))(input)?;
+
+    if continued.is_empty() {
+        return Ok((
+            input,
+            ConfigLine::KeyValue {
+                key: key.to_string(),
+                value: val.trim_end().to_string(),
+                raw: None,
+            },
+        ));
+    }
+    let (_, value) = join_continued(raw);
     Ok((
         input,
         ConfigLine::KeyValue {
             key: key.to_string(),
-            value: val.trim_end().to_string(),
+            value,
+            raw: Some(raw.to_string()),
         },
     ))
 }
@@ -275,4 +473,79 @@ mod tests {
         );
         assert_eq!(config.get("my-key"), Some("my-value"));
     }
+
+    #[test]
+    fn test_continuation_lines() {
+        let input = "\
+smtpd_recipient_restrictions =
+    permit_mynetworks,
+# DNSBLs
+    reject_unauth_destination
+mydomain = example.com
+";
+        let config = parse_config(input).unwrap();
+        assert_eq!(
+            config.get("smtpd_recipient_restrictions"),
+            Some("permit_mynetworks, reject_unauth_destination")
+        );
+        assert_eq!(config.get("mydomain"), Some("example.com"));
+        assert_eq!(config.serialize(), input);
+    }
+
+    #[test]
+    fn test_continuation_without_trailing_newline() {
+        let config = parse_config("mydestination = localhost,\n\t$myhostname").unwrap();
+        assert_eq!(config.get("mydestination"), Some("localhost, $myhostname"));
+    }
+
+    #[test]
+    fn test_get_and_set_list() {
+        let input = "mynetworks = 127.0.0.0/8 [::1]/128\n";
+        let mut config = parse_config(input).unwrap();
+        assert_eq!(
+            config.get_list("mynetworks").unwrap(),
+            vec!["127.0.0.0/8", "[::1]/128"]
+        );
+        config.set_list("mynetworks", &["127.0.0.0/8", "[::1]/128", "192.0.2.0/24"]);
+        assert_eq!(
+            config.serialize(),
+            "mynetworks = 127.0.0.0/8 [::1]/128 192.0.2.0/24\n"
+        );
+
+        // Continued values keep one element per line and the same indent
+        let input = "maps = hash:/etc/a,\n\thash:/etc/b\n";
+        let mut config = parse_config(input).unwrap();
+        config.set_list("maps", &["hash:/etc/a", "hash:/etc/c", "hash:/etc/b"]);
+        assert_eq!(
+            config.serialize(),
+            "maps = hash:/etc/a,\n\thash:/etc/c,\n\thash:/etc/b\n"
+        );
+        assert_eq!(config.get("maps"), Some("hash:/etc/a, hash:/etc/c, hash:/etc/b"));
+
+        config.set_list("new_list", &["a", "b"]);
+        assert_eq!(config.get("new_list"), Some("a, b"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let input = "\
+myhostname = mail.example.com
+mydomain = example.com
+myorigin = $mydomain
+mydestination = $myhostname, localhost.${mydomain}
+relayhost =
+banner = ${relayhost?relayed}${relayhost:direct} $mail_name
+";
+        let config = parse_config(input).unwrap();
+        assert_eq!(config.expand("$myorigin"), "example.com");
+        assert_eq!(
+            config.expand(config.get("mydestination").unwrap()),
+            "mail.example.com, localhost.example.com"
+        );
+        // Unset parameters are Postfix built-ins and stay as written
+        assert_eq!(config.expand(config.get("banner").unwrap()), "direct $mail_name");
+
+        let looped = parse_config("a = $b\nb = $a\n").unwrap();
+        assert!(looped.expand("$a").contains('$'));
+    }
 }
@@ -1,6 +1,41 @@
 use super::parser::{parse_config, ConfigFile, ConfigLine};
+use super::postfix_params::{self, ParamError};
+use crate::acme;
 use std::fmt;
 
+/// smtpd restrictions that take the next list element as their argument
+/// (a DNS list, lookup table or policy service).
+const RESTRICTIONS_WITH_ARGUMENT: &[&str] = &[
+    "check_ccert_access",
+    "check_client_access",
+    "check_client_a_access",
+    "check_client_mx_access",
+    "check_client_ns_access",
+    "check_helo_access",
+    "check_helo_a_access",
+    "check_helo_mx_access",
+    "check_helo_ns_access",
+    "check_policy_service",
+    "check_recipient_access",
+    "check_recipient_a_access",
+    "check_recipient_mx_access",
+    "check_recipient_ns_access",
+    "check_reverse_client_hostname_access",
+    "check_sasl_access",
+    "check_sender_access",
+    "check_sender_a_access",
+    "check_sender_mx_access",
+    "check_sender_ns_access",
+    "permit_dnswl_client",
+    "permit_rhswl_client",
+    "reject_rbl_client",
+    "reject_rhsbl_client",
+    "reject_rhsbl_helo",
+    "reject_rhsbl_recipient",
+    "reject_rhsbl_reverse_client",
+    "reject_rhsbl_sender",
+];
+
 /// Postfix main.cf configuration manager.
 ///
 /// Wraps the generic key=value parser and provides typed access
@@ -96,6 +131,14 @@ impl PostfixConfig {
         self.inner.get("smtpd_tls_key_file")
     }
 
+    pub fn smtp_tls_security_level(&self) -> Option<&str> {
+        self.inner.get("smtp_tls_security_level")
+    }
+
+    pub fn smtp_dns_support_level(&self) -> Option<&str> {
+        self.inner.get("smtp_dns_support_level")
+    }
+
     pub fn smtpd_recipient_restrictions(&self) -> Option<&str> {
         self.inner.get("smtpd_recipient_restrictions")
     }
@@ -132,6 +175,21 @@ impl PostfixConfig {
         self.inner.get("virtual_transport")
     }
 
+    pub fn smtpd_sender_login_maps(&self) -> Option<&str> {
+        self.inner.get("smtpd_sender_login_maps")
+    }
+
+    pub fn smtputf8_enable(&self) -> Option<&str> {
+        self.inner.get("smtputf8_enable")
+    }
+
+    /// Whether SMTPUTF8 (RFC 6531) is switched on. Postfix 3.x defaults to
+    /// `yes` when the parameter is absent, but the generated main.cf always
+    /// sets it explicitly, so absence is treated as disabled.
+    pub fn smtputf8_enabled(&self) -> bool {
+        matches!(self.smtputf8_enable(), Some("yes"))
+    }
+
     // ── typed setters ──────────────────────────────────────────────
 
     pub fn set_myhostname(&mut self, val: &str) {
@@ -178,6 +236,14 @@ impl PostfixConfig {
         self.inner.set("smtpd_tls_key_file", val);
     }
 
+    pub fn set_smtp_tls_security_level(&mut self, val: &str) {
+        self.inner.set("smtp_tls_security_level", val);
+    }
+
+    pub fn set_smtp_dns_support_level(&mut self, val: &str) {
+        self.inner.set("smtp_dns_support_level", val);
+    }
+
     pub fn set_smtpd_recipient_restrictions(&mut self, val: &str) {
         self.inner.set("smtpd_recipient_restrictions", val);
     }
@@ -194,16 +260,137 @@ impl PostfixConfig {
         self.inner.set("non_smtpd_milters", val);
     }
 
+    pub fn set_smtpd_sender_login_maps(&mut self, val: &str) {
+        self.inner.set("smtpd_sender_login_maps", val);
+    }
+
+    pub fn set_smtputf8_enable(&mut self, val: &str) {
+        self.inner.set("smtputf8_enable", val);
+    }
+
     /// Set an arbitrary key.
     pub fn set(&mut self, key: &str, val: &str) {
         self.inner.set(key, val);
     }
 
+    /// Set a key after checking it against the parameter catalogue.
+    pub fn set_param(&mut self, key: &str, val: &str) -> Result<(), ParamError> {
+        postfix_params::validate(key, val)?;
+        self.inner.set(key, val);
+        Ok(())
+    }
+
     /// Get an arbitrary key.
     pub fn get(&self, key: &str) -> Option<&str> {
         self.inner.get(key)
     }
 
+    /// Get a key with `$parameter` references expanded, for display.
+    pub fn get_expanded(&self, key: &str) -> Option<String> {
+        self.inner.get(key).map(|v| self.inner.expand(v))
+    }
+
+    /// Add a milter to both `smtpd_milters` and `non_smtpd_milters`,
+    /// keeping any already listed. Returns whether anything changed.
+    pub fn add_milter(&mut self, milter: &str) -> bool {
+        let mut changed = false;
+        for key in ["smtpd_milters", "non_smtpd_milters"] {
+            let mut milters = self.inner.get_list(key).unwrap_or_default();
+            if !milters.iter().any(|m| m == milter) {
+                milters.push(milter.to_string());
+                self.inner.set_list(key, &milters);
+                changed = true;
+            }
+        }
+        if changed && self.get("milter_default_action").is_none() {
+            self.set("milter_default_action", "accept");
+        }
+        changed
+    }
+
+    /// Whether outbound SMTP authenticates servers that publish TLSA
+    /// records, which only happens with DNSSEC-validated lookups.
+    pub fn outbound_dane_enabled(&self) -> bool {
+        self.smtp_tls_security_level() == Some("dane")
+            && self.smtp_dns_support_level() == Some("dnssec")
+    }
+
+    /// Switch outbound DANE on or off. Switching on also turns on DNSSEC
+    /// lookups; switching off falls back to opportunistic TLS and leaves
+    /// them on. Returns whether anything changed.
+    pub fn set_outbound_dane(&mut self, enabled: bool) -> bool {
+        if enabled {
+            if self.outbound_dane_enabled() {
+                return false;
+            }
+            self.set_smtp_dns_support_level("dnssec");
+            self.set_smtp_tls_security_level("dane");
+            true
+        } else if self.smtp_tls_security_level() == Some("dane") {
+            self.set_smtp_tls_security_level("may");
+            true
+        } else {
+            false
+        }
+    }
+
+    // ── restriction lists ──────────────────────────────────────────
+
+    /// The restrictions in a list such as `smtpd_recipient_restrictions`,
+    /// each with its argument attached (`reject_rbl_client zen.spamhaus.org`).
+    pub fn restrictions(&self, key: &str) -> Vec<String> {
+        let tokens = self.inner.get_list(key).unwrap_or_default();
+        let mut out: Vec<String> = Vec::new();
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            if RESTRICTIONS_WITH_ARGUMENT.contains(&token.as_str()) {
+                match tokens.next() {
+                    Some(arg) => out.push(format!("{} {}", token, arg)),
+                    None => out.push(token),
+                }
+            } else {
+                out.push(token);
+            }
+        }
+        out
+    }
+
+    /// Add a restriction unless it is already present. With `before`, it is
+    /// inserted ahead of the first restriction of that name (e.g. DNSBLs
+    /// before `permit`); otherwise it is appended. Returns whether the list
+    /// changed.
+    pub fn add_restriction(&mut self, key: &str, restriction: &str, before: Option<&str>) -> bool {
+        let restriction = normalize_restriction(restriction);
+        let mut list = self.restrictions(key);
+        if list.contains(&restriction) {
+            return false;
+        }
+        let position = before.and_then(|name| {
+            list.iter()
+                .position(|r| r.split_whitespace().next() == Some(name))
+        });
+        match position {
+            Some(index) => list.insert(index, restriction),
+            None => list.push(restriction),
+        }
+        self.inner.set_list(key, &list);
+        true
+    }
+
+    /// Remove a restriction. A bare name such as `reject_rbl_client` removes
+    /// every entry of that name; with an argument only the exact entry goes.
+    pub fn remove_restriction(&mut self, key: &str, restriction: &str) -> bool {
+        let restriction = normalize_restriction(restriction);
+        let mut list = self.restrictions(key);
+        let before = list.len();
+        list.retain(|r| *r != restriction && r.split_whitespace().next() != Some(restriction.as_str()));
+        if list.len() == before {
+            return false;
+        }
+        self.inner.set_list(key, &list);
+        true
+    }
+
     // ── generation ─────────────────────────────────────────────────
 
     /// Generate a secure default main.cf matching what the bash install
@@ -223,6 +410,7 @@ impl PostfixConfig {
             cfg.entries.push(ConfigLine::KeyValue {
                 key: k.to_string(),
                 value: v.to_string(),
+                raw: None,
             });
         };
 
@@ -256,12 +444,12 @@ impl PostfixConfig {
         push_kv(
             &mut cfg,
             "smtpd_tls_cert_file",
-            &format!("/etc/letsencrypt/live/{}/fullchain.pem", hostname),
+            &acme::store::cert_path(hostname),
         );
         push_kv(
             &mut cfg,
             "smtpd_tls_key_file",
-            &format!("/etc/letsencrypt/live/{}/privkey.pem", hostname),
+            &acme::store::key_path(hostname),
         );
         push_kv(&mut cfg, "smtpd_use_tls", "yes");
         push_kv(&mut cfg, "smtpd_tls_auth_only", "yes");
@@ -281,11 +469,19 @@ impl PostfixConfig {
         push_kv(&mut cfg, "smtpd_sasl_type", "dovecot");
         push_kv(&mut cfg, "smtpd_sasl_path", "private/auth");
         push_kv(&mut cfg, "smtpd_sasl_auth_enable", "yes");
+        // Authenticated users may only use their own address, their aliases,
+        // and addresses they hold an explicit send-as grant for.
+        push_kv(
+            &mut cfg,
+            "smtpd_sender_login_maps",
+            "mysql:/etc/postfix/mysql-sender-login-maps.cf",
+        );
         push_blank(&mut cfg);
 
         // Recipient restrictions with DNSBL
         push_comment(&mut cfg, "# Recipient restrictions (including DNSBL)");
         let restrictions = [
+            "reject_authenticated_sender_login_mismatch",
             "permit_sasl_authenticated",
             "permit_mynetworks",
             "reject_unauth_destination",
@@ -343,6 +539,12 @@ impl PostfixConfig {
         push_kv(&mut cfg, "disable_vrfy_command", "yes");
         push_kv(&mut cfg, "message_size_limit", "52428800");
         push_kv(&mut cfg, "smtpd_banner", "$myhostname ESMTP");
+        push_blank(&mut cfg);
+
+        // Internationalized mail. Off by default: UTF-8 local parts bounce at
+        // any hop that lacks SMTPUTF8, so operators opt in explicitly.
+        push_comment(&mut cfg, "# Internationalized mail (RFC 6531)");
+        push_kv(&mut cfg, "smtputf8_enable", "no");
 
         Self { inner: cfg }
     }
@@ -378,6 +580,16 @@ impl PostfixConfig {
             warnings.push("smtpd_tls_key_file is not configured".to_string());
         }
 
+        if self.smtp_tls_security_level() == Some("dane")
+            && self.smtp_dns_support_level() != Some("dnssec")
+        {
+            warnings.push(
+                "smtp_tls_security_level is dane but smtp_dns_support_level is not dnssec - \
+                 DANE falls back to opportunistic TLS"
+                    .to_string(),
+            );
+        }
+
         // Check inet_interfaces
         if let Some(ifaces) = self.inet_interfaces() {
             if ifaces == "localhost" {
@@ -406,6 +618,22 @@ impl PostfixConfig {
             warnings.push("virtual_mailbox_maps is not configured".to_string());
         }
 
+        // Check sender ownership enforcement
+        if self.smtpd_sender_login_maps().is_none() {
+            warnings.push(
+                "smtpd_sender_login_maps not configured - users can send as any address"
+                    .to_string(),
+            );
+        } else if !self
+            .smtpd_recipient_restrictions()
+            .is_some_and(|r| r.contains("sender_login_mismatch"))
+        {
+            warnings.push(
+                "smtpd_sender_login_maps is set but sender login mismatches are not rejected"
+                    .to_string(),
+            );
+        }
+
         // Check milter for DKIM
         if self.smtpd_milters().is_none() {
             warnings.push("smtpd_milters not configured - DKIM signing may not work".to_string());
@@ -468,12 +696,38 @@ password = {}
 hosts = 127.0.0.1
 dbname = {}
 query = SELECT destination FROM virtual_aliases INNER JOIN virtual_domains ON virtual_aliases.domain_id = virtual_domains.id WHERE source='%s'
+",
+            db_user, db_password, db_name
+        )
+    }
+
+    /// Generate /etc/postfix/mysql-sender-login-maps.cf content.
+    ///
+    /// Maps a sender address to the SASL logins allowed to use it: the
+    /// mailbox owner, the destinations of an alias, and any users holding a
+    /// send-as grant in `sender_grants`.
+    pub fn generate_mysql_sender_login_maps(
+        db_user: &str,
+        db_password: &str,
+        db_name: &str,
+    ) -> String {
+        format!(
+            "\
+user = {}
+password = {}
+hosts = 127.0.0.1
+dbname = {}
+query = SELECT email FROM virtual_users WHERE email='%s' UNION SELECT destination FROM virtual_aliases WHERE source='%s' UNION SELECT virtual_users.email FROM sender_grants INNER JOIN virtual_users ON sender_grants.user_id = virtual_users.id WHERE sender_grants.sender='%s'
 ",
             db_user, db_password, db_name
         )
     }
 }
 
+fn normalize_restriction(restriction: &str) -> String {
+    restriction.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -527,6 +781,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_smtputf8_toggle() {
+        let mut cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
+        assert_eq!(cfg.smtputf8_enable(), Some("no"));
+        assert!(!cfg.smtputf8_enabled());
+        cfg.set_smtputf8_enable("yes");
+        assert!(cfg.smtputf8_enabled());
+        assert!(cfg.to_string().contains("smtputf8_enable = yes"));
+    }
+
     #[test]
     fn test_roundtrip() {
         let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
@@ -560,6 +824,80 @@ smtpd_tls_key_file = /etc/ssl/private/test.key
         assert_eq!(cfg.mydomain(), Some("newdomain.com"));
     }
 
+    #[test]
+    fn test_restriction_list_edits() {
+        let input = "\
+smtpd_recipient_restrictions =
+    permit_mynetworks,
+    permit_sasl_authenticated,
+    reject_unauth_destination,
+    reject_rbl_client zen.spamhaus.org
+smtpd_banner = $myhostname ESMTP
+";
+        let mut cfg = PostfixConfig::parse(input).unwrap();
+        let key = "smtpd_recipient_restrictions";
+        assert_eq!(
+            cfg.restrictions(key),
+            vec![
+                "permit_mynetworks",
+                "permit_sasl_authenticated",
+                "reject_unauth_destination",
+                "reject_rbl_client zen.spamhaus.org",
+            ]
+        );
+        // Unedited continuation lines are written back untouched
+        assert_eq!(cfg.to_string(), input);
+
+        assert!(cfg.add_restriction(
+            key,
+            "reject_authenticated_sender_login_mismatch",
+            Some("permit_mynetworks"),
+        ));
+        assert!(!cfg.add_restriction(key, "reject_rbl_client  zen.spamhaus.org", None));
+        assert!(cfg.add_restriction(key, "reject_rbl_client bl.spamcop.net", None));
+        assert!(cfg.remove_restriction(key, "permit_sasl_authenticated"));
+        assert!(!cfg.remove_restriction(key, "permit_sasl_authenticated"));
+
+        assert_eq!(
+            cfg.to_string(),
+            "\
+smtpd_recipient_restrictions =
+    reject_authenticated_sender_login_mismatch,
+    permit_mynetworks,
+    reject_unauth_destination,
+    reject_rbl_client zen.spamhaus.org,
+    reject_rbl_client bl.spamcop.net
+smtpd_banner = $myhostname ESMTP
+"
+        );
+
+        // A bare name removes every entry of that kind
+        assert!(cfg.remove_restriction(key, "reject_rbl_client"));
+        assert!(!cfg.restrictions(key).iter().any(|r| r.contains("rbl")));
+    }
+
+    #[test]
+    fn test_restriction_edit_on_single_line() {
+        let mut cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
+        let key = "smtpd_recipient_restrictions";
+        assert!(cfg.remove_restriction(key, "reject_rbl_client b.barracudacentral.org"));
+        let value = cfg.smtpd_recipient_restrictions().unwrap();
+        assert!(value.ends_with("reject_rbl_client bl.spamcop.net"));
+        assert!(!value.contains('\n'));
+        assert!(value.contains("zen.spamhaus.org"));
+    }
+
+    #[test]
+    fn test_get_expanded() {
+        let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
+        assert_eq!(cfg.myorigin(), Some("$mydomain"));
+        assert_eq!(cfg.get_expanded("myorigin").as_deref(), Some("example.com"));
+        assert_eq!(
+            cfg.get_expanded("smtpd_banner").as_deref(),
+            Some("mail.example.com ESMTP")
+        );
+    }
+
     #[test]
     fn test_validate_good_config() {
         let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
@@ -637,6 +975,35 @@ smtpd_milters = inet:localhost:8891
         assert!(output.contains("'%s'"));
     }
 
+    #[test]
+    fn test_mysql_sender_login_maps() {
+        let output =
+            PostfixConfig::generate_mysql_sender_login_maps("mailuser", "secret", "mailserver");
+        assert!(output.contains("dbname = mailserver"));
+        assert!(output.contains("virtual_users"));
+        assert!(output.contains("virtual_aliases"));
+        assert!(output.contains("sender_grants"));
+        assert_eq!(output.matches("'%s'").count(), 3);
+    }
+
+    #[test]
+    fn test_sender_login_enforcement() {
+        let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
+        assert!(cfg
+            .smtpd_sender_login_maps()
+            .unwrap()
+            .contains("mysql-sender-login-maps.cf"));
+        let restrictions = cfg.smtpd_recipient_restrictions().unwrap();
+        assert!(restrictions.starts_with("reject_authenticated_sender_login_mismatch"));
+
+        let mut cfg = cfg;
+        cfg.set_smtpd_recipient_restrictions(
+            "permit_sasl_authenticated, reject_unauth_destination, reject_rbl_client zen.spamhaus.org",
+        );
+        let warnings = cfg.validate().unwrap();
+        assert!(warnings.iter().any(|w| w.contains("sender login mismatches")));
+    }
+
     #[test]
     fn test_tls_hardening() {
         let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
@@ -648,10 +1015,48 @@ smtpd_milters = inet:localhost:8891
         assert!(protocols.contains("!TLSv1.1"));
     }
 
+    #[test]
+    fn test_outbound_dane() {
+        let mut cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
+        assert!(!cfg.outbound_dane_enabled());
+        assert!(cfg.set_outbound_dane(true));
+        assert!(!cfg.set_outbound_dane(true));
+        assert!(cfg.outbound_dane_enabled());
+        assert_eq!(cfg.smtp_tls_security_level(), Some("dane"));
+        assert_eq!(cfg.smtp_dns_support_level(), Some("dnssec"));
+        assert!(!cfg.validate().unwrap().iter().any(|w| w.contains("dane")));
+
+        assert!(cfg.set_outbound_dane(false));
+        assert_eq!(cfg.smtp_tls_security_level(), Some("may"));
+        assert!(!cfg.outbound_dane_enabled());
+
+        // dane without DNSSEC lookups silently degrades
+        cfg.set_smtp_tls_security_level("dane");
+        cfg.set_smtp_dns_support_level("enabled");
+        assert!(!cfg.outbound_dane_enabled());
+        assert!(cfg.validate().unwrap().iter().any(|w| w.contains("smtp_dns_support_level")));
+    }
+
     #[test]
     fn test_misc_hardening() {
         let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
         assert_eq!(cfg.get("smtpd_helo_required"), Some("yes"));
         assert_eq!(cfg.get("disable_vrfy_command"), Some("yes"));
     }
+
+    #[test]
+    fn test_add_milter() {
+        let mut cfg = PostfixConfig::parse("myhostname = mail.example.com\n").unwrap();
+        assert!(cfg.add_milter("inet:localhost:8891"));
+        assert_eq!(cfg.smtpd_milters(), Some("inet:localhost:8891"));
+        assert_eq!(cfg.non_smtpd_milters(), Some("inet:localhost:8891"));
+        assert_eq!(cfg.get("milter_default_action"), Some("accept"));
+        assert!(!cfg.add_milter("inet:localhost:8891"));
+
+        assert!(cfg.add_milter("inet:localhost:11332"));
+        assert_eq!(
+            cfg.smtpd_milters(),
+            Some("inet:localhost:8891 inet:localhost:11332")
+        );
+    }
 }
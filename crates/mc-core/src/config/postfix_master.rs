@@ -0,0 +1,899 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till1, take_until},
+    character::complete::{char, line_ending, multispace0, not_line_ending, satisfy, space0, space1},
+    combinator::{eof, peek, recognize},
+    multi::{many0, many1},
+    sequence::{preceded, terminated, tuple},
+    IResult,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Postfix master.cf model.
+///
+/// master.cf is columnar: eight fields per service, with arguments (most
+/// often `-o name=value` overrides) on indented continuation lines. Entries
+/// that are not edited are written back exactly as they were read, so a
+/// parse/serialize cycle leaves the file byte-for-byte unchanged; edited
+/// entries are re-rendered in the usual aligned layout.
+#[derive(Debug, Clone)]
+pub struct MasterCf {
+    entries: Vec<MasterEntry>,
+    trailing_newline: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum MasterEntry {
+    /// A comment line, verbatim (including leading whitespace and `#`).
+    Comment(String),
+    /// An empty or whitespace-only line, verbatim.
+    Blank(String),
+    Service(Box<MasterService>),
+}
+
+/// Errors specific to master.cf handling.
+#[derive(Debug)]
+pub enum MasterCfError {
+    ParseError { line: usize, message: String },
+    InvalidField(String),
+    ServiceNotFound(String),
+}
+
+impl fmt::Display for MasterCfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParseError { line, message } => {
+                write!(f, "master.cf parse error at line {}: {}", line, message)
+            }
+            Self::InvalidField(msg) => write!(f, "Invalid master.cf field: {}", msg),
+            Self::ServiceNotFound(name) => write!(f, "master.cf service not found: {}", name),
+        }
+    }
+}
+
+impl std::error::Error for MasterCfError {}
+
+/// The second master.cf column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ServiceType {
+    Inet,
+    Unix,
+    UnixDgram,
+    Fifo,
+    Pass,
+}
+
+impl ServiceType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Inet => "inet",
+            Self::Unix => "unix",
+            Self::UnixDgram => "unix-dgram",
+            Self::Fifo => "fifo",
+            Self::Pass => "pass",
+        }
+    }
+}
+
+impl fmt::Display for ServiceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ServiceType {
+    type Err = MasterCfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inet" => Ok(Self::Inet),
+            "unix" => Ok(Self::Unix),
+            "unix-dgram" => Ok(Self::UnixDgram),
+            "fifo" => Ok(Self::Fifo),
+            "pass" => Ok(Self::Pass),
+            other => Err(MasterCfError::InvalidField(format!(
+                "unknown service type '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// One master.cf service entry (a logical line).
+///
+/// The flag and limit columns are kept as written, so `-` (use the
+/// built-in default) survives a round trip.
+#[derive(Debug, Clone)]
+pub struct MasterService {
+    pub name: String,
+    pub service_type: ServiceType,
+    pub private: String,
+    pub unprivileged: String,
+    pub chroot: String,
+    pub wakeup: String,
+    pub process_limit: String,
+    pub command: String,
+    /// Everything after the command name, tokenized. `{ ... }` groups are
+    /// kept as a single token.
+    pub args: Vec<String>,
+    /// The text this entry was parsed from, with the fields it produced.
+    source: Option<(String, Box<MasterService>)>,
+}
+
+impl MasterService {
+    pub fn new(name: &str, service_type: ServiceType, command: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            service_type,
+            private: "-".to_string(),
+            unprivileged: "-".to_string(),
+            chroot: "-".to_string(),
+            wakeup: "-".to_string(),
+            process_limit: "-".to_string(),
+            command: command.to_string(),
+            args: Vec::new(),
+            source: None,
+        }
+    }
+
+    /// Set the private/unpriv/chroot/wakeup/maxproc columns in one go.
+    pub fn with_columns(
+        mut self,
+        private: &str,
+        unprivileged: &str,
+        chroot: &str,
+        wakeup: &str,
+        process_limit: &str,
+    ) -> Self {
+        self.private = private.to_string();
+        self.unprivileged = unprivileged.to_string();
+        self.chroot = chroot.to_string();
+        self.wakeup = wakeup.to_string();
+        self.process_limit = process_limit.to_string();
+        self
+    }
+
+    /// `name/type`, the key `postconf -M` uses.
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.name, self.service_type)
+    }
+
+    /// Number of leading daemon options (`-o x=y`, `-v`, ...) in `args`.
+    /// Option parsing stops at the first non-option, as getopt does, so
+    /// `-oi` inside a pipe `argv=` is not mistaken for an override.
+    fn options_end(&self) -> usize {
+        let mut i = 0;
+        while i < self.args.len() {
+            let arg = &self.args[i];
+            if arg == "-o" {
+                i += 2;
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        i.min(self.args.len())
+    }
+
+    /// Indices and parsed `(name, value)` of each `-o` override.
+    fn override_positions(&self) -> Vec<(usize, usize, String, String)> {
+        let end = self.options_end();
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < end {
+            let arg = &self.args[i];
+            let (span, text) = if arg == "-o" {
+                match self.args.get(i + 1) {
+                    Some(next) => (2, next.as_str()),
+                    None => break,
+                }
+            } else if let Some(attached) = arg.strip_prefix("-o") {
+                (1, attached)
+            } else {
+                i += 1;
+                continue;
+            };
+            if let Some((name, value)) = split_override(text) {
+                out.push((i, span, name, value));
+            }
+            i += span;
+        }
+        out
+    }
+
+    /// All `-o name=value` overrides, in order.
+    pub fn overrides(&self) -> Vec<(String, String)> {
+        self.override_positions()
+            .into_iter()
+            .map(|(_, _, name, value)| (name, value))
+            .collect()
+    }
+
+    pub fn get_override(&self, name: &str) -> Option<String> {
+        self.override_positions()
+            .into_iter()
+            .find(|(_, _, n, _)| n == name)
+            .map(|(_, _, _, value)| value)
+    }
+
+    /// Set an override, replacing an existing one in place or appending
+    /// after the last daemon option.
+    pub fn set_override(&mut self, name: &str, value: &str) {
+        let token = if value.chars().any(char::is_whitespace) {
+            format!("{{ {} = {} }}", name, value)
+        } else {
+            format!("{}={}", name, value)
+        };
+        let existing = self
+            .override_positions()
+            .into_iter()
+            .find(|(_, _, n, _)| n == name);
+        match existing {
+            Some((index, span, _, _)) => {
+                self.args
+                    .splice(index..index + span, ["-o".to_string(), token]);
+            }
+            None => {
+                let end = self.options_end();
+                self.args.splice(end..end, ["-o".to_string(), token]);
+            }
+        }
+    }
+
+    /// Remove an override. Returns whether one was present.
+    pub fn remove_override(&mut self, name: &str) -> bool {
+        let positions: Vec<_> = self
+            .override_positions()
+            .into_iter()
+            .filter(|(_, _, n, _)| n == name)
+            .collect();
+        for (index, span, _, _) in positions.iter().rev() {
+            self.args.drain(*index..*index + *span);
+        }
+        !positions.is_empty()
+    }
+
+    /// The columns after the service type, as one line (the value side of
+    /// [`MasterCf::to_map`]).
+    pub fn definition(&self) -> String {
+        let mut parts = vec![
+            self.private.as_str(),
+            self.unprivileged.as_str(),
+            self.chroot.as_str(),
+            self.wakeup.as_str(),
+            self.process_limit.as_str(),
+            self.command.as_str(),
+        ];
+        parts.extend(self.args.iter().map(String::as_str));
+        parts.join(" ")
+    }
+
+    fn same_fields(&self, other: &MasterService) -> bool {
+        self.name == other.name
+            && self.service_type == other.service_type
+            && self.private == other.private
+            && self.unprivileged == other.unprivileged
+            && self.chroot == other.chroot
+            && self.wakeup == other.wakeup
+            && self.process_limit == other.process_limit
+            && self.command == other.command
+            && self.args == other.args
+    }
+
+    fn serialize_into(&self, out: &mut String) {
+        if let Some((raw, parsed)) = &self.source {
+            if self.same_fields(parsed) {
+                out.push_str(raw);
+                return;
+            }
+        }
+        out.push_str(&format!(
+            "{:<9} {:<5} {:<7} {:<7} {:<7} {:<7} {:<7} {}",
+            self.name,
+            self.service_type.as_str(),
+            self.private,
+            self.unprivileged,
+            self.chroot,
+            self.wakeup,
+            self.process_limit,
+            self.command
+        ));
+        let end = self.options_end();
+        let mut i = 0;
+        while i < end {
+            if self.args[i] == "-o" && i + 1 < end {
+                out.push_str(&format!("\n  -o {}", self.args[i + 1]));
+                i += 2;
+            } else {
+                out.push_str(&format!("\n  {}", self.args[i]));
+                i += 1;
+            }
+        }
+        if end < self.args.len() {
+            out.push_str("\n  ");
+            out.push_str(&self.args[end..].join(" "));
+        }
+        out.push('\n');
+    }
+
+    fn validate_fields(&self) -> Result<(), MasterCfError> {
+        let invalid = |what: &str, value: &str| {
+            Err(MasterCfError::InvalidField(format!(
+                "{} '{}' for service {}",
+                what,
+                value,
+                self.key()
+            )))
+        };
+        if self.name.is_empty() || self.name.starts_with('#') || self.name.contains(char::is_whitespace) {
+            return invalid("name", &self.name);
+        }
+        for (what, value) in [
+            ("private", &self.private),
+            ("unpriv", &self.unprivileged),
+            ("chroot", &self.chroot),
+        ] {
+            if !matches!(value.as_str(), "y" | "n" | "-") {
+                return invalid(what, value);
+            }
+        }
+        let wakeup = self.wakeup.trim_end_matches('?');
+        if self.wakeup != "-" && (wakeup.is_empty() || !wakeup.chars().all(|c| c.is_ascii_digit())) {
+            return invalid("wakeup", &self.wakeup);
+        }
+        if self.process_limit != "-" && !self.process_limit.chars().all(|c| c.is_ascii_digit()) {
+            return invalid("maxproc", &self.process_limit);
+        }
+        if self.command.is_empty() || self.command.contains(char::is_whitespace) {
+            return invalid("command", &self.command);
+        }
+        if self.args.iter().any(|a| a.contains(['\n', '\r'])) {
+            return invalid("argument", "<newline>");
+        }
+        Ok(())
+    }
+}
+
+/// Split `name=value` or `{ name = value }`.
+fn split_override(text: &str) -> Option<(String, String)> {
+    let text = text
+        .strip_prefix('{')
+        .and_then(|t| t.strip_suffix('}'))
+        .unwrap_or(text);
+    let (name, value) = text.split_once('=')?;
+    Some((name.trim().to_string(), value.trim().to_string()))
+}
+
+impl MasterCf {
+    /// Parse master.cf content.
+    pub fn parse(input: &str) -> Result<Self, MasterCfError> {
+        let trailing_newline = input.is_empty() || input.ends_with('\n');
+        let owned;
+        let mut rest = if trailing_newline {
+            input
+        } else {
+            owned = format!("{}\n", input);
+            owned.as_str()
+        };
+
+        let mut entries = Vec::new();
+        let mut line = 1;
+        while !rest.is_empty() {
+            let (next, entry) = master_entry(rest).map_err(|e| MasterCfError::ParseError {
+                line,
+                message: e.to_string(),
+            })?;
+            let consumed = &rest[..rest.len() - next.len()];
+            let entry = match entry {
+                RawEntry::Comment(text) => MasterEntry::Comment(text.to_string()),
+                RawEntry::Blank(text) => MasterEntry::Blank(text.to_string()),
+                RawEntry::Service(raw) => {
+                    let service = parse_service(raw).map_err(|message| {
+                        MasterCfError::ParseError { line, message }
+                    })?;
+                    MasterEntry::Service(Box::new(service))
+                }
+            };
+            entries.push(entry);
+            line += consumed.matches('\n').count();
+            rest = next;
+        }
+
+        Ok(Self {
+            entries,
+            trailing_newline,
+        })
+    }
+
+    /// Serialize back to master.cf format.
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        for entry in &self.entries {
+            match entry {
+                MasterEntry::Comment(text) | MasterEntry::Blank(text) => {
+                    out.push_str(text);
+                    out.push('\n');
+                }
+                MasterEntry::Service(service) => service.serialize_into(&mut out),
+            }
+        }
+        if !self.trailing_newline {
+            out.pop();
+        }
+        out
+    }
+
+    pub fn entries(&self) -> &[MasterEntry] {
+        &self.entries
+    }
+
+    pub fn services(&self) -> impl Iterator<Item = &MasterService> {
+        self.entries.iter().filter_map(|e| match e {
+            MasterEntry::Service(s) => Some(s.as_ref()),
+            _ => None,
+        })
+    }
+
+    pub fn get(&self, name: &str, service_type: ServiceType) -> Option<&MasterService> {
+        self.services()
+            .find(|s| s.name == name && s.service_type == service_type)
+    }
+
+    pub fn get_mut(&mut self, name: &str, service_type: ServiceType) -> Option<&mut MasterService> {
+        self.entries.iter_mut().find_map(|e| match e {
+            MasterEntry::Service(s) if s.name == name && s.service_type == service_type => {
+                Some(s.as_mut())
+            }
+            _ => None,
+        })
+    }
+
+    fn get_mut_or_err(
+        &mut self,
+        name: &str,
+        service_type: ServiceType,
+    ) -> Result<&mut MasterService, MasterCfError> {
+        self.get_mut(name, service_type)
+            .ok_or_else(|| MasterCfError::ServiceNotFound(format!("{}/{}", name, service_type)))
+    }
+
+    /// Add a service, or replace the existing entry with the same name and
+    /// type in place.
+    pub fn upsert(&mut self, service: MasterService) -> Result<(), MasterCfError> {
+        service.validate_fields()?;
+        match self.get_mut(&service.name, service.service_type) {
+            Some(existing) => {
+                // Keep the original text so an identical upsert is a no-op
+                let source = existing.source.take();
+                *existing = MasterService { source, ..service };
+            }
+            None => self.entries.push(MasterEntry::Service(Box::new(service))),
+        }
+        Ok(())
+    }
+
+    /// Remove a service. Returns whether it was present.
+    pub fn remove(&mut self, name: &str, service_type: ServiceType) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| {
+            !matches!(e, MasterEntry::Service(s) if s.name == name && s.service_type == service_type)
+        });
+        self.entries.len() != before
+    }
+
+    /// Service definitions keyed by `name/type`, values as in
+    /// [`MasterService::definition`].
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.services()
+            .map(|s| (s.key(), s.definition()))
+            .collect()
+    }
+
+    /// Set a service from its `name/type` key and a definition line such
+    /// as `n - y - - smtpd -o smtpd_tls_wrappermode=yes`.
+    pub fn set_definition(&mut self, key: &str, definition: &str) -> Result<(), MasterCfError> {
+        let (name, service_type) = key
+            .split_once('/')
+            .ok_or_else(|| MasterCfError::InvalidField(format!("service key '{}'", key)))?;
+        let line = format!("{} {} {}", name, service_type, definition);
+        if line.contains(['\n', '\r']) {
+            return Err(MasterCfError::InvalidField(format!(
+                "definition for {} spans lines",
+                key
+            )));
+        }
+        let mut service = parse_service(&line).map_err(MasterCfError::InvalidField)?;
+        service.source = None;
+        self.upsert(service)
+    }
+
+    pub fn set_override(
+        &mut self,
+        name: &str,
+        service_type: ServiceType,
+        key: &str,
+        value: &str,
+    ) -> Result<(), MasterCfError> {
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == '=') {
+            return Err(MasterCfError::InvalidField(format!("override name '{}'", key)));
+        }
+        if value.contains(['\n', '\r', '{', '}']) {
+            return Err(MasterCfError::InvalidField(format!("override value for {}", key)));
+        }
+        self.get_mut_or_err(name, service_type)?.set_override(key, value);
+        Ok(())
+    }
+
+    pub fn remove_override(
+        &mut self,
+        name: &str,
+        service_type: ServiceType,
+        key: &str,
+    ) -> Result<bool, MasterCfError> {
+        Ok(self.get_mut_or_err(name, service_type)?.remove_override(key))
+    }
+
+    /// Enable the submission service (port 587, STARTTLS required, SASL
+    /// only). Returns `false` if it was already enabled.
+    pub fn enable_submission(&mut self) -> bool {
+        self.add_client_service(
+            "submission",
+            "# Submission port (587) — email clients with STARTTLS",
+            &[
+                ("syslog_name", "postfix/submission"),
+                ("smtpd_tls_security_level", "encrypt"),
+                ("smtpd_tls_wrappermode", "no"),
+            ],
+        )
+    }
+
+    /// Enable the smtps service (port 465, implicit TLS, SASL only).
+    /// Returns `false` if it was already enabled.
+    pub fn enable_smtps(&mut self) -> bool {
+        self.add_client_service(
+            "smtps",
+            "# SMTPS port (465) — email clients with implicit TLS",
+            &[
+                ("syslog_name", "postfix/smtps"),
+                ("smtpd_tls_wrappermode", "yes"),
+            ],
+        )
+    }
+
+    fn add_client_service(&mut self, name: &str, comment: &str, transport: &[(&str, &str)]) -> bool {
+        if self.get(name, ServiceType::Inet).is_some() {
+            return false;
+        }
+        let mut service = MasterService::new(name, ServiceType::Inet, "smtpd")
+            .with_columns("n", "-", "y", "-", "-");
+        let common = [
+            ("smtpd_sasl_auth_enable", "yes"),
+            ("smtpd_relay_restrictions", "permit_sasl_authenticated,reject"),
+            ("smtpd_recipient_restrictions", "permit_sasl_authenticated,reject"),
+            ("smtpd_client_restrictions", "permit_sasl_authenticated,reject"),
+            ("milter_macro_daemon_name", "ORIGINATING"),
+        ];
+        for (key, value) in transport.iter().chain(common.iter()) {
+            service.set_override(key, value);
+        }
+        if !matches!(self.entries.last(), None | Some(MasterEntry::Blank(_))) {
+            self.entries.push(MasterEntry::Blank(String::new()));
+        }
+        self.entries.push(MasterEntry::Comment(comment.to_string()));
+        self.entries.push(MasterEntry::Service(Box::new(service)));
+        true
+    }
+
+    /// Add (or replace) a `pipe(8)` transport, e.g. for a content filter.
+    /// `argv` is the command line the pipe runs, `${sender}`-style macros
+    /// included.
+    pub fn add_pipe_transport(
+        &mut self,
+        name: &str,
+        user: &str,
+        argv: &str,
+    ) -> Result<(), MasterCfError> {
+        if user.is_empty() || user.contains(char::is_whitespace) {
+            return Err(MasterCfError::InvalidField(format!("pipe user '{}'", user)));
+        }
+        let mut argv_tokens = argv.split_whitespace();
+        let program = argv_tokens
+            .next()
+            .ok_or_else(|| MasterCfError::InvalidField("empty pipe argv".to_string()))?;
+        let mut service = MasterService::new(name, ServiceType::Unix, "pipe")
+            .with_columns("-", "n", "n", "-", "-");
+        service.args.push(format!("user={}", user));
+        service.args.push(format!("argv={}", program));
+        service.args.extend(argv_tokens.map(str::to_string));
+        self.upsert(service)
+    }
+
+    /// Route mail received by `service` through the `filter` transport.
+    pub fn set_content_filter(
+        &mut self,
+        name: &str,
+        service_type: ServiceType,
+        filter: &str,
+    ) -> Result<(), MasterCfError> {
+        if self.get(filter, ServiceType::Unix).is_none() {
+            return Err(MasterCfError::ServiceNotFound(format!("{}/unix", filter)));
+        }
+        self.set_override(name, service_type, "content_filter", filter)
+    }
+
+    /// Pass inbound SMTP mail through SpamAssassin via `spamc`.
+    pub fn enable_spamassassin_filter(&mut self) -> Result<(), MasterCfError> {
+        self.add_pipe_transport(
+            "spamassassin",
+            "debian-spamd",
+            "/usr/bin/spamc -f -e /usr/sbin/sendmail -oi -f ${sender} ${recipient}",
+        )?;
+        self.set_content_filter("smtp", ServiceType::Inet, "spamassassin")
+    }
+}
+
+// nom parsers
+
+enum RawEntry<'a> {
+    Comment(&'a str),
+    Blank(&'a str),
+    Service(&'a str),
+}
+
+fn comment_text(input: &str) -> IResult<&str, &str> {
+    terminated(recognize(tuple((space0, char('#'), not_line_ending))), line_ending)(input)
+}
+
+fn blank_text(input: &str) -> IResult<&str, &str> {
+    terminated(space0, line_ending)(input)
+}
+
+/// An indented, non-comment line that continues the previous logical line.
+fn continuation(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        space1,
+        peek(satisfy(|c| c != '#' && c != '\n' && c != '\r')),
+        not_line_ending,
+        line_ending,
+    )))(input)
+}
+
+/// A logical line: the service line, its continuations, and any comment
+/// or blank lines Postfix skips between them.
+fn service_block(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        peek(satisfy(|c| !c.is_whitespace() && c != '#')),
+        not_line_ending,
+        line_ending,
+        many0(preceded(
+            many0(alt((comment_text, blank_text))),
+            continuation,
+        )),
+    )))(input)
+}
+
+fn master_entry(input: &str) -> IResult<&str, RawEntry<'_>> {
+    alt((
+        |i| comment_text(i).map(|(r, t)| (r, RawEntry::Comment(t))),
+        |i| blank_text(i).map(|(r, t)| (r, RawEntry::Blank(t))),
+        |i| service_block(i).map(|(r, t)| (r, RawEntry::Service(t))),
+    ))(input)
+}
+
+/// One whitespace-separated field, or a `{ ... }` group.
+fn token(input: &str) -> IResult<&str, &str> {
+    alt((
+        recognize(tuple((char('{'), take_until("}"), tag("}")))),
+        take_till1(|c: char| c.is_whitespace()),
+    ))(input)
+}
+
+fn tokens(input: &str) -> IResult<&str, Vec<&str>> {
+    terminated(many1(preceded(multispace0, token)), tuple((multispace0, eof)))(input)
+}
+
+fn parse_service(raw: &str) -> Result<MasterService, String> {
+    let logical: Vec<&str> = raw
+        .lines()
+        .filter(|l| {
+            let t = l.trim_start();
+            !t.is_empty() && !t.starts_with('#')
+        })
+        .collect();
+    let logical = logical.join(" ");
+    let (_, fields) = tokens(&logical).map_err(|e| format!("cannot tokenize entry: {}", e))?;
+    if fields.len() < 8 {
+        return Err(format!(
+            "expected at least 8 fields, found {} in '{}'",
+            fields.len(),
+            logical.trim()
+        ));
+    }
+    let service_type = fields[1].parse::<ServiceType>().map_err(|e| e.to_string())?;
+    let mut service = MasterService {
+        name: fields[0].to_string(),
+        service_type,
+        private: fields[2].to_string(),
+        unprivileged: fields[3].to_string(),
+        chroot: fields[4].to_string(),
+        wakeup: fields[5].to_string(),
+        process_limit: fields[6].to_string(),
+        command: fields[7].to_string(),
+        args: fields[8..].iter().map(|s| s.to_string()).collect(),
+        source: None,
+    };
+    service.validate_fields().map_err(|e| e.to_string())?;
+    service.source = Some((raw.to_string(), Box::new(service.clone())));
+    Ok(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+# Postfix master process configuration file.
+# ==========================================================================
+# service type  private unpriv  chroot  wakeup  maxproc command + args
+# ==========================================================================
+smtp      inet  n       -       y       -       -       smtpd
+#submission inet n       -       y       -       -       smtpd
+#  -o syslog_name=postfix/submission
+pickup    unix  n       -       y       60      1       pickup
+tlsmgr    unix  -       -       y       1000?   1       tlsmgr
+relay     unix  -       -       y       -       -       smtp
+        -o syslog_name=postfix/$service_name
+#       -o smtp_helo_timeout=5 -o smtp_connect_timeout=5
+        -o smtp_bind_address=192.0.2.1
+postlog   unix-dgram n  -       n       -       1       postlogd
+
+dovecot   unix  -       n       n       -       -       pipe
+  flags=DRhu user=vmail:vmail argv=/usr/lib/dovecot/deliver -f ${sender} -d ${recipient}
+";
+
+    #[test]
+    fn test_roundtrip_is_lossless() {
+        let cf = MasterCf::parse(SAMPLE).unwrap();
+        assert_eq!(cf.serialize(), SAMPLE);
+
+        let no_newline = "smtp inet n - y - - smtpd\n  -o a=b";
+        assert_eq!(MasterCf::parse(no_newline).unwrap().serialize(), no_newline);
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let cf = MasterCf::parse(SAMPLE).unwrap();
+        assert_eq!(cf.services().count(), 6);
+
+        let tlsmgr = cf.get("tlsmgr", ServiceType::Unix).unwrap();
+        assert_eq!(tlsmgr.wakeup, "1000?");
+        assert_eq!(tlsmgr.process_limit, "1");
+
+        // Continuations across an interleaved comment belong to the entry
+        let relay = cf.get("relay", ServiceType::Unix).unwrap();
+        assert_eq!(
+            relay.overrides(),
+            vec![
+                ("syslog_name".to_string(), "postfix/$service_name".to_string()),
+                ("smtp_bind_address".to_string(), "192.0.2.1".to_string()),
+            ]
+        );
+
+        assert!(cf.get("postlog", ServiceType::UnixDgram).is_some());
+        // Commented-out services are comments, not entries
+        assert!(cf.get("submission", ServiceType::Inet).is_none());
+    }
+
+    #[test]
+    fn test_pipe_argv_is_not_an_override() {
+        let cf = MasterCf::parse(SAMPLE).unwrap();
+        let dovecot = cf.get("dovecot", ServiceType::Unix).unwrap();
+        assert!(dovecot.overrides().is_empty());
+        assert_eq!(dovecot.args[0], "flags=DRhu");
+    }
+
+    #[test]
+    fn test_edit_only_rerenders_changed_entry() {
+        let mut cf = MasterCf::parse(SAMPLE).unwrap();
+        cf.set_override("smtp", ServiceType::Inet, "smtpd_tls_security_level", "may")
+            .unwrap();
+        let out = cf.serialize();
+        assert!(out.contains(
+            "smtp      inet  n       -       y       -       -       smtpd\n  -o smtpd_tls_security_level=may\n"
+        ));
+        // Untouched entries keep their original layout
+        assert!(out.contains("        -o syslog_name=postfix/$service_name\n"));
+
+        cf.set_override("smtp", ServiceType::Inet, "smtpd_tls_security_level", "encrypt")
+            .unwrap();
+        let smtp = cf.get("smtp", ServiceType::Inet).unwrap();
+        assert_eq!(smtp.overrides().len(), 1);
+        assert_eq!(smtp.get_override("smtpd_tls_security_level").as_deref(), Some("encrypt"));
+
+        assert!(cf.remove_override("smtp", ServiceType::Inet, "smtpd_tls_security_level").unwrap());
+        assert_eq!(cf.serialize(), SAMPLE);
+    }
+
+    #[test]
+    fn test_braced_override() {
+        let input = "smtp inet n - y - - smtpd\n  -o { smtpd_client_restrictions = permit_mynetworks, reject }\n";
+        let mut cf = MasterCf::parse(input).unwrap();
+        let smtp = cf.get("smtp", ServiceType::Inet).unwrap();
+        assert_eq!(
+            smtp.get_override("smtpd_client_restrictions").as_deref(),
+            Some("permit_mynetworks, reject")
+        );
+        cf.set_override("smtp", ServiceType::Inet, "smtpd_helo_restrictions", "permit_mynetworks, reject")
+            .unwrap();
+        assert!(cf
+            .serialize()
+            .contains("  -o { smtpd_helo_restrictions = permit_mynetworks, reject }\n"));
+    }
+
+    #[test]
+    fn test_enable_submission_and_smtps() {
+        let mut cf = MasterCf::parse(SAMPLE).unwrap();
+        assert!(cf.enable_submission());
+        assert!(!cf.enable_submission());
+        assert!(cf.enable_smtps());
+
+        let reparsed = MasterCf::parse(&cf.serialize()).unwrap();
+        let submission = reparsed.get("submission", ServiceType::Inet).unwrap();
+        assert_eq!(submission.command, "smtpd");
+        assert_eq!(
+            submission.get_override("smtpd_tls_security_level").as_deref(),
+            Some("encrypt")
+        );
+        let smtps = reparsed.get("smtps", ServiceType::Inet).unwrap();
+        assert_eq!(smtps.get_override("smtpd_tls_wrappermode").as_deref(), Some("yes"));
+        assert_eq!(
+            smtps.get_override("smtpd_sasl_auth_enable").as_deref(),
+            Some("yes")
+        );
+    }
+
+    #[test]
+    fn test_spamassassin_content_filter() {
+        let mut cf = MasterCf::parse(SAMPLE).unwrap();
+        assert!(matches!(
+            cf.set_content_filter("smtp", ServiceType::Inet, "spamassassin"),
+            Err(MasterCfError::ServiceNotFound(_))
+        ));
+        cf.enable_spamassassin_filter().unwrap();
+
+        let reparsed = MasterCf::parse(&cf.serialize()).unwrap();
+        let smtp = reparsed.get("smtp", ServiceType::Inet).unwrap();
+        assert_eq!(smtp.get_override("content_filter").as_deref(), Some("spamassassin"));
+        let pipe = reparsed.get("spamassassin", ServiceType::Unix).unwrap();
+        assert_eq!(pipe.command, "pipe");
+        assert!(pipe.overrides().is_empty());
+        assert!(pipe.args.contains(&"argv=/usr/bin/spamc".to_string()));
+    }
+
+    #[test]
+    fn test_map_and_set_definition() {
+        let mut cf = MasterCf::parse(SAMPLE).unwrap();
+        let map = cf.to_map();
+        assert_eq!(map.get("pickup/unix").map(String::as_str), Some("n - y 60 1 pickup"));
+
+        cf.set_definition("pickup/unix", "n - y 60 1 pickup").unwrap();
+        assert_eq!(cf.serialize(), SAMPLE);
+
+        cf.set_definition("pickup/unix", "n - n 60 1 pickup").unwrap();
+        assert_eq!(cf.get("pickup", ServiceType::Unix).unwrap().chroot, "n");
+        assert!(cf.set_definition("pickup/unix", "maybe - n 60 1 pickup").is_err());
+        assert!(cf.set_definition("pickup", "n - n 60 1 pickup").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors_report_line() {
+        let err = MasterCf::parse("# header\nsmtp inet n - y\n").unwrap_err();
+        assert!(matches!(err, MasterCfError::ParseError { line: 2, .. }));
+
+        let err = MasterCf::parse("smtp socket n - y - - smtpd\n").unwrap_err();
+        assert!(err.to_string().contains("unknown service type"));
+    }
+}
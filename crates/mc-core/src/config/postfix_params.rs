@@ -0,0 +1,415 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::fmt;
+
+use super::parser::split_list;
+
+/// The kind of value a Postfix parameter takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "values", rename_all = "snake_case")]
+pub enum ParamType {
+    /// `yes` or `no`.
+    Boolean,
+    /// A number with an optional unit: `s`, `m`, `h`, `d` or `w`.
+    Time,
+    /// A byte count.
+    Size,
+    /// A non-negative integer.
+    Integer,
+    /// Comma- or whitespace-separated values.
+    List,
+    /// One or more `type:name` lookup tables.
+    LookupTable,
+    /// Domain names, `/file/name` patterns and `type:name` lookup tables,
+    /// mixed freely (`virtual_mailbox_domains` and the like).
+    DomainList,
+    /// An email address or local part; may be empty.
+    Address,
+    /// A host or domain name, optionally `[bracketed]` and with `:port`.
+    Hostname,
+    /// An absolute path.
+    Path,
+    /// One of a fixed set of words.
+    Enum(&'static [&'static str]),
+    /// Free text.
+    Text,
+}
+
+/// One entry of the parameter catalogue.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub kind: ParamType,
+    /// Postfix's built-in default, as `postconf -d` prints it.
+    pub default: &'static str,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    Unknown(String),
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "Unknown Postfix parameter: {}", name),
+            Self::Invalid { name, value, reason } => {
+                write!(f, "Invalid value {:?} for {}: {}", value, name, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+const TLS_LEVELS: &[&str] = &["none", "may", "encrypt", "dane", "dane-only", "fingerprint", "verify", "secure"];
+const CIPHER_GRADES: &[&str] = &["export", "low", "medium", "high", "null"];
+
+macro_rules! param {
+    ($name:literal, $kind:expr, $default:literal, $doc:literal) => {
+        ParamSpec {
+            name: $name,
+            kind: $kind,
+            default: $default,
+            description: $doc,
+        }
+    };
+}
+
+use ParamType::*;
+
+/// The main.cf parameters mission-control knows how to edit.
+pub static PARAMETERS: &[ParamSpec] = &[
+    // ── identity ───────────────────────────────────────────────────
+    param!("myhostname", Hostname, "", "Fully-qualified name of this mail server, used in the SMTP greeting and as the default for many other parameters."),
+    param!("mydomain", Hostname, "", "Internet domain of this mail server; defaults to $myhostname minus its first label."),
+    param!("myorigin", Text, "$myhostname", "Domain appended to addresses of locally posted mail."),
+    param!("mydestination", List, "$myhostname, localhost.$mydomain, localhost", "Domains delivered locally via the local(8) transport. Virtual domains must not be listed here."),
+    param!("mynetworks", List, "127.0.0.0/8 [::1]/128", "Trusted client networks allowed to relay through this server without authentication."),
+    param!("inet_interfaces", List, "all", "Network interfaces Postfix listens on."),
+    param!("inet_protocols", Enum(&["all", "ipv4", "ipv6", "ipv4, ipv6"]), "all", "IP protocols Postfix uses for listening and outbound connections."),
+    param!("smtpd_banner", Text, "$myhostname ESMTP $mail_name", "Text after the 220 status code in the SMTP greeting. Must start with $myhostname."),
+    param!("relayhost", Hostname, "", "Next-hop destination for non-local mail, e.g. [smtp.example.net]:587. Empty delivers directly."),
+    param!("recipient_delimiter", Text, "", "Separator between a user name and an address extension, e.g. + for user+tag@domain."),
+    param!("append_dot_mydomain", Boolean, "no", "Append .$mydomain to addresses that have no domain part."),
+    param!("biff", Boolean, "yes", "Notify local users of new mail through the biff service."),
+    param!("compatibility_level", Text, "0", "Backwards-compatibility level for changed defaults."),
+    param!("alias_maps", LookupTable, "hash:/etc/aliases", "Tables of local aliases used by local(8)."),
+    param!("alias_database", LookupTable, "hash:/etc/aliases", "Alias databases rebuilt by newaliases."),
+    // ── TLS ────────────────────────────────────────────────────────
+    param!("smtpd_tls_cert_file", Path, "", "PEM certificate chain presented to SMTP clients."),
+    param!("smtpd_tls_key_file", Path, "$smtpd_tls_cert_file", "Private key for smtpd_tls_cert_file."),
+    param!("smtpd_use_tls", Boolean, "no", "Announce STARTTLS to SMTP clients (obsolete; prefer smtpd_tls_security_level)."),
+    param!("smtpd_tls_auth_only", Boolean, "no", "Offer SASL authentication only over TLS."),
+    param!("smtpd_tls_security_level", Enum(&["none", "may", "encrypt"]), "", "TLS policy for inbound SMTP: none, may (opportunistic) or encrypt (mandatory)."),
+    param!("smtp_tls_security_level", Enum(TLS_LEVELS), "", "Default TLS policy for outbound SMTP, e.g. may or dane."),
+    param!("smtpd_tls_protocols", List, "!SSLv2, !SSLv3", "TLS protocols the SMTP server accepts with opportunistic TLS."),
+    param!("smtp_tls_protocols", List, "!SSLv2, !SSLv3", "TLS protocols the SMTP client uses with opportunistic TLS."),
+    param!("smtpd_tls_mandatory_protocols", List, "!SSLv2, !SSLv3", "TLS protocols the SMTP server accepts with mandatory TLS."),
+    param!("smtpd_tls_mandatory_ciphers", Enum(CIPHER_GRADES), "medium", "Minimum cipher grade for mandatory TLS on the SMTP server."),
+    param!("smtpd_tls_ciphers", Enum(CIPHER_GRADES), "medium", "Minimum cipher grade for opportunistic TLS on the SMTP server."),
+    param!("smtp_tls_ciphers", Enum(CIPHER_GRADES), "medium", "Minimum cipher grade for opportunistic TLS on the SMTP client."),
+    param!("smtpd_tls_loglevel", Integer, "0", "SMTP server TLS logging verbosity, 0-4."),
+    param!("smtp_tls_loglevel", Integer, "0", "SMTP client TLS logging verbosity, 0-4."),
+    param!("smtpd_tls_session_cache_database", LookupTable, "", "Cache for SMTP server TLS sessions."),
+    param!("smtp_tls_session_cache_database", LookupTable, "", "Cache for SMTP client TLS sessions."),
+    param!("smtp_tls_policy_maps", LookupTable, "", "Per-destination TLS policy, e.g. for MTA-STS or pinned fingerprints."),
+    param!("smtp_tls_CAfile", Path, "", "CA certificates used to verify remote SMTP servers."),
+    param!("smtp_dns_support_level", Enum(&["", "disabled", "enabled", "dnssec"]), "", "DNS features for the SMTP client; dnssec is required for DANE."),
+    param!("tls_preempt_cipherlist", Boolean, "no", "Prefer the server's cipher order over the client's."),
+    // ── SASL ───────────────────────────────────────────────────────
+    param!("smtpd_sasl_type", Enum(&["cyrus", "dovecot"]), "cyrus", "SASL implementation used for SMTP AUTH."),
+    param!("smtpd_sasl_path", Text, "smtpd", "SASL socket (for Dovecot, relative to the queue directory)."),
+    param!("smtpd_sasl_auth_enable", Boolean, "no", "Enable SMTP AUTH on the SMTP server."),
+    param!("smtpd_sasl_security_options", List, "noanonymous", "SASL mechanism restrictions for unencrypted sessions."),
+    param!("smtpd_sasl_local_domain", Text, "", "Realm appended to SASL user names."),
+    param!("smtpd_sender_login_maps", LookupTable, "", "Which SASL logins own which sender addresses."),
+    param!("broken_sasl_auth_clients", Boolean, "no", "Also announce AUTH in the obsolete AUTH= form."),
+    // ── restrictions ───────────────────────────────────────────────
+    param!("smtpd_client_restrictions", List, "", "Access restrictions applied when a client connects."),
+    param!("smtpd_helo_restrictions", List, "", "Access restrictions applied to the HELO/EHLO command."),
+    param!("smtpd_sender_restrictions", List, "", "Access restrictions applied to MAIL FROM."),
+    param!("smtpd_relay_restrictions", List, "permit_mynetworks, permit_sasl_authenticated, defer_unauth_destination", "Relay control restrictions applied to RCPT TO, before smtpd_recipient_restrictions."),
+    param!("smtpd_recipient_restrictions", List, "", "Spam and access restrictions applied to RCPT TO."),
+    param!("smtpd_data_restrictions", List, "", "Access restrictions applied to the DATA command."),
+    param!("smtpd_restriction_classes", List, "", "User-defined names for groups of restrictions."),
+    param!("smtpd_helo_required", Boolean, "no", "Require clients to send HELO or EHLO first."),
+    param!("disable_vrfy_command", Boolean, "no", "Disable the SMTP VRFY command, which leaks valid addresses."),
+    param!("strict_rfc821_envelopes", Boolean, "no", "Require angle brackets around MAIL FROM and RCPT TO addresses."),
+    param!("smtpd_delay_reject", Boolean, "yes", "Wait until RCPT TO before rejecting on client, HELO or sender restrictions."),
+    param!("postscreen_dnsbl_sites", List, "", "DNS blocklists postscreen queries, with optional weights."),
+    param!("postscreen_dnsbl_threshold", Integer, "1", "Combined DNSBL weight at which postscreen rejects a client."),
+    param!("postscreen_greet_action", Enum(&["ignore", "enforce", "drop"]), "ignore", "What postscreen does with clients that talk before their turn."),
+    // ── virtual hosting ────────────────────────────────────────────
+    param!("virtual_mailbox_domains", DomainList, "$virtual_mailbox_maps", "Domains whose mail is delivered to virtual mailboxes."),
+    param!("virtual_mailbox_maps", LookupTable, "", "Valid virtual mailbox addresses and their mailbox paths."),
+    param!("virtual_alias_maps", LookupTable, "$virtual_maps", "Address rewriting for virtual aliases and forwards."),
+    param!("virtual_alias_domains", DomainList, "$virtual_alias_maps", "Domains that only have aliases, no mailboxes."),
+    param!("virtual_mailbox_base", Path, "", "Prefix for mailbox paths from virtual_mailbox_maps."),
+    param!("virtual_minimum_uid", Integer, "100", "Lowest UID the virtual(8) agent will deliver as."),
+    param!("virtual_uid_maps", LookupTable, "", "UID used for each virtual mailbox, e.g. static:5000."),
+    param!("virtual_gid_maps", LookupTable, "", "GID used for each virtual mailbox, e.g. static:5000."),
+    param!("virtual_transport", Text, "virtual", "Delivery transport for virtual mailbox domains, e.g. lmtp:unix:private/dovecot-lmtp."),
+    param!("local_recipient_maps", LookupTable, "proxy:unix:passwd.byname $alias_maps", "Valid local recipients; empty accepts any local address."),
+    param!("transport_maps", LookupTable, "", "Per-domain or per-address delivery transport overrides."),
+    param!("sender_canonical_maps", LookupTable, "", "Rewriting of envelope and header sender addresses."),
+    param!("recipient_canonical_maps", LookupTable, "", "Rewriting of envelope and header recipient addresses."),
+    param!("sender_dependent_relayhost_maps", LookupTable, "", "Relay host chosen by sender address."),
+    param!("smtp_sasl_password_maps", LookupTable, "", "Credentials the SMTP client uses with each relay host."),
+    param!("smtp_sasl_auth_enable", Boolean, "no", "Authenticate to relay hosts with SASL."),
+    param!("smtp_sasl_security_options", List, "noplaintext, noanonymous", "SASL mechanism restrictions for the SMTP client."),
+    param!("header_checks", LookupTable, "", "Patterns matched against message headers."),
+    param!("body_checks", LookupTable, "", "Patterns matched against message body lines."),
+    // ── milters & content filtering ────────────────────────────────
+    param!("milter_protocol", Integer, "6", "Milter protocol version spoken to milter applications."),
+    param!("milter_default_action", Enum(&["accept", "reject", "tempfail", "quarantine"]), "tempfail", "What to do with mail when a milter is unavailable."),
+    param!("smtpd_milters", List, "", "Milters applied to mail received over SMTP, e.g. inet:localhost:8891 for OpenDKIM."),
+    param!("non_smtpd_milters", List, "", "Milters applied to locally submitted mail."),
+    param!("content_filter", Text, "", "Transport that all mail is passed through before delivery."),
+    // ── limits & queue ─────────────────────────────────────────────
+    param!("message_size_limit", Size, "10240000", "Largest message accepted, in bytes."),
+    param!("mailbox_size_limit", Size, "51200000", "Largest local mailbox file, in bytes; 0 is unlimited."),
+    param!("virtual_mailbox_limit", Size, "51200000", "Largest virtual mailbox file, in bytes; 0 is unlimited."),
+    param!("smtpd_recipient_limit", Integer, "1000", "Most recipients accepted per message."),
+    param!("smtpd_client_connection_count_limit", Integer, "50", "Most simultaneous connections from one client."),
+    param!("smtpd_client_connection_rate_limit", Integer, "0", "Most connections per client per anvil_rate_time_unit; 0 is unlimited."),
+    param!("smtpd_client_message_rate_limit", Integer, "0", "Most messages per client per anvil_rate_time_unit; 0 is unlimited."),
+    param!("anvil_rate_time_unit", Time, "60s", "Interval over which client rate limits are counted."),
+    param!("maximal_queue_lifetime", Time, "5d", "How long deferred mail is retried before it bounces."),
+    param!("bounce_queue_lifetime", Time, "5d", "How long undeliverable bounces are retried."),
+    param!("minimal_backoff_time", Time, "300s", "Shortest interval between delivery attempts for deferred mail."),
+    param!("maximal_backoff_time", Time, "4000s", "Longest interval between delivery attempts for deferred mail."),
+    param!("queue_run_delay", Time, "300s", "How often the deferred queue is scanned."),
+    param!("delay_warning_time", Time, "0h", "When to warn senders that mail is delayed; 0 disables warnings."),
+    param!("smtpd_timeout", Time, "300s", "How long the SMTP server waits for a client request."),
+    param!("smtp_helo_timeout", Time, "300s", "How long the SMTP client waits for the remote greeting."),
+    // ── addresses & misc ───────────────────────────────────────────
+    param!("bounce_notice_recipient", Address, "postmaster", "Who receives notices about bounced mail."),
+    param!("double_bounce_sender", Address, "double-bounce", "Sender of double bounces, which are discarded."),
+    param!("empty_address_recipient", Address, "MAILER-DAEMON", "Where mail to the null sender is delivered."),
+    param!("notify_classes", List, "resource, software", "Problem classes reported to the postmaster."),
+    param!("smtputf8_enable", Boolean, "yes", "Accept and deliver internationalized (SMTPUTF8) addresses."),
+    param!("readme_directory", Text, "no", "Location of Postfix README files."),
+    param!("mailbox_command", Text, "", "External command used by local(8) for mailbox delivery."),
+    param!("mailbox_transport", Text, "", "Transport used for local mailbox delivery, e.g. lmtp:unix:private/dovecot-lmtp."),
+    param!("home_mailbox", Text, "", "Mailbox path relative to home directories, e.g. Maildir/."),
+];
+
+static BY_NAME: Lazy<std::collections::HashMap<&'static str, &'static ParamSpec>> =
+    Lazy::new(|| PARAMETERS.iter().map(|p| (p.name, p)).collect());
+
+static PARAM_REF: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\$(\w+|\{\w+\})$").unwrap());
+static TIME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d+[smhdw]?$").unwrap());
+static HOSTNAME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\[[A-Za-z0-9.:-]+\]|[A-Za-z0-9]([A-Za-z0-9.-]*[A-Za-z0-9])?)(:\d{1,5})?$").unwrap()
+});
+static ADDRESS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9._%+=-]+(@[A-Za-z0-9.-]+)?$").unwrap());
+static DOMAIN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\.?[A-Za-z0-9]([A-Za-z0-9.-]*[A-Za-z0-9])?$").unwrap());
+static TABLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-z]+:\S+$").unwrap());
+
+/// Lookup table types Postfix supports (`postconf -m`), plus `proxy`.
+const TABLE_TYPES: &[&str] = &[
+    "btree", "cdb", "cidr", "environ", "fail", "hash", "inline", "internal", "ldap", "lmdb",
+    "memcache", "mongodb", "mysql", "pcre", "pgsql", "pipemap", "proxy", "randmap", "regexp",
+    "sdbm", "socketmap", "sqlite", "static", "tcp", "texthash", "unionmap", "unix",
+];
+
+pub fn lookup(name: &str) -> Option<&'static ParamSpec> {
+    BY_NAME.get(name).copied()
+}
+
+/// Check `value` against the catalogue entry for `name`.
+///
+/// A value that is just a `$parameter` reference is accepted for any type,
+/// since it can only be checked once expanded. Empty values are accepted
+/// for list-like types, where they mean "none".
+pub fn validate(name: &str, value: &str) -> Result<(), ParamError> {
+    let spec = lookup(name).ok_or_else(|| ParamError::Unknown(name.to_string()))?;
+    spec.kind.check(value.trim()).map_err(|reason| ParamError::Invalid {
+        name: name.to_string(),
+        value: value.to_string(),
+        reason,
+    })
+}
+
+/// Check a value for a parameter Postfix knows (`postconf -d`) but the
+/// catalogue does not describe. It is held to free text only.
+pub fn validate_untyped(name: &str, value: &str) -> Result<(), ParamError> {
+    Text.check(value.trim()).map_err(|reason| ParamError::Invalid {
+        name: name.to_string(),
+        value: value.to_string(),
+        reason,
+    })
+}
+
+fn check_table(table: &str) -> Result<(), String> {
+    let kind = table.split(':').next().unwrap_or("");
+    if TABLE.is_match(table) && TABLE_TYPES.contains(&kind) {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not a type:name lookup table", table))
+    }
+}
+
+impl ParamType {
+    fn check(&self, value: &str) -> Result<(), String> {
+        if PARAM_REF.is_match(value) {
+            return Ok(());
+        }
+        match self {
+            Boolean => match value {
+                "yes" | "no" => Ok(()),
+                _ => Err("expected yes or no".to_string()),
+            },
+            Time => {
+                if TIME.is_match(value) {
+                    Ok(())
+                } else {
+                    Err("expected a number with an optional s, m, h, d or w unit".to_string())
+                }
+            }
+            Size | Integer => {
+                if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+                    Ok(())
+                } else {
+                    Err("expected a non-negative integer".to_string())
+                }
+            }
+            List | Text => {
+                if value.contains('\n') {
+                    Err("must be a single line".to_string())
+                } else {
+                    Ok(())
+                }
+            }
+            LookupTable => {
+                for table in split_list(value) {
+                    if !table.starts_with('$') {
+                        check_table(&table)?;
+                    }
+                }
+                Ok(())
+            }
+            DomainList => {
+                for item in split_list(value) {
+                    if item.contains('$') || item.starts_with('/') {
+                        continue;
+                    }
+                    if item.contains(':') {
+                        check_table(&item)?;
+                    } else if !DOMAIN.is_match(&item) {
+                        return Err(format!("{:?} is not a domain, file or lookup table", item));
+                    }
+                }
+                Ok(())
+            }
+            Address => {
+                if value.is_empty() || ADDRESS.is_match(value) {
+                    Ok(())
+                } else {
+                    Err("expected an address or local part".to_string())
+                }
+            }
+            Hostname => {
+                if value.is_empty() || HOSTNAME.is_match(value) {
+                    Ok(())
+                } else {
+                    Err("expected a host name, optionally [bracketed] with :port".to_string())
+                }
+            }
+            Path => {
+                if value.is_empty() || value.starts_with('/') {
+                    Ok(())
+                } else {
+                    Err("expected an absolute path".to_string())
+                }
+            }
+            Enum(allowed) => {
+                let normalized = split_list(value).join(", ");
+                if allowed.contains(&value) || allowed.contains(&normalized.as_str()) {
+                    Ok(())
+                } else {
+                    Err(format!("expected one of: {}", allowed.join(", ")))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::postfix::PostfixConfig;
+
+    #[test]
+    fn test_catalogue_names_unique() {
+        let mut names: Vec<_> = PARAMETERS.iter().map(|p| p.name).collect();
+        names.sort_unstable();
+        let before = names.len();
+        names.dedup();
+        assert_eq!(names.len(), before);
+    }
+
+    #[test]
+    fn test_generated_main_cf_is_valid() {
+        let cfg = PostfixConfig::generate_default("mail.example.com", "example.com");
+        for (key, value) in cfg.to_string().lines().filter_map(|l| l.split_once(" = ")) {
+            validate(key, value).unwrap_or_else(|e| panic!("{}", e));
+        }
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_mistyped() {
+        assert_eq!(
+            validate("smtpd_helo_requried", "yes"),
+            Err(ParamError::Unknown("smtpd_helo_requried".to_string()))
+        );
+        assert!(validate("smtpd_helo_required", "true").is_err());
+        assert!(validate("message_size_limit", "10M").is_err());
+        assert!(validate("maximal_queue_lifetime", "5 days").is_err());
+        assert!(validate("virtual_alias_maps", "/etc/postfix/virtual").is_err());
+        assert!(validate("virtual_alias_maps", "nosuch:/etc/postfix/virtual").is_err());
+        assert!(validate("smtp_tls_security_level", "maybe").is_err());
+        assert!(validate("relayhost", "smtp example net").is_err());
+    }
+
+    #[test]
+    fn test_accepts_valid_values() {
+        validate("maximal_queue_lifetime", "3d").unwrap();
+        validate("message_size_limit", "52428800").unwrap();
+        validate("relayhost", "[smtp.example.net]:587").unwrap();
+        validate("virtual_alias_maps", "proxy:mysql:/etc/postfix/a.cf, hash:/etc/postfix/b").unwrap();
+        validate("smtp_tls_security_level", "dane").unwrap();
+        validate("inet_protocols", "ipv4,ipv6").unwrap();
+        validate("bounce_notice_recipient", "admin@example.com").unwrap();
+        validate("message_size_limit", "$default_size").unwrap();
+        validate("smtpd_milters", "").unwrap();
+    }
+
+    #[test]
+    fn test_domain_lists_mix_names_files_and_tables() {
+        validate("virtual_mailbox_domains", "example.com, .example.org").unwrap();
+        validate("virtual_mailbox_domains", "example.com /etc/postfix/domains proxy:mysql:/etc/postfix/d.cf").unwrap();
+        validate("virtual_alias_domains", "$virtual_alias_maps").unwrap();
+        validate("virtual_alias_domains", "").unwrap();
+        assert!(validate("virtual_mailbox_domains", "example.com nosuch:/etc/postfix/d").is_err());
+        assert!(validate("virtual_alias_domains", "exa mple!.com").is_err());
+    }
+
+    #[test]
+    fn test_untyped_values_are_single_line() {
+        validate_untyped("smtpd_upstream_proxy_protocol", "haproxy").unwrap();
+        assert!(validate_untyped("smtpd_upstream_proxy_protocol", "haproxy\nrelayhost = evil").is_err());
+    }
+}
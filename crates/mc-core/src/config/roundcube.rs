@@ -260,6 +260,43 @@ $config['mime_param_folding'] = 0;
     Ok(output)
 }
 
+/// Keys whose values are credentials: the database DSN carries the
+/// password and `des_key` encrypts session passwords.
+pub const SECRET_KEYS: &[&str] = &["db_dsnw", "des_key"];
+
+/// Replace the values of [`SECRET_KEYS`] with a placeholder, so the
+/// config can be shown in previews and logs.
+pub fn mask_secrets(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let secret = SECRET_KEYS
+            .iter()
+            .find(|key| trimmed.starts_with(&format!("$config['{}']", key)));
+        match secret {
+            Some(key) => {
+                let indent = &line[..line.len() - trimmed.len()];
+                out.push_str(&format!("{}$config['{}'] = '********';", indent, key));
+                if line.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            None => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// [`mask_secrets`] for a unified diff of the config.
+pub fn mask_diff_secrets(diff: &str) -> String {
+    diff.split_inclusive('\n')
+        .map(|line| match line.chars().next() {
+            Some(marker @ ('+' | '-' | ' ')) => format!("{}{}", marker, mask_secrets(&line[1..])),
+            _ => line.to_string(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -462,4 +499,17 @@ mod tests {
         assert!(output.contains("$config['smtp_user'] = '%u'"));
         assert!(output.contains("$config['smtp_pass'] = '%p'"));
     }
+
+    #[test]
+    fn test_mask_secrets() {
+        let mut cfg = RoundcubeConfig::generate_default("example.com", "admin@example.com");
+        cfg.db_password = "s3cret".to_string();
+        let output = mask_secrets(&generate_config_inc_php(&cfg).unwrap());
+
+        assert!(!output.contains("s3cret"));
+        assert!(!output.contains(&cfg.des_key));
+        assert!(output.contains("$config['db_dsnw'] = '********';\n"));
+        assert!(output.contains("$config['des_key'] = '********';\n"));
+        assert!(output.contains("$config['skin'] = 'elastic';"));
+    }
 }
@@ -0,0 +1,370 @@
+//! Config validation with the daemons' own checkers.
+//!
+//! Each validator writes the candidate content into a scratch directory,
+//! runs the tool against it and turns the tool's output into line-level
+//! [`ConfigDiagnostic`]s, so callers can point at the offending line instead
+//! of showing raw stderr.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Where Debian keeps SpamAssassin's site config (`*.pre`, `*.cf`).
+const SPAMASSASSIN_SITE_DIR: &str = "/etc/spamassassin";
+const APACHE_SERVER_ROOT: &str = "/etc/apache2";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem reported by a config checker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigDiagnostic {
+    /// 1-based line in the checked content, when the tool gave one.
+    pub line: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl ConfigDiagnostic {
+    fn error(line: Option<u32>, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(line: Option<u32>, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.line {
+            Some(line) => write!(f, "line {}: {}: {}", line, severity, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Whether any diagnostic is an error.
+pub fn has_errors(diagnostics: &[ConfigDiagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+#[derive(Debug)]
+pub enum ValidateError {
+    /// The checker is not installed; validation was not possible.
+    Unavailable(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ValidateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(tool) => write!(f, "{} not available", tool),
+            Self::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ValidateError {}
+
+impl From<std::io::Error> for ValidateError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<Output, ValidateError> {
+    Command::new(program).args(args).output().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            ValidateError::Unavailable(program.to_string())
+        } else {
+            ValidateError::Io(e)
+        }
+    })
+}
+
+fn combined_output(out: &Output) -> String {
+    let mut text = String::from_utf8_lossy(&out.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&out.stderr));
+    text
+}
+
+/// A failing exit with nothing we could parse still has to fail.
+fn ensure_failure_reported(out: &Output, tool: &str, diagnostics: &mut Vec<ConfigDiagnostic>) {
+    if !out.status.success() && !has_errors(diagnostics) {
+        let text = combined_output(out);
+        let message = text.trim();
+        diagnostics.push(ConfigDiagnostic::error(
+            None,
+            if message.is_empty() {
+                format!("{} exited with {}", tool, out.status)
+            } else {
+                message.to_string()
+            },
+        ));
+    }
+}
+
+// ── SpamAssassin ───────────────────────────────────────────────────
+
+/// Lint a candidate `local.cf` with `spamassassin --lint`, using a copy of
+/// the site config directory so the installed `*.pre` plugin loads apply.
+pub fn spamassassin_local(content: &str) -> Result<Vec<ConfigDiagnostic>, ValidateError> {
+    let dir = tempfile::tempdir()?;
+    if let Ok(entries) = std::fs::read_dir(SPAMASSASSIN_SITE_DIR) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let keep = name
+                .to_str()
+                .is_some_and(|n| n != "local.cf" && (n.ends_with(".pre") || n.ends_with(".cf")));
+            if keep {
+                std::fs::copy(entry.path(), dir.path().join(&name))?;
+            }
+        }
+    }
+    std::fs::write(dir.path().join("local.cf"), content)?;
+
+    let site = format!("--siteconfigpath={}", dir.path().display());
+    let out = run("spamassassin", &["--lint", &site])?;
+    let mut diagnostics = parse_spamassassin_lint(&combined_output(&out), content);
+    ensure_failure_reported(&out, "spamassassin --lint", &mut diagnostics);
+    Ok(diagnostics)
+}
+
+/// SpamAssassin does not print line numbers; it echoes the offending line
+/// after "skipping:" (or at the end of the message), which is matched back
+/// against the content.
+pub fn parse_spamassassin_lint(output: &str, content: &str) -> Vec<ConfigDiagnostic> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let message = line.strip_prefix("config: ")?;
+            let echoed = message
+                .rsplit_once("skipping: ")
+                .or_else(|| message.rsplit_once(": "))
+                .map(|(_, rest)| rest.trim());
+            let line_no = echoed.and_then(|text| find_line(content, text));
+            let severity = if message.starts_with("warning") {
+                Severity::Warning
+            } else {
+                Severity::Error
+            };
+            Some(ConfigDiagnostic {
+                line: line_no,
+                severity,
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn find_line(content: &str, text: &str) -> Option<u32> {
+    let text = text.trim_matches('"');
+    if text.is_empty() {
+        return None;
+    }
+    let normalized: Vec<&str> = text.split_whitespace().collect();
+    content
+        .lines()
+        .position(|l| l.split_whitespace().collect::<Vec<_>>() == normalized)
+        .map(|i| i as u32 + 1)
+}
+
+// ── OpenDKIM ───────────────────────────────────────────────────────
+
+static OPENDKIM_ERROR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"configuration error at line (\d+):\s*(.*)").unwrap());
+
+/// Check a candidate `opendkim.conf` with `opendkim -n`.
+pub fn opendkim_conf(content: &str) -> Result<Vec<ConfigDiagnostic>, ValidateError> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("opendkim.conf");
+    std::fs::write(&path, content)?;
+
+    let out = run("opendkim", &["-n", "-x", &path.display().to_string()])?;
+    let mut diagnostics = parse_opendkim(&combined_output(&out), &path);
+    ensure_failure_reported(&out, "opendkim -n", &mut diagnostics);
+    Ok(diagnostics)
+}
+
+pub fn parse_opendkim(output: &str, checked: &Path) -> Vec<ConfigDiagnostic> {
+    let checked = checked.display().to_string();
+    output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| match OPENDKIM_ERROR.captures(l) {
+            Some(caps) => ConfigDiagnostic::error(caps[1].parse().ok(), caps[2].trim()),
+            // Other messages (e.g. an unreadable KeyTable) name the file
+            None => ConfigDiagnostic::error(None, l.trim().replace(&checked, "opendkim.conf")),
+        })
+        .collect()
+}
+
+// ── Apache ─────────────────────────────────────────────────────────
+
+static APACHE_SYNTAX_ERROR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"Syntax error on line (\d+) of ([^:]+):").unwrap());
+
+/// Check a candidate vhost with `apache2ctl -t`, in an isolated config that
+/// loads the enabled modules and this vhost only, so other sites cannot
+/// mask or cause errors.
+pub fn apache_vhost(content: &str) -> Result<Vec<ConfigDiagnostic>, ValidateError> {
+    let dir = tempfile::tempdir()?;
+    let vhost = dir.path().join("vhost.conf");
+    std::fs::write(&vhost, content)?;
+    let main = dir.path().join("apache2.conf");
+    std::fs::write(
+        &main,
+        format!(
+            "ServerName localhost\n\
+             IncludeOptional {root}/mods-enabled/*.load\n\
+             IncludeOptional {root}/mods-enabled/*.conf\n\
+             Include {vhost}\n",
+            root = APACHE_SERVER_ROOT,
+            vhost = vhost.display(),
+        ),
+    )?;
+
+    let main = main.display().to_string();
+    let out = run("apache2ctl", &["-t", "-d", APACHE_SERVER_ROOT, "-f", &main])?;
+    let mut diagnostics = parse_apache(&combined_output(&out), &vhost);
+    ensure_failure_reported(&out, "apache2ctl -t", &mut diagnostics);
+    Ok(diagnostics)
+}
+
+/// apache2ctl prints `Syntax error on line N of FILE:` followed by the
+/// message on the next line.
+pub fn parse_apache(output: &str, vhost: &Path) -> Vec<ConfigDiagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if let Some(caps) = APACHE_SYNTAX_ERROR.captures(line) {
+            let detail = lines.next().unwrap_or("").trim();
+            if Path::new(&caps[2]) == vhost {
+                diagnostics.push(ConfigDiagnostic::error(caps[1].parse().ok(), detail));
+            } else {
+                diagnostics.push(ConfigDiagnostic::error(
+                    None,
+                    format!("{} (line {} of {})", detail, &caps[1], &caps[2]),
+                ));
+            }
+        } else if line.contains("AH") && line.contains("warn") {
+            diagnostics.push(ConfigDiagnostic::warning(None, line));
+        }
+    }
+    diagnostics
+}
+
+// ── Roundcube ──────────────────────────────────────────────────────
+
+static PHP_ERROR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:PHP )?(Parse|Fatal) error:\s*(.*?) in .* on line (\d+)").unwrap()
+});
+
+/// Syntax-check a candidate Roundcube `config.inc.php` with `php -l`.
+pub fn roundcube_php(content: &str) -> Result<Vec<ConfigDiagnostic>, ValidateError> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("config.inc.php");
+    std::fs::write(&path, content)?;
+
+    let out = run("php", &["-l", &path.display().to_string()])?;
+    let mut diagnostics = parse_php_lint(&combined_output(&out));
+    ensure_failure_reported(&out, "php -l", &mut diagnostics);
+    Ok(diagnostics)
+}
+
+pub fn parse_php_lint(output: &str) -> Vec<ConfigDiagnostic> {
+    let mut diagnostics: Vec<ConfigDiagnostic> = Vec::new();
+    for caps in output.lines().filter_map(|l| PHP_ERROR.captures(l)) {
+        let diagnostic = ConfigDiagnostic::error(caps[3].parse().ok(), caps[2].trim());
+        // php -l prints the error on both stdout and stderr
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spamassassin_lint() {
+        let content = "required_score 5.0\nrequired_score abc\nscore FOO 1.0\n";
+        let output = "\
+config: SpamAssassin failed to parse line, \"abc\" is not valid for \"required_score\", skipping: required_score abc
+config: warning: score set for non-existent rule FOO
+lint: 2 issues detected, please rerun with debug enabled for more information
+";
+        let diags = parse_spamassassin_lint(output, content);
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].line, Some(2));
+        assert_eq!(diags[0].severity, Severity::Error);
+        assert_eq!(diags[1].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_parse_opendkim() {
+        let checked = Path::new("/tmp/x/opendkim.conf");
+        let output = "opendkim: /tmp/x/opendkim.conf: configuration error at line 7: unrecognized parameter\n";
+        let diags = parse_opendkim(output, checked);
+        assert_eq!(
+            diags,
+            vec![ConfigDiagnostic::error(Some(7), "unrecognized parameter")]
+        );
+    }
+
+    #[test]
+    fn test_parse_apache() {
+        let vhost = Path::new("/tmp/abc/vhost.conf");
+        let output = "\
+AH00526: Syntax error on line 4 of /tmp/abc/vhost.conf:
+Invalid command 'SSLEngin', perhaps misspelled or defined by a module not included in the server configuration
+";
+        let diags = parse_apache(output, vhost);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].line, Some(4));
+        assert!(diags[0].message.starts_with("Invalid command 'SSLEngin'"));
+
+        let elsewhere = "AH00526: Syntax error on line 2 of /etc/apache2/mods-enabled/ssl.conf:\nbad\n";
+        let diags = parse_apache(elsewhere, vhost);
+        assert_eq!(diags[0].line, None);
+        assert!(diags[0].message.contains("ssl.conf"));
+    }
+
+    #[test]
+    fn test_parse_php_lint() {
+        let output = "\
+PHP Parse error:  syntax error, unexpected token \";\", expecting \"]\" in /tmp/x/config.inc.php on line 12
+Parse error: syntax error, unexpected token \";\", expecting \"]\" in /tmp/x/config.inc.php on line 12
+Errors parsing /tmp/x/config.inc.php
+";
+        let diags = parse_php_lint(output);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].line, Some(12));
+        assert!(diags[0].message.starts_with("syntax error"));
+        assert_eq!(diags[0].to_string(), format!("line 12: error: {}", diags[0].message));
+    }
+}
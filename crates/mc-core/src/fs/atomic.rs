@@ -26,6 +26,19 @@ pub fn atomic_write(
     path: &Path,
     content: &[u8],
     mode: Option<u32>,
+) -> Result<(), AtomicWriteError> {
+    atomic_write_owned(path, content, mode, None)
+}
+
+/// [`atomic_write`], also giving the file `owner` as `(uid, gid)`.
+///
+/// The temp file belongs to whoever writes it, so replacing a file owned
+/// by another user (e.g. `root:opendkim`) needs this to keep its owner.
+pub fn atomic_write_owned(
+    path: &Path,
+    content: &[u8],
+    mode: Option<u32>,
+    owner: Option<(u32, u32)>,
 ) -> Result<(), AtomicWriteError> {
     let parent = path.parent()
         .ok_or_else(|| AtomicWriteError::NoParentDir(path.to_path_buf()))?;
@@ -43,7 +56,10 @@ pub fn atomic_write(
     // fsync to ensure data is on disk
     temp.as_file().sync_all()?;
 
-    // Set permissions before rename if specified
+    // Set ownership, then permissions (chown clears setuid/setgid bits)
+    if let Some((uid, gid)) = owner {
+        std::os::unix::fs::fchown(temp.as_file(), Some(uid), Some(gid))?;
+    }
     if let Some(m) = mode {
         let permissions = fs::Permissions::from_mode(m);
         fs::set_permissions(temp.path(), permissions)?;
@@ -125,6 +141,21 @@ mod tests {
         assert!(backup.exists());
     }
 
+    #[test]
+    fn test_atomic_write_owned_keeps_owner() {
+        use std::os::unix::fs::MetadataExt;
+        // Giving a file away needs root
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("opendkim.conf");
+        atomic_write_owned(&path, b"Mode sv\n", Some(0o640), Some((1234, 5678))).unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (1234, 5678));
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+    }
+
     #[test]
     fn test_atomic_write_permissions() {
         let dir = TempDir::new().unwrap();
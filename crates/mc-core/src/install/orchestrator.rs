@@ -1,6 +1,14 @@
 use std::process::Command;
 use thiserror::Error;
-use tracing::{info, error};
+use crate::acme;
+use crate::acme::challenge::{ChallengeType, Http01Webroot};
+use crate::acme::store::{self, CertificatePurpose, CertificateStore, ManagedCertificate};
+use crate::config::changeset::Changeset;
+use crate::config::history::{ConfigChange, ConfigHistory};
+use crate::config::postfix::PostfixConfig;
+use crate::mail::dkim::{self, DkimAlgorithm};
+use crate::security::credentials::{self, CredentialStore};
+use tracing::{info, error, warn};
 
 #[derive(Debug, Error)]
 pub enum InstallError {
@@ -38,9 +46,17 @@ pub struct InstallConfig {
     pub php_version: String,
 }
 
+/// The mail database and the MySQL account Postfix and Dovecot use.
+const DB_NAME: &str = "ceymail_db";
+const DB_USER: &str = "ceymail";
+/// Credential store entry for the password of [`DB_USER`].
+const DB_CREDENTIAL: &str = "mysql-ceymail";
+
 pub struct InstallOrchestrator {
     config: InstallConfig,
     steps: Vec<StepState>,
+    /// `None` opens the default store when it is first needed.
+    credentials: Option<CredentialStore>,
 }
 
 impl InstallOrchestrator {
@@ -71,7 +87,17 @@ impl InstallOrchestrator {
             })
             .collect();
 
-        Self { config, steps }
+        Self {
+            config,
+            steps,
+            credentials: None,
+        }
+    }
+
+    /// Keep the database password in `store` instead of the default store.
+    pub fn with_credential_store(mut self, store: CredentialStore) -> Self {
+        self.credentials = Some(store);
+        self
     }
 
     /// Return a reference to the current step states.
@@ -180,6 +206,23 @@ impl InstallOrchestrator {
         Ok(&self.steps[index])
     }
 
+    /// The config files a step would write, staged but not written, so
+    /// they can be diffed and validated before the step runs. Only steps
+    /// that do nothing but write config files have one.
+    pub fn step_changeset(&self, index: usize) -> Result<Changeset, InstallError> {
+        let step = self.steps.get(index).ok_or_else(|| InstallError::StepFailed {
+            step: format!("index_{}", index),
+            message: "Step index out of bounds".to_string(),
+        })?;
+        match step.name.as_str() {
+            "service_config" => self.service_config_changeset(),
+            _ => Err(InstallError::StepFailed {
+                step: step.name.clone(),
+                message: "Step cannot be previewed".to_string(),
+            }),
+        }
+    }
+
     // =====================================================================
     // Config validation
     // =====================================================================
@@ -374,10 +417,13 @@ impl InstallOrchestrator {
         // hex::encode(random_bytes), guaranteed to contain only [0-9a-f] chars.
         // This makes SQL injection impossible for this value.
         let sql = format!(
-            "CREATE DATABASE IF NOT EXISTS ceymail_db; \
-             CREATE USER IF NOT EXISTS 'ceymail'@'localhost' IDENTIFIED BY '{password}'; \
-             GRANT ALL PRIVILEGES ON ceymail_db.* TO 'ceymail'@'localhost'; \
+            "CREATE DATABASE IF NOT EXISTS {db}; \
+             CREATE USER IF NOT EXISTS '{user}'@'localhost' IDENTIFIED BY '{password}'; \
+             ALTER USER '{user}'@'localhost' IDENTIFIED BY '{password}'; \
+             GRANT ALL PRIVILEGES ON {db}.* TO '{user}'@'localhost'; \
              FLUSH PRIVILEGES;",
+            db = DB_NAME,
+            user = DB_USER,
             password = db_password,
         );
 
@@ -407,7 +453,12 @@ impl InstallOrchestrator {
             });
         }
 
-        // Store the generated password in the credential store
+        // The service_config step writes it into the Postfix maps
+        self.with_credentials(|store| store.store(DB_CREDENTIAL, &db_password))
+            .map_err(|e| InstallError::StepFailed {
+                step: "database_setup".into(),
+                message: format!("Failed to store the database password: {}", e),
+            })?;
         info!("Database password generated and stored (not logged)");
 
         Ok("Database ceymail_db created and migrations applied".to_string())
@@ -420,30 +471,33 @@ impl InstallOrchestrator {
             "Requesting SSL certificates"
         );
 
-        let output = Command::new("certbot")
-            .args([
-                "certonly",
-                "--apache",
-                "-d",
-                &self.config.hostname,
-                "--non-interactive",
-                "--agree-tos",
-                "--email",
-                &self.config.admin_email,
-            ])
-            .output()
-            .map_err(|e| InstallError::CommandFailed(format!("certbot failed: {}", e)))?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(InstallError::StepFailed {
-                step: "ssl_certificates".into(),
-                message: format!("Certbot failed: {}", stderr),
-            });
-        }
+        let certificate = ManagedCertificate::new(
+            vec![self.config.hostname.clone()],
+            CertificatePurpose::Mail,
+            ChallengeType::Http01,
+        );
+        let contact = self.config.admin_email.clone();
+        // Apache's default site serves the webroot on port 80 at this
+        // point; the ACME exchange blocks while the CA validates
+        tokio::task::spawn_blocking(move || {
+            acme::obtain(
+                acme::LETS_ENCRYPT,
+                &CertificateStore::new(),
+                &certificate,
+                Some(&contact),
+                &Http01Webroot::default(),
+                None,
+            )
+        })
+        .await
+        .map_err(|e| InstallError::CommandFailed(format!("Certificate request failed: {}", e)))?
+        .map_err(|e| InstallError::StepFailed {
+            step: "ssl_certificates".into(),
+            message: format!("Certificate request failed: {}", e),
+        })?;
 
         Ok(format!(
-            "SSL certificate issued for {}. Auto-renewal enabled.",
+            "SSL certificate issued for {}. Mission Control renews it before it expires.",
             self.config.hostname
         ))
     }
@@ -451,6 +505,48 @@ impl InstallOrchestrator {
     async fn step_service_config(&self) -> Result<String, InstallError> {
         info!("Generating service configuration files");
 
+        let applied = self
+            .service_config_changeset()?
+            .write_all()
+            .map_err(|e| InstallError::StepFailed {
+                step: "service_config".into(),
+                message: e.to_string(),
+            })?;
+
+        // The written files are the baseline drift detection compares against
+        let history = ConfigHistory::new();
+        let change = ConfigChange::new("installer", "Service configuration");
+        for (path, previous, content) in applied.changes() {
+            if let Err(e) = history.record(path, previous, content, &change) {
+                warn!("Failed to record config history for {}: {}", path.display(), e);
+            }
+        }
+
+        Ok("Service configuration files generated for Postfix, Dovecot, and OpenDKIM".to_string())
+    }
+
+    /// Run `f` with the credential store.
+    fn with_credentials<T>(
+        &self,
+        f: impl FnOnce(&CredentialStore) -> Result<T, credentials::CredentialError>,
+    ) -> Result<T, credentials::CredentialError> {
+        match &self.credentials {
+            Some(store) => f(store),
+            None => f(&CredentialStore::new(std::path::Path::new(credentials::KEY_PATH))?),
+        }
+    }
+
+    /// The files written by the service_config step. Needs the database
+    /// password stored by the database_setup step.
+    fn service_config_changeset(&self) -> Result<Changeset, InstallError> {
+        let db_password = self
+            .with_credentials(|store| store.retrieve(DB_CREDENTIAL))
+            .map_err(|e| InstallError::StepFailed {
+                step: "service_config".into(),
+                message: format!("Database password unavailable, run database_setup first: {}", e),
+            })?;
+        let mut changeset = Changeset::new();
+
         // Generate Postfix main.cf
         let postfix_main_cf = format!(
             "# CeyMail Postfix Configuration\n\
@@ -464,12 +560,18 @@ impl InstallOrchestrator {
              mydestination = $myhostname, localhost.$mydomain, localhost\n\
              \n\
              # TLS\n\
-             smtpd_tls_cert_file = /etc/letsencrypt/live/{hostname}/fullchain.pem\n\
-             smtpd_tls_key_file = /etc/letsencrypt/live/{hostname}/privkey.pem\n\
+             smtpd_tls_cert_file = {cert}\n\
+             smtpd_tls_key_file = {key}\n\
              smtpd_use_tls = yes\n\
              smtpd_tls_security_level = may\n\
              smtp_tls_security_level = may\n\
              \n\
+             # Authenticated users may only send as their own address, their\n\
+             # aliases, and addresses they hold a send-as grant for\n\
+             smtpd_sender_login_maps = mysql:/etc/postfix/mysql-sender-login-maps.cf\n\
+             smtpd_recipient_restrictions = reject_authenticated_sender_login_mismatch, \
+             permit_sasl_authenticated, permit_mynetworks, reject_unauth_destination\n\
+             \n\
              # Virtual mailbox\n\
              virtual_transport = lmtp:unix:private/dovecot-lmtp\n\
              virtual_mailbox_domains = mysql:/etc/postfix/mysql-virtual-mailbox-domains.cf\n\
@@ -483,13 +585,22 @@ impl InstallOrchestrator {
              non_smtpd_milters = $smtpd_milters\n",
             hostname = self.config.hostname,
             domain = self.config.mail_domain,
+            cert = store::cert_path(&self.config.hostname),
+            key = store::key_path(&self.config.hostname),
         );
 
-        std::fs::write("/etc/postfix/main.cf", &postfix_main_cf)
-            .map_err(|e| InstallError::StepFailed {
-                step: "service_config".into(),
-                message: format!("Failed to write postfix main.cf: {}", e),
-            })?;
+        changeset.stage("/etc/postfix/main.cf", postfix_main_cf);
+        // Holds the database password: readable by Postfix, not by users
+        let postfix_gid = nix::unistd::Group::from_name("postfix")
+            .ok()
+            .flatten()
+            .map(|group| group.gid.as_raw());
+        changeset.stage_owned(
+            "/etc/postfix/mysql-sender-login-maps.cf",
+            PostfixConfig::generate_mysql_sender_login_maps(DB_USER, &db_password, DB_NAME),
+            0o640,
+            postfix_gid.map(|gid| (0, gid)),
+        );
 
         // Generate Dovecot configuration
         let dovecot_conf = format!(
@@ -498,8 +609,8 @@ impl InstallOrchestrator {
              listen = *, ::\n\
              \n\
              ssl = required\n\
-             ssl_cert = </etc/letsencrypt/live/{hostname}/fullchain.pem\n\
-             ssl_key = </etc/letsencrypt/live/{hostname}/privkey.pem\n\
+             ssl_cert = <{cert}\n\
+             ssl_key = <{key}\n\
              ssl_min_protocol = TLSv1.2\n\
              \n\
              mail_location = maildir:/var/mail/vhosts/%d/%n\n\
@@ -516,14 +627,11 @@ impl InstallOrchestrator {
                driver = static\n\
                args = uid=vmail gid=vmail home=/var/mail/vhosts/%d/%n\n\
              }}\n",
-            hostname = self.config.hostname,
+            cert = store::cert_path(&self.config.hostname),
+            key = store::key_path(&self.config.hostname),
         );
 
-        std::fs::write("/etc/dovecot/dovecot.conf", &dovecot_conf)
-            .map_err(|e| InstallError::StepFailed {
-                step: "service_config".into(),
-                message: format!("Failed to write dovecot.conf: {}", e),
-            })?;
+        changeset.stage("/etc/dovecot/dovecot.conf", dovecot_conf);
 
         // Generate OpenDKIM configuration
         let opendkim_conf = format!(
@@ -543,44 +651,22 @@ impl InstallOrchestrator {
             domain = self.config.mail_domain,
         );
 
-        std::fs::write("/etc/opendkim.conf", &opendkim_conf)
-            .map_err(|e| InstallError::StepFailed {
-                step: "service_config".into(),
-                message: format!("Failed to write opendkim.conf: {}", e),
-            })?;
+        changeset.stage("/etc/opendkim.conf", opendkim_conf);
 
-        Ok("Service configuration files generated for Postfix, Dovecot, and OpenDKIM".to_string())
+        Ok(changeset)
     }
 
     async fn step_dkim_setup(&self) -> Result<String, InstallError> {
         let domain = &self.config.mail_domain;
         info!(domain = %domain, "Generating DKIM keys");
 
-        // Create key directory
-        let key_dir = format!("/etc/opendkim/keys/{}", domain);
-        std::fs::create_dir_all(&key_dir).map_err(|e| InstallError::StepFailed {
-            step: "dkim_setup".into(),
-            message: format!("Failed to create DKIM key directory: {}", e),
-        })?;
-
-        // Generate DKIM key pair using opendkim-genkey
-        let output = Command::new("opendkim-genkey")
-            .args(["-b", "2048", "-d", domain, "-D", &key_dir, "-s", "mail", "-v"])
-            .output()
-            .map_err(|e| InstallError::CommandFailed(format!("opendkim-genkey failed: {}", e)))?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(InstallError::StepFailed {
+        let key_dir = std::path::Path::new("/etc/opendkim/keys").join(domain);
+        dkim::generate_selector_key(&key_dir, domain, "mail", DkimAlgorithm::Rsa2048).map_err(|e| {
+            InstallError::StepFailed {
                 step: "dkim_setup".into(),
-                message: format!("DKIM key generation failed: {}", stderr),
-            });
-        }
-
-        // Fix ownership
-        let _ = Command::new("chown")
-            .args(["-R", "opendkim:opendkim", "/etc/opendkim/keys"])
-            .output();
+                message: format!("DKIM key generation failed: {}", e),
+            }
+        })?;
 
         Ok(format!(
             "DKIM keys generated for {}. Selector: mail._domainkey.{}",
@@ -828,4 +914,44 @@ mod tests {
         assert_eq!(orch.get_config().admin_email, "admin@example.com");
         assert_eq!(orch.get_config().php_version, "8.2");
     }
+
+    #[test]
+    fn test_preview_service_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let store =
+            CredentialStore::with_credentials_dir(&dir.path().join("key.txt"), &dir.path().join("credentials")).unwrap();
+        let orch = InstallOrchestrator::new(test_config()).with_credential_store(store);
+        // The database step has not stored the password yet
+        assert!(orch.service_config_changeset().is_err());
+        orch.with_credentials(|store| store.store(DB_CREDENTIAL, "secret")).unwrap();
+
+        let changeset = orch.service_config_changeset().unwrap();
+        let files: Vec<_> = changeset.files().map(|f| f.path.clone()).collect();
+        assert_eq!(files.len(), 4);
+        assert!(files.iter().any(|p| p.ends_with("postfix/main.cf")));
+
+        // Authenticated senders are held to their own addresses
+        let main_cf = &changeset.get(std::path::Path::new("/etc/postfix/main.cf")).unwrap().content;
+        let postfix = PostfixConfig::parse(main_cf).unwrap();
+        assert_eq!(
+            postfix.smtpd_sender_login_maps(),
+            Some("mysql:/etc/postfix/mysql-sender-login-maps.cf")
+        );
+        assert!(postfix.validate().unwrap().iter().all(|w| !w.contains("sender")));
+        let map = changeset
+            .get(std::path::Path::new("/etc/postfix/mysql-sender-login-maps.cf"))
+            .unwrap();
+        assert_eq!(map.mode, 0o640);
+        assert!(map.content.contains("user = ceymail\npassword = secret\n"));
+        assert!(map.content.contains("dbname = ceymail_db\n"));
+
+        let index = orch.get_steps().iter().position(|s| s.name == "service_config").unwrap();
+        for diff in orch.step_changeset(index).unwrap().preview().unwrap() {
+            if !diff.path.ends_with("mysql-sender-login-maps.cf") {
+                assert!(diff.diff.contains("CeyMail"));
+            }
+        }
+        assert!(orch.step_changeset(0).is_err());
+        assert!(orch.step_changeset(99).is_err());
+    }
 }
@@ -14,8 +14,6 @@ pub enum PackageError {
 /// These are installed via `apt-get` on Debian/Ubuntu systems.
 pub const CORE_PACKAGES: &[&str] = &[
     "apache2",
-    "certbot",
-    "python3-certbot-apache",
     "wget",
     "unzip",
     "curl",
@@ -166,7 +164,6 @@ mod tests {
         assert!(CORE_PACKAGES.contains(&"opendkim"));
         assert!(CORE_PACKAGES.contains(&"mariadb-server"));
         assert!(CORE_PACKAGES.contains(&"apache2"));
-        assert!(CORE_PACKAGES.contains(&"certbot"));
         assert!(CORE_PACKAGES.contains(&"spamassassin"));
         assert!(CORE_PACKAGES.contains(&"unbound"));
     }
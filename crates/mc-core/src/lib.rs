@@ -2,6 +2,7 @@ pub mod config;
 pub mod service;
 pub mod system;
 pub mod mail;
+pub mod acme;
 pub mod install;
 pub mod security;
 pub mod fs;
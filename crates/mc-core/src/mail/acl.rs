@@ -0,0 +1,308 @@
+//! IMAP ACL rights for shared mailboxes (Dovecot `acl` / `imap_acl` plugins).
+//!
+//! The dashboard exposes six coarse rights. Each maps onto one or more of
+//! Dovecot's RFC 4314 rights, so granting "read" also lets the grantee mark
+//! messages as seen, which is what people expect from a shared inbox.
+//! Grants are applied with `doveadm acl`, which also keeps Dovecot's
+//! `acl_shared_dict` up to date so the mailbox shows up under `shared/`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::process::Command;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::security::idn::normalize_email;
+use crate::security::input::assert_no_shell_metacharacters;
+
+#[derive(Debug, Error)]
+pub enum AclError {
+    #[error("Unknown mailbox right: {0}")]
+    InvalidRight(String),
+    #[error("Invalid mailbox name: {0}")]
+    InvalidMailbox(String),
+    #[error("Invalid user: {0}")]
+    InvalidUser(String),
+    #[error("No rights given")]
+    EmptyRights,
+    #[error("doveadm not found. Is Dovecot installed?")]
+    ToolNotFound,
+    #[error("doveadm acl failed: {0}")]
+    CommandFailed(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A right that can be granted on a mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailboxRight {
+    /// See the mailbox in LIST and subscribe to it.
+    Lookup,
+    /// Open the mailbox and read messages; mark them as seen.
+    Read,
+    /// Change flags and keywords other than \Deleted.
+    Write,
+    /// Append or copy messages into the mailbox.
+    Insert,
+    /// Flag messages \Deleted and expunge them.
+    Delete,
+    /// Change the mailbox ACL and manage child mailboxes.
+    Admin,
+}
+
+impl MailboxRight {
+    pub const ALL: [MailboxRight; 6] = [
+        MailboxRight::Lookup,
+        MailboxRight::Read,
+        MailboxRight::Write,
+        MailboxRight::Insert,
+        MailboxRight::Delete,
+        MailboxRight::Admin,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lookup => "lookup",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Insert => "insert",
+            Self::Delete => "delete",
+            Self::Admin => "admin",
+        }
+    }
+
+    /// Dovecot ACL right names this right expands to.
+    pub fn dovecot_rights(self) -> &'static [&'static str] {
+        match self {
+            Self::Lookup => &["lookup"],
+            Self::Read => &["read", "write-seen"],
+            Self::Write => &["write"],
+            Self::Insert => &["insert", "post"],
+            Self::Delete => &["write-deleted", "expunge"],
+            Self::Admin => &["admin", "create", "delete"],
+        }
+    }
+}
+
+impl fmt::Display for MailboxRight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MailboxRight {
+    type Err = AclError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|r| r.as_str() == s.trim().to_ascii_lowercase())
+            .ok_or_else(|| AclError::InvalidRight(s.to_string()))
+    }
+}
+
+/// A non-empty set of [`MailboxRight`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxRights(BTreeSet<MailboxRight>);
+
+impl MailboxRights {
+    pub fn new(rights: impl IntoIterator<Item = MailboxRight>) -> Result<Self, AclError> {
+        let set: BTreeSet<MailboxRight> = rights.into_iter().collect();
+        if set.is_empty() {
+            return Err(AclError::EmptyRights);
+        }
+        Ok(Self(set))
+    }
+
+    /// Parse the comma-separated form stored in the mail DB, e.g. `"lookup,read"`.
+    pub fn parse(s: &str) -> Result<Self, AclError> {
+        let rights = s
+            .split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(MailboxRight::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(rights)
+    }
+
+    pub fn contains(&self, right: MailboxRight) -> bool {
+        self.0.contains(&right)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = MailboxRight> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Dovecot right names for `doveadm acl set`, deduplicated.
+    ///
+    /// Lookup is always included: a grantee who cannot see the mailbox
+    /// cannot use any other right on it.
+    pub fn dovecot_rights(&self) -> Vec<&'static str> {
+        let mut out: Vec<&'static str> = MailboxRight::Lookup.dovecot_rights().to_vec();
+        for right in &self.0 {
+            for name in right.dovecot_rights() {
+                if !out.contains(name) {
+                    out.push(name);
+                }
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for MailboxRights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.0.iter().map(|r| r.as_str()).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+/// Validate an IMAP mailbox (folder) name such as `INBOX` or `Projects/2026`.
+///
+/// Allows letters (including non-ASCII), digits, spaces and `/ . _ -`, with `/`
+/// as the hierarchy separator. Rejects traversal, empty segments and anything
+/// a shell or Dovecot pattern would interpret.
+pub fn validate_mailbox_name(name: &str) -> Result<&str, AclError> {
+    let invalid = || AclError::InvalidMailbox(name.to_string());
+    if name.is_empty() || name.len() > 255 {
+        return Err(invalid());
+    }
+    assert_no_shell_metacharacters(name).map_err(|_| invalid())?;
+    if name.contains(['*', '%', '\\', '"']) {
+        return Err(invalid());
+    }
+    for segment in name.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." || segment.trim() != segment {
+            return Err(invalid());
+        }
+        if !segment
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '.' | '_' | '-'))
+        {
+            return Err(invalid());
+        }
+    }
+    Ok(name)
+}
+
+/// Grant `grantee` the given rights on `owner`'s mailbox, replacing any
+/// rights they already had there.
+pub fn apply_acl(
+    owner: &str,
+    mailbox: &str,
+    grantee: &str,
+    rights: &MailboxRights,
+) -> Result<(), AclError> {
+    validate_user(owner)?;
+    validate_user(grantee)?;
+    validate_mailbox_name(mailbox)?;
+
+    let mut cmd = Command::new("doveadm");
+    cmd.args(["acl", "set", "-u", owner, mailbox])
+        .arg(format!("user={}", grantee))
+        .args(rights.dovecot_rights());
+    run_doveadm(cmd)?;
+
+    info!("Set ACL on {}:{} for {}: {}", owner, mailbox, grantee, rights);
+    Ok(())
+}
+
+/// Remove every right `grantee` holds on `owner`'s mailbox.
+pub fn remove_acl(owner: &str, mailbox: &str, grantee: &str) -> Result<(), AclError> {
+    validate_user(owner)?;
+    validate_user(grantee)?;
+    validate_mailbox_name(mailbox)?;
+
+    let mut cmd = Command::new("doveadm");
+    cmd.args(["acl", "delete", "-u", owner, mailbox])
+        .arg(format!("user={}", grantee));
+    run_doveadm(cmd)?;
+
+    info!("Removed ACL on {}:{} for {}", owner, mailbox, grantee);
+    Ok(())
+}
+
+/// Users are stored addresses, which may carry a UTF-8 local part.
+fn validate_user(address: &str) -> Result<(), AclError> {
+    normalize_email(address, true)
+        .map(|_| ())
+        .map_err(|e| AclError::InvalidUser(e.to_string()))
+}
+
+fn run_doveadm(mut cmd: Command) -> Result<(), AclError> {
+    let output = cmd.output().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            AclError::ToolNotFound
+        } else {
+            AclError::Io(e)
+        }
+    })?;
+    if !output.status.success() {
+        return Err(AclError::CommandFailed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rights() {
+        let rights = MailboxRights::parse("read, lookup,INSERT").unwrap();
+        assert!(rights.contains(MailboxRight::Read));
+        assert!(rights.contains(MailboxRight::Insert));
+        assert!(!rights.contains(MailboxRight::Admin));
+        // Stored form is normalized and ordered
+        assert_eq!(rights.to_string(), "lookup,read,insert");
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_and_empty() {
+        assert!(matches!(
+            MailboxRights::parse("read,sudo"),
+            Err(AclError::InvalidRight(_))
+        ));
+        assert!(matches!(MailboxRights::parse(""), Err(AclError::EmptyRights)));
+    }
+
+    #[test]
+    fn test_dovecot_rights_mapping() {
+        let rights = MailboxRights::new([MailboxRight::Read, MailboxRight::Delete]).unwrap();
+        assert_eq!(
+            rights.dovecot_rights(),
+            vec!["lookup", "read", "write-seen", "write-deleted", "expunge"]
+        );
+
+        let admin = MailboxRights::new([MailboxRight::Lookup, MailboxRight::Admin]).unwrap();
+        assert_eq!(
+            admin.dovecot_rights(),
+            vec!["lookup", "admin", "create", "delete"]
+        );
+    }
+
+    #[test]
+    fn test_valid_mailbox_names() {
+        assert!(validate_mailbox_name("INBOX").is_ok());
+        assert!(validate_mailbox_name("Projects/2026").is_ok());
+        assert!(validate_mailbox_name("Sent Messages").is_ok());
+        assert!(validate_mailbox_name("Entwürfe").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_mailbox_names() {
+        assert!(validate_mailbox_name("").is_err());
+        assert!(validate_mailbox_name("../other").is_err());
+        assert!(validate_mailbox_name("/INBOX").is_err());
+        assert!(validate_mailbox_name("INBOX/").is_err());
+        assert!(validate_mailbox_name("a//b").is_err());
+        assert!(validate_mailbox_name("*").is_err());
+        assert!(validate_mailbox_name("INBOX; rm -rf /").is_err());
+        assert!(validate_mailbox_name("INBOX\nuser=evil").is_err());
+    }
+}
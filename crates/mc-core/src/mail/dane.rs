@@ -0,0 +1,425 @@
+//! DANE for inbound SMTP (RFC 7672): the TLSA records that let DNSSEC-
+//! validating senders authenticate this server's certificate.
+//!
+//! Records are `3 1 1` (DANE-EE, SubjectPublicKeyInfo, SHA-256): they pin
+//! the server's public key rather than the certificate, so a renewal that
+//! keeps the key needs no DNS change. One that changes the key must not
+//! go live before DNS serves a record for the new key, or DANE senders
+//! stop delivering until it does. A key change therefore goes:
+//!
+//! 1. the next key is generated ahead of the renewal ([`generate_next_key`])
+//! 2. the operator publishes its record next to the current one
+//! 3. once DNS serves it ([`DaneKeys::next_published`]), the certificate is
+//!    renewed with the next key
+//! 4. the old key's record is removed
+//!
+//! [`check_tlsa`] compares what DNS serves with the live certificate.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rand::rngs::OsRng;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+use x509_cert::der::Encode;
+use x509_cert::Certificate;
+
+use crate::mail::dns_check::{CheckKind, CheckResult, CheckStatus};
+use crate::system::dns::DnsResolver;
+
+/// Where next keys wait for the renewal that puts them to use.
+pub const NEXT_KEY_DIR: &str = "/etc/ceymail-mc/dane";
+
+/// DANE-EE: the record names the server's own key, not a CA.
+pub const USAGE_DANE_EE: u8 = 3;
+/// The record covers the SubjectPublicKeyInfo.
+pub const SELECTOR_SPKI: u8 = 1;
+/// The record holds a SHA-256 digest.
+pub const MATCHING_SHA256: u8 = 1;
+
+const NEXT_KEY_BITS: usize = 2048;
+
+#[derive(Debug, Error)]
+pub enum DaneError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid certificate: {0}")]
+    Certificate(String),
+    #[error("Invalid key: {0}")]
+    Key(String),
+    #[error("Invalid TLSA record: {0}")]
+    InvalidRecord(String),
+}
+
+/// One TLSA record's data.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TlsaRecord {
+    pub usage: u8,
+    pub selector: u8,
+    pub matching: u8,
+    pub data: Vec<u8>,
+}
+
+impl TlsaRecord {
+    /// The `3 1 1` record for a DER-encoded SubjectPublicKeyInfo.
+    pub fn for_spki(spki: &[u8]) -> Self {
+        Self {
+            usage: USAGE_DANE_EE,
+            selector: SELECTOR_SPKI,
+            matching: MATCHING_SHA256,
+            data: Sha256::digest(spki).to_vec(),
+        }
+    }
+
+    /// Whether the record pins the key `spki` as a server key. Records
+    /// naming a CA or a whole certificate never match.
+    pub fn matches_spki(&self, spki: &[u8]) -> bool {
+        if self.usage != USAGE_DANE_EE || self.selector != SELECTOR_SPKI {
+            return false;
+        }
+        match self.matching {
+            0 => self.data == spki,
+            1 => self.data == Sha256::digest(spki).as_slice(),
+            2 => self.data == Sha512::digest(spki).as_slice(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TlsaRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.usage,
+            self.selector,
+            self.matching,
+            hex::encode_upper(&self.data)
+        )
+    }
+}
+
+impl FromStr for TlsaRecord {
+    type Err = DaneError;
+
+    /// Parse record data as a zone file writes it; the digest may be
+    /// split by whitespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DaneError::InvalidRecord(s.to_string());
+        let mut fields = s.split_whitespace();
+        let mut number = || -> Result<u8, DaneError> {
+            fields.next().and_then(|f| f.parse().ok()).ok_or_else(invalid)
+        };
+        let (usage, selector, matching) = (number()?, number()?, number()?);
+        let data = hex::decode(fields.collect::<String>()).map_err(|_| invalid())?;
+        if data.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            usage,
+            selector,
+            matching,
+            data,
+        })
+    }
+}
+
+/// The name TLSA records for SMTP on `hostname` are published at.
+pub fn tlsa_name(hostname: &str) -> String {
+    format!("_25._tcp.{}", hostname.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// The DER SubjectPublicKeyInfo of the first certificate in a PEM chain,
+/// which for `smtpd_tls_cert_file` is the server's own.
+pub fn spki_from_cert_pem(pem: &str) -> Result<Vec<u8>, DaneError> {
+    let chain = Certificate::load_pem_chain(pem.as_bytes())
+        .map_err(|e| DaneError::Certificate(e.to_string()))?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| DaneError::Certificate("no certificate in the file".to_string()))?;
+    leaf.tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| DaneError::Certificate(e.to_string()))
+}
+
+/// The DER SubjectPublicKeyInfo of an RSA private key in PKCS#8 or
+/// PKCS#1 PEM.
+pub fn spki_from_key_pem(pem: &str) -> Result<Vec<u8>, DaneError> {
+    let key = RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .map_err(|e| DaneError::Key(e.to_string()))?;
+    key.to_public_key()
+        .to_public_key_der()
+        .map(|der| der.as_bytes().to_vec())
+        .map_err(|e| DaneError::Key(e.to_string()))
+}
+
+/// A new RSA key to renew with, as PKCS#8 PEM. RSA because every sending
+/// TLS stack supports it.
+pub fn generate_next_key() -> Result<String, DaneError> {
+    let key = RsaPrivateKey::new(&mut OsRng, NEXT_KEY_BITS).map_err(|e| DaneError::Key(e.to_string()))?;
+    key.to_pkcs8_pem(LineEnding::LF)
+        .map(|pem| pem.to_string())
+        .map_err(|e| DaneError::Key(e.to_string()))
+}
+
+/// The next key of `hostname` under `dir`.
+pub fn next_key_path(dir: &Path, hostname: &str) -> PathBuf {
+    dir.join(format!("{}.next-key.pem", hostname.trim_end_matches('.').to_ascii_lowercase()))
+}
+
+/// The key the server presents, and the one it will present after the
+/// next key change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaneKeys {
+    /// SubjectPublicKeyInfo of the live certificate.
+    pub current: Vec<u8>,
+    /// SubjectPublicKeyInfo of the next key, when one is waiting.
+    pub next: Option<Vec<u8>>,
+}
+
+impl DaneKeys {
+    /// Keys from the certificate chain at `cert_path` and, if it exists,
+    /// the key at `next_key_path`. A next key the certificate already
+    /// uses is no longer next.
+    pub fn load(cert_path: &Path, next_key_path: &Path) -> Result<Self, DaneError> {
+        let current = spki_from_cert_pem(&std::fs::read_to_string(cert_path)?)?;
+        let next = match std::fs::read_to_string(next_key_path) {
+            Ok(pem) => Some(spki_from_key_pem(&pem)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::new(current, next))
+    }
+
+    pub fn new(current: Vec<u8>, next: Option<Vec<u8>>) -> Self {
+        let next = next.filter(|next| *next != current);
+        Self { current, next }
+    }
+
+    pub fn current_record(&self) -> TlsaRecord {
+        TlsaRecord::for_spki(&self.current)
+    }
+
+    pub fn next_record(&self) -> Option<TlsaRecord> {
+        self.next.as_deref().map(TlsaRecord::for_spki)
+    }
+
+    /// The records to publish: the current key's, then the next key's.
+    pub fn records(&self) -> Vec<TlsaRecord> {
+        std::iter::once(self.current_record())
+            .chain(self.next_record())
+            .collect()
+    }
+
+    /// Whether `published` covers the next key, so the certificate can
+    /// change to it. `false` when there is no next key.
+    pub fn next_published(&self, published: &[TlsaRecord]) -> bool {
+        self.next
+            .as_deref()
+            .is_some_and(|next| published.iter().any(|r| r.matches_spki(next)))
+    }
+}
+
+/// Check the TLSA records of `hostname` against `keys`.
+///
+/// Fails when records are published but none matches the live key, as
+/// DANE senders then refuse to deliver. Warns when nothing is published,
+/// or when a waiting next key is not published yet.
+pub fn check_tlsa(resolver: &dyn DnsResolver, hostname: &str, keys: &DaneKeys) -> CheckResult {
+    let name = tlsa_name(hostname);
+    let (status, detail) = tlsa_status(resolver, &name, keys);
+    CheckResult {
+        kind: CheckKind::Tlsa,
+        record: name,
+        status,
+        detail,
+    }
+}
+
+fn tlsa_status(resolver: &dyn DnsResolver, name: &str, keys: &DaneKeys) -> (CheckStatus, String) {
+    let published = match resolver.lookup_tlsa(name) {
+        Ok(published) => published,
+        Err(e) => return (CheckStatus::Fail, e.to_string()),
+    };
+    let publish = keys
+        .records()
+        .iter()
+        .map(|r| format!("TLSA {}", r))
+        .collect::<Vec<_>>()
+        .join(" and ");
+
+    if published.is_empty() {
+        return (
+            CheckStatus::Warn,
+            format!("No TLSA records are published, so DANE is not in use; publish: {}", publish),
+        );
+    }
+    if !published.iter().any(|r| r.matches_spki(&keys.current)) {
+        return (
+            CheckStatus::Fail,
+            format!(
+                "The published TLSA records no longer match the certificate's key, \
+                 and DANE senders will not deliver; publish: {}",
+                publish
+            ),
+        );
+    }
+    if let Some(next) = keys.next_record() {
+        if !keys.next_published(&published) {
+            return (
+                CheckStatus::Warn,
+                format!(
+                    "The next key is not published; publish TLSA {} before renewing the certificate",
+                    next
+                ),
+            );
+        }
+    }
+
+    let stale = published
+        .iter()
+        .filter(|r| !r.matches_spki(&keys.current))
+        .filter(|r| !keys.next.as_deref().is_some_and(|next| r.matches_spki(next)))
+        .count();
+    let mut detail = match keys.next {
+        Some(_) => "Publishes the current and next keys".to_string(),
+        None => "Publishes the certificate's key".to_string(),
+    };
+    if stale > 0 {
+        detail.push_str(&format!("; {} other record(s) can be removed", stale));
+    }
+    (CheckStatus::Pass, detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use crate::system::dns::NativeResolver;
+    use crate::system::dns_stub::StubDns;
+
+    // Self-signed P-256 certificate for mail.example.com
+    const CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBijCCATGgAwIBAgIUR91mK8Vy4ph6zNZqMmaZsJ6snJkwCgYIKoZIzj0EAwIw
+GzEZMBcGA1UEAwwQbWFpbC5leGFtcGxlLmNvbTAeFw0yNjEwMTgxNDQyNTlaFw0z
+NjEwMTUxNDQyNTlaMBsxGTAXBgNVBAMMEG1haWwuZXhhbXBsZS5jb20wWTATBgcq
+hkjOPQIBBggqhkjOPQMBBwNCAASYHY14cePzGbvaINMlvQe8k96HavJqSBcy818f
+eo3FIKGO4iEW5o91V0POx0A1k4QmR27wNiqNy9RNQRPeQH8lo1MwUTAdBgNVHQ4E
+FgQUVJadkLaQ/41pn2hC1BYi7aFQg3kwHwYDVR0jBBgwFoAUVJadkLaQ/41pn2hC
+1BYi7aFQg3kwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiA0gnc7
+Ozx+CbEothTB0tJiBIucWCgOaFG2+VvejYHsQQIgO6+eFuQCRe5tCUQqdaIyOXq0
+nTwiXkbwKk/My6EGjhs=
+-----END CERTIFICATE-----
+";
+
+    // openssl x509 -pubkey | openssl pkey -pubin -outform DER | sha256sum
+    const CERT_SPKI_SHA256: &str = "c000db925dc99a4ed555e9ce9533117d811c674a6b3328cc578db76fc3654871";
+
+    fn resolver(server: SocketAddr) -> NativeResolver {
+        NativeResolver::with_nameservers(vec![server]).with_timeout(Duration::from_secs(2))
+    }
+
+    #[test]
+    fn test_record_from_certificate() {
+        let spki = spki_from_cert_pem(CERT).unwrap();
+        let record = TlsaRecord::for_spki(&spki);
+        assert_eq!(hex::encode(&record.data), CERT_SPKI_SHA256);
+        assert_eq!(record.to_string(), format!("3 1 1 {}", CERT_SPKI_SHA256.to_uppercase()));
+        assert!(record.matches_spki(&spki));
+
+        let chain = format!("{}{}", CERT, CERT);
+        assert_eq!(spki_from_cert_pem(&chain).ok(), Some(spki));
+        assert!(spki_from_cert_pem("not a certificate").is_err());
+    }
+
+    #[test]
+    fn test_record_parse() {
+        let text = format!("3 1 1 {} {}", &CERT_SPKI_SHA256[..32], &CERT_SPKI_SHA256[32..]);
+        let record: TlsaRecord = text.parse().unwrap();
+        assert_eq!((record.usage, record.selector, record.matching), (3, 1, 1));
+        assert!(record.matches_spki(&spki_from_cert_pem(CERT).unwrap()));
+        assert_eq!(record.to_string().parse::<TlsaRecord>().unwrap(), record);
+
+        for bad in ["", "3 1 1", "3 1 x ab", "3 1 1 xyz", "300 1 1 ab"] {
+            assert!(bad.parse::<TlsaRecord>().is_err(), "{:?} accepted", bad);
+        }
+
+        // A CA or full-certificate record does not pin the server key
+        let spki = spki_from_cert_pem(CERT).unwrap();
+        let ca = TlsaRecord { usage: 2, ..TlsaRecord::for_spki(&spki) };
+        assert!(!ca.matches_spki(&spki));
+        let full = TlsaRecord { matching: 0, data: spki.clone(), ..TlsaRecord::for_spki(&spki) };
+        assert!(full.matches_spki(&spki));
+    }
+
+    #[test]
+    fn test_next_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert_path = dir.path().join("fullchain.pem");
+        std::fs::write(&cert_path, CERT).unwrap();
+        let next_path = next_key_path(dir.path(), "Mail.Example.com.");
+        assert_eq!(next_path.file_name().unwrap(), "mail.example.com.next-key.pem");
+
+        let keys = DaneKeys::load(&cert_path, &next_path).unwrap();
+        assert_eq!(keys.next, None);
+        assert_eq!(keys.records().len(), 1);
+
+        let pem = generate_next_key().unwrap();
+        std::fs::write(&next_path, &pem).unwrap();
+        let keys = DaneKeys::load(&cert_path, &next_path).unwrap();
+        let next = keys.next_record().unwrap();
+        assert_eq!(keys.records(), vec![keys.current_record(), next.clone()]);
+        assert!(!keys.next_published(&[keys.current_record()]));
+        assert!(keys.next_published(&[keys.current_record(), next]));
+
+        // Once the certificate uses the next key it is the current one
+        let spki = spki_from_key_pem(&pem).unwrap();
+        let renewed = DaneKeys::new(spki.clone(), Some(spki));
+        assert_eq!(renewed.next, None);
+    }
+
+    #[test]
+    fn test_check_tlsa() {
+        let current = spki_from_cert_pem(CERT).unwrap();
+        let next = spki_from_key_pem(&generate_next_key().unwrap()).unwrap();
+        let stale = TlsaRecord::for_spki(b"an old key");
+        let dns = StubDns::new()
+            .tlsa("_25._tcp.current.example.com", &TlsaRecord::for_spki(&current))
+            .tlsa("_25._tcp.rolling.example.com", &TlsaRecord::for_spki(&current))
+            .tlsa("_25._tcp.rolling.example.com", &TlsaRecord::for_spki(&next))
+            .tlsa("_25._tcp.rolling.example.com", &stale)
+            .tlsa("_25._tcp.stale.example.com", &stale)
+            .start();
+        let resolver = resolver(dns);
+        let keys = DaneKeys::new(current.clone(), None);
+        let rolling = DaneKeys::new(current, Some(next));
+
+        let check = check_tlsa(&resolver, "current.example.com", &keys);
+        assert_eq!((check.kind, check.status), (CheckKind::Tlsa, CheckStatus::Pass));
+        assert_eq!(check.record, "_25._tcp.current.example.com");
+
+        let check = check_tlsa(&resolver, "current.example.com", &rolling);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(check.detail.contains("before renewing"));
+
+        let check = check_tlsa(&resolver, "rolling.example.com", &rolling);
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.detail.ends_with("1 other record(s) can be removed"));
+
+        let check = check_tlsa(&resolver, "stale.example.com", &keys);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("no longer match"));
+        assert!(check.detail.contains(&keys.current_record().to_string()));
+
+        let check = check_tlsa(&resolver, "none.example.com", &keys);
+        assert_eq!(check.status, CheckStatus::Warn);
+    }
+}
@@ -0,0 +1,476 @@
+//! DKIM key rotation with overlapping publication.
+//!
+//! A rotation moves a domain from one selector to a new one without a
+//! window where receivers cannot find the signing key:
+//!
+//! 1. a key is generated for a new, date-based selector and added to the
+//!    key table, while the old selector keeps signing
+//! 2. the operator publishes the new TXT record ([`Rotation::instructions`])
+//! 3. once DNS serves the new key, the signing table switches to it
+//! 4. the old selector stays in the key table, and in DNS, for a grace
+//!    period so mail signed just before the switch still verifies
+//! 5. the old selector is retired
+//!
+//! [`Rotation::advance`] moves through steps 3 to 5 and is meant to be
+//! called periodically; [`RotationStore::due`] says which signing keys are
+//! due for a new rotation under a [`RotationPolicy`]. A domain signing with
+//! both RSA and Ed25519 rotates each key separately.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::config::opendkim::{DkimDomainEntry, OpendkimConfig, OpendkimConfigError};
+use crate::fs::atomic::{atomic_write, AtomicWriteError};
+use crate::mail::dkim::{public_key_tag, DkimAlgorithm};
+use crate::system::dns::TxtResolver;
+
+/// Where rotation state is kept between runs.
+pub const ROTATION_STATE_PATH: &str = "/var/lib/ceymail-mc/dkim-rotations.json";
+
+#[derive(Debug, Error)]
+pub enum RotationError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Write failed: {0}")]
+    Write(#[from] AtomicWriteError),
+    #[error("Corrupt rotation state: {0}")]
+    Corrupt(String),
+    #[error("Domain has no signing key: {0}")]
+    NotSigning(String),
+    #[error("A rotation is already in progress for this key type: {0}")]
+    InProgress(String),
+    #[error("OpenDKIM config error: {0}")]
+    Config(#[from] OpendkimConfigError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationPhase {
+    /// The new key is in the key table but not signing yet; waiting for
+    /// DNS to serve it.
+    Publishing,
+    /// Signing with the new key; the old one is still published.
+    Overlap,
+    /// The old selector has been retired.
+    Complete,
+}
+
+/// What a call to [`Rotation::advance`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationStep {
+    /// DNS does not serve the new key yet.
+    AwaitingDns,
+    /// The signing table now names the new selector.
+    Switched,
+    /// Still inside the grace period.
+    Overlapping,
+    /// The old selector left the key table; its key files can go.
+    Retired,
+    /// Nothing left to do.
+    Complete,
+}
+
+impl RotationStep {
+    /// Whether the OpenDKIM tables changed and need writing.
+    pub fn changes_config(self) -> bool {
+        matches!(self, RotationStep::Switched | RotationStep::Retired)
+    }
+}
+
+/// One domain's move from `old_selector` to `new_selector`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rotation {
+    pub domain: String,
+    pub old_selector: String,
+    pub new_selector: String,
+    /// Type of the new key, the same as the old one's.
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+    /// The TXT value to publish for the new selector.
+    pub txt_value: String,
+    pub phase: RotationPhase,
+    pub started_at: DateTime<Utc>,
+    pub switched_at: Option<DateTime<Utc>>,
+    /// When the old selector may be retired.
+    pub retire_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl Rotation {
+    pub fn start(
+        domain: &str,
+        old_selector: &str,
+        new_selector: &str,
+        algorithm: DkimAlgorithm,
+        txt_value: &str,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            domain: domain.to_string(),
+            old_selector: old_selector.to_string(),
+            new_selector: new_selector.to_string(),
+            algorithm,
+            txt_value: txt_value.to_string(),
+            phase: RotationPhase::Publishing,
+            started_at: now,
+            switched_at: None,
+            retire_at: None,
+            completed_at: None,
+        }
+    }
+
+    /// The DNS name the new key is published at.
+    pub fn record_name(&self) -> String {
+        format!("{}._domainkey.{}", self.new_selector, self.domain)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.phase != RotationPhase::Complete
+    }
+
+    /// What the operator needs to do for the rotation to progress.
+    pub fn instructions(&self) -> String {
+        match self.phase {
+            RotationPhase::Publishing => format!(
+                "Publish a TXT record at {} with the value:\n  {}\n\
+                 Keep {}._domainkey.{} published. Signing switches to the new \
+                 key once DNS serves it.",
+                self.record_name(),
+                self.txt_value,
+                self.old_selector,
+                self.domain
+            ),
+            RotationPhase::Overlap => format!(
+                "Signing with {}. Keep {}._domainkey.{} published until {}; it is \
+                 retired after that.",
+                self.record_name(),
+                self.old_selector,
+                self.domain,
+                self.retire_at
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_else(|| "the grace period ends".to_string())
+            ),
+            RotationPhase::Complete => format!(
+                "Rotation complete. The TXT record at {}._domainkey.{} can be removed.",
+                self.old_selector, self.domain
+            ),
+        }
+    }
+
+    /// Move the rotation on as far as DNS and the clock allow, updating
+    /// `config` to match. The caller writes the config when the returned
+    /// step [changes it](RotationStep::changes_config), and deletes the old
+    /// key files after [`RotationStep::Retired`].
+    pub fn advance(
+        &mut self,
+        config: &mut OpendkimConfig,
+        resolver: &dyn TxtResolver,
+        grace: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<RotationStep, RotationError> {
+        match self.phase {
+            RotationPhase::Publishing => {
+                if !self.published(resolver) {
+                    return Ok(RotationStep::AwaitingDns);
+                }
+                config.set_signing_selector(&self.domain, &self.new_selector)?;
+                self.phase = RotationPhase::Overlap;
+                self.switched_at = Some(now);
+                self.retire_at = Some(now + grace);
+                info!(
+                    "DKIM rotation for {}: signing with {}",
+                    self.domain, self.new_selector
+                );
+                Ok(RotationStep::Switched)
+            }
+            RotationPhase::Overlap => {
+                if self.retire_at.is_some_and(|t| now < t) {
+                    return Ok(RotationStep::Overlapping);
+                }
+                match config.remove_selector(&self.domain, &self.old_selector) {
+                    Ok(()) | Err(OpendkimConfigError::SelectorNotFound(_)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                self.phase = RotationPhase::Complete;
+                self.completed_at = Some(now);
+                info!(
+                    "DKIM rotation for {}: retired {}",
+                    self.domain, self.old_selector
+                );
+                Ok(RotationStep::Retired)
+            }
+            RotationPhase::Complete => Ok(RotationStep::Complete),
+        }
+    }
+
+    /// Whether DNS serves the new public key.
+    fn published(&self, resolver: &dyn TxtResolver) -> bool {
+        let Some(expected) = public_key_tag(&self.txt_value) else {
+            return false;
+        };
+        match resolver.lookup_txt(&self.record_name()) {
+            Ok(records) => records
+                .iter()
+                .any(|record| public_key_tag(record) == Some(expected)),
+            Err(e) => {
+                debug!("TXT lookup for {} failed: {}", self.record_name(), e);
+                false
+            }
+        }
+    }
+}
+
+/// When to rotate and how long to overlap.
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    /// Time between rotations.
+    pub interval: Duration,
+    /// How long the old selector stays published after the switch.
+    pub grace: Duration,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::days(365),
+            grace: Duration::days(7),
+        }
+    }
+}
+
+/// A date-based selector (`s202610`) not already in `taken`.
+pub fn next_selector(now: DateTime<Utc>, taken: &[&str]) -> String {
+    let base = format!("s{}", now.format("%Y%m"));
+    let mut selector = base.clone();
+    let mut n = 2;
+    while taken.contains(&selector.as_str()) {
+        selector = format!("{}-{}", base, n);
+        n += 1;
+    }
+    selector
+}
+
+/// Every rotation, active and finished, persisted as JSON.
+#[derive(Debug)]
+pub struct RotationStore {
+    path: PathBuf,
+    rotations: Vec<Rotation>,
+}
+
+impl RotationStore {
+    /// Load the store at `path`; a missing file is an empty store.
+    pub fn load(path: &Path) -> Result<Self, RotationError> {
+        let rotations = match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| RotationError::Corrupt(format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotations,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), RotationError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(&self.rotations)
+            .map_err(|e| RotationError::Corrupt(e.to_string()))?;
+        atomic_write(&self.path, &json, Some(0o644))?;
+        Ok(())
+    }
+
+    pub fn rotations(&self) -> &[Rotation] {
+        &self.rotations
+    }
+
+    /// The active rotation of a domain's key of `algorithm`'s type.
+    pub fn active(&self, domain: &str, algorithm: DkimAlgorithm) -> Option<&Rotation> {
+        self.rotations
+            .iter()
+            .find(|r| r.domain == domain && r.algorithm.same_key_type(algorithm) && r.is_active())
+    }
+
+    pub fn active_mut(&mut self) -> impl Iterator<Item = &mut Rotation> {
+        self.rotations.iter_mut().filter(|r| r.is_active())
+    }
+
+    /// Record a new rotation; a domain has at most one active per key type.
+    pub fn begin(&mut self, rotation: Rotation) -> Result<(), RotationError> {
+        if self.active(&rotation.domain, rotation.algorithm).is_some() {
+            return Err(RotationError::InProgress(format!(
+                "{} ({})",
+                rotation.domain,
+                rotation.algorithm.key_type()
+            )));
+        }
+        self.rotations.push(rotation);
+        Ok(())
+    }
+
+    /// Signing keys due for rotation under `policy`: those without an
+    /// active rotation whose last rotation started, or whose key file was
+    /// written, at least `policy.interval` ago.
+    pub fn due(&self, config: &OpendkimConfig, policy: &RotationPolicy, now: DateTime<Utc>) -> Vec<DkimDomainEntry> {
+        config
+            .signing_table
+            .iter()
+            .filter(|entry| self.active(&entry.domain, entry.algorithm).is_none())
+            .filter(|entry| {
+                let last = self
+                    .rotations
+                    .iter()
+                    .filter(|r| r.domain == entry.domain && r.algorithm.same_key_type(entry.algorithm))
+                    .map(|r| r.started_at)
+                    .max()
+                    .or_else(|| {
+                        let path = config.key_path(&entry.domain, &entry.selector);
+                        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+                        Some(DateTime::<Utc>::from(modified))
+                    });
+                last.is_some_and(|last| last + policy.interval <= now)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::dns::DnsError;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct StubResolver(Mutex<HashMap<String, Vec<String>>>);
+
+    impl StubResolver {
+        fn publish(&self, name: &str, value: &str) {
+            self.0.lock().unwrap().entry(name.to_string()).or_default().push(value.to_string());
+        }
+    }
+
+    impl TxtResolver for StubResolver {
+        fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+            self.0
+                .lock()
+                .unwrap()
+                .get(name)
+                .cloned()
+                .ok_or_else(|| DnsError::LookupFailed(format!("NXDOMAIN {}", name)))
+        }
+    }
+
+    fn at(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap()
+    }
+
+    fn rotating_config() -> (OpendkimConfig, Rotation) {
+        let mut config = OpendkimConfig::generate_default();
+        config.add_domain("example.com", "mail").unwrap();
+        config.add_selector("example.com", "s202610", DkimAlgorithm::Rsa2048).unwrap();
+        let rotation = Rotation::start(
+            "example.com",
+            "mail",
+            "s202610",
+            DkimAlgorithm::Rsa2048,
+            "v=DKIM1; k=rsa; p=NEWKEY",
+            at(2026, 10, 1),
+        );
+        (config, rotation)
+    }
+
+    #[test]
+    fn test_rotation_waits_for_dns() {
+        let (mut config, mut rotation) = rotating_config();
+        let dns = StubResolver::default();
+        let grace = Duration::days(7);
+
+        assert_eq!(rotation.advance(&mut config, &dns, grace, at(2026, 10, 2)).unwrap(), RotationStep::AwaitingDns);
+        assert!(rotation.instructions().contains("s202610._domainkey.example.com"));
+
+        // A stale record with another key does not count
+        dns.publish("s202610._domainkey.example.com", "v=DKIM1; k=rsa; p=OTHER");
+        assert_eq!(rotation.advance(&mut config, &dns, grace, at(2026, 10, 2)).unwrap(), RotationStep::AwaitingDns);
+        assert_eq!(config.signing_selector("example.com", DkimAlgorithm::Rsa2048), Some("mail"));
+    }
+
+    #[test]
+    fn test_rotation_overlaps_then_retires() {
+        let (mut config, mut rotation) = rotating_config();
+        let dns = StubResolver::default();
+        dns.publish("s202610._domainkey.example.com", "v=DKIM1; k=rsa; p=NEWKEY");
+        let grace = Duration::days(7);
+
+        let step = rotation.advance(&mut config, &dns, grace, at(2026, 10, 3)).unwrap();
+        assert_eq!(step, RotationStep::Switched);
+        assert!(step.changes_config());
+        assert_eq!(config.signing_selector("example.com", DkimAlgorithm::Rsa2048), Some("s202610"));
+        // The old key stays in the key table during the overlap
+        assert_eq!(config.selectors("example.com"), vec!["mail", "s202610"]);
+
+        assert_eq!(rotation.advance(&mut config, &dns, grace, at(2026, 10, 9)).unwrap(), RotationStep::Overlapping);
+        assert_eq!(rotation.advance(&mut config, &dns, grace, at(2026, 10, 10)).unwrap(), RotationStep::Retired);
+        assert_eq!(config.selectors("example.com"), vec!["s202610"]);
+        assert!(!rotation.is_active());
+        assert_eq!(rotation.advance(&mut config, &dns, grace, at(2026, 10, 11)).unwrap(), RotationStep::Complete);
+    }
+
+    #[test]
+    fn test_next_selector() {
+        assert_eq!(next_selector(at(2026, 10, 18), &["mail"]), "s202610");
+        assert_eq!(next_selector(at(2026, 10, 18), &["s202610"]), "s202610-2");
+        assert_eq!(next_selector(at(2026, 10, 18), &["s202610", "s202610-2"]), "s202610-3");
+    }
+
+    #[test]
+    fn test_store_round_trip_and_due() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rotations.json");
+        let mut config = OpendkimConfig::generate_default();
+        config.key_base_dir = dir.path().join("keys").display().to_string();
+        config.add_domain("example.com", "mail").unwrap();
+        config.add_domain("other.org", "mail").unwrap();
+        let policy = RotationPolicy::default();
+
+        // other.org's key was written two years ago
+        let other_key = config.key_path("other.org", "mail");
+        std::fs::create_dir_all(other_key.parent().unwrap()).unwrap();
+        let file = std::fs::File::create(&other_key).unwrap();
+        file.set_modified((at(2024, 10, 1)).into()).unwrap();
+
+        let mut store = RotationStore::load(&path).unwrap();
+        let mut rotation = Rotation::start("example.com", "mail", "s202510", DkimAlgorithm::Rsa2048, "p=X", at(2025, 10, 1));
+        rotation.phase = RotationPhase::Complete;
+        store.begin(rotation).unwrap();
+        store.save().unwrap();
+
+        let store = RotationStore::load(&path).unwrap();
+        assert_eq!(store.rotations().len(), 1);
+        let due = |now| -> Vec<String> { store.due(&config, &policy, now).into_iter().map(|e| e.domain).collect() };
+        assert_eq!(due(at(2026, 9, 1)), vec!["other.org"]);
+        assert_eq!(due(at(2026, 10, 1)), vec!["example.com", "other.org"]);
+    }
+
+    #[test]
+    fn test_one_active_rotation_per_domain() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = RotationStore::load(&dir.path().join("rotations.json")).unwrap();
+        let rsa = DkimAlgorithm::Rsa2048;
+        store.begin(Rotation::start("example.com", "mail", "s1", rsa, "p=X", at(2026, 1, 1))).unwrap();
+        let err = store.begin(Rotation::start("example.com", "mail", "s2", rsa, "p=Y", at(2026, 1, 2))).unwrap_err();
+        assert!(matches!(err, RotationError::InProgress(_)));
+        // The Ed25519 key of a dual-signing domain rotates on its own
+        store
+            .begin(Rotation::start("example.com", "ed", "e1", DkimAlgorithm::Ed25519, "p=Z", at(2026, 1, 2)))
+            .unwrap();
+    }
+}
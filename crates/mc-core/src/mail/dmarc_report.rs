@@ -0,0 +1,514 @@
+//! DMARC aggregate reports (RFC 7489 section 7.2 and appendix C).
+//!
+//! Receivers mail these to the `rua=` address as a gzip, zip or plain XML
+//! attachment; this module pulls the reports out of such messages.
+
+use std::io::{Cursor, Read};
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::mail::report_mailbox;
+
+/// Largest report accepted once decompressed. Reports from the big
+/// receivers run to a few megabytes; anything far past that is a
+/// decompression bomb.
+pub const MAX_REPORT_BYTES: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum DmarcReportError {
+    #[error("No DMARC report found: {0}")]
+    NotAReport(String),
+    #[error("Invalid report XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("Invalid report: {0}")]
+    Invalid(String),
+    #[error("Archive error: {0}")]
+    Archive(String),
+    #[error("Report exceeds {MAX_REPORT_BYTES} bytes uncompressed")]
+    TooLarge,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// One aggregate report: what one receiver saw of one domain's mail over
+/// one period.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AggregateReport {
+    /// The receiver that sent the report, e.g. `google.com`.
+    pub org_name: String,
+    /// Unique per `org_name`; a re-sent report carries the same id.
+    pub report_id: String,
+    pub begin: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub policy: PublishedPolicy,
+    pub records: Vec<ReportRecord>,
+}
+
+/// The DMARC record the receiver found when it evaluated the mail.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PublishedPolicy {
+    pub domain: String,
+    /// `p=`: none, quarantine or reject.
+    pub policy: String,
+    pub subdomain_policy: Option<String>,
+    pub percent: Option<u8>,
+}
+
+/// Mail from one source address sharing one evaluation result.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportRecord {
+    pub source_ip: IpAddr,
+    pub count: u64,
+    /// What the receiver did: none, quarantine or reject.
+    pub disposition: String,
+    /// DKIM passed for a domain aligned with `header_from`.
+    pub dkim_aligned: bool,
+    /// SPF passed for a domain aligned with `header_from`.
+    pub spf_aligned: bool,
+    pub header_from: String,
+    /// Domains of the DKIM signatures found, with their raw results.
+    pub dkim: Vec<AuthResult>,
+    /// The SPF domain checked, with its raw result.
+    pub spf: Vec<AuthResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthResult {
+    pub domain: String,
+    pub result: String,
+}
+
+impl ReportRecord {
+    /// DMARC passes when either mechanism passes aligned.
+    pub fn passed(&self) -> bool {
+        self.dkim_aligned || self.spf_aligned
+    }
+
+    /// The first DKIM domain that passed, else the first one seen: the
+    /// best hint at who actually sent the mail.
+    pub fn dkim_domain(&self) -> Option<&str> {
+        self.dkim
+            .iter()
+            .find(|r| r.result == "pass")
+            .or_else(|| self.dkim.first())
+            .map(|r| r.domain.as_str())
+    }
+
+    pub fn spf_domain(&self) -> Option<&str> {
+        self.spf.first().map(|r| r.domain.as_str())
+    }
+}
+
+impl AggregateReport {
+    /// Parse the report XML. Element namespaces are ignored, so reports in
+    /// the draft DMARCbis namespace read the same.
+    pub fn parse_xml(xml: &str) -> Result<Self, DmarcReportError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let feedback = doc.root_element();
+        if feedback.tag_name().name() != "feedback" {
+            return Err(DmarcReportError::NotAReport(format!(
+                "root element is <{}>",
+                feedback.tag_name().name()
+            )));
+        }
+
+        let metadata = required(feedback, "report_metadata")?;
+        let range = required(metadata, "date_range")?;
+        let published = required(feedback, "policy_published")?;
+
+        let policy = PublishedPolicy {
+            domain: text(published, "domain")
+                .map(|d| d.trim_end_matches('.').to_ascii_lowercase())
+                .ok_or_else(|| missing("policy_published/domain"))?,
+            policy: text(published, "p").unwrap_or_else(|| "none".to_string()),
+            subdomain_policy: text(published, "sp"),
+            percent: text(published, "pct").and_then(|p| p.parse().ok()),
+        };
+
+        let records = children(feedback, "record")
+            .map(parse_record)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            org_name: text(metadata, "org_name").ok_or_else(|| missing("org_name"))?,
+            report_id: text(metadata, "report_id").ok_or_else(|| missing("report_id"))?,
+            begin: timestamp(range, "begin")?,
+            end: timestamp(range, "end")?,
+            policy,
+            records,
+        })
+    }
+
+    /// Parse a report in any of the forms receivers send: gzip, zip, or
+    /// bare XML.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DmarcReportError> {
+        Self::parse_xml(&decompress(data)?)
+    }
+
+    /// Messages covered by the report.
+    pub fn total(&self) -> u64 {
+        self.records.iter().map(|r| r.count).sum()
+    }
+
+    /// Messages that failed DMARC.
+    pub fn failed(&self) -> u64 {
+        self.records
+            .iter()
+            .filter(|r| !r.passed())
+            .map(|r| r.count)
+            .sum()
+    }
+}
+
+fn parse_record(record: roxmltree::Node) -> Result<ReportRecord, DmarcReportError> {
+    let row = required(record, "row")?;
+    let evaluated = required(row, "policy_evaluated")?;
+    let source_ip = text(row, "source_ip").ok_or_else(|| missing("row/source_ip"))?;
+    let source_ip = source_ip
+        .parse()
+        .map_err(|_| DmarcReportError::Invalid(format!("bad source_ip: {}", source_ip)))?;
+    let count = text(row, "count")
+        .and_then(|c| c.parse().ok())
+        .ok_or_else(|| missing("row/count"))?;
+
+    let header_from = child(record, "identifiers")
+        .and_then(|ids| text(ids, "header_from"))
+        .map(|d| d.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let auth = child(record, "auth_results");
+    let results = |kind: &str| -> Vec<AuthResult> {
+        auth.into_iter()
+            .flat_map(|auth| children(auth, kind))
+            .filter_map(|r| {
+                Some(AuthResult {
+                    domain: text(r, "domain")?.to_ascii_lowercase(),
+                    result: text(r, "result").unwrap_or_default().to_ascii_lowercase(),
+                })
+            })
+            .collect()
+    };
+
+    Ok(ReportRecord {
+        source_ip,
+        count,
+        disposition: text(evaluated, "disposition").unwrap_or_else(|| "none".to_string()),
+        dkim_aligned: text(evaluated, "dkim").is_some_and(|r| r == "pass"),
+        spf_aligned: text(evaluated, "spf").is_some_and(|r| r == "pass"),
+        header_from,
+        dkim: results("dkim"),
+        spf: results("spf"),
+    })
+}
+
+fn children<'a, 'input: 'a>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn required<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Result<roxmltree::Node<'a, 'input>, DmarcReportError> {
+    child(node, name).ok_or_else(|| missing(name))
+}
+
+/// Trimmed text of a child element; `None` when absent or empty.
+fn text(node: roxmltree::Node, name: &str) -> Option<String> {
+    let value = child(node, name)?.text()?.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn timestamp(node: roxmltree::Node, name: &str) -> Result<DateTime<Utc>, DmarcReportError> {
+    text(node, name)
+        .and_then(|t| t.parse::<i64>().ok())
+        .and_then(|t| DateTime::from_timestamp(t, 0))
+        .ok_or_else(|| missing(&format!("date_range/{}", name)))
+}
+
+fn missing(what: &str) -> DmarcReportError {
+    DmarcReportError::Invalid(format!("missing {}", what))
+}
+
+/// The report XML inside a gzip or zip archive, or the data itself when it
+/// is already XML.
+fn decompress(data: &[u8]) -> Result<String, DmarcReportError> {
+    let mut xml = Vec::new();
+    if data.starts_with(&[0x1f, 0x8b]) {
+        MultiGzDecoder::new(data)
+            .take(MAX_REPORT_BYTES + 1)
+            .read_to_end(&mut xml)?;
+    } else if data.starts_with(b"PK\x03\x04") {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))
+            .map_err(|e| DmarcReportError::Archive(e.to_string()))?;
+        let name = archive
+            .file_names()
+            .find(|n| n.to_ascii_lowercase().ends_with(".xml"))
+            .map(str::to_string)
+            .ok_or_else(|| DmarcReportError::NotAReport("no .xml file in zip".to_string()))?;
+        archive
+            .by_name(&name)
+            .map_err(|e| DmarcReportError::Archive(e.to_string()))?
+            .take(MAX_REPORT_BYTES + 1)
+            .read_to_end(&mut xml)?;
+    } else {
+        xml.extend_from_slice(data);
+    }
+    if xml.len() as u64 > MAX_REPORT_BYTES {
+        return Err(DmarcReportError::TooLarge);
+    }
+    String::from_utf8(xml).map_err(|_| DmarcReportError::Invalid("report is not UTF-8".to_string()))
+}
+
+/// Every aggregate report attached to a raw RFC 5322 message.
+///
+/// Parts are taken as report candidates by content type or file name, as
+/// receivers disagree on both; the first error is returned only when no
+/// candidate parses.
+pub fn reports_from_message(raw: &[u8]) -> Result<Vec<AggregateReport>, DmarcReportError> {
+    let candidates = report_mailbox::attachments(raw).into_iter().filter(|part| {
+        matches!(
+            part.mime_type.as_str(),
+            "application/gzip"
+                | "application/x-gzip"
+                | "application/zip"
+                | "application/x-zip-compressed"
+                | "application/xml"
+                | "text/xml"
+        ) || part.has_extension(&[".xml", ".gz", ".zip"])
+    });
+
+    let mut reports = Vec::new();
+    let mut first_error = None;
+    for part in candidates {
+        match AggregateReport::from_bytes(&part.data) {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match (reports.is_empty(), first_error) {
+        (true, Some(e)) => Err(e),
+        (true, None) => Err(DmarcReportError::NotAReport(
+            "message has no report attachment".to_string(),
+        )),
+        _ => Ok(reports),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<feedback>
+  <report_metadata>
+    <org_name>google.com</org_name>
+    <email>noreply-dmarc-support@google.com</email>
+    <report_id>4312847763205</report_id>
+    <date_range>
+      <begin>1760745600</begin>
+      <end>1760831999</end>
+    </date_range>
+  </report_metadata>
+  <policy_published>
+    <domain>Example.com</domain>
+    <adkim>r</adkim>
+    <aspf>r</aspf>
+    <p>quarantine</p>
+    <sp>quarantine</sp>
+    <pct>100</pct>
+  </policy_published>
+  <record>
+    <row>
+      <source_ip>203.0.113.10</source_ip>
+      <count>41</count>
+      <policy_evaluated>
+        <disposition>none</disposition>
+        <dkim>pass</dkim>
+        <spf>pass</spf>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <dkim>
+        <domain>example.com</domain>
+        <result>pass</result>
+        <selector>mail</selector>
+      </dkim>
+      <spf>
+        <domain>example.com</domain>
+        <result>pass</result>
+      </spf>
+    </auth_results>
+  </record>
+  <record>
+    <row>
+      <source_ip>2001:db8::66</source_ip>
+      <count>3</count>
+      <policy_evaluated>
+        <disposition>quarantine</disposition>
+        <dkim>fail</dkim>
+        <spf>fail</spf>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <dkim>
+        <domain>spoofer.test</domain>
+        <result>pass</result>
+      </dkim>
+      <spf>
+        <domain>spoofer.test</domain>
+        <result>softfail</result>
+      </spf>
+    </auth_results>
+  </record>
+</feedback>
+"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn zip(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn wrap(data: &[u8]) -> String {
+        BASE64
+            .encode(data)
+            .as_bytes()
+            .chunks(76)
+            .map(|l| String::from_utf8_lossy(l).to_string())
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = AggregateReport::parse_xml(REPORT).unwrap();
+        assert_eq!(report.org_name, "google.com");
+        assert_eq!(report.report_id, "4312847763205");
+        assert_eq!(report.begin.timestamp(), 1760745600);
+        assert_eq!(report.policy.domain, "example.com");
+        assert_eq!(report.policy.policy, "quarantine");
+        assert_eq!(report.policy.percent, Some(100));
+        assert_eq!(report.records.len(), 2);
+        assert_eq!(report.total(), 44);
+        assert_eq!(report.failed(), 3);
+
+        let spoofed = &report.records[1];
+        assert_eq!(spoofed.source_ip, "2001:db8::66".parse::<IpAddr>().unwrap());
+        assert!(!spoofed.passed());
+        assert_eq!(spoofed.disposition, "quarantine");
+        assert_eq!(spoofed.dkim_domain(), Some("spoofer.test"));
+        assert_eq!(spoofed.spf[0].result, "softfail");
+
+        // Namespaced reports read the same
+        let namespaced = REPORT.replace(
+            "<feedback>",
+            r#"<feedback xmlns="urn:ietf:params:xml:ns:dmarc-2.0">"#,
+        );
+        assert_eq!(AggregateReport::parse_xml(&namespaced).unwrap(), report);
+
+        assert!(matches!(
+            AggregateReport::parse_xml("<html/>"),
+            Err(DmarcReportError::NotAReport(_))
+        ));
+        let no_id = REPORT.replace("<report_id>4312847763205</report_id>", "");
+        assert!(matches!(
+            AggregateReport::parse_xml(&no_id),
+            Err(DmarcReportError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_compressed_reports() {
+        let expected = AggregateReport::parse_xml(REPORT).unwrap();
+        assert_eq!(AggregateReport::from_bytes(&gzip(REPORT.as_bytes())).unwrap(), expected);
+        let zipped = zip("google.com!example.com!1760745600!1760831999.xml", REPORT.as_bytes());
+        assert_eq!(AggregateReport::from_bytes(&zipped).unwrap(), expected);
+
+        let bomb = gzip(&vec![b' '; MAX_REPORT_BYTES as usize + 1]);
+        assert!(matches!(
+            AggregateReport::from_bytes(&bomb),
+            Err(DmarcReportError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_reports_from_message() {
+        let multipart = format!(
+            "From: noreply-dmarc-support@google.com\r\n\
+             Subject: Report domain: example.com\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed;\r\n\tboundary=\"b1\"\r\n\
+             \r\n\
+             --b1\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             This is an aggregate report from google.com.\r\n\
+             --b1\r\n\
+             Content-Type: application/gzip;\r\n name=\"google.com!example.com.xml.gz\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             {}\r\n\
+             --b1--\r\n",
+            wrap(&gzip(REPORT.as_bytes()))
+        );
+        let reports = reports_from_message(multipart.as_bytes()).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].report_id, "4312847763205");
+
+        // Some receivers send the archive as the whole body
+        let single = format!(
+            "Subject: Report Domain: example.com\n\
+             Content-Type: application/zip\n\
+             Content-Disposition: attachment; filename=\"report.zip\"\n\
+             Content-Transfer-Encoding: base64\n\
+             \n\
+             {}\n",
+            wrap(&zip("report.xml", REPORT.as_bytes()))
+        );
+        assert_eq!(reports_from_message(single.as_bytes()).unwrap(), reports);
+
+        let plain = "Subject: hello\r\nContent-Type: text/plain\r\n\r\nhi\r\n";
+        assert!(matches!(
+            reports_from_message(plain.as_bytes()),
+            Err(DmarcReportError::NotAReport(_))
+        ));
+    }
+}
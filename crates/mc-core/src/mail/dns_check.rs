@@ -0,0 +1,701 @@
+//! Checks that a domain's mail records are published the way this server
+//! needs them:
+//!
+//! - every DKIM selector in the key table serves the local public key
+//! - SPF authorises the server's addresses
+//! - a DMARC record exists and parses
+//! - MX points at the server's hostname
+//! - the server's addresses have forward-confirmed reverse DNS
+//!
+//! The result is a [`DomainScorecard`] per domain.
+
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config::opendkim::OpendkimConfig;
+use crate::mail::dkim::{public_key_tag, DkimError, DkimKeyPair};
+use crate::mail::dns_records::DmarcRecord;
+use crate::system::dns::DnsResolver;
+
+/// SPF evaluation may take at most ten DNS-querying terms (RFC 7208 4.6.4).
+const SPF_LOOKUP_LIMIT: u32 = 10;
+
+/// Ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but not as intended, e.g. DMARC with `p=none`.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    Dkim,
+    Spf,
+    Dmarc,
+    Mx,
+    /// Forward-confirmed reverse DNS of a server address.
+    Ptr,
+    /// DANE records of the server's certificate.
+    Tlsa,
+}
+
+impl fmt::Display for CheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckKind::Dkim => write!(f, "DKIM"),
+            CheckKind::Spf => write!(f, "SPF"),
+            CheckKind::Dmarc => write!(f, "DMARC"),
+            CheckKind::Mx => write!(f, "MX"),
+            CheckKind::Ptr => write!(f, "PTR"),
+            CheckKind::Tlsa => write!(f, "TLSA"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub kind: CheckKind,
+    /// The name looked up; the address for PTR checks.
+    pub record: String,
+    pub status: CheckStatus,
+    /// What was found, and what to publish when it is wrong.
+    pub detail: String,
+}
+
+/// Every check for one domain.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DomainScorecard {
+    pub domain: String,
+    pub checks: Vec<CheckResult>,
+}
+
+impl DomainScorecard {
+    /// The worst status of any check.
+    pub fn status(&self) -> CheckStatus {
+        self.checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass)
+    }
+
+    /// Passed checks, out of all checks.
+    pub fn score(&self) -> (usize, usize) {
+        let passed = self
+            .checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Pass)
+            .count();
+        (passed, self.checks.len())
+    }
+}
+
+/// The server the records should point at.
+#[derive(Debug, Clone)]
+pub struct MailServer {
+    /// Postfix `myhostname`.
+    pub hostname: String,
+    /// Addresses the server sends mail from.
+    pub ips: Vec<IpAddr>,
+}
+
+impl MailServer {
+    fn hostname(&self) -> String {
+        self.hostname.trim_end_matches('.').to_ascii_lowercase()
+    }
+}
+
+/// Check each of `domains`. DKIM selectors come from `dkim`'s key table.
+/// The reverse DNS checks concern the server rather than a domain, so
+/// they run once and appear on every scorecard.
+pub fn verify_domains(
+    resolver: &dyn DnsResolver,
+    server: &MailServer,
+    dkim: &OpendkimConfig,
+    domains: &[String],
+) -> Vec<DomainScorecard> {
+    let reverse = check_reverse_dns(resolver, server);
+    domains
+        .iter()
+        .map(|domain| {
+            let mut checks = check_dkim(resolver, dkim, domain);
+            checks.push(check_spf(resolver, server, domain));
+            checks.push(check_dmarc(resolver, domain));
+            checks.push(check_mx(resolver, server, domain));
+            checks.extend(reverse.iter().cloned());
+            DomainScorecard {
+                domain: domain.clone(),
+                checks,
+            }
+        })
+        .collect()
+}
+
+fn result(kind: CheckKind, record: &str, status: CheckStatus, detail: impl Into<String>) -> CheckResult {
+    CheckResult {
+        kind,
+        record: record.to_string(),
+        status,
+        detail: detail.into(),
+    }
+}
+
+// ── DKIM ───────────────────────────────────────────────────────────
+
+fn check_dkim(resolver: &dyn DnsResolver, config: &OpendkimConfig, domain: &str) -> Vec<CheckResult> {
+    let keys: Vec<_> = config.key_table.iter().filter(|e| e.domain == domain).collect();
+    if keys.is_empty() {
+        return vec![result(
+            CheckKind::Dkim,
+            &format!("_domainkey.{}", domain),
+            CheckStatus::Fail,
+            "No DKIM key is configured for the domain",
+        )];
+    }
+
+    keys.into_iter()
+        .map(|entry| {
+            let record = format!("{}._domainkey.{}", entry.selector, domain);
+            let (status, detail) = dkim_status(resolver, &record, &config.key_path(domain, &entry.selector));
+            // A key that does not sign yet, e.g. mid-rotation, cannot break
+            // verification by being missing
+            let signing = config.signing_table.contains(entry);
+            let status = match status {
+                CheckStatus::Fail if !signing => CheckStatus::Warn,
+                status => status,
+            };
+            result(CheckKind::Dkim, &record, status, detail)
+        })
+        .collect()
+}
+
+fn dkim_status(resolver: &dyn DnsResolver, record: &str, key_path: &Path) -> (CheckStatus, String) {
+    let local = match fs::read_to_string(key_path)
+        .map_err(DkimError::from)
+        .and_then(|pem| DkimKeyPair::from_private_pem(&pem))
+    {
+        Ok(local) => local,
+        Err(e) => {
+            return (
+                CheckStatus::Fail,
+                format!("Cannot read the local key {}: {}", key_path.display(), e),
+            )
+        }
+    };
+    let values = match resolver.lookup_txt(record) {
+        Ok(values) => values,
+        Err(e) => return (CheckStatus::Fail, e.to_string()),
+    };
+
+    // p= may be folded with whitespace
+    let published: Vec<String> = values
+        .iter()
+        .filter_map(|v| public_key_tag(v))
+        .map(|key| key.split_whitespace().collect())
+        .collect();
+    match published.as_slice() {
+        [] => (
+            CheckStatus::Fail,
+            format!("No DKIM record is published; publish: {}", local.txt_value()),
+        ),
+        [key] if key.is_empty() => (
+            CheckStatus::Fail,
+            "The published record revokes the key (empty p=)".to_string(),
+        ),
+        [key] if *key == local.public_key => (
+            CheckStatus::Pass,
+            format!("Publishes the local {} key", local.algorithm),
+        ),
+        [_] => (
+            CheckStatus::Fail,
+            format!(
+                "The published key does not match {}; publish: {}",
+                key_path.display(),
+                local.txt_value()
+            ),
+        ),
+        _ => (
+            CheckStatus::Fail,
+            "Several DKIM records are published; keep only one".to_string(),
+        ),
+    }
+}
+
+// ── SPF ────────────────────────────────────────────────────────────
+
+/// The outcome of evaluating SPF for one address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfResult {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+    /// No SPF record.
+    None,
+}
+
+impl fmt::Display for SpfResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfResult::Pass => write!(f, "pass"),
+            SpfResult::Fail => write!(f, "fail"),
+            SpfResult::SoftFail => write!(f, "softfail"),
+            SpfResult::Neutral => write!(f, "neutral"),
+            SpfResult::None => write!(f, "none"),
+        }
+    }
+}
+
+fn check_spf(resolver: &dyn DnsResolver, server: &MailServer, domain: &str) -> CheckResult {
+    if server.ips.is_empty() {
+        return result(CheckKind::Spf, domain, CheckStatus::Warn, "No server address to check against");
+    }
+
+    let mut refused = Vec::new();
+    for ip in &server.ips {
+        match evaluate_spf(resolver, domain, *ip) {
+            Ok(SpfResult::Pass) => {}
+            Ok(SpfResult::None) => {
+                return result(
+                    CheckKind::Spf,
+                    domain,
+                    CheckStatus::Fail,
+                    "No SPF record is published; publish: v=spf1 mx -all",
+                )
+            }
+            Ok(other) => refused.push(format!("{} ({})", ip, other)),
+            Err(e) => return result(CheckKind::Spf, domain, CheckStatus::Fail, e),
+        }
+    }
+
+    if refused.is_empty() {
+        let ips: Vec<String> = server.ips.iter().map(IpAddr::to_string).collect();
+        result(CheckKind::Spf, domain, CheckStatus::Pass, format!("Authorises {}", ips.join(", ")))
+    } else {
+        result(
+            CheckKind::Spf,
+            domain,
+            CheckStatus::Fail,
+            format!("Does not authorise {}", refused.join(", ")),
+        )
+    }
+}
+
+/// SPF's verdict on mail from `ip` for `domain` (RFC 7208), without macro
+/// expansion: `exists` and `ptr` never match. An error is a permanent or
+/// temporary SPF error.
+pub fn evaluate_spf(resolver: &dyn DnsResolver, domain: &str, ip: IpAddr) -> Result<SpfResult, String> {
+    let mut lookups = 0;
+    evaluate_spf_record(resolver, domain, ip, &mut lookups)
+}
+
+fn evaluate_spf_record(
+    resolver: &dyn DnsResolver,
+    domain: &str,
+    ip: IpAddr,
+    lookups: &mut u32,
+) -> Result<SpfResult, String> {
+    let records: Vec<String> = resolver
+        .lookup_txt(domain)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|v| {
+            let v = v.to_ascii_lowercase();
+            v == "v=spf1" || v.starts_with("v=spf1 ")
+        })
+        .collect();
+    let record = match records.as_slice() {
+        [] => return Ok(SpfResult::None),
+        [record] => record.to_ascii_lowercase(),
+        _ => return Err(format!("{} publishes more than one SPF record", domain)),
+    };
+
+    let mut redirect = None;
+    for term in record.split_whitespace().skip(1) {
+        if let Some(target) = term.strip_prefix("redirect=") {
+            redirect = Some(target.to_string());
+            continue;
+        }
+        // exp= and unknown modifiers do not affect the result
+        if term.contains('=') {
+            continue;
+        }
+        let (verdict, mechanism) = match term.as_bytes()[0] {
+            b'+' => (SpfResult::Pass, &term[1..]),
+            b'-' => (SpfResult::Fail, &term[1..]),
+            b'~' => (SpfResult::SoftFail, &term[1..]),
+            b'?' => (SpfResult::Neutral, &term[1..]),
+            _ => (SpfResult::Pass, term),
+        };
+        if spf_matches(resolver, domain, mechanism, ip, lookups)? {
+            return Ok(verdict);
+        }
+    }
+
+    match redirect {
+        Some(target) => {
+            count_spf_lookup(lookups)?;
+            match evaluate_spf_record(resolver, &target, ip, lookups)? {
+                SpfResult::None => Err(format!("redirect={} has no SPF record", target)),
+                verdict => Ok(verdict),
+            }
+        }
+        None => Ok(SpfResult::Neutral),
+    }
+}
+
+fn count_spf_lookup(lookups: &mut u32) -> Result<(), String> {
+    *lookups += 1;
+    if *lookups > SPF_LOOKUP_LIMIT {
+        return Err(format!("SPF needs more than {} DNS lookups", SPF_LOOKUP_LIMIT));
+    }
+    Ok(())
+}
+
+fn spf_matches(
+    resolver: &dyn DnsResolver,
+    domain: &str,
+    mechanism: &str,
+    ip: IpAddr,
+    lookups: &mut u32,
+) -> Result<bool, String> {
+    let invalid = || format!("Invalid SPF term: {}", mechanism);
+    let (body, cidr) = match mechanism.split_once('/') {
+        Some((body, cidr)) => (body, Some(cidr)),
+        None => (mechanism, None),
+    };
+    let (name, argument) = match body.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (body, None),
+    };
+    let dns = |e: crate::system::dns::DnsError| e.to_string();
+
+    match name {
+        "all" => Ok(true),
+        "ip4" | "ip6" => {
+            let network: IpAddr = argument.and_then(|a| a.parse().ok()).ok_or_else(invalid)?;
+            let prefix = match cidr {
+                Some(cidr) => cidr.parse().map_err(|_| invalid())?,
+                None if network.is_ipv4() => 32,
+                None => 128,
+            };
+            Ok(in_network(ip, network, prefix))
+        }
+        "a" | "mx" => {
+            count_spf_lookup(lookups)?;
+            let (v4, v6) = dual_cidr(cidr).ok_or_else(invalid)?;
+            let target = argument.unwrap_or(domain);
+            let hosts = if name == "a" {
+                vec![target.to_string()]
+            } else {
+                resolver
+                    .lookup_mx(target)
+                    .map_err(dns)?
+                    .into_iter()
+                    .map(|(_, host)| host)
+                    .collect()
+            };
+            for host in hosts {
+                for address in resolver.lookup_ip(&host).map_err(dns)? {
+                    let prefix = if address.is_ipv4() { v4 } else { v6 };
+                    if in_network(ip, address, prefix) {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok(false)
+        }
+        "include" => {
+            count_spf_lookup(lookups)?;
+            let target = argument.ok_or_else(invalid)?;
+            match evaluate_spf_record(resolver, target, ip, lookups)? {
+                SpfResult::Pass => Ok(true),
+                SpfResult::None => Err(format!("include:{} has no SPF record", target)),
+                _ => Ok(false),
+            }
+        }
+        "exists" | "ptr" => {
+            count_spf_lookup(lookups)?;
+            Ok(false)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// The `/v4//v6` prefix lengths of an `a` or `mx` mechanism.
+fn dual_cidr(cidr: Option<&str>) -> Option<(u8, u8)> {
+    let Some(cidr) = cidr else { return Some((32, 128)) };
+    let (v4, v6) = match cidr.split_once('/') {
+        Some((v4, v6)) => (v4, Some(v6)),
+        None => (cidr, None),
+    };
+    let v4 = if v4.is_empty() { 32 } else { v4.parse().ok().filter(|p| *p <= 32)? };
+    let v6 = match v6 {
+        Some(v6) => v6.strip_prefix('/')?.parse().ok().filter(|p| *p <= 128)?,
+        None => 128,
+    };
+    Some((v4, v6))
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) if prefix <= 32 => {
+            prefix == 0 || (u32::from(ip) ^ u32::from(network)) >> (32 - prefix) == 0
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) if prefix <= 128 => {
+            prefix == 0 || (u128::from(ip) ^ u128::from(network)) >> (128 - prefix) == 0
+        }
+        _ => false,
+    }
+}
+
+// ── DMARC ──────────────────────────────────────────────────────────
+
+fn check_dmarc(resolver: &dyn DnsResolver, domain: &str) -> CheckResult {
+    let record = format!("_dmarc.{}", domain);
+    let check = |status, detail: String| result(CheckKind::Dmarc, &record, status, detail);
+
+    let values: Vec<String> = match resolver.lookup_txt(&record) {
+        Ok(values) => values
+            .into_iter()
+            .filter(|v| v.to_ascii_lowercase().starts_with("v=dmarc1"))
+            .collect(),
+        Err(e) => return check(CheckStatus::Fail, e.to_string()),
+    };
+    let dmarc = match values.as_slice() {
+        [] => {
+            return check(
+                CheckStatus::Fail,
+                format!(
+                    "No DMARC record is published; publish: v=DMARC1; p=quarantine; rua=mailto:postmaster@{}",
+                    domain
+                ),
+            )
+        }
+        [value] => match DmarcRecord::parse(value) {
+            Ok(dmarc) => dmarc,
+            Err(e) => return check(CheckStatus::Fail, e),
+        },
+        _ => return check(CheckStatus::Fail, "Several DMARC records are published; keep only one".to_string()),
+    };
+
+    if dmarc.policy == "none" {
+        check(
+            CheckStatus::Warn,
+            "p=none only monitors; receivers deliver mail that fails".to_string(),
+        )
+    } else if dmarc.percent < 100 {
+        check(
+            CheckStatus::Warn,
+            format!("p={} applies to only {}% of failing mail", dmarc.policy, dmarc.percent),
+        )
+    } else {
+        check(CheckStatus::Pass, format!("p={}", dmarc.policy))
+    }
+}
+
+// ── MX and reverse DNS ─────────────────────────────────────────────
+
+fn check_mx(resolver: &dyn DnsResolver, server: &MailServer, domain: &str) -> CheckResult {
+    let hostname = server.hostname();
+    let check = |status, detail: String| result(CheckKind::Mx, domain, status, detail);
+
+    let mut exchanges = match resolver.lookup_mx(domain) {
+        Ok(exchanges) => exchanges,
+        Err(e) => return check(CheckStatus::Fail, e.to_string()),
+    };
+    exchanges.sort();
+    let ours = exchanges.iter().find(|(_, host)| *host == hostname);
+    match (ours, exchanges.first()) {
+        (_, None) => check(
+            CheckStatus::Fail,
+            format!("No MX records are published; publish: MX 10 {}", hostname),
+        ),
+        (None, Some(_)) => {
+            let hosts: Vec<&str> = exchanges.iter().map(|(_, host)| host.as_str()).collect();
+            check(
+                CheckStatus::Fail,
+                format!("MX points at {}, not {}", hosts.join(", "), hostname),
+            )
+        }
+        (Some((preference, _)), Some((best, preferred))) if best < preference => check(
+            CheckStatus::Warn,
+            format!("{} is a backup MX; {} is preferred", hostname, preferred),
+        ),
+        (Some(_), Some(_)) => check(CheckStatus::Pass, format!("MX points at {}", hostname)),
+    }
+}
+
+fn check_reverse_dns(resolver: &dyn DnsResolver, server: &MailServer) -> Vec<CheckResult> {
+    let hostname = server.hostname();
+    if server.ips.is_empty() {
+        return vec![result(
+            CheckKind::Ptr,
+            &hostname,
+            CheckStatus::Warn,
+            "No server address to check",
+        )];
+    }
+
+    server
+        .ips
+        .iter()
+        .map(|ip| {
+            let (status, detail) = match resolver.lookup_ptr(*ip) {
+                Err(e) => (CheckStatus::Fail, e.to_string()),
+                Ok(names) if names.is_empty() => (
+                    CheckStatus::Fail,
+                    format!("{} has no PTR record; ask the provider to set it to {}", ip, hostname),
+                ),
+                Ok(names) if !names.contains(&hostname) => (
+                    CheckStatus::Fail,
+                    format!("PTR of {} is {}, not {}", ip, names.join(", "), hostname),
+                ),
+                Ok(_) => match resolver.lookup_ip(&hostname) {
+                    Ok(addresses) if addresses.contains(ip) => (
+                        CheckStatus::Pass,
+                        format!("{} and {} resolve to each other", ip, hostname),
+                    ),
+                    Ok(_) => (
+                        CheckStatus::Fail,
+                        format!("PTR of {} is {}, but {} does not resolve to it", ip, hostname, hostname),
+                    ),
+                    Err(e) => (CheckStatus::Fail, e.to_string()),
+                },
+            };
+            result(CheckKind::Ptr, &ip.to_string(), status, detail)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use crate::mail::dkim::{generate_selector_key, txt_record_value, DkimAlgorithm};
+    use crate::system::dns::NativeResolver;
+    use crate::system::dns_stub::StubDns;
+
+    fn resolver(server: SocketAddr) -> NativeResolver {
+        NativeResolver::with_nameservers(vec![server]).with_timeout(Duration::from_secs(2))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn statuses(card: &DomainScorecard, kind: CheckKind) -> Vec<CheckStatus> {
+        card.checks.iter().filter(|c| c.kind == kind).map(|c| c.status).collect()
+    }
+
+    #[test]
+    fn test_scorecards() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut dkim = OpendkimConfig::generate_default();
+        dkim.key_base_dir = dir.path().display().to_string();
+        dkim.add_domain_with_algorithm("example.com", "mail", DkimAlgorithm::Ed25519).unwrap();
+        dkim.add_domain_with_algorithm("other.org", "mail", DkimAlgorithm::Ed25519).unwrap();
+        let key = generate_selector_key(&dkim.key_dir("example.com"), "example.com", "mail", DkimAlgorithm::Ed25519)
+            .unwrap();
+        generate_selector_key(&dkim.key_dir("other.org"), "other.org", "mail", DkimAlgorithm::Ed25519).unwrap();
+        // A rotation's next key, not yet published or signing
+        dkim.add_selector("example.com", "s202610", DkimAlgorithm::Ed25519).unwrap();
+        generate_selector_key(&dkim.key_dir("example.com"), "example.com", "s202610", DkimAlgorithm::Ed25519)
+            .unwrap();
+        let stale = DkimKeyPair::generate(DkimAlgorithm::Ed25519).unwrap();
+
+        let server_ip = ip("192.0.2.25");
+        let dns = StubDns::new()
+            .txt("mail._domainkey.example.com", &txt_record_value(&key.dns_record))
+            .txt("example.com", "v=spf1 ip4:192.0.2.0/24 -all")
+            .txt("_dmarc.example.com", "v=DMARC1; p=reject; rua=mailto:dmarc@example.com")
+            .mx("example.com", 10, "mail.example.com")
+            .ip("mail.example.com", server_ip)
+            .ptr(server_ip, "mail.example.com")
+            .txt("mail._domainkey.other.org", &stale.txt_value())
+            .txt("other.org", "v=spf1 include:_spf.provider.net ~all")
+            .txt("_spf.provider.net", "v=spf1 ip4:198.51.100.0/24 -all")
+            .txt("_dmarc.other.org", "v=DMARC1; p=none")
+            .mx("other.org", 10, "mx.provider.net")
+            .start();
+        let server = MailServer {
+            hostname: "mail.example.com".to_string(),
+            ips: vec![server_ip],
+        };
+        let cards = verify_domains(
+            &resolver(dns),
+            &server,
+            &dkim,
+            &["example.com".to_string(), "other.org".to_string()],
+        );
+
+        let good = &cards[0];
+        assert_eq!(statuses(good, CheckKind::Dkim), vec![CheckStatus::Pass, CheckStatus::Warn]);
+        assert_eq!(statuses(good, CheckKind::Spf), vec![CheckStatus::Pass]);
+        assert_eq!(statuses(good, CheckKind::Dmarc), vec![CheckStatus::Pass]);
+        assert_eq!(statuses(good, CheckKind::Mx), vec![CheckStatus::Pass]);
+        assert_eq!(statuses(good, CheckKind::Ptr), vec![CheckStatus::Pass]);
+        assert_eq!(good.status(), CheckStatus::Warn);
+        assert_eq!(good.score(), (5, 6));
+
+        let bad = &cards[1];
+        assert_eq!(statuses(bad, CheckKind::Dkim), vec![CheckStatus::Fail]);
+        assert!(bad.checks[0].detail.contains("does not match"));
+        let spf = bad.checks.iter().find(|c| c.kind == CheckKind::Spf).unwrap();
+        assert_eq!(spf.detail, "Does not authorise 192.0.2.25 (softfail)");
+        assert_eq!(statuses(bad, CheckKind::Dmarc), vec![CheckStatus::Warn]);
+        assert_eq!(statuses(bad, CheckKind::Mx), vec![CheckStatus::Fail]);
+        assert_eq!(bad.status(), CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_spf_evaluation() {
+        let dns = StubDns::new()
+            .txt("example.com", "v=spf1 a/24 mx:relay.example.com -all")
+            .ip("example.com", ip("192.0.2.1"))
+            .mx("relay.example.com", 10, "out.example.com")
+            .ip("out.example.com", ip("2001:db8::25"))
+            .txt("redirected.org", "v=spf1 redirect=example.com")
+            .txt("loop.org", "v=spf1 include:loop.org -all")
+            .txt("twice.org", "v=spf1 -all")
+            .txt("twice.org", "v=spf1 +all")
+            .start();
+        let dns = resolver(dns);
+
+        assert_eq!(evaluate_spf(&dns, "example.com", ip("192.0.2.200")), Ok(SpfResult::Pass));
+        assert_eq!(evaluate_spf(&dns, "example.com", ip("2001:db8::25")), Ok(SpfResult::Pass));
+        assert_eq!(evaluate_spf(&dns, "example.com", ip("198.51.100.1")), Ok(SpfResult::Fail));
+        assert_eq!(evaluate_spf(&dns, "redirected.org", ip("192.0.2.9")), Ok(SpfResult::Pass));
+        assert_eq!(evaluate_spf(&dns, "missing.org", ip("192.0.2.9")), Ok(SpfResult::None));
+        assert!(evaluate_spf(&dns, "loop.org", ip("192.0.2.9")).unwrap_err().contains("more than 10"));
+        assert!(evaluate_spf(&dns, "twice.org", ip("192.0.2.9")).is_err());
+    }
+
+    #[test]
+    fn test_reverse_dns_mismatch() {
+        let dns = StubDns::new()
+            .ptr(ip("192.0.2.25"), "host-25.provider.net")
+            .ptr(ip("192.0.2.26"), "mail.example.com")
+            .ip("mail.example.com", ip("192.0.2.27"))
+            .start();
+        let server = MailServer {
+            hostname: "Mail.Example.com.".to_string(),
+            ips: vec![ip("192.0.2.25"), ip("192.0.2.26"), ip("192.0.2.28")],
+        };
+        let checks = check_reverse_dns(&resolver(dns), &server);
+        assert!(checks.iter().all(|c| c.status == CheckStatus::Fail));
+        assert!(checks[0].detail.contains("host-25.provider.net"));
+        assert!(checks[1].detail.contains("does not resolve"));
+        assert!(checks[2].detail.contains("no PTR record"));
+    }
+}
@@ -0,0 +1,420 @@
+//! The records a mail domain should publish, built from what this server
+//! serves and rendered as a BIND zone fragment or as JSON for DNS-provider
+//! APIs.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::mail::dane::{self, TlsaRecord};
+use crate::mail::dkim::split_txt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Mx,
+    Srv,
+    Tlsa,
+    Txt,
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Cname => write!(f, "CNAME"),
+            RecordType::Mx => write!(f, "MX"),
+            RecordType::Srv => write!(f, "SRV"),
+            RecordType::Tlsa => write!(f, "TLSA"),
+            RecordType::Txt => write!(f, "TXT"),
+        }
+    }
+}
+
+/// One record, in the shape most DNS-provider APIs take.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DnsRecord {
+    /// Owner name, fully qualified, without the trailing dot.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: RecordType,
+    pub ttl: u32,
+    /// MX and SRV priority.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    /// SRV weight.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    /// SRV port.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// The address, the target host, the TLSA data, or the unquoted TXT
+    /// value.
+    pub content: String,
+    /// What the record is for.
+    pub comment: String,
+}
+
+impl DnsRecord {
+    fn new(name: &str, record_type: RecordType, ttl: u32, content: impl Into<String>, comment: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            record_type,
+            ttl,
+            priority: None,
+            weight: None,
+            port: None,
+            content: content.into(),
+            comment: comment.to_string(),
+        }
+    }
+
+    /// The record as a zone-file line with absolute names.
+    pub fn to_bind(&self) -> String {
+        let data = match self.record_type {
+            RecordType::Txt => split_txt(&self.content)
+                .iter()
+                .map(|s| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(" "),
+            RecordType::Mx => format!("{} {}.", self.priority.unwrap_or(10), self.content),
+            RecordType::Srv => format!(
+                "{} {} {} {}.",
+                self.priority.unwrap_or(0),
+                self.weight.unwrap_or(0),
+                self.port.unwrap_or(0),
+                self.content
+            ),
+            RecordType::Cname => format!("{}.", self.content),
+            RecordType::A | RecordType::Aaaa | RecordType::Tlsa => self.content.clone(),
+        };
+        format!("{}.\t{}\tIN\t{}\t{}", self.name, self.ttl, self.record_type, data)
+    }
+}
+
+// ── DMARC ──────────────────────────────────────────────────────────
+
+/// A DMARC record (RFC 7489).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DmarcRecord {
+    /// `p=`: none, quarantine or reject.
+    pub policy: String,
+    /// `sp=`, the policy for subdomains when it differs.
+    pub subdomain_policy: Option<String>,
+    /// `pct=`: the share of failing mail the policy applies to.
+    pub percent: u8,
+    /// `rua=`: where aggregate reports are sent.
+    pub aggregate_reports: Vec<String>,
+}
+
+impl DmarcRecord {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut tags = value
+            .split(';')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                t.split_once('=')
+                    .map(|(name, value)| (name.trim(), value.trim()))
+                    .ok_or_else(|| format!("Malformed tag: {}", t))
+            });
+        match tags.next() {
+            Some(Ok(("v", "DMARC1"))) => {}
+            _ => return Err("The record must start with v=DMARC1".to_string()),
+        }
+
+        let policy_value = |value: &str| {
+            let value = value.to_ascii_lowercase();
+            match value.as_str() {
+                "none" | "quarantine" | "reject" => Ok(value),
+                _ => Err(format!("Unknown policy: {}", value)),
+            }
+        };
+        let mut policy = None;
+        let mut record = Self {
+            policy: String::new(),
+            subdomain_policy: None,
+            percent: 100,
+            aggregate_reports: Vec::new(),
+        };
+        for tag in tags {
+            let (name, value) = tag?;
+            match name {
+                "p" => policy = Some(policy_value(value)?),
+                "sp" => record.subdomain_policy = Some(policy_value(value)?),
+                "pct" => {
+                    record.percent = value
+                        .parse()
+                        .ok()
+                        .filter(|p| *p <= 100)
+                        .ok_or_else(|| format!("Invalid pct: {}", value))?
+                }
+                "rua" => {
+                    record.aggregate_reports = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|uri| !uri.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        record.policy = policy.ok_or("The record has no p= tag")?;
+        Ok(record)
+    }
+}
+
+impl fmt::Display for DmarcRecord {
+    /// The TXT value, e.g. `v=DMARC1; p=quarantine; rua=mailto:...`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v=DMARC1; p={}", self.policy)?;
+        if let Some(policy) = &self.subdomain_policy {
+            write!(f, "; sp={}", policy)?;
+        }
+        if self.percent < 100 {
+            write!(f, "; pct={}", self.percent)?;
+        }
+        if !self.aggregate_reports.is_empty() {
+            write!(f, "; rua={}", self.aggregate_reports.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// The records to publish for one mail domain handled by this server.
+#[derive(Debug, Clone)]
+pub struct MailZone {
+    pub domain: String,
+    /// The server's hostname: the MX target, and the host behind the SRV
+    /// records.
+    pub hostname: String,
+    /// Server addresses. SPF authorises them, and they are published for
+    /// `hostname` when it lies inside `domain`.
+    pub ips: Vec<IpAddr>,
+    /// (selector, TXT value) of each DKIM key.
+    pub dkim_keys: Vec<(String, String)>,
+    pub dmarc: DmarcRecord,
+    /// `id=` of the MTA-STS record; it must change whenever the policy does.
+    pub mta_sts_id: String,
+    /// Where TLS-RPT reports are sent.
+    pub tls_reports: Vec<String>,
+    /// DANE records of the server's key and, ahead of a key change, the
+    /// next one. Published with the addresses.
+    pub tlsa: Vec<TlsaRecord>,
+    pub ttl: u32,
+}
+
+impl MailZone {
+    /// Records for `domain` pointing at `hostname`, with DMARC set to
+    /// quarantine and DMARC and TLS reports going to postmaster.
+    pub fn new(domain: &str, hostname: &str) -> Self {
+        let postmaster = format!("mailto:postmaster@{}", domain);
+        Self {
+            domain: domain.to_string(),
+            hostname: hostname.trim_end_matches('.').to_string(),
+            ips: Vec::new(),
+            dkim_keys: Vec::new(),
+            dmarc: DmarcRecord {
+                policy: "quarantine".to_string(),
+                subdomain_policy: None,
+                percent: 100,
+                aggregate_reports: vec![postmaster.clone()],
+            },
+            mta_sts_id: Utc::now().format("%Y%m%d%H%M%S").to_string(),
+            tls_reports: vec![postmaster],
+            tlsa: Vec::new(),
+            ttl: 3600,
+        }
+    }
+
+    pub fn records(&self) -> Vec<DnsRecord> {
+        let domain = self.domain.as_str();
+        let hostname = self.hostname.as_str();
+        let ttl = self.ttl;
+        let sub = |label: &str| format!("{}.{}", label, domain);
+        let mut records = Vec::new();
+
+        let mut mx = DnsRecord::new(domain, RecordType::Mx, ttl, hostname, "Deliver the domain's mail here");
+        mx.priority = Some(10);
+        records.push(mx);
+        if hostname == domain || hostname.ends_with(&format!(".{}", domain)) {
+            for ip in &self.ips {
+                let record_type = if ip.is_ipv4() { RecordType::A } else { RecordType::Aaaa };
+                records.push(DnsRecord::new(hostname, record_type, ttl, ip.to_string(), "Mail server address"));
+            }
+            for tlsa in &self.tlsa {
+                records.push(DnsRecord::new(
+                    &dane::tlsa_name(hostname),
+                    RecordType::Tlsa,
+                    ttl,
+                    tlsa.to_string(),
+                    "DANE: the mail server's TLS key",
+                ));
+            }
+        }
+
+        let mut spf = vec!["v=spf1".to_string(), "mx".to_string()];
+        spf.extend(self.ips.iter().map(|ip| match ip {
+            IpAddr::V4(ip) => format!("ip4:{}", ip),
+            IpAddr::V6(ip) => format!("ip6:{}", ip),
+        }));
+        spf.push("-all".to_string());
+        records.push(DnsRecord::new(domain, RecordType::Txt, ttl, spf.join(" "), "SPF: hosts allowed to send as the domain"));
+
+        for (selector, value) in &self.dkim_keys {
+            records.push(DnsRecord::new(
+                &sub(&format!("{}._domainkey", selector)),
+                RecordType::Txt,
+                ttl,
+                value.as_str(),
+                "DKIM public key",
+            ));
+        }
+        records.push(DnsRecord::new(&sub("_dmarc"), RecordType::Txt, ttl, self.dmarc.to_string(), "DMARC policy"));
+
+        records.push(DnsRecord::new(
+            &sub("_mta-sts"),
+            RecordType::Txt,
+            ttl,
+            format!("v=STSv1; id={}", self.mta_sts_id),
+            "MTA-STS: a TLS policy is published",
+        ));
+        records.push(DnsRecord::new(&sub("mta-sts"), RecordType::Cname, ttl, hostname, "MTA-STS policy host (HTTPS)"));
+        if !self.tls_reports.is_empty() {
+            records.push(DnsRecord::new(
+                &sub("_smtp._tls"),
+                RecordType::Txt,
+                ttl,
+                format!("v=TLSRPTv1; rua={}", self.tls_reports.join(",")),
+                "TLS-RPT: where TLS failure reports go",
+            ));
+        }
+
+        // Client autoconfiguration: RFC 6186 and RFC 8314 service records,
+        // Outlook's autodiscover, and Thunderbird's autoconfig host
+        for (service, port, comment) in [
+            ("_submissions._tcp", 465, "Mail submission over TLS"),
+            ("_submission._tcp", 587, "Mail submission with STARTTLS"),
+            ("_imaps._tcp", 993, "IMAP over TLS"),
+            ("_autodiscover._tcp", 443, "Outlook autodiscover"),
+        ] {
+            let mut srv = DnsRecord::new(&sub(service), RecordType::Srv, ttl, hostname, comment);
+            srv.priority = Some(0);
+            srv.weight = Some(1);
+            srv.port = Some(port);
+            records.push(srv);
+        }
+        records.push(DnsRecord::new(&sub("autoconfig"), RecordType::Cname, ttl, hostname, "Thunderbird autoconfig"));
+
+        records
+    }
+
+    /// The records as a zone fragment, each after a comment saying what it
+    /// is for.
+    pub fn to_bind(&self) -> String {
+        let mut zone = format!("; Mail records for {}\n", self.domain);
+        for record in self.records() {
+            zone.push_str(&format!("\n; {}\n{}\n", record.comment, record.to_bind()));
+        }
+        zone
+    }
+
+    /// The records as a JSON array.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&self.records())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone() -> MailZone {
+        let mut zone = MailZone::new("example.com", "mail.example.com.");
+        zone.ips = vec!["192.0.2.25".parse().unwrap(), "2001:db8::25".parse().unwrap()];
+        zone.dkim_keys = vec![("mail".to_string(), format!("v=DKIM1; k=rsa; p={}", "A".repeat(300)))];
+        zone.mta_sts_id = "20261018".to_string();
+        zone.tlsa = vec![format!("3 1 1 {}", "ab".repeat(32)).parse().unwrap()];
+        zone
+    }
+
+    #[test]
+    fn test_records() {
+        let records = zone().records();
+        let find = |name: &str, record_type| {
+            records
+                .iter()
+                .find(|r| r.name == name && r.record_type == record_type)
+                .unwrap_or_else(|| panic!("no {} {}", record_type, name))
+        };
+
+        assert_eq!(find("example.com", RecordType::Mx).content, "mail.example.com");
+        assert_eq!(find("mail.example.com", RecordType::Aaaa).content, "2001:db8::25");
+        assert_eq!(
+            find("example.com", RecordType::Txt).content,
+            "v=spf1 mx ip4:192.0.2.25 ip6:2001:db8::25 -all"
+        );
+        assert_eq!(
+            find("_dmarc.example.com", RecordType::Txt).content,
+            "v=DMARC1; p=quarantine; rua=mailto:postmaster@example.com"
+        );
+        assert_eq!(find("_mta-sts.example.com", RecordType::Txt).content, "v=STSv1; id=20261018");
+        assert_eq!(
+            find("_smtp._tls.example.com", RecordType::Txt).content,
+            "v=TLSRPTv1; rua=mailto:postmaster@example.com"
+        );
+        assert_eq!(find("_submission._tcp.example.com", RecordType::Srv).port, Some(587));
+        assert_eq!(
+            find("_25._tcp.mail.example.com", RecordType::Tlsa).content,
+            format!("3 1 1 {}", "AB".repeat(32))
+        );
+
+        // The server's records are not the domain's to publish
+        let mut other = zone();
+        other.domain = "other.org".to_string();
+        assert!(!other
+            .records()
+            .iter()
+            .any(|r| matches!(r.record_type, RecordType::A | RecordType::Tlsa)));
+    }
+
+    #[test]
+    fn test_bind_and_json() {
+        let zone = zone();
+        let bind = zone.to_bind();
+        assert!(bind.contains("\nexample.com.\t3600\tIN\tMX\t10 mail.example.com.\n"));
+        assert!(bind.contains("\n_imaps._tcp.example.com.\t3600\tIN\tSRV\t0 1 993 mail.example.com.\n"));
+        assert!(bind.contains(&format!("\n_25._tcp.mail.example.com.\t3600\tIN\tTLSA\t3 1 1 {}\n", "AB".repeat(32))));
+        // Long TXT values are split into 255-byte strings
+        let dkim = bind.lines().find(|l| l.starts_with("mail._domainkey")).unwrap();
+        assert_eq!(dkim.matches('"').count(), 4);
+
+        let json: serde_json::Value = serde_json::from_str(&zone.to_json().unwrap()).unwrap();
+        let mx = &json[0];
+        assert_eq!(mx["type"], "MX");
+        assert_eq!(mx["priority"], 10);
+        assert!(mx.get("port").is_none());
+    }
+
+    #[test]
+    fn test_dmarc_parse() {
+        let record = DmarcRecord::parse("v=DMARC1; p=Quarantine; sp=reject; pct=50; rua=mailto:a@x.org, mailto:b@y.org").unwrap();
+        assert_eq!(record.policy, "quarantine");
+        assert_eq!(record.subdomain_policy.as_deref(), Some("reject"));
+        assert_eq!(record.percent, 50);
+        assert_eq!(record.aggregate_reports, vec!["mailto:a@x.org", "mailto:b@y.org"]);
+        assert_eq!(DmarcRecord::parse(&record.to_string()), Ok(record));
+
+        assert!(DmarcRecord::parse("p=reject; v=DMARC1").is_err());
+        assert!(DmarcRecord::parse("v=DMARC1; rua=mailto:a@x.org").is_err());
+        assert!(DmarcRecord::parse("v=DMARC1; p=block").is_err());
+        assert!(DmarcRecord::parse("v=DMARC1; p=none; pct=150").is_err());
+    }
+}
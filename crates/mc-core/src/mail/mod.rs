@@ -1,2 +1,12 @@
 pub mod password;
 pub mod dkim;
+pub mod acl;
+pub mod sieve;
+pub mod dkim_rotation;
+pub mod dns_check;
+pub mod dns_records;
+pub mod report_mailbox;
+pub mod dmarc_report;
+pub mod mta_sts;
+pub mod tls_report;
+pub mod dane;
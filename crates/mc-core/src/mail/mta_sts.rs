@@ -0,0 +1,292 @@
+//! MTA-STS (RFC 8461): the policy telling senders to insist on TLS with a
+//! valid certificate when delivering to this server's MX hosts.
+//!
+//! The policy is served at `https://mta-sts.<domain>/.well-known/mta-sts.txt`
+//! from `<MTA_STS_ROOT>/<domain>/`, and announced by the `_mta-sts` TXT
+//! record, whose `id=` is derived from the policy text so that it changes
+//! whenever the policy does.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::security::input::validate_hostname;
+
+/// Directory holding one policy directory per domain, served by the
+/// `mta-sts.<domain>` vhosts.
+pub const MTA_STS_ROOT: &str = "/var/www/mta-sts";
+
+/// Policy path below a domain's directory, fixed by RFC 8461 section 3.2.
+pub const POLICY_PATH: &str = ".well-known/mta-sts.txt";
+
+/// One week: long enough to ride out an outage of the policy host, short
+/// enough to back out of a mistake.
+pub const DEFAULT_MAX_AGE: u32 = 604_800;
+
+/// The largest `max_age` senders must honour (about a year).
+pub const MAX_MAX_AGE: u32 = 31_557_600;
+
+#[derive(Debug, Error)]
+pub enum MtaStsError {
+    #[error("Invalid mode: {0} (expected enforce, testing or none)")]
+    InvalidMode(String),
+    #[error("max_age must be at most {MAX_MAX_AGE} seconds, got {0}")]
+    InvalidMaxAge(u64),
+    #[error("Invalid MX pattern: {0}")]
+    InvalidMx(String),
+    #[error("Policy lists no MX hosts")]
+    NoMx,
+    #[error("Invalid policy: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MtaStsMode {
+    /// Senders refuse to deliver without valid TLS.
+    Enforce,
+    /// Senders deliver anyway but send TLS-RPT reports of failures.
+    #[default]
+    Testing,
+    /// Withdraws a policy senders have cached.
+    None,
+}
+
+impl fmt::Display for MtaStsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MtaStsMode::Enforce => write!(f, "enforce"),
+            MtaStsMode::Testing => write!(f, "testing"),
+            MtaStsMode::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for MtaStsMode {
+    type Err = MtaStsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "enforce" => Ok(MtaStsMode::Enforce),
+            "testing" => Ok(MtaStsMode::Testing),
+            "none" => Ok(MtaStsMode::None),
+            other => Err(MtaStsError::InvalidMode(other.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MtaStsPolicy {
+    pub mode: MtaStsMode,
+    /// Seconds senders may cache the policy.
+    pub max_age: u32,
+    /// MX host names, or `*.` wildcards matching one label.
+    pub mx: Vec<String>,
+}
+
+impl MtaStsPolicy {
+    pub fn new(mode: MtaStsMode, max_age: u32, mx: Vec<String>) -> Result<Self, MtaStsError> {
+        let policy = Self {
+            mode,
+            max_age,
+            mx: mx
+                .into_iter()
+                .map(|m| m.trim().trim_end_matches('.').to_ascii_lowercase())
+                .collect(),
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<(), MtaStsError> {
+        if self.max_age > MAX_MAX_AGE {
+            return Err(MtaStsError::InvalidMaxAge(self.max_age as u64));
+        }
+        // A "none" policy exists to be withdrawn and need not list hosts
+        if self.mx.is_empty() && self.mode != MtaStsMode::None {
+            return Err(MtaStsError::NoMx);
+        }
+        for mx in &self.mx {
+            let host = mx.strip_prefix("*.").unwrap_or(mx);
+            if validate_hostname(host).is_err() || !host.contains('.') {
+                return Err(MtaStsError::InvalidMx(mx.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a policy file. Unknown keys are ignored, as senders must.
+    pub fn parse(text: &str) -> Result<Self, MtaStsError> {
+        let mut version = None;
+        let mut mode = None;
+        let mut max_age = None;
+        let mut mx = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| MtaStsError::Invalid(format!("not a key: value line: {}", line)))?;
+            let value = value.trim();
+            match key.trim() {
+                "version" => version = Some(value.to_string()),
+                "mode" => mode = Some(value.parse()?),
+                "max_age" => {
+                    let age: u64 = value
+                        .parse()
+                        .map_err(|_| MtaStsError::Invalid(format!("max_age: {}", value)))?;
+                    max_age = Some(u32::try_from(age).map_err(|_| MtaStsError::InvalidMaxAge(age))?);
+                }
+                "mx" => mx.push(value.to_string()),
+                _ => {}
+            }
+        }
+        if version.as_deref() != Some("STSv1") {
+            return Err(MtaStsError::Invalid("version must be STSv1".to_string()));
+        }
+        Self::new(
+            mode.ok_or_else(|| MtaStsError::Invalid("missing mode".to_string()))?,
+            max_age.ok_or_else(|| MtaStsError::Invalid("missing max_age".to_string()))?,
+            mx,
+        )
+    }
+
+    /// The policy file, with CRLF line endings as RFC 8461 writes it.
+    pub fn to_text(&self) -> String {
+        let mut out = format!("version: STSv1\r\nmode: {}\r\n", self.mode);
+        for mx in &self.mx {
+            out.push_str(&format!("mx: {}\r\n", mx));
+        }
+        out.push_str(&format!("max_age: {}\r\n", self.max_age));
+        out
+    }
+
+    /// The `id=` for the `_mta-sts` record: a digest of the policy text,
+    /// so publishing a changed policy always comes with a new id.
+    pub fn id(&self) -> String {
+        hex::encode(Sha1::digest(self.to_text().as_bytes()))[..20].to_string()
+    }
+
+    /// Whether `host` is covered by one of the MX patterns.
+    pub fn matches_mx(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.mx.iter().any(|pattern| match pattern.strip_prefix("*.") {
+            Some(suffix) => host
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+            None => *pattern == host,
+        })
+    }
+}
+
+/// The host serving a domain's policy.
+pub fn policy_host(domain: &str) -> String {
+    format!("mta-sts.{}", domain)
+}
+
+/// The document root of a domain's policy vhost under `root`.
+pub fn policy_dir(root: &Path, domain: &str) -> PathBuf {
+    root.join(domain)
+}
+
+/// The policy file of a domain under `root`.
+pub fn policy_path(root: &Path, domain: &str) -> PathBuf {
+    policy_dir(root, domain).join(POLICY_PATH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_text_round_trip() {
+        let policy = MtaStsPolicy::new(
+            MtaStsMode::Enforce,
+            DEFAULT_MAX_AGE,
+            vec!["Mail.Example.com.".to_string(), "*.backup.example.net".to_string()],
+        )
+        .unwrap();
+        let text = policy.to_text();
+        assert_eq!(
+            text,
+            "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.backup.example.net\r\nmax_age: 604800\r\n"
+        );
+        assert_eq!(MtaStsPolicy::parse(&text).unwrap(), policy);
+
+        // LF endings and unknown keys are accepted
+        let loose = "version: STSv1\nmode: testing\nmx: mail.example.com\nmax_age: 86400\nextension: x\n";
+        let parsed = MtaStsPolicy::parse(loose).unwrap();
+        assert_eq!(parsed.mode, MtaStsMode::Testing);
+        assert_eq!(parsed.max_age, 86400);
+    }
+
+    #[test]
+    fn test_policy_id_follows_content() {
+        let mx = vec!["mail.example.com".to_string()];
+        let testing = MtaStsPolicy::new(MtaStsMode::Testing, DEFAULT_MAX_AGE, mx.clone()).unwrap();
+        let enforce = MtaStsPolicy::new(MtaStsMode::Enforce, DEFAULT_MAX_AGE, mx).unwrap();
+        assert_eq!(testing.id(), testing.clone().id());
+        assert_ne!(testing.id(), enforce.id());
+        assert_eq!(testing.id().len(), 20);
+        assert!(testing.id().chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let mx = || vec!["mail.example.com".to_string()];
+        assert!(matches!(
+            MtaStsPolicy::new(MtaStsMode::Enforce, MAX_MAX_AGE + 1, mx()),
+            Err(MtaStsError::InvalidMaxAge(_))
+        ));
+        assert!(matches!(
+            MtaStsPolicy::new(MtaStsMode::Enforce, 3600, Vec::new()),
+            Err(MtaStsError::NoMx)
+        ));
+        assert!(MtaStsPolicy::new(MtaStsMode::None, 3600, Vec::new()).is_ok());
+        for bad in ["mail example.com", "*.*.example.com", "localhost", "-x.example.com"] {
+            assert!(
+                matches!(
+                    MtaStsPolicy::new(MtaStsMode::Testing, 3600, vec![bad.to_string()]),
+                    Err(MtaStsError::InvalidMx(_))
+                ),
+                "{} accepted",
+                bad
+            );
+        }
+        assert!(MtaStsPolicy::parse("mode: enforce\nmx: mail.example.com\nmax_age: 1\n").is_err());
+        assert!(matches!(
+            MtaStsPolicy::parse("version: STSv1\nmode: strict\n"),
+            Err(MtaStsError::InvalidMode(_))
+        ));
+    }
+
+    #[test]
+    fn test_matches_mx() {
+        let policy = MtaStsPolicy::new(
+            MtaStsMode::Enforce,
+            DEFAULT_MAX_AGE,
+            vec!["mail.example.com".to_string(), "*.mx.example.net".to_string()],
+        )
+        .unwrap();
+        assert!(policy.matches_mx("MAIL.example.com."));
+        assert!(policy.matches_mx("a.mx.example.net"));
+        assert!(!policy.matches_mx("a.b.mx.example.net"));
+        assert!(!policy.matches_mx("mx.example.net"));
+        assert!(!policy.matches_mx("other.example.com"));
+    }
+
+    #[test]
+    fn test_policy_path() {
+        assert_eq!(policy_host("example.com"), "mta-sts.example.com");
+        assert_eq!(
+            policy_path(Path::new(MTA_STS_ROOT), "example.com"),
+            Path::new("/var/www/mta-sts/example.com/.well-known/mta-sts.txt")
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Mailboxes that receive machine-generated reports, such as DMARC
+//! aggregate and TLS-RPT reports: the attachments of a raw message, and
+//! the Maildir they are delivered to.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use thiserror::Error;
+
+use crate::security::idn::normalize_email;
+
+/// Nesting depth of multipart bodies followed before giving up.
+const MAX_MIME_DEPTH: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum ReportMailboxError {
+    #[error("Invalid mailbox: {0}")]
+    InvalidMailbox(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// One non-multipart body part of a message, transfer-decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// Lowercased, without parameters, e.g. `application/gzip`.
+    pub mime_type: String,
+    /// Lowercased file name from `Content-Disposition` or `Content-Type`;
+    /// empty when there is none.
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Whether the file name ends with any of `extensions`.
+    pub fn has_extension(&self, extensions: &[&str]) -> bool {
+        extensions.iter().any(|ext| self.filename.ends_with(ext))
+    }
+}
+
+/// Every leaf part of a raw RFC 5322 message, the body of a single-part
+/// message included. Base64 parts are decoded; parts that fail to decode
+/// are dropped.
+pub fn attachments(raw: &[u8]) -> Vec<Attachment> {
+    let mut out = Vec::new();
+    collect(raw, 0, &mut out);
+    out
+}
+
+fn collect(entity: &[u8], depth: usize, out: &mut Vec<Attachment>) {
+    let (head, body) = split_entity(entity);
+    let headers = parse_headers(head);
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("")
+    };
+    let content_type = header("content-type");
+    let mime_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    if mime_type.starts_with("multipart/") {
+        if depth >= MAX_MIME_DEPTH {
+            return;
+        }
+        if let Some(boundary) = header_param(content_type, "boundary") {
+            for part in multipart_parts(body, &boundary) {
+                collect(part, depth + 1, out);
+            }
+        }
+        return;
+    }
+
+    let filename = header_param(header("content-disposition"), "filename")
+        .or_else(|| header_param(content_type, "name"))
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let encoding = header("content-transfer-encoding").trim().to_ascii_lowercase();
+    let data = if encoding == "base64" {
+        let compact: Vec<u8> = body
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        match BASE64.decode(compact) {
+            Ok(data) => data,
+            Err(_) => return,
+        }
+    } else {
+        body.to_vec()
+    };
+    out.push(Attachment {
+        mime_type,
+        filename,
+        data,
+    });
+}
+
+/// Split an entity at the blank line ending its header.
+fn split_entity(entity: &[u8]) -> (&[u8], &[u8]) {
+    let mut pos = 0;
+    while pos < entity.len() {
+        let end = line_end(entity, pos);
+        if trim_newline(&entity[pos..end]).is_empty() {
+            return (&entity[..pos], &entity[end..]);
+        }
+        pos = end;
+    }
+    (entity, &[])
+}
+
+/// Header fields with folded lines joined and names lowercased.
+fn parse_headers(head: &[u8]) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in String::from_utf8_lossy(head).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// A parameter of a structured header value, unquoted.
+fn header_param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, val) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| val.trim().trim_matches('"').to_string())
+    })
+}
+
+/// The body parts between the boundary delimiters of a multipart body.
+fn multipart_parts<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = line_end(body, pos);
+        let line = trim_newline(&body[pos..end]);
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes()) {
+            if let Some(start) = start {
+                parts.push(trim_newline(&body[start..pos]));
+            }
+            if rest.starts_with(b"--") {
+                return parts;
+            }
+            start = Some(end);
+        }
+        pos = end;
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+fn line_end(data: &[u8], pos: usize) -> usize {
+    data[pos..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| pos + i + 1)
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// The Maildir a report address delivers to.
+#[derive(Debug, Clone)]
+pub struct ReportMaildir {
+    path: PathBuf,
+}
+
+impl ReportMaildir {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// The Maildir of a virtual mailbox under `home_base`, laid out as
+    /// Dovecot's `mail_location` has it: `<domain>/<local part>`, with the
+    /// messages in a `Maildir` subdirectory when there is one.
+    pub fn for_address(home_base: &Path, email: &str) -> Result<Self, ReportMailboxError> {
+        let normalized =
+            normalize_email(email, true).map_err(|e| ReportMailboxError::InvalidMailbox(e.to_string()))?;
+        let local = &normalized.local_part;
+        if local.contains('/') || local.starts_with('.') {
+            return Err(ReportMailboxError::InvalidMailbox(email.to_string()));
+        }
+        let home = home_base.join(&normalized.domain.ascii).join(local);
+        let maildir = home.join("Maildir");
+        Ok(Self::new(if maildir.is_dir() { &maildir } else { &home }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Messages not yet marked seen: everything in `new/`, and anything in
+    /// `cur/` without the `S` flag. Oldest first, by file name, which
+    /// Maildir delivery starts with the delivery time.
+    pub fn unread(&self) -> Result<Vec<PathBuf>, ReportMailboxError> {
+        self.list(false)
+    }
+
+    /// Every delivered message, seen or not, oldest first.
+    pub fn messages(&self) -> Result<Vec<PathBuf>, ReportMailboxError> {
+        self.list(true)
+    }
+
+    fn list(&self, include_seen: bool) -> Result<Vec<PathBuf>, ReportMailboxError> {
+        let mut messages = Vec::new();
+        for dir in ["new", "cur"] {
+            let dir = self.path.join(dir);
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') || (!include_seen && is_seen(&name)) {
+                    continue;
+                }
+                messages.push(entry.path());
+            }
+        }
+        messages.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        Ok(messages)
+    }
+
+    /// Mark a message seen, moving it to `cur/` as an IMAP client would,
+    /// so it is not ingested again. Returns the new path.
+    pub fn mark_seen(&self, message: &Path) -> Result<PathBuf, ReportMailboxError> {
+        let name = message
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .ok_or_else(|| ReportMailboxError::InvalidMailbox(message.display().to_string()))?;
+        let seen = match name.split_once(":2,") {
+            Some((_, flags)) if flags.contains('S') => return Ok(message.to_path_buf()),
+            Some((base, flags)) => {
+                let mut flags: Vec<char> = flags.chars().chain(['S']).collect();
+                flags.sort_unstable();
+                format!("{}:2,{}", base, flags.into_iter().collect::<String>())
+            }
+            None => format!("{}:2,S", name),
+        };
+        let target = self.path.join("cur").join(seen);
+        fs::rename(message, &target)?;
+        Ok(target)
+    }
+}
+
+fn is_seen(name: &str) -> bool {
+    name.split_once(":2,")
+        .is_some_and(|(_, flags)| flags.contains('S'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachments() {
+        let message = "From: reports@example.net\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed;\r\n\tboundary=\"outer\"\r\n\
+             \r\n\
+             preamble\r\n\
+             --outer\r\n\
+             Content-Type: multipart/alternative; boundary=inner\r\n\
+             \r\n\
+             --inner\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             See attached.\r\n\
+             --inner--\r\n\
+             --outer\r\n\
+             Content-Type: application/gzip\r\n\
+             Content-Disposition: attachment;\r\n filename=\"Report.XML.gz\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             aGVsbG8g\r\n\
+             d29ybGQ=\r\n\
+             --outer--\r\n\
+             epilogue\r\n";
+        let parts = attachments(message.as_bytes());
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].mime_type, "text/plain");
+        assert_eq!(parts[0].data, b"See attached.");
+        assert_eq!(parts[1].mime_type, "application/gzip");
+        assert_eq!(parts[1].filename, "report.xml.gz");
+        assert!(parts[1].has_extension(&[".zip", ".gz"]));
+        assert_eq!(parts[1].data, b"hello world");
+
+        let single = attachments(b"Subject: hi\nContent-Type: text/plain\n\nbody\n");
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].data, b"body\n");
+    }
+
+    #[test]
+    fn test_maildir_unread() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["new", "cur", "tmp"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        fs::write(dir.path().join("new/1760800000.M1.host"), "a").unwrap();
+        fs::write(dir.path().join("cur/1760700000.M2.host:2,"), "b").unwrap();
+        fs::write(dir.path().join("cur/1760600000.M3.host:2,FS"), "c").unwrap();
+        fs::write(dir.path().join("tmp/1760900000.M4.host"), "d").unwrap();
+
+        let maildir = ReportMaildir::new(dir.path());
+        assert_eq!(
+            ReportMaildir::for_address(dir.path(), "dmarc@Example.com").unwrap().path(),
+            dir.path().join("example.com/dmarc")
+        );
+        assert!(ReportMaildir::for_address(dir.path(), "../x@example.com").is_err());
+        let unread = maildir.unread().unwrap();
+        let names: Vec<_> = unread
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["1760700000.M2.host:2,", "1760800000.M1.host"]);
+
+        assert_eq!(maildir.messages().unwrap().len(), 3);
+
+        let seen = maildir.mark_seen(&unread[1]).unwrap();
+        assert_eq!(seen, dir.path().join("cur/1760800000.M1.host:2,S"));
+        maildir.mark_seen(&unread[0]).unwrap();
+        assert!(maildir.unread().unwrap().is_empty());
+        assert!(dir.path().join("cur/1760700000.M2.host:2,S").exists());
+    }
+}
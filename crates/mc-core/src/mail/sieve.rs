@@ -0,0 +1,688 @@
+//! Per-user Sieve scripts and the vacation autoresponder (Dovecot Pigeonhole).
+//!
+//! Scripts live where Pigeonhole and ManageSieve expect them, so changes made
+//! here and from a ManageSieve client are the same thing:
+//!
+//! - `<home>/sieve/<name>.sieve` - the user's scripts
+//! - `<home>/.dovecot.sieve` - symlink to the active script
+//! - `<home>/sieve-after/vacation.sieve` - the vacation responder, run via
+//!   `sieve_after` so it works alongside whatever filters the user has
+//!
+//! `<home>` is `/var/mail/vhosts/<domain>/<address>/`, matching the
+//! `user_query` in `dovecot-sql.conf.ext`.
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::fs::atomic::{atomic_write, AtomicWriteError};
+use crate::security::idn::normalize_email;
+use crate::security::input::validate_path_component;
+
+/// Base directory for virtual mailbox homes.
+pub const MAIL_HOME_BASE: &str = "/var/mail/vhosts";
+
+/// uid/gid of the vmail user that owns mailbox homes.
+pub const VMAIL_UID: u32 = 5000;
+pub const VMAIL_GID: u32 = 5000;
+
+/// Longest reply interval accepted; Pigeonhole's default
+/// `sieve_vacation_max_period` is 60 days.
+pub const MAX_VACATION_DAYS: u32 = 60;
+
+/// Pigeonhole's default `sieve_max_script_size`.
+pub const MAX_SCRIPT_BYTES: usize = 1024 * 1024;
+
+const SCRIPT_DIR: &str = "sieve";
+const ACTIVE_LINK: &str = ".dovecot.sieve";
+const AFTER_DIR: &str = "sieve-after";
+const VACATION_SCRIPT: &str = "vacation.sieve";
+const VACATION_SETTINGS: &str = "vacation.json";
+
+#[derive(Debug, Error)]
+pub enum SieveError {
+    #[error("Invalid user: {0}")]
+    InvalidUser(String),
+    #[error("Invalid script name: {0}")]
+    InvalidScriptName(String),
+    #[error("Invalid vacation settings: {0}")]
+    InvalidVacation(String),
+    #[error("Script too large: max {max} bytes, got {actual}")]
+    ScriptTooLarge { max: usize, actual: usize },
+    #[error("Script does not compile: {}", format_diagnostics(.0))]
+    CompileFailed(Vec<SieveDiagnostic>),
+    #[error("sievec not found. Is dovecot-sieve installed?")]
+    ToolNotFound,
+    #[error("Script not found: {0}")]
+    NotFound(String),
+    #[error("Cannot delete the active script: {0}")]
+    ScriptActive(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Write failed: {0}")]
+    Write(#[from] AtomicWriteError),
+    #[error("Settings error: {0}")]
+    Settings(#[from] serde_json::Error),
+}
+
+/// One compiler error, with the script line when `sievec` reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SieveDiagnostic {
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+fn format_diagnostics(diagnostics: &[SieveDiagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|d| match d.line {
+            Some(line) => format!("line {}: {}", line, d.message),
+            None => d.message.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// ── vacation ───────────────────────────────────────────────────────
+
+/// Structured vacation-responder settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VacationSettings {
+    /// First day to reply (inclusive). `None` starts immediately.
+    pub start: Option<NaiveDate>,
+    /// Last day to reply (inclusive). `None` replies until disabled.
+    pub end: Option<NaiveDate>,
+    /// Reply subject. Empty lets Pigeonhole use "Auto: <original subject>".
+    pub subject: String,
+    /// Reply body, plain text.
+    pub body: String,
+    /// Reply to each sender at most once per this many days.
+    pub days: u32,
+    /// Other addresses of this user (aliases) that should trigger a reply.
+    #[serde(default)]
+    pub addresses: Vec<String>,
+}
+
+impl VacationSettings {
+    pub fn validate(&self) -> Result<(), SieveError> {
+        let invalid = |msg: &str| Err(SieveError::InvalidVacation(msg.to_string()));
+        if self.body.trim().is_empty() {
+            return invalid("body must not be empty");
+        }
+        if self.subject.contains(['\r', '\n']) {
+            return invalid("subject must be a single line");
+        }
+        if self.subject.chars().count() > 255 {
+            return invalid("subject must be at most 255 characters");
+        }
+        if self.days == 0 || self.days > MAX_VACATION_DAYS {
+            return Err(SieveError::InvalidVacation(format!(
+                "days must be between 1 and {}",
+                MAX_VACATION_DAYS
+            )));
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if end < start {
+                return invalid("end date is before start date");
+            }
+        }
+        for address in &self.addresses {
+            normalize_email(address, true)
+                .map_err(|e| SieveError::InvalidVacation(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Compile the settings to a Sieve script.
+    pub fn to_sieve(&self) -> Result<String, SieveError> {
+        self.validate()?;
+
+        let mut action = format!("vacation :days {}", self.days);
+        if !self.subject.is_empty() {
+            action.push_str(&format!(" :subject {}", quote(&self.subject)));
+        }
+        if !self.addresses.is_empty() {
+            let list: Vec<String> = self.addresses.iter().map(|a| quote(a)).collect();
+            action.push_str(&format!(" :addresses [{}]", list.join(", ")));
+        }
+        action.push_str(&format!(" {};", quote(&self.body)));
+
+        let mut conditions = Vec::new();
+        if let Some(start) = self.start {
+            conditions.push(format!(
+                "currentdate :value \"ge\" \"date\" \"{}\"",
+                start.format("%Y-%m-%d")
+            ));
+        }
+        if let Some(end) = self.end {
+            conditions.push(format!(
+                "currentdate :value \"le\" \"date\" \"{}\"",
+                end.format("%Y-%m-%d")
+            ));
+        }
+
+        let mut script = String::from(
+            "# Generated by mission-control from the vacation settings.\n\
+             # Changes made here are overwritten.\n",
+        );
+        if conditions.is_empty() {
+            script.push_str("require [\"vacation\"];\n\n");
+            script.push_str(&action);
+            script.push('\n');
+        } else {
+            script.push_str("require [\"vacation\", \"date\", \"relational\"];\n\n");
+            script.push_str(&format!("if allof ({}) {{\n", conditions.join(",\n          ")));
+            script.push_str(&format!("  {}\n", action));
+            script.push_str("}\n");
+        }
+        Ok(script)
+    }
+}
+
+/// Sieve quoted string (RFC 5228 section 2.4.2): only `\` and `"` need escaping.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        if c == '\\' || c == '"' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+// ── validation ─────────────────────────────────────────────────────
+
+/// Compile `script` with `sievec` and return its errors, if any.
+pub fn validate_script(script: &str) -> Result<(), SieveError> {
+    if script.len() > MAX_SCRIPT_BYTES {
+        return Err(SieveError::ScriptTooLarge {
+            max: MAX_SCRIPT_BYTES,
+            actual: script.len(),
+        });
+    }
+
+    let dir = tempfile::tempdir()?;
+    let source = dir.path().join("check.sieve");
+    let binary = dir.path().join("check.svbin");
+    fs::write(&source, script)?;
+
+    let output = Command::new("sievec")
+        .arg(&source)
+        .arg(&binary)
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                SieveError::ToolNotFound
+            } else {
+                SieveError::Io(e)
+            }
+        })?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(SieveError::CompileFailed(parse_sievec_output(&stderr)))
+}
+
+/// Parse `sievec` error output, e.g.
+/// `check: line 3: error: unknown command 'fileinto'.`
+fn parse_sievec_output(stderr: &str) -> Vec<SieveDiagnostic> {
+    let mut diagnostics = Vec::new();
+    for line in stderr.lines() {
+        let Some(idx) = line.find("error: ") else {
+            continue;
+        };
+        let message = line[idx + "error: ".len()..].trim().to_string();
+        let number = line
+            .split(": ")
+            .find_map(|part| part.strip_prefix("line "))
+            .and_then(|n| n.parse().ok());
+        // "sievec(root): Error: failed to compile" summary lines carry no detail
+        if message.is_empty() {
+            continue;
+        }
+        diagnostics.push(SieveDiagnostic {
+            line: number,
+            message,
+        });
+    }
+    if diagnostics.is_empty() {
+        diagnostics.push(SieveDiagnostic {
+            line: None,
+            message: stderr.trim().to_string(),
+        });
+    }
+    diagnostics
+}
+
+// ── storage ────────────────────────────────────────────────────────
+
+/// A stored script and whether it is the active one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SieveScriptInfo {
+    pub name: String,
+    pub active: bool,
+}
+
+/// Reads and writes Sieve scripts in mailbox home directories.
+///
+/// Scripts are not validated here; run [`validate_script`] first.
+#[derive(Debug, Clone)]
+pub struct SieveStore {
+    home_base: PathBuf,
+    owner: Option<(u32, u32)>,
+}
+
+impl Default for SieveStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SieveStore {
+    /// Store rooted at [`MAIL_HOME_BASE`], writing files as vmail.
+    pub fn new() -> Self {
+        Self {
+            home_base: PathBuf::from(MAIL_HOME_BASE),
+            owner: Some((VMAIL_UID, VMAIL_GID)),
+        }
+    }
+
+    /// Store rooted elsewhere. `owner` of `None` leaves file ownership alone.
+    pub fn with_home_base(home_base: &Path, owner: Option<(u32, u32)>) -> Self {
+        Self {
+            home_base: home_base.to_path_buf(),
+            owner,
+        }
+    }
+
+    pub fn list_scripts(&self, email: &str) -> Result<Vec<SieveScriptInfo>, SieveError> {
+        let home = self.home(email)?;
+        let dir = home.join(SCRIPT_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let active = self.active_script(email)?;
+
+        let mut scripts = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("sieve") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                scripts.push(SieveScriptInfo {
+                    name: name.to_string(),
+                    active: active.as_deref() == Some(name),
+                });
+            }
+        }
+        scripts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(scripts)
+    }
+
+    pub fn get_script(&self, email: &str, name: &str) -> Result<String, SieveError> {
+        let path = self.script_path(email, name)?;
+        if !path.exists() {
+            return Err(SieveError::NotFound(name.to_string()));
+        }
+        Ok(fs::read_to_string(path)?)
+    }
+
+    /// Create or replace a script. Does not change which script is active.
+    pub fn put_script(&self, email: &str, name: &str, content: &str) -> Result<(), SieveError> {
+        let path = self.script_path(email, name)?;
+        self.ensure_dir(path.parent().expect("script path has a parent"))?;
+        atomic_write(&path, content.as_bytes(), Some(0o600))?;
+        self.chown(&path)?;
+        info!("Stored Sieve script {} for {}", name, email);
+        Ok(())
+    }
+
+    /// Point `.dovecot.sieve` at the named script.
+    pub fn activate(&self, email: &str, name: &str) -> Result<(), SieveError> {
+        let path = self.script_path(email, name)?;
+        if !path.exists() {
+            return Err(SieveError::NotFound(name.to_string()));
+        }
+        let home = self.home(email)?;
+        let link = home.join(ACTIVE_LINK);
+        let temp_link = home.join(format!("{}.tmp", ACTIVE_LINK));
+
+        // Relative target, as ManageSieve creates it; swap in atomically
+        let _ = fs::remove_file(&temp_link);
+        symlink(Path::new(SCRIPT_DIR).join(format!("{}.sieve", name)), &temp_link)?;
+        fs::rename(&temp_link, &link)?;
+        info!("Activated Sieve script {} for {}", name, email);
+        Ok(())
+    }
+
+    /// Remove the active-script link, leaving the scripts in place.
+    pub fn deactivate(&self, email: &str) -> Result<(), SieveError> {
+        let link = self.home(email)?.join(ACTIVE_LINK);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link)?;
+            info!("Deactivated Sieve filtering for {}", email);
+        }
+        Ok(())
+    }
+
+    /// Name of the active script, if `.dovecot.sieve` points into `sieve/`.
+    pub fn active_script(&self, email: &str) -> Result<Option<String>, SieveError> {
+        let link = self.home(email)?.join(ACTIVE_LINK);
+        let target = match fs::read_link(&link) {
+            Ok(target) => target,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            // A regular file (not managed by us) or unreadable link
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let in_script_dir = target
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|d| d == SCRIPT_DIR)
+            .unwrap_or(false);
+        if !in_script_dir || target.extension().and_then(|e| e.to_str()) != Some("sieve") {
+            return Ok(None);
+        }
+        Ok(target
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string()))
+    }
+
+    pub fn delete_script(&self, email: &str, name: &str) -> Result<(), SieveError> {
+        let path = self.script_path(email, name)?;
+        if !path.exists() {
+            return Err(SieveError::NotFound(name.to_string()));
+        }
+        if self.active_script(email)?.as_deref() == Some(name) {
+            return Err(SieveError::ScriptActive(name.to_string()));
+        }
+        fs::remove_file(&path)?;
+        let _ = fs::remove_file(path.with_extension("svbin"));
+        info!("Deleted Sieve script {} for {}", name, email);
+        Ok(())
+    }
+
+    /// Install the compiled vacation script and the settings it came from.
+    pub fn put_vacation(
+        &self,
+        email: &str,
+        settings: &VacationSettings,
+        script: &str,
+    ) -> Result<(), SieveError> {
+        let dir = self.home(email)?.join(AFTER_DIR);
+        self.ensure_dir(&dir)?;
+
+        let settings_path = dir.join(VACATION_SETTINGS);
+        atomic_write(
+            &settings_path,
+            serde_json::to_string_pretty(settings)?.as_bytes(),
+            Some(0o600),
+        )?;
+        self.chown(&settings_path)?;
+
+        let script_path = dir.join(VACATION_SCRIPT);
+        atomic_write(&script_path, script.as_bytes(), Some(0o600))?;
+        self.chown(&script_path)?;
+        // Force Pigeonhole to recompile rather than trust a stale binary
+        let _ = fs::remove_file(script_path.with_extension("svbin"));
+
+        info!("Enabled vacation responder for {}", email);
+        Ok(())
+    }
+
+    pub fn get_vacation(&self, email: &str) -> Result<Option<VacationSettings>, SieveError> {
+        let dir = self.home(email)?.join(AFTER_DIR);
+        if !dir.join(VACATION_SCRIPT).exists() {
+            return Ok(None);
+        }
+        match fs::read_to_string(dir.join(VACATION_SETTINGS)) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn remove_vacation(&self, email: &str) -> Result<(), SieveError> {
+        let dir = self.home(email)?.join(AFTER_DIR);
+        for file in [VACATION_SCRIPT, "vacation.svbin", VACATION_SETTINGS] {
+            match fs::remove_file(dir.join(file)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        info!("Disabled vacation responder for {}", email);
+        Ok(())
+    }
+
+    /// Mailbox home for an address. The address is validated so it cannot
+    /// escape the home base.
+    fn home(&self, email: &str) -> Result<PathBuf, SieveError> {
+        let normalized =
+            normalize_email(email, true).map_err(|e| SieveError::InvalidUser(e.to_string()))?;
+        let address = normalized.address();
+        if address.contains('/') || address.starts_with('.') {
+            return Err(SieveError::InvalidUser(email.to_string()));
+        }
+        Ok(self
+            .home_base
+            .join(&normalized.domain.ascii)
+            .join(address))
+    }
+
+    fn script_path(&self, email: &str, name: &str) -> Result<PathBuf, SieveError> {
+        validate_script_name(name)?;
+        Ok(self
+            .home(email)?
+            .join(SCRIPT_DIR)
+            .join(format!("{}.sieve", name)))
+    }
+
+    fn ensure_dir(&self, dir: &Path) -> Result<(), SieveError> {
+        if !dir.exists() {
+            fs::create_dir_all(dir)?;
+            self.chown(dir)?;
+        }
+        Ok(())
+    }
+
+    fn chown(&self, path: &Path) -> Result<(), SieveError> {
+        if let Some((uid, gid)) = self.owner {
+            nix::unistd::chown(
+                path,
+                Some(nix::unistd::Uid::from_raw(uid)),
+                Some(nix::unistd::Gid::from_raw(gid)),
+            )
+            .map_err(|e| SieveError::Io(e.into()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Script names become file names: safe path component, no leading dot.
+pub fn validate_script_name(name: &str) -> Result<&str, SieveError> {
+    validate_path_component(name).map_err(|_| SieveError::InvalidScriptName(name.to_string()))?;
+    if name.starts_with('.') || name.len() > 64 {
+        return Err(SieveError::InvalidScriptName(name.to_string()));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vacation() -> VacationSettings {
+        VacationSettings {
+            start: None,
+            end: None,
+            subject: "Out of office".to_string(),
+            body: "I am away.".to_string(),
+            days: 7,
+            addresses: Vec::new(),
+        }
+    }
+
+    fn store() -> (tempfile::TempDir, SieveStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SieveStore::with_home_base(dir.path(), None);
+        (dir, store)
+    }
+
+    #[test]
+    fn test_vacation_without_dates() {
+        let script = vacation().to_sieve().unwrap();
+        assert!(script.contains("require [\"vacation\"];"));
+        assert!(script.contains("vacation :days 7 :subject \"Out of office\" \"I am away.\";"));
+        assert!(!script.contains("currentdate"));
+    }
+
+    #[test]
+    fn test_vacation_with_dates_and_addresses() {
+        let settings = VacationSettings {
+            start: NaiveDate::from_ymd_opt(2026, 7, 1),
+            end: NaiveDate::from_ymd_opt(2026, 7, 14),
+            addresses: vec!["info@example.com".to_string()],
+            ..vacation()
+        };
+        let script = settings.to_sieve().unwrap();
+        assert!(script.contains("require [\"vacation\", \"date\", \"relational\"];"));
+        assert!(script.contains("currentdate :value \"ge\" \"date\" \"2026-07-01\""));
+        assert!(script.contains("currentdate :value \"le\" \"date\" \"2026-07-14\""));
+        assert!(script.contains(":addresses [\"info@example.com\"]"));
+    }
+
+    #[test]
+    fn test_vacation_escapes_strings() {
+        let settings = VacationSettings {
+            subject: "Away \"for now\"".to_string(),
+            body: "Back soon.\n\"; discard; #\nC:\\temp".to_string(),
+            ..vacation()
+        };
+        let script = settings.to_sieve().unwrap();
+        assert!(script.contains(":subject \"Away \\\"for now\\\"\""));
+        assert!(script.contains("Back soon.\n\\\"; discard; #\nC:\\\\temp\";"));
+    }
+
+    #[test]
+    fn test_vacation_validation() {
+        let bad = |s: VacationSettings| s.validate().is_err();
+        assert!(bad(VacationSettings { days: 0, ..vacation() }));
+        assert!(bad(VacationSettings { days: 61, ..vacation() }));
+        assert!(bad(VacationSettings { body: "  ".to_string(), ..vacation() }));
+        assert!(bad(VacationSettings {
+            subject: "a\r\nBcc: x@example.com".to_string(),
+            ..vacation()
+        }));
+        assert!(bad(VacationSettings {
+            start: NaiveDate::from_ymd_opt(2026, 7, 14),
+            end: NaiveDate::from_ymd_opt(2026, 7, 1),
+            ..vacation()
+        }));
+        assert!(bad(VacationSettings {
+            addresses: vec!["not an address".to_string()],
+            ..vacation()
+        }));
+    }
+
+    #[test]
+    fn test_parse_sievec_output() {
+        let stderr = "check: line 3: error: unknown command 'fileinto' (only reported once).\n\
+                      check: error: validation failed.\n\
+                      sievec(root): Error: failed to compile sieve script 'check.sieve'\n";
+        let diags = parse_sievec_output(stderr);
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].line, Some(3));
+        assert!(diags[0].message.starts_with("unknown command 'fileinto'"));
+        assert_eq!(diags[1].line, None);
+    }
+
+    #[test]
+    fn test_script_names() {
+        assert!(validate_script_name("filters").is_ok());
+        assert!(validate_script_name("my-rules_2").is_ok());
+        assert!(validate_script_name("../evil").is_err());
+        assert!(validate_script_name(".hidden").is_err());
+        assert!(validate_script_name("a/b").is_err());
+    }
+
+    #[test]
+    fn test_store_put_activate_delete() {
+        let (dir, store) = store();
+        let email = "alice@example.com";
+
+        store.put_script(email, "filters", "keep;\n").unwrap();
+        store.put_script(email, "spam", "discard;\n").unwrap();
+        assert!(dir
+            .path()
+            .join("example.com/alice@example.com/sieve/filters.sieve")
+            .exists());
+        assert_eq!(store.get_script(email, "filters").unwrap(), "keep;\n");
+        assert_eq!(store.active_script(email).unwrap(), None);
+
+        store.activate(email, "filters").unwrap();
+        assert_eq!(store.active_script(email).unwrap().as_deref(), Some("filters"));
+        let scripts = store.list_scripts(email).unwrap();
+        assert_eq!(
+            scripts,
+            vec![
+                SieveScriptInfo { name: "filters".to_string(), active: true },
+                SieveScriptInfo { name: "spam".to_string(), active: false },
+            ]
+        );
+
+        assert!(matches!(
+            store.delete_script(email, "filters"),
+            Err(SieveError::ScriptActive(_))
+        ));
+        store.activate(email, "spam").unwrap();
+        store.delete_script(email, "filters").unwrap();
+        assert!(matches!(
+            store.get_script(email, "filters"),
+            Err(SieveError::NotFound(_))
+        ));
+
+        store.deactivate(email).unwrap();
+        assert_eq!(store.active_script(email).unwrap(), None);
+    }
+
+    #[test]
+    fn test_store_rejects_bad_users() {
+        let (_dir, store) = store();
+        assert!(store.put_script("../../etc@example.com", "x", "keep;").is_err());
+        assert!(store.put_script("a/b@example.com", "x", "keep;").is_err());
+        assert!(store.put_script("not-an-email", "x", "keep;").is_err());
+    }
+
+    #[test]
+    fn test_store_vacation_roundtrip() {
+        let (dir, store) = store();
+        let email = "bob@example.com";
+        assert_eq!(store.get_vacation(email).unwrap(), None);
+
+        let settings = vacation();
+        let script = settings.to_sieve().unwrap();
+        store.put_vacation(email, &settings, &script).unwrap();
+        assert!(dir
+            .path()
+            .join("example.com/bob@example.com/sieve-after/vacation.sieve")
+            .exists());
+        assert_eq!(store.get_vacation(email).unwrap(), Some(settings));
+
+        store.remove_vacation(email).unwrap();
+        assert_eq!(store.get_vacation(email).unwrap(), None);
+    }
+}
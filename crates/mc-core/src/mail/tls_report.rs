@@ -0,0 +1,376 @@
+//! SMTP TLS reports (TLS-RPT, RFC 8460).
+//!
+//! Sending servers that honour the `_smtp._tls` record mail a daily JSON
+//! report, usually gzipped, of how their TLS sessions to our MX hosts
+//! went. Summed per sending organization, the failures show certificate
+//! or MTA-STS problems on our side before senders start deferring mail.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Read;
+
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+use crate::mail::report_mailbox;
+
+/// Largest report accepted once decompressed; real reports are a few
+/// kilobytes.
+pub const MAX_REPORT_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum TlsReportError {
+    #[error("No TLS report found: {0}")]
+    NotAReport(String),
+    #[error("Invalid report JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Report exceeds {MAX_REPORT_BYTES} bytes uncompressed")]
+    TooLarge,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsReport {
+    /// The sending organization, e.g. `Google Inc.`.
+    pub organization_name: String,
+    pub date_range: DateRange,
+    #[serde(default)]
+    pub contact_info: String,
+    pub report_id: String,
+    #[serde(default)]
+    pub policies: Vec<PolicyResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DateRange {
+    pub start_datetime: DateTime<Utc>,
+    pub end_datetime: DateTime<Utc>,
+}
+
+/// Sessions to one domain under one policy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyResult {
+    pub policy: PolicyDetails,
+    pub summary: SessionSummary,
+    #[serde(default)]
+    pub failure_details: Vec<FailureDetails>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyDetails {
+    /// `sts`, `tlsa` or `no-policy-found`.
+    pub policy_type: String,
+    #[serde(default)]
+    pub policy_string: Vec<String>,
+    pub policy_domain: String,
+    /// Some senders send a single string rather than an array.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub mx_host: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionSummary {
+    pub total_successful_session_count: u64,
+    pub total_failure_session_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FailureDetails {
+    /// e.g. `certificate-expired`, `starttls-not-supported`,
+    /// `sts-policy-fetch-error`.
+    pub result_type: String,
+    #[serde(default)]
+    pub sending_mta_ip: Option<String>,
+    #[serde(default)]
+    pub receiving_mx_hostname: Option<String>,
+    #[serde(default)]
+    pub receiving_ip: Option<String>,
+    pub failed_session_count: u64,
+    #[serde(default)]
+    pub failure_reason_code: Option<String>,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(host) => vec![host],
+        OneOrMany::Many(hosts) => hosts,
+    })
+}
+
+impl TlsReport {
+    pub fn parse_json(json: &str) -> Result<Self, TlsReportError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Parse a report as sent: gzipped (`application/tlsrpt+gzip`) or
+    /// bare JSON (`application/tlsrpt+json`).
+    pub fn from_bytes(data: &[u8]) -> Result<Self, TlsReportError> {
+        let mut json = Vec::new();
+        if data.starts_with(&[0x1f, 0x8b]) {
+            MultiGzDecoder::new(data)
+                .take(MAX_REPORT_BYTES + 1)
+                .read_to_end(&mut json)?;
+            if json.len() as u64 > MAX_REPORT_BYTES {
+                return Err(TlsReportError::TooLarge);
+            }
+        } else {
+            json.extend_from_slice(data);
+        }
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    pub fn successful(&self) -> u64 {
+        self.policies
+            .iter()
+            .map(|p| p.summary.total_successful_session_count)
+            .sum()
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.policies
+            .iter()
+            .map(|p| p.summary.total_failure_session_count)
+            .sum()
+    }
+}
+
+/// Every TLS report attached to a raw RFC 5322 message; the first error
+/// is returned only when no attachment parses.
+pub fn reports_from_message(raw: &[u8]) -> Result<Vec<TlsReport>, TlsReportError> {
+    let candidates = report_mailbox::attachments(raw).into_iter().filter(|part| {
+        part.mime_type.starts_with("application/tlsrpt")
+            || part.has_extension(&[".json", ".json.gz"])
+            || (part.mime_type == "application/gzip" && part.filename.contains("tlsrpt"))
+    });
+
+    let mut reports = Vec::new();
+    let mut first_error = None;
+    for part in candidates {
+        match TlsReport::from_bytes(&part.data) {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match (reports.is_empty(), first_error) {
+        (true, Some(e)) => Err(e),
+        (true, None) => Err(TlsReportError::NotAReport(
+            "message has no TLS report attachment".to_string(),
+        )),
+        _ => Ok(reports),
+    }
+}
+
+/// Sessions one sending organization reported, across its reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OrganizationSummary {
+    pub organization: String,
+    pub reports: u32,
+    pub successful: u64,
+    pub failed: u64,
+    /// Failed sessions by result type.
+    pub failures: BTreeMap<String, u64>,
+    /// Our MX hosts the failures were against.
+    pub mx_hosts: BTreeSet<String>,
+    /// Our domains the organization sent to.
+    pub domains: BTreeSet<String>,
+    pub last_report: Option<DateTime<Utc>>,
+}
+
+/// Sum reports per organization, most failed sessions first. A report
+/// sent twice counts once.
+pub fn summarize(reports: &[TlsReport]) -> Vec<OrganizationSummary> {
+    let mut seen = HashSet::new();
+    let mut by_org: BTreeMap<&str, OrganizationSummary> = BTreeMap::new();
+    for report in reports {
+        if !seen.insert((&report.organization_name, &report.report_id)) {
+            continue;
+        }
+        let summary = by_org
+            .entry(&report.organization_name)
+            .or_insert_with(|| OrganizationSummary {
+                organization: report.organization_name.clone(),
+                ..OrganizationSummary::default()
+            });
+        summary.reports += 1;
+        summary.successful += report.successful();
+        summary.failed += report.failed();
+        summary.last_report = summary.last_report.max(Some(report.date_range.end_datetime));
+        for policy in &report.policies {
+            summary.domains.insert(policy.policy.policy_domain.to_ascii_lowercase());
+            for failure in &policy.failure_details {
+                *summary.failures.entry(failure.result_type.clone()).or_default() +=
+                    failure.failed_session_count;
+                if let Some(mx) = &failure.receiving_mx_hostname {
+                    summary
+                        .mx_hosts
+                        .insert(mx.trim_end_matches('.').to_ascii_lowercase());
+                }
+            }
+        }
+    }
+
+    let mut summaries: Vec<OrganizationSummary> = by_org.into_values().collect();
+    summaries.sort_by(|a, b| b.failed.cmp(&a.failed).then(a.organization.cmp(&b.organization)));
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// The example from RFC 8460 appendix B.
+    const REPORT: &str = r#"{
+      "organization-name": "Company-X",
+      "date-range": {
+        "start-datetime": "2016-04-01T00:00:00Z",
+        "end-datetime": "2016-04-01T23:59:59Z"
+      },
+      "contact-info": "sts-reporting@company-x.example",
+      "report-id": "5065427c-23d3-47ca-b6e0-946ea0e8c4be",
+      "policies": [{
+        "policy": {
+          "policy-type": "sts",
+          "policy-string": ["version: STSv1", "mode: testing",
+                            "mx: *.mail.company-y.example", "max_age: 86400"],
+          "policy-domain": "company-y.example",
+          "mx-host": ["*.mail.company-y.example"]
+        },
+        "summary": {
+          "total-successful-session-count": 5326,
+          "total-failure-session-count": 303
+        },
+        "failure-details": [{
+          "result-type": "certificate-expired",
+          "sending-mta-ip": "2001:db8:abcd:0012::1",
+          "receiving-mx-hostname": "mx1.mail.company-y.example",
+          "failed-session-count": 100
+        }, {
+          "result-type": "starttls-not-supported",
+          "sending-mta-ip": "2001:db8:abcd:0013::1",
+          "receiving-mx-hostname": "mx2.mail.company-y.example",
+          "receiving-ip": "203.0.113.56",
+          "failed-session-count": 200,
+          "additional-information": "https://reports.company-x.example/report_info?id=5065427c-23d3#StarttlsNotSupported"
+        }, {
+          "result-type": "validation-failure",
+          "sending-mta-ip": "198.51.100.62",
+          "receiving-ip": "203.0.113.58",
+          "receiving-mx-hostname": "mx-backup.mail.company-y.example",
+          "failed-session-count": 3,
+          "failure-reason-code": "X509_V_ERR_PROXY_PATH_LENGTH_EXCEEDED"
+        }]
+      }]
+    }"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = TlsReport::parse_json(REPORT).unwrap();
+        assert_eq!(report.organization_name, "Company-X");
+        assert_eq!(report.successful(), 5326);
+        assert_eq!(report.failed(), 303);
+        let policy = &report.policies[0];
+        assert_eq!(policy.policy.policy_type, "sts");
+        assert_eq!(policy.policy.mx_host, ["*.mail.company-y.example"]);
+        assert_eq!(policy.failure_details.len(), 3);
+        assert_eq!(
+            policy.failure_details[2].failure_reason_code.as_deref(),
+            Some("X509_V_ERR_PROXY_PATH_LENGTH_EXCEEDED")
+        );
+        assert_eq!(TlsReport::from_bytes(&gzip(REPORT.as_bytes())).unwrap(), report);
+
+        let single_mx = REPORT.replace(r#"["*.mail.company-y.example"]"#, r#""*.mail.company-y.example""#);
+        assert_eq!(TlsReport::parse_json(&single_mx).unwrap().policies[0].policy.mx_host.len(), 1);
+        assert!(TlsReport::parse_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_reports_from_message() {
+        let message = format!(
+            "From: noreply-smtp-tls-reporting@google.com\r\n\
+             TLS-Report-Domain: company-y.example\r\n\
+             TLS-Report-Submitter: google.com\r\n\
+             Content-Type: multipart/report; report-type=\"tlsrpt\";\r\n boundary=\"rpt\"\r\n\
+             \r\n\
+             --rpt\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             This is an aggregate TLS report from google.com\r\n\
+             --rpt\r\n\
+             Content-Type: application/tlsrpt+gzip\r\n\
+             Content-Disposition: attachment;\r\n filename=\"google.com!company-y.example!1459468800!1459555199!001.json.gz\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             {}\r\n\
+             --rpt--\r\n",
+            BASE64.encode(gzip(REPORT.as_bytes()))
+        );
+        let reports = reports_from_message(message.as_bytes()).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].report_id, "5065427c-23d3-47ca-b6e0-946ea0e8c4be");
+
+        assert!(matches!(
+            reports_from_message(b"Subject: hi\r\nContent-Type: text/plain\r\n\r\nhi\r\n"),
+            Err(TlsReportError::NotAReport(_))
+        ));
+    }
+
+    #[test]
+    fn test_summarize_per_organization() {
+        let first = TlsReport::parse_json(REPORT).unwrap();
+        let mut second = first.clone();
+        second.report_id = "next-day".to_string();
+        second.date_range.end_datetime = "2016-04-02T23:59:59Z".parse().unwrap();
+        let mut clean = first.clone();
+        clean.organization_name = "Company-Z".to_string();
+        clean.policies[0].summary.total_failure_session_count = 0;
+        clean.policies[0].failure_details.clear();
+
+        // The first report re-sent must not count twice
+        let summaries = summarize(&[first.clone(), clean, second.clone(), first]);
+        assert_eq!(summaries.len(), 2);
+        let x = &summaries[0];
+        assert_eq!(x.organization, "Company-X");
+        assert_eq!(x.reports, 2);
+        assert_eq!(x.successful, 2 * 5326);
+        assert_eq!(x.failed, 2 * 303);
+        assert_eq!(x.failures["certificate-expired"], 200);
+        assert_eq!(x.failures["starttls-not-supported"], 400);
+        assert!(x.mx_hosts.contains("mx-backup.mail.company-y.example"));
+        assert_eq!(x.domains.iter().collect::<Vec<_>>(), ["company-y.example"]);
+        assert_eq!(x.last_report, Some(second.date_range.end_datetime));
+
+        assert_eq!(summaries[1].organization, "Company-Z");
+        assert_eq!(summaries[1].failed, 0);
+        assert!(summaries[1].failures.is_empty());
+    }
+}
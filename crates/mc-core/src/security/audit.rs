@@ -144,6 +144,9 @@ pub struct AuditEvent {
     /// Additional human-readable details about the event.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub details: Option<String>,
+    /// True when the action was only previewed and nothing was changed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
 }
 
 impl AuditEvent {
@@ -161,6 +164,7 @@ impl AuditEvent {
             target: target.into(),
             result,
             details: None,
+            dry_run: false,
         }
     }
 
@@ -179,6 +183,7 @@ impl AuditEvent {
             target: target.into(),
             result,
             details: Some(details.into()),
+            dry_run: false,
         }
     }
 
@@ -200,6 +205,13 @@ impl AuditEvent {
     ) -> Self {
         Self::with_details(action, actor, target, AuditResult::Failure, error.to_string())
     }
+
+    /// Mark the event as a dry run, so previews are kept apart from
+    /// changes that were actually applied.
+    pub fn preview(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
 }
 
 impl fmt::Display for AuditEvent {
@@ -213,6 +225,9 @@ impl fmt::Display for AuditEvent {
             self.target,
             self.result,
         )?;
+        if self.dry_run {
+            write!(f, " [dry run]")?;
+        }
         if let Some(ref details) = self.details {
             write!(f, ": {}", details)?;
         }
@@ -587,6 +602,24 @@ mod tests {
         assert_eq!(value["result"], "success");
         // details should be absent when None, not null.
         assert!(!value.as_object().unwrap().contains_key("details"));
+        assert!(!value.as_object().unwrap().contains_key("dry_run"));
+    }
+
+    #[test]
+    fn test_audit_event_preview() {
+        let event = AuditEvent::success(AuditAction::ConfigChange, "admin", "/etc/postfix/main.cf")
+            .preview();
+        assert!(event.dry_run);
+        assert!(event.to_string().contains("(success) [dry run]"));
+
+        let json = serde_json::to_string(&event).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["dry_run"], true);
+
+        // Events written before the flag existed are applies.
+        let old = r#"{"timestamp":"2026-01-01T00:00:00Z","action":"config_change","actor":"admin","target":"x","result":"success"}"#;
+        let parsed: AuditEvent = serde_json::from_str(old).unwrap();
+        assert!(!parsed.dry_run);
     }
 
     #[test]
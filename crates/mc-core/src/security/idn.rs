@@ -0,0 +1,373 @@
+//! Internationalized domain names (IDNA 2008 / UTS #46) and SMTPUTF8 addresses.
+//!
+//! Domains are always stored, written into config files, and published in DNS
+//! in their ASCII (punycode) form. The Unicode form is derived on demand and is
+//! only ever used for display. Non-ASCII local parts are accepted only when
+//! Postfix has `smtputf8_enable = yes`, since without it such mail bounces.
+
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+
+use super::input::{validate_domain, ValidationError};
+
+/// ASCII local part (RFC 5321 dot-atom characters), 1-64 octets.
+static ASCII_LOCAL_PART_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]{1,64}$").unwrap());
+
+/// Maximum length of a local part in octets (RFC 5321 section 4.5.3.1.1).
+const MAX_LOCAL_PART_OCTETS: usize = 64;
+
+/// Maximum length of a full address in octets.
+const MAX_ADDRESS_OCTETS: usize = 254;
+
+/// A potential homograph problem found in a domain label.
+///
+/// These are warnings, not errors: legitimate IDNs can trip them, so the
+/// operator is shown the warning and decides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HomographWarning {
+    /// The label mixes characters from several scripts (e.g. Latin and Cyrillic).
+    MixedScript { label: String },
+    /// The label is visually confusable with the given ASCII label.
+    Confusable { label: String, lookalike: String },
+}
+
+impl fmt::Display for HomographWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MixedScript { label } => {
+                write!(f, "Label '{}' mixes characters from several scripts", label)
+            }
+            Self::Confusable { label, lookalike } => {
+                write!(f, "Label '{}' can be confused with '{}'", label, lookalike)
+            }
+        }
+    }
+}
+
+/// A validated domain in both its storage (ASCII) and display (Unicode) forms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdnDomain {
+    /// Lowercased ASCII form with punycode `xn--` labels. Use this everywhere
+    /// except the UI.
+    pub ascii: String,
+    /// Unicode form for display.
+    pub unicode: String,
+    /// Homograph warnings for the operator.
+    pub warnings: Vec<HomographWarning>,
+}
+
+impl IdnDomain {
+    /// Whether the domain contains any internationalized labels.
+    pub fn is_internationalized(&self) -> bool {
+        self.ascii != self.unicode
+    }
+}
+
+/// A validated email address in storage and display forms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdnEmail {
+    /// NFC-normalized local part.
+    pub local_part: String,
+    /// The domain part.
+    pub domain: IdnDomain,
+    /// Whether delivering to this address requires SMTPUTF8 (non-ASCII local part).
+    pub requires_smtputf8: bool,
+}
+
+impl IdnEmail {
+    /// The address as stored in the mail DB: local part plus ASCII domain.
+    pub fn address(&self) -> String {
+        format!("{}@{}", self.local_part, self.domain.ascii)
+    }
+
+    /// The address as shown to users: local part plus Unicode domain.
+    pub fn display(&self) -> String {
+        format!("{}@{}", self.local_part, self.domain.unicode)
+    }
+}
+
+/// Validate and normalize a (possibly internationalized) domain name.
+///
+/// Applies UTS #46 processing with STD3 rules and DNS length checks, which
+/// gives IDNA 2008 compatible output. The resulting ASCII form must still pass
+/// [`validate_domain`], so everything downstream keeps its existing guarantees.
+pub fn normalize_domain(domain: &str) -> Result<IdnDomain, ValidationError> {
+    if domain.is_empty() || domain.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(ValidationError::InvalidDomain(domain.to_string()));
+    }
+
+    let ascii = idna::domain_to_ascii_strict(domain)
+        .map_err(|_| ValidationError::InvalidDomain(domain.to_string()))?;
+    validate_domain(&ascii)?;
+
+    let unicode = to_unicode(&ascii);
+    let warnings = homograph_warnings(&unicode);
+
+    Ok(IdnDomain {
+        ascii,
+        unicode,
+        warnings,
+    })
+}
+
+/// Validate and normalize a (possibly internationalized) email address.
+///
+/// The domain is always converted to ASCII. A non-ASCII local part is only
+/// accepted when `smtputf8` is true (Postfix `smtputf8_enable = yes`).
+pub fn normalize_email(email: &str, smtputf8: bool) -> Result<IdnEmail, ValidationError> {
+    if email.len() > MAX_ADDRESS_OCTETS {
+        return Err(ValidationError::TooLong {
+            max: MAX_ADDRESS_OCTETS,
+            actual: email.len(),
+        });
+    }
+
+    let (local, domain) = email
+        .rsplit_once('@')
+        .ok_or_else(|| ValidationError::InvalidEmail(email.to_string()))?;
+
+    let domain = normalize_domain(domain)
+        .map_err(|_| ValidationError::InvalidEmail(email.to_string()))?;
+
+    let (local_part, requires_smtputf8) = if local.is_ascii() {
+        if !ASCII_LOCAL_PART_RE.is_match(local) {
+            return Err(ValidationError::InvalidEmail(email.to_string()));
+        }
+        (local.to_string(), false)
+    } else {
+        if !smtputf8 {
+            return Err(ValidationError::Smtputf8Required(email.to_string()));
+        }
+        let normalized: String = local.nfc().collect();
+        if !is_valid_utf8_local_part(&normalized) {
+            return Err(ValidationError::InvalidEmail(email.to_string()));
+        }
+        (normalized, true)
+    };
+
+    let normalized = IdnEmail {
+        local_part,
+        domain,
+        requires_smtputf8,
+    };
+
+    let stored_len = normalized.address().len();
+    if stored_len > MAX_ADDRESS_OCTETS {
+        return Err(ValidationError::TooLong {
+            max: MAX_ADDRESS_OCTETS,
+            actual: stored_len,
+        });
+    }
+
+    Ok(normalized)
+}
+
+/// Convert a stored ASCII domain back to its Unicode display form.
+///
+/// Falls back to the input unchanged if it does not decode cleanly, so a
+/// malformed stored value is shown as-is rather than with replacement characters.
+pub fn to_unicode(ascii_domain: &str) -> String {
+    match idna::domain_to_unicode(ascii_domain) {
+        (unicode, Ok(())) => unicode,
+        (_, Err(_)) => ascii_domain.to_string(),
+    }
+}
+
+/// Convert a stored address (`local@ascii-domain`) to its display form.
+pub fn email_to_unicode(address: &str) -> String {
+    match address.rsplit_once('@') {
+        Some((local, domain)) => format!("{}@{}", local, to_unicode(domain)),
+        None => address.to_string(),
+    }
+}
+
+/// UTF-8 local parts: same rules as ASCII dot-atom for ASCII characters, and
+/// only letters, digits and combining marks beyond ASCII. Dots may not lead,
+/// trail, or repeat.
+fn is_valid_utf8_local_part(local: &str) -> bool {
+    if local.is_empty() || local.len() > MAX_LOCAL_PART_OCTETS {
+        return false;
+    }
+    if local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return false;
+    }
+    local.chars().all(|c| {
+        if c.is_ascii() {
+            c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~.-".contains(c)
+        } else {
+            c.is_alphanumeric() || is_combining_mark(c)
+        }
+    })
+}
+
+/// Combining diacritical marks that survive NFC (e.g. in scripts without
+/// precomposed forms).
+fn is_combining_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{0900}'..='\u{0903}'
+        | '\u{093A}'..='\u{094F}'
+        | '\u{0E31}'..='\u{0E3A}'
+        | '\u{0E47}'..='\u{0E4E}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+/// Check each non-ASCII label for UTS #39 mixed-script and confusable issues.
+fn homograph_warnings(unicode_domain: &str) -> Vec<HomographWarning> {
+    let mut warnings = Vec::new();
+    for label in unicode_domain.split('.') {
+        if label.is_ascii() {
+            continue;
+        }
+        if !label.is_single_script() {
+            warnings.push(HomographWarning::MixedScript {
+                label: label.to_string(),
+            });
+        }
+        let lookalike: String = skeleton(label).collect();
+        if lookalike.is_ascii() && lookalike != label {
+            warnings.push(HomographWarning::Confusable {
+                label: label.to_string(),
+                lookalike,
+            });
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_domain_unchanged() {
+        let d = normalize_domain("example.com").unwrap();
+        assert_eq!(d.ascii, "example.com");
+        assert_eq!(d.unicode, "example.com");
+        assert!(!d.is_internationalized());
+        assert!(d.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_unicode_domain_to_punycode() {
+        let d = normalize_domain("münchen.de").unwrap();
+        assert_eq!(d.ascii, "xn--mnchen-3ya.de");
+        assert_eq!(d.unicode, "münchen.de");
+        assert!(d.is_internationalized());
+        assert!(d.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_uppercase_is_folded() {
+        let d = normalize_domain("MÜNCHEN.DE").unwrap();
+        assert_eq!(d.ascii, "xn--mnchen-3ya.de");
+    }
+
+    #[test]
+    fn test_idn_tld() {
+        let d = normalize_domain("пример.рф").unwrap();
+        assert_eq!(d.ascii, "xn--e1afmkfd.xn--p1ai");
+        assert_eq!(d.unicode, "пример.рф");
+    }
+
+    #[test]
+    fn test_punycode_input_roundtrips() {
+        let d = normalize_domain("xn--mnchen-3ya.de").unwrap();
+        assert_eq!(d.ascii, "xn--mnchen-3ya.de");
+        assert_eq!(d.unicode, "münchen.de");
+    }
+
+    #[test]
+    fn test_invalid_domains_rejected() {
+        assert!(normalize_domain("").is_err());
+        assert!(normalize_domain("exa mple.com").is_err());
+        assert!(normalize_domain("example.com; rm -rf /").is_err());
+        assert!(normalize_domain("-bad.com").is_err());
+        assert!(normalize_domain("example").is_err());
+        assert!(normalize_domain("münchen.de\n").is_err());
+    }
+
+    #[test]
+    fn test_mixed_script_warning() {
+        // Latin "p", "y", "l" mixed with Cyrillic "а"
+        let d = normalize_domain("pаypal.com").unwrap();
+        assert!(d
+            .warnings
+            .iter()
+            .any(|w| matches!(w, HomographWarning::MixedScript { .. })));
+    }
+
+    #[test]
+    fn test_whole_script_confusable_warning() {
+        // All-Cyrillic label that looks like "cope"
+        let d = normalize_domain("соре.com").unwrap();
+        assert!(d.warnings.iter().any(|w| matches!(
+            w,
+            HomographWarning::Confusable { lookalike, .. } if lookalike == "cope"
+        )));
+    }
+
+    #[test]
+    fn test_ascii_email() {
+        let e = normalize_email("user+tag@example.com", false).unwrap();
+        assert_eq!(e.address(), "user+tag@example.com");
+        assert!(!e.requires_smtputf8);
+    }
+
+    #[test]
+    fn test_email_with_idn_domain() {
+        let e = normalize_email("info@münchen.de", false).unwrap();
+        assert_eq!(e.address(), "info@xn--mnchen-3ya.de");
+        assert_eq!(e.display(), "info@münchen.de");
+        assert!(!e.requires_smtputf8);
+    }
+
+    #[test]
+    fn test_utf8_local_part_requires_smtputf8() {
+        let err = normalize_email("jörg@example.com", false).unwrap_err();
+        assert!(matches!(err, ValidationError::Smtputf8Required(_)));
+
+        let e = normalize_email("jörg@example.com", true).unwrap();
+        assert_eq!(e.local_part, "jörg");
+        assert!(e.requires_smtputf8);
+    }
+
+    #[test]
+    fn test_utf8_local_part_is_nfc_normalized() {
+        // "o" + combining diaeresis becomes precomposed "ö"
+        let e = normalize_email("jo\u{0308}rg@example.com", true).unwrap();
+        assert_eq!(e.local_part, "j\u{00F6}rg");
+    }
+
+    #[test]
+    fn test_invalid_emails_rejected() {
+        assert!(normalize_email("no-at-sign", true).is_err());
+        assert!(normalize_email("@example.com", true).is_err());
+        assert!(normalize_email("user@", true).is_err());
+        assert!(normalize_email("us er@example.com", true).is_err());
+        assert!(normalize_email("jö rg@example.com", true).is_err());
+        assert!(normalize_email(".jörg@example.com", true).is_err());
+        assert!(normalize_email("user@example.com; DROP TABLE users;--", true).is_err());
+    }
+
+    #[test]
+    fn test_local_part_length_limit() {
+        let long_local = "ö".repeat(33); // 66 octets
+        assert!(normalize_email(&format!("{}@example.com", long_local), true).is_err());
+    }
+
+    #[test]
+    fn test_email_to_unicode() {
+        assert_eq!(email_to_unicode("info@xn--mnchen-3ya.de"), "info@münchen.de");
+        assert_eq!(email_to_unicode("user@example.com"), "user@example.com");
+    }
+}
@@ -30,16 +30,19 @@ pub enum ValidationError {
     ForbiddenCharacters(String),
     #[error("Password does not meet requirements")]
     WeakPassword,
+    #[error("Internationalized local part requires SMTPUTF8: {0}")]
+    Smtputf8Required(String),
 }
 
 // ---------------------------------------------------------------------------
 // Strict regex patterns -- allowlists only, never denylists.
 // ---------------------------------------------------------------------------
 
-/// Fully-qualified domain name (RFC 1035 / RFC 1123 compatible).
+/// Fully-qualified domain name (RFC 1035 / RFC 1123 compatible). The TLD may be
+/// an IDNA A-label (`xn--...`); Unicode input goes through `security::idn` first.
 static DOMAIN_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
-        r"^[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*\.[a-zA-Z]{2,}$",
+        r"^[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*\.(?:[a-zA-Z]{2,}|xn--[a-zA-Z0-9-]{1,59})$",
     )
     .unwrap()
 });
@@ -47,7 +50,7 @@ static DOMAIN_RE: Lazy<Regex> = Lazy::new(|| {
 /// RFC 5321 compatible email address (simplified but safe).
 static EMAIL_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
-        r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*\.[a-zA-Z]{2,}$",
+        r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*\.(?:[a-zA-Z]{2,}|xn--[a-zA-Z0-9-]{1,59})$",
     )
     .unwrap()
 });
@@ -196,6 +199,9 @@ pub fn assert_no_shell_metacharacters(input: &str) -> Result<&str, ValidationErr
 /// - Contains at least one lowercase letter
 /// - Contains at least one ASCII digit
 /// - Contains at least one non-alphanumeric (special) character
+///
+/// This is the fixed baseline. Mailbox passwords go through the configurable
+/// [`super::password_policy`] engine instead.
 pub fn validate_password(password: &str) -> Result<(), ValidationError> {
     if password.len() < 12 {
         return Err(ValidationError::WeakPassword);
@@ -258,6 +264,15 @@ mod tests {
         assert!(validate_domain("example.com.").is_err());
     }
 
+    #[test]
+    fn test_punycode_domains() {
+        assert!(validate_domain("xn--mnchen-3ya.de").is_ok());
+        assert!(validate_domain("xn--e1afmkfd.xn--p1ai").is_ok());
+        assert!(validate_email("info@xn--e1afmkfd.xn--p1ai").is_ok());
+        assert!(validate_domain("example.xn--").is_err());
+        assert!(validate_domain("münchen.de").is_err());
+    }
+
     #[test]
     fn test_domain_too_long() {
         let long_domain = format!("{}.com", "a".repeat(250));
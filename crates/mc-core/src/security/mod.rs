@@ -7,3 +7,5 @@
 pub mod input;
 pub mod credentials;
 pub mod audit;
+pub mod idn;
+pub mod password_policy;
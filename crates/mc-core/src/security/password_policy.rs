@@ -0,0 +1,693 @@
+//! Configurable password policy for mailbox users.
+//!
+//! Replaces the fixed rules in [`super::input::validate_password`] with a policy
+//! that can be tuned per deployment and overridden per domain. A check returns
+//! every rule the password broke, not just the first, so the dashboard can show
+//! the user all of them at once.
+//!
+//! # Breached passwords
+//!
+//! The breached-password list is SHA-1 hashes, each optionally followed by
+//! `:count`, in one of the layouts of the Have I Been Pwned downloads:
+//!
+//! - a directory of range files, one per 5-hex-digit prefix, named by the
+//!   prefix and holding the remaining 35 digits of each hash. This is the
+//!   k-anonymity split the HIBP range API uses; a lookup reads one file.
+//! - a single file of full hashes sorted in ascending order, searched in
+//!   place.
+//!
+//! Neither is read into memory as a whole, since the full list runs to
+//! hundreds of millions of hashes. Nothing leaves the machine.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use super::idn;
+use crate::mail::password;
+
+/// Default location of the deployment-wide policy file.
+pub const PASSWORD_POLICY_PATH: &str = "/etc/ceymail-mc/password-policy.json";
+
+/// Length of the hash prefix naming a breached-list range file.
+const PREFIX_LEN: usize = 5;
+
+/// Local part / domain tokens shorter than this are ignored by the identity
+/// rule; otherwise a two-letter user name would rule out far too much.
+const MIN_IDENTITY_TOKEN_LEN: usize = 3;
+
+/// Errors loading the policy or the breached-password list.
+#[derive(Debug, Error)]
+pub enum PolicyLoadError {
+    #[error("I/O error reading {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid policy file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// A character class counted towards [`PasswordPolicy::min_character_classes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterClass {
+    Uppercase,
+    Lowercase,
+    Digit,
+    Special,
+}
+
+impl CharacterClass {
+    pub const ALL: [CharacterClass; 4] = [
+        CharacterClass::Uppercase,
+        CharacterClass::Lowercase,
+        CharacterClass::Digit,
+        CharacterClass::Special,
+    ];
+
+    fn matches(self, c: char) -> bool {
+        match self {
+            Self::Uppercase => c.is_uppercase(),
+            Self::Lowercase => c.is_lowercase(),
+            Self::Digit => c.is_ascii_digit(),
+            Self::Special => !c.is_alphanumeric(),
+        }
+    }
+}
+
+impl fmt::Display for CharacterClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uppercase => write!(f, "uppercase letter"),
+            Self::Lowercase => write!(f, "lowercase letter"),
+            Self::Digit => write!(f, "digit"),
+            Self::Special => write!(f, "special character"),
+        }
+    }
+}
+
+/// One broken rule. Serialized with a stable `code` tag for the UI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PolicyViolation {
+    TooShort { min: usize, actual: usize },
+    TooLong { max: usize, actual: usize },
+    TooFewCharacterClasses {
+        required: usize,
+        actual: usize,
+        missing: Vec<CharacterClass>,
+    },
+    Breached,
+    ContainsLocalPart,
+    ContainsDomain,
+    RecentlyUsed { history_depth: usize },
+}
+
+impl PolicyViolation {
+    /// Stable machine-readable identifier, matching the serde tag.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TooShort { .. } => "too_short",
+            Self::TooLong { .. } => "too_long",
+            Self::TooFewCharacterClasses { .. } => "too_few_character_classes",
+            Self::Breached => "breached",
+            Self::ContainsLocalPart => "contains_local_part",
+            Self::ContainsDomain => "contains_domain",
+            Self::RecentlyUsed { .. } => "recently_used",
+        }
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { min, actual } => write!(
+                f,
+                "Password must be at least {} characters (got {})",
+                min, actual
+            ),
+            Self::TooLong { max, actual } => write!(
+                f,
+                "Password must be at most {} characters (got {})",
+                max, actual
+            ),
+            Self::TooFewCharacterClasses {
+                required, missing, ..
+            } => {
+                let missing: Vec<String> = missing.iter().map(|c| c.to_string()).collect();
+                write!(
+                    f,
+                    "Password must use at least {} of: uppercase, lowercase, digit, special \
+                     (missing: {})",
+                    required,
+                    missing.join(", ")
+                )
+            }
+            Self::Breached => write!(f, "Password appears in a list of breached passwords"),
+            Self::ContainsLocalPart => write!(f, "Password must not contain the user name"),
+            Self::ContainsDomain => write!(f, "Password must not contain the domain name"),
+            Self::RecentlyUsed { history_depth } => write!(
+                f,
+                "Password must differ from the last {} passwords",
+                history_depth
+            ),
+        }
+    }
+}
+
+/// Returned when a password fails one or more policy rules.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Password rejected: {}", join_violations(.violations))]
+pub struct PasswordRejected {
+    pub violations: Vec<PolicyViolation>,
+}
+
+fn join_violations(violations: &[PolicyViolation]) -> String {
+    violations
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Rules for a single deployment or domain.
+///
+/// Missing fields in the policy file fall back to the defaults, which match
+/// the fixed rules the dashboard enforced before policies were configurable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    /// Minimum length in characters.
+    pub min_length: usize,
+    /// Maximum length in characters; bounds hashing cost.
+    pub max_length: usize,
+    /// How many of the four [`CharacterClass`]es must appear (0-4).
+    pub min_character_classes: usize,
+    /// Reject passwords found in the breached-password list.
+    pub reject_breached: bool,
+    /// Reject passwords containing the mailbox local part or domain.
+    pub reject_identity: bool,
+    /// Number of previous passwords that may not be reused. 0 disables.
+    pub history_depth: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            min_character_classes: 4,
+            reject_breached: true,
+            reject_identity: true,
+            history_depth: 5,
+        }
+    }
+}
+
+/// What the policy needs to know about the account besides the password.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PasswordContext<'a> {
+    /// The mailbox address, used for the identity rule and domain overrides.
+    pub email: Option<&'a str>,
+    /// Previous password hashes, newest first.
+    pub previous_hashes: &'a [String],
+}
+
+impl PasswordPolicy {
+    /// Check `password` against this policy, collecting every violation.
+    ///
+    /// `breached` is consulted only if `reject_breached` is set.
+    pub fn check(
+        &self,
+        password: &str,
+        context: &PasswordContext<'_>,
+        breached: Option<&BreachedPasswordList>,
+    ) -> Result<(), PasswordRejected> {
+        let mut violations = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PolicyViolation::TooShort {
+                min: self.min_length,
+                actual: length,
+            });
+        }
+        if length > self.max_length {
+            violations.push(PolicyViolation::TooLong {
+                max: self.max_length,
+                actual: length,
+            });
+        }
+
+        let (present, missing): (Vec<CharacterClass>, Vec<CharacterClass>) = CharacterClass::ALL
+            .iter()
+            .partition(|class| password.chars().any(|c| class.matches(c)));
+        if present.len() < self.min_character_classes {
+            violations.push(PolicyViolation::TooFewCharacterClasses {
+                required: self.min_character_classes,
+                actual: present.len(),
+                missing,
+            });
+        }
+
+        if self.reject_breached {
+            if let Some(list) = breached {
+                if list.contains(password) {
+                    violations.push(PolicyViolation::Breached);
+                }
+            }
+        }
+
+        if self.reject_identity {
+            if let Some(email) = context.email {
+                violations.extend(identity_violations(password, email));
+            }
+        }
+
+        if self.history_depth > 0 {
+            let reused = context
+                .previous_hashes
+                .iter()
+                .take(self.history_depth)
+                .any(|hash| password::verify_password(password, hash).unwrap_or(false));
+            if reused {
+                violations.push(PolicyViolation::RecentlyUsed {
+                    history_depth: self.history_depth,
+                });
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordRejected { violations })
+        }
+    }
+}
+
+/// Check the password for the local part and domain labels of `email`,
+/// case-insensitively and in both the ASCII and Unicode forms of the domain.
+fn identity_violations(password: &str, email: &str) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+    let password = password.to_lowercase();
+    let (local, domain) = email.rsplit_once('@').unwrap_or((email, ""));
+
+    let local = local.to_lowercase();
+    let mut local_tokens = std::iter::once(local.as_str())
+        .chain(local.split(['.', '_', '-', '+']))
+        .filter(|t| t.chars().count() >= MIN_IDENTITY_TOKEN_LEN);
+    if local_tokens.any(|t| password.contains(t)) {
+        violations.push(PolicyViolation::ContainsLocalPart);
+    }
+
+    let ascii = domain.to_lowercase();
+    let unicode = idn::to_unicode(&ascii);
+    let domain_hit = [ascii.as_str(), unicode.as_str()].iter().any(|d| {
+        let labels: Vec<&str> = d.split('.').collect();
+        // Skip the TLD: "com" or "org" inside a password says nothing about the user.
+        let significant = &labels[..labels.len().saturating_sub(1)];
+        significant
+            .iter()
+            .filter(|l| l.chars().count() >= MIN_IDENTITY_TOKEN_LEN)
+            .any(|l| password.contains(*l))
+    });
+    if domain_hit {
+        violations.push(PolicyViolation::ContainsDomain);
+    }
+
+    violations
+}
+
+/// Deployment policy plus per-domain overrides, as stored in
+/// [`PASSWORD_POLICY_PATH`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicyConfig {
+    /// Policy for any domain without an override.
+    pub default: PasswordPolicy,
+    /// Overrides keyed by ASCII (punycode) domain name.
+    pub domains: HashMap<String, PasswordPolicy>,
+    /// Breached-password hash list, if any: a directory of range files or
+    /// a sorted file.
+    pub breached_list: Option<PathBuf>,
+}
+
+impl PasswordPolicyConfig {
+    /// The policy that applies to `domain`.
+    pub fn for_domain(&self, domain: &str) -> &PasswordPolicy {
+        self.domains
+            .get(&domain.to_lowercase())
+            .unwrap_or(&self.default)
+    }
+}
+
+/// SHA-1 hashes of breached passwords, looked up on disk. See the module
+/// docs for the formats.
+#[derive(Debug, Clone)]
+pub enum BreachedPasswordList {
+    /// A directory of range files named by hash prefix.
+    Ranges(PathBuf),
+    /// One file of full hashes in ascending order.
+    Sorted(PathBuf),
+}
+
+impl BreachedPasswordList {
+    /// Open a hash list: a directory of range files, or a sorted file.
+    pub fn load(path: &Path) -> Result<Self, PolicyLoadError> {
+        let metadata = fs::metadata(path).map_err(|e| PolicyLoadError::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        let list = if metadata.is_dir() {
+            Self::Ranges(path.to_path_buf())
+        } else {
+            Self::Sorted(path.to_path_buf())
+        };
+        debug!("Using breached password list {:?}", list);
+        Ok(list)
+    }
+
+    /// Whether `password` is on the list. A list that cannot be read
+    /// counts as not containing it.
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let found = match self {
+            Self::Ranges(dir) => range_contains(dir, &hash),
+            Self::Sorted(path) => sorted_contains(path, &hash),
+        };
+        found.unwrap_or_else(|e| {
+            warn!("Cannot read breached password list: {}", e);
+            false
+        })
+    }
+}
+
+/// The hash part of a list line, without the `:count`.
+fn line_hash(line: &str) -> &str {
+    line.split(':').next().unwrap_or("").trim()
+}
+
+fn range_contains(dir: &Path, hash: &str) -> std::io::Result<bool> {
+    let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+    let content = match fs::read_to_string(dir.join(prefix)) {
+        Ok(content) => content,
+        // No range file: nothing breached shares the prefix
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    Ok(content.lines().any(|line| line_hash(line).eq_ignore_ascii_case(suffix)))
+}
+
+/// Binary search over byte offsets. Each probe reads the first line
+/// starting at or after the midpoint, so memory use stays at one line.
+fn sorted_contains(path: &Path, hash: &str) -> std::io::Result<bool> {
+    let mut file = BufReader::new(fs::File::open(path)?);
+    let (mut lo, mut hi) = (0, file.get_ref().metadata()?.len());
+    let mut line = String::new();
+    // Invariant: the line for `hash`, if any, starts in [lo, hi)
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let mut start = mid;
+        if mid > 0 {
+            // Skip the rest of the line `mid` falls in
+            file.seek(SeekFrom::Start(mid - 1))?;
+            line.clear();
+            start = mid - 1 + file.read_line(&mut line)? as u64;
+        }
+        file.seek(SeekFrom::Start(start))?;
+        line.clear();
+        let len = file.read_line(&mut line)? as u64;
+        if len == 0 {
+            hi = mid;
+            continue;
+        }
+        match line_hash(&line).to_ascii_uppercase().as_str().cmp(hash) {
+            Ordering::Equal => return Ok(true),
+            Ordering::Less => lo = start + len,
+            Ordering::Greater => hi = mid,
+        }
+    }
+    Ok(false)
+}
+
+/// Policy configuration together with its loaded breached list.
+#[derive(Debug, Clone, Default)]
+pub struct PasswordPolicyEngine {
+    config: PasswordPolicyConfig,
+    breached: Option<BreachedPasswordList>,
+}
+
+impl PasswordPolicyEngine {
+    pub fn new(config: PasswordPolicyConfig, breached: Option<BreachedPasswordList>) -> Self {
+        Self { config, breached }
+    }
+
+    /// Load the policy file and the breached list it points at.
+    pub fn load(path: &Path) -> Result<Self, PolicyLoadError> {
+        let content = fs::read_to_string(path).map_err(|e| PolicyLoadError::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        let config: PasswordPolicyConfig =
+            serde_json::from_str(&content).map_err(|e| PolicyLoadError::Parse {
+                path: path.to_path_buf(),
+                source: e,
+            })?;
+        let breached = match &config.breached_list {
+            Some(list_path) => Some(BreachedPasswordList::load(list_path)?),
+            None => None,
+        };
+        Ok(Self::new(config, breached))
+    }
+
+    /// Load [`PASSWORD_POLICY_PATH`], falling back to the default policy if
+    /// the file does not exist.
+    pub fn load_or_default() -> Result<Self, PolicyLoadError> {
+        let path = Path::new(PASSWORD_POLICY_PATH);
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn config(&self) -> &PasswordPolicyConfig {
+        &self.config
+    }
+
+    /// The policy that applies to `email`'s domain, or the default.
+    pub fn policy_for(&self, email: Option<&str>) -> &PasswordPolicy {
+        match email.and_then(|e| e.rsplit_once('@')) {
+            Some((_, domain)) => self.config.for_domain(domain),
+            None => &self.config.default,
+        }
+    }
+
+    /// Check a password with the policy for its account.
+    pub fn check(
+        &self,
+        password: &str,
+        context: &PasswordContext<'_>,
+    ) -> Result<(), PasswordRejected> {
+        self.policy_for(context.email)
+            .check(password, context, self.breached.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRONG: &str = "Tr0ub4dor&Horse!";
+
+    fn violations(result: Result<(), PasswordRejected>) -> Vec<PolicyViolation> {
+        result.err().map(|r| r.violations).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_default_policy_accepts_strong_password() {
+        let policy = PasswordPolicy::default();
+        assert!(policy
+            .check(STRONG, &PasswordContext::default(), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_reports_all_violations() {
+        let policy = PasswordPolicy::default();
+        let v = violations(policy.check("short", &PasswordContext::default(), None));
+        assert!(v.contains(&PolicyViolation::TooShort { min: 12, actual: 5 }));
+        assert!(v.iter().any(|v| matches!(
+            v,
+            PolicyViolation::TooFewCharacterClasses { actual: 1, missing, .. } if missing.len() == 3
+        )));
+    }
+
+    #[test]
+    fn test_relaxed_character_classes() {
+        let policy = PasswordPolicy {
+            min_character_classes: 2,
+            ..PasswordPolicy::default()
+        };
+        assert!(policy
+            .check("correcthorsebattery9", &PasswordContext::default(), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_too_long() {
+        let policy = PasswordPolicy {
+            max_length: 16,
+            ..PasswordPolicy::default()
+        };
+        let v = violations(policy.check("Aa1!Aa1!Aa1!Aa1!Aa1!", &PasswordContext::default(), None));
+        assert_eq!(v, vec![PolicyViolation::TooLong { max: 16, actual: 20 }]);
+    }
+
+    #[test]
+    fn test_breached_list_lookup() {
+        // SHA-1 of "Password123!" is 49EFEF5F70D47ADC2DB2EB397FBEF5F7BC560E29
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("49EFE"),
+            "0000000000000000000000000000000000A:1\nF5F70D47ADC2DB2EB397FBEF5F7BC560E29:42\n",
+        )
+        .unwrap();
+        let list = BreachedPasswordList::load(dir.path()).unwrap();
+        assert!(matches!(list, BreachedPasswordList::Ranges(_)));
+        assert!(list.contains("Password123!"));
+        assert!(!list.contains(STRONG));
+
+        let policy = PasswordPolicy::default();
+        let v = violations(policy.check("Password123!", &PasswordContext::default(), Some(&list)));
+        assert_eq!(v, vec![PolicyViolation::Breached]);
+    }
+
+    #[test]
+    fn test_sorted_breached_list() {
+        let passwords: Vec<String> = (0..500).map(|i| format!("password{}", i)).collect();
+        let mut lines: Vec<String> = passwords
+            .iter()
+            .map(|p| format!("{}:{}", hex::encode_upper(Sha1::digest(p.as_bytes())), p.len()))
+            .collect();
+        lines.sort();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pwned-passwords-sha1-ordered-by-hash.txt");
+        fs::write(&path, lines.join("\r\n")).unwrap();
+
+        let list = BreachedPasswordList::load(&path).unwrap();
+        assert!(matches!(list, BreachedPasswordList::Sorted(_)));
+        for password in &passwords {
+            assert!(list.contains(password), "{} not found", password);
+        }
+        assert!(!list.contains("password500"));
+        assert!(!list.contains(STRONG));
+    }
+
+    #[test]
+    fn test_rejects_local_part_and_domain() {
+        let policy = PasswordPolicy::default();
+        let ctx = PasswordContext {
+            email: Some("john.smith@acme-corp.com"),
+            ..Default::default()
+        };
+        let v = violations(policy.check("Smith#2024-Secure", &ctx, None));
+        assert_eq!(v, vec![PolicyViolation::ContainsLocalPart]);
+
+        let v = violations(policy.check("Acme-Corp#2024!x", &ctx, None));
+        assert_eq!(v, vec![PolicyViolation::ContainsDomain]);
+
+        // The TLD alone is not a hit
+        assert!(policy.check("Com#2024-Secure!", &ctx, None).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_unicode_domain_label() {
+        let policy = PasswordPolicy::default();
+        let ctx = PasswordContext {
+            email: Some("info@xn--mnchen-3ya.de"),
+            ..Default::default()
+        };
+        let v = violations(policy.check("München#2024!x", &ctx, None));
+        assert_eq!(v, vec![PolicyViolation::ContainsDomain]);
+    }
+
+    #[test]
+    fn test_password_history() {
+        let old = password::hash_password(STRONG).unwrap();
+        let history = vec![old];
+        let ctx = PasswordContext {
+            email: None,
+            previous_hashes: &history,
+        };
+
+        let policy = PasswordPolicy::default();
+        let v = violations(policy.check(STRONG, &ctx, None));
+        assert_eq!(v, vec![PolicyViolation::RecentlyUsed { history_depth: 5 }]);
+
+        let no_history = PasswordPolicy {
+            history_depth: 0,
+            ..PasswordPolicy::default()
+        };
+        assert!(no_history.check(STRONG, &ctx, None).is_ok());
+    }
+
+    #[test]
+    fn test_domain_override() {
+        let json = r#"{
+            "default": { "min_length": 14 },
+            "domains": { "example.com": { "min_length": 20, "history_depth": 0 } }
+        }"#;
+        let config: PasswordPolicyConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.default.min_length, 14);
+        assert_eq!(config.default.min_character_classes, 4);
+        assert_eq!(config.for_domain("EXAMPLE.COM").min_length, 20);
+        assert_eq!(config.for_domain("other.org").min_length, 14);
+
+        let engine = PasswordPolicyEngine::new(config, None);
+        let ctx = PasswordContext {
+            email: Some("user@example.com"),
+            ..Default::default()
+        };
+        let v = violations(engine.check(STRONG, &ctx));
+        assert_eq!(v, vec![PolicyViolation::TooShort { min: 20, actual: 16 }]);
+    }
+
+    #[test]
+    fn test_violation_serialization() {
+        let v = PolicyViolation::TooShort { min: 12, actual: 5 };
+        let json = serde_json::to_value(&v).unwrap();
+        assert_eq!(json["code"], "too_short");
+        assert_eq!(json["min"], 12);
+        assert_eq!(v.code(), "too_short");
+    }
+
+    #[test]
+    fn test_engine_load_with_breached_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let list_path = dir.path().join("breached.txt");
+        fs::write(&list_path, "49EFEF5F70D47ADC2DB2EB397FBEF5F7BC560E29:3\n").unwrap();
+        let policy_path = dir.path().join("password-policy.json");
+        fs::write(
+            &policy_path,
+            format!(r#"{{ "breached_list": "{}" }}"#, list_path.display()),
+        )
+        .unwrap();
+
+        let engine = PasswordPolicyEngine::load(&policy_path).unwrap();
+        let v = violations(engine.check("Password123!", &PasswordContext::default()));
+        assert_eq!(v, vec![PolicyViolation::Breached]);
+    }
+}
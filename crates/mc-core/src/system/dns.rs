@@ -1,7 +1,15 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
 use std::process::Command;
+use std::time::Duration;
+
+use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
 use thiserror::Error;
 use tracing::{debug, info, warn};
 
+use crate::mail::dane::TlsaRecord;
+
 #[derive(Debug, Error)]
 pub enum DnsError {
     #[error("IO error: {0}")]
@@ -121,6 +129,233 @@ pub fn check_dnsbl(ip: &str) -> Result<Vec<String>, DnsError> {
     Ok(listed_on)
 }
 
+/// Looks up TXT records, so DNS-dependent workflows can be driven by a
+/// stub in tests.
+pub trait TxtResolver: Send + Sync {
+    /// Every TXT record at `name`, each with its character-strings joined.
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError>;
+}
+
+/// The lookups mail record checks need beyond TXT.
+pub trait DnsResolver: TxtResolver {
+    /// MX records at `domain` as (preference, exchange).
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<(u16, String)>, DnsError>;
+    /// A and AAAA records at `name`.
+    fn lookup_ip(&self, name: &str) -> Result<Vec<IpAddr>, DnsError>;
+    /// PTR names of `ip`.
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, DnsError>;
+    /// TLSA records at `name`, e.g. `_25._tcp.mail.example.com`.
+    fn lookup_tlsa(&self, name: &str) -> Result<Vec<TlsaRecord>, DnsError>;
+}
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Resolver that queries nameservers directly, without `dig`.
+///
+/// Queries go over UDP with EDNS and are retried over TCP when the answer
+/// is truncated, as large DKIM keys are. Lookups block.
+#[derive(Debug, Clone)]
+pub struct NativeResolver {
+    nameservers: Vec<SocketAddr>,
+    timeout: Duration,
+}
+
+impl NativeResolver {
+    /// Use the nameservers listed in /etc/resolv.conf.
+    pub fn from_system_conf() -> Result<Self, DnsError> {
+        let content = std::fs::read_to_string(RESOLV_CONF)?;
+        let nameservers: Vec<SocketAddr> = content
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .map(|ip| SocketAddr::new(ip, 53))
+            .collect();
+        if nameservers.is_empty() {
+            return Err(DnsError::LookupFailed(format!("No nameservers in {}", RESOLV_CONF)));
+        }
+        Ok(Self::with_nameservers(nameservers))
+    }
+
+    pub fn with_nameservers(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// How long to wait for each nameserver.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Records of `record_type` at `name`. A name that does not exist has
+    /// no records; only a failure to get an answer is an error.
+    pub fn query(&self, name: &str, record_type: RecordType) -> Result<Vec<RData>, DnsError> {
+        if name.is_empty() || name.len() > 253 {
+            return Err(DnsError::InvalidInput(format!("Invalid record name: {}", name)));
+        }
+        let name = Name::from_ascii(format!("{}.", name.trim_end_matches('.')))
+            .map_err(|e| DnsError::InvalidInput(format!("{}: {}", name, e)))?;
+        self.query_name(name, record_type)
+    }
+
+    fn query_name(&self, name: Name, record_type: RecordType) -> Result<Vec<RData>, DnsError> {
+        let mut last_error = DnsError::LookupFailed("No nameservers configured".to_string());
+        for server in &self.nameservers {
+            let response = match self.exchange(*server, &name, record_type) {
+                Ok(response) => response,
+                Err(e) => {
+                    debug!("Nameserver {} failed for {} {}: {}", server, name, record_type, e);
+                    last_error = e;
+                    continue;
+                }
+            };
+            match response.response_code() {
+                ResponseCode::NoError => {
+                    return Ok(response
+                        .answers()
+                        .iter()
+                        .filter(|r| r.record_type() == record_type)
+                        .filter_map(|r| r.data().cloned())
+                        .collect())
+                }
+                ResponseCode::NXDomain => return Ok(Vec::new()),
+                code => {
+                    last_error = DnsError::LookupFailed(format!("{} {}: {}", name, record_type, code));
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn exchange(&self, server: SocketAddr, name: &Name, record_type: RecordType) -> Result<Message, DnsError> {
+        let mut request = Message::new();
+        request
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(name.clone(), record_type));
+        let mut edns = Edns::new();
+        edns.set_max_payload(1232);
+        request.set_edns(edns);
+        let bytes = request
+            .to_vec()
+            .map_err(|e| DnsError::InvalidInput(e.to_string()))?;
+        let failed = |e: std::io::Error| DnsError::LookupFailed(format!("{}: {}", server, e));
+
+        let local: SocketAddr = if server.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(server).map_err(failed)?;
+        socket.send(&bytes).map_err(failed)?;
+        let mut buf = [0u8; 4096];
+        let response = loop {
+            let len = socket.recv(&mut buf).map_err(failed)?;
+            // Ignore stray datagrams, e.g. late answers to an earlier query
+            if let Ok(message) = Message::from_vec(&buf[..len]) {
+                if message.id() == request.id() {
+                    break message;
+                }
+            }
+        };
+        if !response.truncated() {
+            return Ok(response);
+        }
+
+        let mut stream = TcpStream::connect_timeout(&server, self.timeout).map_err(failed)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(&(bytes.len() as u16).to_be_bytes()).map_err(failed)?;
+        stream.write_all(&bytes).map_err(failed)?;
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).map_err(failed)?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf).map_err(failed)?;
+        Message::from_vec(&buf).map_err(|e| DnsError::LookupFailed(format!("{}: {}", server, e)))
+    }
+}
+
+/// A name as written in records, without the root dot.
+fn record_name(name: &Name) -> String {
+    name.to_ascii().trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl TxtResolver for NativeResolver {
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        Ok(self
+            .query(name, RecordType::TXT)?
+            .into_iter()
+            .filter_map(|data| match data {
+                RData::TXT(txt) => Some(
+                    txt.txt_data()
+                        .iter()
+                        .map(|s| String::from_utf8_lossy(s))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+impl DnsResolver for NativeResolver {
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<(u16, String)>, DnsError> {
+        Ok(self
+            .query(domain, RecordType::MX)?
+            .into_iter()
+            .filter_map(|data| match data {
+                RData::MX(mx) => Some((mx.preference(), record_name(mx.exchange()))),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn lookup_ip(&self, name: &str) -> Result<Vec<IpAddr>, DnsError> {
+        let mut ips = Vec::new();
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            ips.extend(self.query(name, record_type)?.into_iter().filter_map(|data| match data {
+                RData::A(a) => Some(IpAddr::V4(a.0)),
+                RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            }));
+        }
+        Ok(ips)
+    }
+
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, DnsError> {
+        Ok(self
+            .query_name(Name::from(ip), RecordType::PTR)?
+            .into_iter()
+            .filter_map(|data| match data {
+                RData::PTR(ptr) => Some(record_name(&ptr.0)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn lookup_tlsa(&self, name: &str) -> Result<Vec<TlsaRecord>, DnsError> {
+        Ok(self
+            .query(name, RecordType::TLSA)?
+            .into_iter()
+            .filter_map(|data| match data {
+                RData::TLSA(tlsa) => Some(TlsaRecord {
+                    usage: tlsa.cert_usage().into(),
+                    selector: tlsa.selector().into(),
+                    matching: tlsa.matching().into(),
+                    data: tlsa.cert_data().to_vec(),
+                }),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
 /// Test if the Unbound DNS resolver is responding.
 ///
 /// Sends a simple query to localhost (where Unbound should be listening)
@@ -161,3 +396,50 @@ pub fn test_unbound() -> Result<bool, DnsError> {
 
     Ok(has_response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::dns_stub::StubDns;
+
+    fn resolver(server: SocketAddr) -> NativeResolver {
+        NativeResolver::with_nameservers(vec![server]).with_timeout(Duration::from_secs(2))
+    }
+
+    #[test]
+    fn test_native_lookups() {
+        let ip: IpAddr = "192.0.2.25".parse().unwrap();
+        let server = StubDns::new()
+            .txt("example.com", "v=spf1 mx -all")
+            .mx("example.com", 10, "Mail.Example.com")
+            .ip("mail.example.com", ip)
+            .ip("mail.example.com", "2001:db8::25".parse().unwrap())
+            .ptr(ip, "mail.example.com")
+            .start();
+        let dns = resolver(server);
+
+        assert_eq!(dns.lookup_txt("example.com").unwrap(), vec!["v=spf1 mx -all"]);
+        assert_eq!(dns.lookup_mx("example.com").unwrap(), vec![(10, "mail.example.com".to_string())]);
+        assert_eq!(dns.lookup_ip("mail.example.com").unwrap().len(), 2);
+        assert_eq!(dns.lookup_ptr(ip).unwrap(), vec!["mail.example.com"]);
+        // Missing names and types are empty answers, not errors
+        assert!(dns.lookup_txt("missing.example.com").unwrap().is_empty());
+        assert!(dns.lookup_mx("mail.example.com").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_answer_retried_over_tcp() {
+        let value = format!("v=DKIM1; k=rsa; p={}", "A".repeat(1500));
+        let server = StubDns::new().txt("big._domainkey.example.com", &value).start();
+        assert_eq!(resolver(server).lookup_txt("big._domainkey.example.com").unwrap(), vec![value]);
+    }
+
+    #[test]
+    fn test_unreachable_nameserver() {
+        // Nothing answers on the port of a socket we hold but never read
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dns = NativeResolver::with_nameservers(vec![silent.local_addr().unwrap()])
+            .with_timeout(Duration::from_millis(200));
+        assert!(matches!(dns.lookup_txt("example.com"), Err(DnsError::LookupFailed(_))));
+    }
+}
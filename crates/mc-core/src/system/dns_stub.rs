@@ -0,0 +1,139 @@
+//! A local DNS server answering from a fixed record set, for testing the
+//! resolver and the checks built on it without network access.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA, MX, PTR, TLSA, TXT};
+use hickory_proto::rr::{Name, RData, Record};
+
+use crate::mail::dane::TlsaRecord;
+use crate::mail::dkim::split_txt;
+
+/// Answers larger than this go out truncated over UDP, so the client has
+/// to retry over TCP.
+const UDP_LIMIT: usize = 1232;
+
+#[derive(Default)]
+pub(crate) struct StubDns {
+    records: Vec<Record>,
+}
+
+fn name(name: &str) -> Name {
+    Name::from_ascii(format!("{}.", name.trim_end_matches('.'))).unwrap()
+}
+
+impl StubDns {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(mut self, owner: Name, data: RData) -> Self {
+        self.records.push(Record::from_rdata(owner, 300, data));
+        self
+    }
+
+    /// A TXT record, split into 255-byte strings as a zone would hold it.
+    pub(crate) fn txt(self, owner: &str, value: &str) -> Self {
+        self.record(name(owner), RData::TXT(TXT::new(split_txt(value))))
+    }
+
+    pub(crate) fn mx(self, owner: &str, preference: u16, exchange: &str) -> Self {
+        self.record(name(owner), RData::MX(MX::new(preference, name(exchange))))
+    }
+
+    pub(crate) fn ip(self, owner: &str, ip: IpAddr) -> Self {
+        let data = match ip {
+            IpAddr::V4(ip) => RData::A(A(ip)),
+            IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+        };
+        self.record(name(owner), data)
+    }
+
+    pub(crate) fn ptr(self, ip: IpAddr, target: &str) -> Self {
+        self.record(Name::from(ip), RData::PTR(PTR(name(target))))
+    }
+
+    pub(crate) fn tlsa(self, owner: &str, record: &TlsaRecord) -> Self {
+        let data = TLSA::new(
+            record.usage.into(),
+            record.selector.into(),
+            record.matching.into(),
+            record.data.clone(),
+        );
+        self.record(name(owner), RData::TLSA(data))
+    }
+
+    /// Serve the records on an ephemeral localhost port, over UDP and TCP,
+    /// until the test process exits.
+    pub(crate) fn start(self) -> SocketAddr {
+        let (udp, tcp) = loop {
+            let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+            if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+                break (udp, tcp);
+            }
+        };
+        let addr = udp.local_addr().unwrap();
+        let records = Arc::new(self.records);
+
+        let udp_records = Arc::clone(&records);
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf) {
+                let Some(mut response) = answer(&udp_records, &buf[..len]) else { continue };
+                let mut bytes = response.to_vec().unwrap();
+                if bytes.len() > UDP_LIMIT {
+                    response.answers_mut().clear();
+                    response.set_truncated(true);
+                    bytes = response.to_vec().unwrap();
+                }
+                let _ = udp.send_to(&bytes, peer);
+            }
+        });
+        thread::spawn(move || {
+            for mut stream in tcp.incoming().flatten() {
+                let mut len = [0u8; 2];
+                if stream.read_exact(&mut len).is_err() {
+                    continue;
+                }
+                let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+                if stream.read_exact(&mut buf).is_err() {
+                    continue;
+                }
+                let Some(response) = answer(&records, &buf) else { continue };
+                let bytes = response.to_vec().unwrap();
+                let _ = stream.write_all(&(bytes.len() as u16).to_be_bytes());
+                let _ = stream.write_all(&bytes);
+            }
+        });
+        addr
+    }
+}
+
+/// The response to a query: the matching records, NXDOMAIN for names
+/// with no records at all.
+fn answer(records: &[Record], request: &[u8]) -> Option<Message> {
+    let request = Message::from_vec(request).ok()?;
+    let query = request.queries().first()?.clone();
+    let at_name: Vec<&Record> = records
+        .iter()
+        .filter(|r| r.name() == query.name())
+        .collect();
+
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_recursion_available(true)
+        .add_query(query.clone());
+    if at_name.is_empty() {
+        response.set_response_code(ResponseCode::NXDomain);
+    }
+    for record in at_name.into_iter().filter(|r| r.record_type() == query.query_type()) {
+        response.add_answer(record.clone());
+    }
+    Some(response)
+}
@@ -1,3 +1,5 @@
 pub mod stats;
 pub mod queue;
 pub mod dns;
+#[cfg(test)]
+pub(crate) mod dns_stub;
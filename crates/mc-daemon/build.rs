@@ -9,11 +9,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "ceymail/v1/config.proto",
         "ceymail/v1/users.proto",
         "ceymail/v1/dkim.proto",
+        "ceymail/v1/dns.proto",
+        "ceymail/v1/dmarc.proto",
+        "ceymail/v1/mta_sts.proto",
+        "ceymail/v1/dane.proto",
+        "ceymail/v1/acme.proto",
         "ceymail/v1/logs.proto",
         "ceymail/v1/stats.proto",
         "ceymail/v1/install.proto",
         "ceymail/v1/webmail.proto",
         "ceymail/v1/backup.proto",
+        "ceymail/v1/sieve.proto",
         "ceymail/v1/control.proto",
     ];
 
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
@@ -10,6 +10,10 @@ use sqlx::FromRow;
 pub struct VirtualDomain {
     pub id: i64,
     pub name: String,
+    /// Unicode form of `name`, filled in by the service layer.
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub display_name: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -18,6 +22,11 @@ pub struct VirtualUser {
     pub domain_id: i64,
     pub email: String,
     pub password: String,
+    /// `email` with its domain in Unicode form, filled in by the service
+    /// layer.
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub display_email: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -28,6 +37,26 @@ pub struct VirtualAlias {
     pub destination: String,
 }
 
+/// Explicit permission for a user to send as an address they do not own,
+/// e.g. a shared mailbox. Consulted by Postfix `smtpd_sender_login_maps`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SenderGrant {
+    pub id: i64,
+    pub user_id: i64,
+    pub sender: String,
+}
+
+/// IMAP ACL grant on one of a user's mailboxes. `rights` is the
+/// comma-separated form of `mc_core::mail::acl::MailboxRights`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MailboxAcl {
+    pub id: i64,
+    pub owner_id: i64,
+    pub mailbox: String,
+    pub grantee_id: i64,
+    pub rights: String,
+}
+
 // ============================================================
 // Dashboard database models (new ceymail_dashboard schema)
 // ============================================================
@@ -76,3 +105,59 @@ pub struct DashboardUser {
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
 }
+
+// ============================================================
+// DMARC aggregate report models (dashboard database)
+// ============================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DmarcReport {
+    pub id: i64,
+    pub org_name: String,
+    pub report_id: String,
+    pub domain: String,
+    pub policy: String,
+    pub date_begin: DateTime<Utc>,
+    pub date_end: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Mail from one source address that got one result.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DmarcReportRecord {
+    pub source_ip: String,
+    pub header_from: String,
+    pub message_count: i64,
+    pub disposition: String,
+    pub dkim_aligned: bool,
+    pub spf_aligned: bool,
+    pub dkim_domain: Option<String>,
+    pub spf_domain: Option<String>,
+}
+
+/// Alignment results for one domain on one day, summed across reporters.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DmarcDailySummary {
+    pub domain: String,
+    pub day: NaiveDate,
+    pub messages: i64,
+    pub passed: i64,
+    pub failed: i64,
+    pub dkim_failed: i64,
+    pub spf_failed: i64,
+}
+
+/// Everything reported about one source address sending as one domain.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DmarcSourceSummary {
+    pub domain: String,
+    pub source_ip: String,
+    pub messages: i64,
+    pub passed: i64,
+    pub failed: i64,
+    pub reporters: i64,
+    pub dkim_domain: Option<String>,
+    pub spf_domain: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
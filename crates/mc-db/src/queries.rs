@@ -1,5 +1,6 @@
 use crate::models::*;
 use crate::pool::DbError;
+use chrono::{DateTime, Utc};
 use sqlx::MySqlPool;
 use tracing::debug;
 
@@ -170,6 +171,59 @@ pub async fn delete_user(pool: &MySqlPool, id: i64) -> Result<(), DbError> {
     Ok(())
 }
 
+// ============================================================
+// Password History (mail database)
+// ============================================================
+
+pub async fn add_password_history(
+    pool: &MySqlPool,
+    user_id: i64,
+    password_hash: &str,
+) -> Result<(), DbError> {
+    sqlx::query("INSERT INTO password_history (user_id, password_hash) VALUES (?, ?)")
+        .bind(user_id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Most recent password hashes for a user, newest first.
+pub async fn list_password_history(
+    pool: &MySqlPool,
+    user_id: i64,
+    limit: i64,
+) -> Result<Vec<String>, DbError> {
+    let hashes = sqlx::query_scalar::<_, String>(
+        "SELECT password_hash FROM password_history WHERE user_id = ? ORDER BY id DESC LIMIT ?"
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(hashes)
+}
+
+/// Drop all but the `keep` most recent history entries for a user.
+pub async fn prune_password_history(
+    pool: &MySqlPool,
+    user_id: i64,
+    keep: i64,
+) -> Result<(), DbError> {
+    // MariaDB does not allow LIMIT in an IN subquery; the derived table works around it.
+    sqlx::query(
+        "DELETE FROM password_history WHERE user_id = ? AND id NOT IN \
+         (SELECT id FROM (SELECT id FROM password_history WHERE user_id = ? \
+         ORDER BY id DESC LIMIT ?) AS recent)"
+    )
+    .bind(user_id)
+    .bind(user_id)
+    .bind(keep)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 // ============================================================
 // Virtual Aliases (mail database)
 // ============================================================
@@ -234,6 +288,160 @@ pub async fn delete_alias(pool: &MySqlPool, id: i64) -> Result<(), DbError> {
     Ok(())
 }
 
+// ============================================================
+// Sender Grants (mail database)
+// ============================================================
+
+pub async fn create_sender_grant(
+    pool: &MySqlPool,
+    user_id: i64,
+    sender: &str,
+) -> Result<i64, DbError> {
+    let result = sqlx::query("INSERT INTO sender_grants (user_id, sender) VALUES (?, ?)")
+        .bind(user_id)
+        .bind(sender)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            if let sqlx::Error::Database(ref db_err) = e {
+                if db_err.code().as_deref() == Some("23000") {
+                    return DbError::Duplicate(format!(
+                        "Send-as grant already exists: user {} -> {}",
+                        user_id, sender
+                    ));
+                }
+            }
+            DbError::Connection(e)
+        })?;
+
+    debug!("Granted send-as: user {} -> {}", user_id, sender);
+    Ok(result.last_insert_id() as i64)
+}
+
+pub async fn list_sender_grants(pool: &MySqlPool) -> Result<Vec<SenderGrant>, DbError> {
+    let grants = sqlx::query_as::<_, SenderGrant>(
+        "SELECT id, user_id, sender FROM sender_grants ORDER BY sender"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(grants)
+}
+
+pub async fn list_sender_grants_by_user(pool: &MySqlPool, user_id: i64) -> Result<Vec<SenderGrant>, DbError> {
+    let grants = sqlx::query_as::<_, SenderGrant>(
+        "SELECT id, user_id, sender FROM sender_grants WHERE user_id = ? ORDER BY sender"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(grants)
+}
+
+pub async fn delete_sender_grant(pool: &MySqlPool, id: i64) -> Result<(), DbError> {
+    let result = sqlx::query("DELETE FROM sender_grants WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::NotFound(format!("Send-as grant with id {}", id)));
+    }
+
+    debug!("Deleted send-as grant id: {}", id);
+    Ok(())
+}
+
+// ============================================================
+// Mailbox ACLs (mail database)
+// ============================================================
+
+/// Insert a grant, or replace the rights of an existing one for the same
+/// owner, mailbox and grantee. Returns the grant id.
+pub async fn upsert_mailbox_acl(
+    pool: &MySqlPool,
+    owner_id: i64,
+    mailbox: &str,
+    grantee_id: i64,
+    rights: &str,
+) -> Result<i64, DbError> {
+    // LAST_INSERT_ID(id) makes the existing row's id visible on update
+    let result = sqlx::query(
+        "INSERT INTO mailbox_acl (owner_id, mailbox, grantee_id, rights) VALUES (?, ?, ?, ?) \
+         ON DUPLICATE KEY UPDATE rights = VALUES(rights), id = LAST_INSERT_ID(id)"
+    )
+    .bind(owner_id)
+    .bind(mailbox)
+    .bind(grantee_id)
+    .bind(rights)
+    .execute(pool)
+    .await?;
+
+    debug!("Set mailbox ACL: owner {} {} -> grantee {}: {}", owner_id, mailbox, grantee_id, rights);
+    Ok(result.last_insert_id() as i64)
+}
+
+pub async fn get_mailbox_acl(pool: &MySqlPool, id: i64) -> Result<MailboxAcl, DbError> {
+    sqlx::query_as::<_, MailboxAcl>(
+        "SELECT id, owner_id, mailbox, grantee_id, rights FROM mailbox_acl WHERE id = ?"
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| DbError::NotFound(format!("Mailbox ACL with id {}", id)))
+}
+
+pub async fn find_mailbox_acl(
+    pool: &MySqlPool,
+    owner_id: i64,
+    mailbox: &str,
+    grantee_id: i64,
+) -> Result<Option<MailboxAcl>, DbError> {
+    let acl = sqlx::query_as::<_, MailboxAcl>(
+        "SELECT id, owner_id, mailbox, grantee_id, rights FROM mailbox_acl \
+         WHERE owner_id = ? AND mailbox = ? AND grantee_id = ?"
+    )
+    .bind(owner_id)
+    .bind(mailbox)
+    .bind(grantee_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(acl)
+}
+
+pub async fn list_mailbox_acls_by_owner(pool: &MySqlPool, owner_id: i64) -> Result<Vec<MailboxAcl>, DbError> {
+    let acls = sqlx::query_as::<_, MailboxAcl>(
+        "SELECT id, owner_id, mailbox, grantee_id, rights FROM mailbox_acl WHERE owner_id = ? ORDER BY mailbox, grantee_id"
+    )
+    .bind(owner_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(acls)
+}
+
+pub async fn list_mailbox_acls_by_grantee(pool: &MySqlPool, grantee_id: i64) -> Result<Vec<MailboxAcl>, DbError> {
+    let acls = sqlx::query_as::<_, MailboxAcl>(
+        "SELECT id, owner_id, mailbox, grantee_id, rights FROM mailbox_acl WHERE grantee_id = ? ORDER BY owner_id, mailbox"
+    )
+    .bind(grantee_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(acls)
+}
+
+pub async fn delete_mailbox_acl(pool: &MySqlPool, id: i64) -> Result<(), DbError> {
+    let result = sqlx::query("DELETE FROM mailbox_acl WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::NotFound(format!("Mailbox ACL with id {}", id)));
+    }
+
+    debug!("Deleted mailbox ACL id: {}", id);
+    Ok(())
+}
+
 // ============================================================
 // Dashboard database queries
 // ============================================================
@@ -365,6 +573,151 @@ pub async fn get_dashboard_user_by_username(
     .ok_or_else(|| DbError::NotFound(format!("Dashboard user: {}", username)))
 }
 
+// ============================================================
+// DMARC aggregate reports (dashboard database)
+// ============================================================
+
+/// Store a report and its records in one transaction. A report the
+/// reporter already sent is rejected as `Duplicate`, so mailboxes can be
+/// ingested again without double counting.
+pub async fn save_dmarc_report(
+    pool: &MySqlPool,
+    report: &DmarcReport,
+    records: &[DmarcReportRecord],
+) -> Result<i64, DbError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(
+        "INSERT INTO dmarc_reports (org_name, report_id, domain, policy, date_begin, date_end, received_at) VALUES (?, ?, ?, ?, ?, ?, NOW())"
+    )
+    .bind(&report.org_name)
+    .bind(&report.report_id)
+    .bind(&report.domain)
+    .bind(&report.policy)
+    .bind(report.date_begin)
+    .bind(report.date_end)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        if let sqlx::Error::Database(ref db_err) = e {
+            if db_err.code().as_deref() == Some("23000") {
+                return DbError::Duplicate(format!(
+                    "DMARC report {} from {}",
+                    report.report_id, report.org_name
+                ));
+            }
+        }
+        DbError::Connection(e)
+    })?;
+    let id = result.last_insert_id() as i64;
+
+    for record in records {
+        sqlx::query(
+            "INSERT INTO dmarc_report_records (report_id, source_ip, header_from, message_count, disposition, dkim_aligned, spf_aligned, dkim_domain, spf_domain) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(id)
+        .bind(&record.source_ip)
+        .bind(&record.header_from)
+        .bind(record.message_count)
+        .bind(&record.disposition)
+        .bind(record.dkim_aligned)
+        .bind(record.spf_aligned)
+        .bind(&record.dkim_domain)
+        .bind(&record.spf_domain)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    debug!("Saved DMARC report {} from {} ({} records)", report.report_id, report.org_name, records.len());
+    Ok(id)
+}
+
+/// Reports received, newest period first; `domain` of `None` lists all.
+pub async fn list_dmarc_reports(
+    pool: &MySqlPool,
+    domain: Option<&str>,
+    limit: i32,
+) -> Result<Vec<DmarcReport>, DbError> {
+    let reports = sqlx::query_as::<_, DmarcReport>(
+        "SELECT id, org_name, report_id, domain, policy, date_begin, date_end, received_at FROM dmarc_reports WHERE (? IS NULL OR domain = ?) ORDER BY date_begin DESC, id DESC LIMIT ?"
+    )
+    .bind(domain)
+    .bind(domain)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(reports)
+}
+
+/// Pass and fail counts per domain per day, for reporting periods that
+/// began at or after `since`.
+pub async fn dmarc_daily_summary(
+    pool: &MySqlPool,
+    domain: Option<&str>,
+    since: DateTime<Utc>,
+) -> Result<Vec<DmarcDailySummary>, DbError> {
+    let summary = sqlx::query_as::<_, DmarcDailySummary>(
+        "SELECT r.domain, DATE(r.date_begin) AS day, \
+         CAST(SUM(c.message_count) AS SIGNED) AS messages, \
+         CAST(SUM(IF(c.dkim_aligned OR c.spf_aligned, c.message_count, 0)) AS SIGNED) AS passed, \
+         CAST(SUM(IF(c.dkim_aligned OR c.spf_aligned, 0, c.message_count)) AS SIGNED) AS failed, \
+         CAST(SUM(IF(c.dkim_aligned, 0, c.message_count)) AS SIGNED) AS dkim_failed, \
+         CAST(SUM(IF(c.spf_aligned, 0, c.message_count)) AS SIGNED) AS spf_failed \
+         FROM dmarc_reports r JOIN dmarc_report_records c ON c.report_id = r.id \
+         WHERE (? IS NULL OR r.domain = ?) AND r.date_begin >= ? \
+         GROUP BY r.domain, DATE(r.date_begin) ORDER BY day, r.domain"
+    )
+    .bind(domain)
+    .bind(domain)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+    Ok(summary)
+}
+
+/// Totals per source address and domain since `since`, most failures
+/// first. With `failing_only`, sources whose mail always passed are left
+/// out: what remains is spoofing or a sender that still needs DKIM or SPF
+/// set up.
+pub async fn dmarc_source_summary(
+    pool: &MySqlPool,
+    domain: Option<&str>,
+    since: DateTime<Utc>,
+    failing_only: bool,
+) -> Result<Vec<DmarcSourceSummary>, DbError> {
+    let sources = sqlx::query_as::<_, DmarcSourceSummary>(
+        "SELECT r.domain, c.source_ip, \
+         CAST(SUM(c.message_count) AS SIGNED) AS messages, \
+         CAST(SUM(IF(c.dkim_aligned OR c.spf_aligned, c.message_count, 0)) AS SIGNED) AS passed, \
+         CAST(SUM(IF(c.dkim_aligned OR c.spf_aligned, 0, c.message_count)) AS SIGNED) AS failed, \
+         COUNT(DISTINCT r.org_name) AS reporters, \
+         MAX(c.dkim_domain) AS dkim_domain, MAX(c.spf_domain) AS spf_domain, \
+         MIN(r.date_begin) AS first_seen, MAX(r.date_end) AS last_seen \
+         FROM dmarc_reports r JOIN dmarc_report_records c ON c.report_id = r.id \
+         WHERE (? IS NULL OR r.domain = ?) AND r.date_begin >= ? \
+         GROUP BY r.domain, c.source_ip \
+         HAVING NOT ? OR failed > 0 \
+         ORDER BY failed DESC, messages DESC"
+    )
+    .bind(domain)
+    .bind(domain)
+    .bind(since)
+    .bind(failing_only)
+    .fetch_all(pool)
+    .await?;
+    Ok(sources)
+}
+
+/// Delete reports whose period ended before `before`; their records go
+/// with them.
+pub async fn prune_dmarc_reports(pool: &MySqlPool, before: DateTime<Utc>) -> Result<u64, DbError> {
+    let result = sqlx::query("DELETE FROM dmarc_reports WHERE date_end < ?")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    debug!("Pruned {} DMARC reports", result.rows_affected());
+    Ok(result.rows_affected())
+}
+
 /// Database backup using mysqldump subprocess (credentials passed via environment, not CLI args)
 pub async fn dump_database(database_name: &str, output_path: &str, username: &str, password: &str) -> Result<(), DbError> {
     use std::process::Command;
@@ -0,0 +1,351 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use mc_core::acme::challenge::ChallengeType;
+use mc_core::acme::csr::CertificateKey;
+use mc_core::acme::store::{CertificatePurpose, CertificateStore, ManagedCertificate, RENEW_BEFORE_DAYS};
+use mc_core::acme::{self, AcmeError, LETS_ENCRYPT};
+use mc_core::config::apache;
+use mc_core::config::changeset::Changeset;
+use mc_core::config::dovecot::DovecotConfig;
+use mc_core::config::history::ConfigChange;
+use mc_core::config::postfix::PostfixConfig;
+use mc_core::mail::dane::{self, NEXT_KEY_DIR};
+use mc_core::security::idn;
+use mc_core::service::manager::ServiceManager;
+use mc_core::system::dns::{DnsResolver, NativeResolver};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::{ConfigError, ConfigFileType, ConfigService, ConfigUpdate, DEFAULT_HEALTH_GRACE};
+use crate::dane::load_keys;
+use crate::mta_sts::{policy_vhost, policy_vhost_path};
+
+#[derive(Debug, Error)]
+pub enum AcmeServiceError {
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("ACME error: {0}")]
+    Acme(#[from] AcmeError),
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Config apply failed: {0}")]
+    Apply(#[from] ConfigError),
+    #[error("ACME task failed: {0}")]
+    Task(String),
+}
+
+/// A managed certificate and when it expires.
+#[derive(Debug, Clone)]
+pub struct CertificateStatus {
+    pub certificate: ManagedCertificate,
+    /// `None` when the certificate file is missing or unreadable.
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+/// Result of [`AcmeService::renew_due`].
+#[derive(Debug, Clone, Default)]
+pub struct Renewals {
+    /// Certificates renewed and deployed.
+    pub renewed: Vec<String>,
+    /// Certificates that were due but could not be renewed, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Certificates from an ACME CA: issued and renewed into the store, then
+/// installed into Postfix, Dovecot and the Apache vhosts for their names.
+pub struct AcmeService {
+    config: ConfigService,
+    store_dir: PathBuf,
+    directory_url: String,
+    dane_key_dir: PathBuf,
+}
+
+impl AcmeService {
+    pub fn new() -> Self {
+        Self::with_config_service(ConfigService::new())
+    }
+
+    pub fn with_config_service(config: ConfigService) -> Self {
+        Self {
+            config,
+            store_dir: PathBuf::from(acme::store::ACME_DIR),
+            directory_url: LETS_ENCRYPT.to_string(),
+            dane_key_dir: PathBuf::from(NEXT_KEY_DIR),
+        }
+    }
+
+    /// Order from the CA at `directory_url` instead of Let's Encrypt, e.g.
+    /// [`acme::LETS_ENCRYPT_STAGING`].
+    pub fn with_directory(mut self, directory_url: &str) -> Self {
+        self.directory_url = directory_url.to_string();
+        self
+    }
+
+    /// Keep certificates under `dir` instead of the default store.
+    pub fn with_store_dir(mut self, dir: &Path) -> Self {
+        self.store_dir = dir.to_path_buf();
+        self
+    }
+
+    fn store(&self) -> CertificateStore {
+        CertificateStore::with_dir(&self.store_dir)
+    }
+
+    /// Every managed certificate with its expiry.
+    pub async fn list(&self) -> Result<Vec<CertificateStatus>, AcmeServiceError> {
+        let store = self.store();
+        Ok(store
+            .list()?
+            .into_iter()
+            .map(|certificate| CertificateStatus {
+                not_after: store.not_after(certificate.name()).ok(),
+                certificate,
+            })
+            .collect())
+    }
+
+    /// Issue a certificate for `domains` and install it. The account is
+    /// registered on first use with `contact` as its email address.
+    pub async fn issue(
+        &self,
+        domains: &[String],
+        purpose: CertificatePurpose,
+        challenge: ChallengeType,
+        dns_hook: Option<&Path>,
+        contact: Option<&str>,
+        change: &ConfigChange,
+    ) -> Result<ConfigUpdate, AcmeServiceError> {
+        if domains.is_empty() {
+            return Err(AcmeServiceError::Validation("at least one domain is required".to_string()));
+        }
+        let domains = domains
+            .iter()
+            .map(|d| idn::normalize_domain(d).map(|d| d.ascii))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AcmeServiceError::Validation(e.to_string()))?;
+        let mut certificate = ManagedCertificate::new(domains, purpose, challenge);
+        certificate.dns_hook = dns_hook.map(Path::to_path_buf);
+
+        self.obtain(&certificate, contact).await?;
+        self.deploy(&certificate, change).await
+    }
+
+    /// Renew `name` now, whether or not it is due.
+    pub async fn renew(&self, name: &str, change: &ConfigChange) -> Result<ConfigUpdate, AcmeServiceError> {
+        let certificate = self
+            .store()
+            .list()?
+            .into_iter()
+            .find(|c| c.name() == name)
+            .ok_or_else(|| AcmeServiceError::Validation(format!("no managed certificate named {}", name)))?;
+        self.obtain(&certificate, None).await?;
+        self.deploy(&certificate, change).await
+    }
+
+    /// The periodic renewal job: renew every certificate within `before`
+    /// of expiry, [`RENEW_BEFORE_DAYS`] when not given.
+    pub async fn renew_due(&self, before: Option<Duration>, change: &ConfigChange) -> Result<Renewals, AcmeServiceError> {
+        let before = before.unwrap_or_else(|| Duration::days(RENEW_BEFORE_DAYS));
+        let mut renewals = Renewals::default();
+        for certificate in self.store().due(Utc::now(), before)? {
+            let name = certificate.name().to_string();
+            let result = match self.obtain(&certificate, None).await {
+                Ok(()) => self.deploy(&certificate, change).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => renewals.renewed.push(name),
+                Err(e) => {
+                    warn!("Renewing the certificate for {} failed: {}", name, e);
+                    renewals.failed.push((name, e.to_string()));
+                }
+            }
+        }
+        Ok(renewals)
+    }
+
+    /// Run the ACME order and store the result, switching the mail
+    /// certificate to the prepared DANE key once its TLSA record is served.
+    async fn obtain(&self, certificate: &ManagedCertificate, contact: Option<&str>) -> Result<(), AcmeServiceError> {
+        let solver = certificate.solver()?;
+        let (key, next_key_path) = match self.published_next_key(certificate).await {
+            Some((key, path)) => (Some(key), Some(path)),
+            None => (None, None),
+        };
+
+        let store = self.store();
+        let directory_url = self.directory_url.clone();
+        let ordered = certificate.clone();
+        let contact = contact.map(str::to_string);
+        // The order blocks while the CA validates
+        tokio::task::spawn_blocking(move || {
+            acme::obtain(&directory_url, &store, &ordered, contact.as_deref(), solver.as_ref(), key)
+        })
+        .await
+        .map_err(|e| AcmeServiceError::Task(e.to_string()))??;
+
+        if let Some(path) = next_key_path {
+            // The certificate now uses it; DANE prepares a fresh one next time
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove {}: {}", path.display(), e);
+            }
+            info!("Certificate for {} switched to the prepared DANE key", certificate.name());
+        }
+        Ok(())
+    }
+
+    /// The prepared DANE next key for the mail certificate, with its path,
+    /// when DNS already serves its TLSA record. Until then the certificate
+    /// is renewed with its current key, so the published records keep
+    /// matching.
+    async fn published_next_key(&self, certificate: &ManagedCertificate) -> Option<(CertificateKey, PathBuf)> {
+        if certificate.purpose != CertificatePurpose::Mail {
+            return None;
+        }
+        let (hostname, keys) = load_keys(&self.dane_key_dir).ok()?;
+        if hostname != certificate.name() || keys.next.is_none() {
+            return None;
+        }
+        let name = dane::tlsa_name(&hostname);
+        // Lookups block
+        let published = tokio::task::spawn_blocking(move || {
+            NativeResolver::from_system_conf().and_then(|resolver| resolver.lookup_tlsa(&name))
+        })
+        .await
+        .ok()?;
+        match published {
+            Ok(records) if keys.next_published(&records) => {}
+            Ok(_) => {
+                warn!("TLSA record for the next key of {} is not published; keeping the current key", hostname);
+                return None;
+            }
+            Err(e) => {
+                warn!("Cannot look up TLSA records for {}, keeping the current key: {}", hostname, e);
+                return None;
+            }
+        }
+
+        let path = dane::next_key_path(&self.dane_key_dir, &hostname);
+        let pem = std::fs::read_to_string(&path).ok()?;
+        match CertificateKey::from_pem(&pem) {
+            Ok(key) => Some((key, path)),
+            Err(e) => {
+                warn!("Ignoring DANE next key {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Point the configs at the stored certificate and reload what uses it.
+    ///
+    /// The store keeps the same paths across renewals, so after the first
+    /// deployment this usually only reloads Postfix, Dovecot and Apache.
+    async fn deploy(&self, certificate: &ManagedCertificate, change: &ConfigChange) -> Result<ConfigUpdate, AcmeServiceError> {
+        let store = self.store();
+        let cert_path = store.cert_path(certificate.name())?.display().to_string();
+        let key_path = store.key_path(certificate.name())?.display().to_string();
+
+        let mut changeset = Changeset::new();
+        let mut units = vec!["apache2"];
+        if certificate.purpose == CertificatePurpose::Mail {
+            units.extend(["postfix", "dovecot"]);
+            let main_cf = ConfigFileType::PostfixMain.path();
+            let content = std::fs::read_to_string(main_cf)
+                .map_err(|e| AcmeServiceError::Config(format!("{}: {}", main_cf, e)))?;
+            let mut postfix =
+                PostfixConfig::parse(&content).map_err(|e| AcmeServiceError::Config(e.to_string()))?;
+            if postfix.get("smtpd_tls_cert_file") != Some(cert_path.as_str())
+                || postfix.get("smtpd_tls_key_file") != Some(key_path.as_str())
+            {
+                postfix.set("smtpd_tls_cert_file", &cert_path);
+                postfix.set("smtpd_tls_key_file", &key_path);
+                changeset.stage(main_cf, postfix.to_string());
+            }
+        }
+        stage_vhosts(&mut changeset, &certificate.domains, &cert_path, &key_path)?;
+        if certificate.purpose == CertificatePurpose::Vhost {
+            stage_policy_vhosts(&mut changeset, &certificate.domains, &cert_path, &key_path)?;
+        }
+
+        let mut update = if changeset.is_empty() {
+            ConfigUpdate::default()
+        } else {
+            self.config
+                .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+                .await?
+        };
+        let reloaded: Vec<&str> = changeset.checks().into_iter().map(|check| check.unit()).collect();
+
+        if certificate.purpose == CertificatePurpose::Mail {
+            let mut dovecot = DovecotConfig::generate_default(certificate.name());
+            dovecot.set_ssl_cert(&cert_path);
+            dovecot.set_ssl_key(&key_path);
+            let warnings = self
+                .config
+                .update_dovecot_settings(&dovecot.ssl_settings(), true, change)
+                .await?;
+            update.warnings.extend(warnings);
+        }
+
+        // The files are read at startup and on reload only
+        let manager = ServiceManager::new().map_err(|e| AcmeServiceError::Config(e.to_string()))?;
+        for unit in units.into_iter().filter(|unit| !reloaded.contains(unit)) {
+            if !matches!(manager.is_active(unit), Ok(true)) {
+                continue;
+            }
+            if let Err(e) = manager.reload(unit) {
+                update.warnings.push(format!("Failed to reload {}: {}", unit, e));
+            }
+        }
+        info!("Deployed certificate for {}", certificate.domains.join(", "));
+        Ok(update)
+    }
+}
+
+/// Stage every vhost in sites-available that serves one of `domains`,
+/// pointed at the certificate.
+fn stage_vhosts(changeset: &mut Changeset, domains: &[String], cert: &str, key: &str) -> Result<(), AcmeServiceError> {
+    let sites = Path::new(ConfigFileType::ApacheVhost.path());
+    let entries = match std::fs::read_dir(sites) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(AcmeServiceError::Config(format!("{}: {}", sites.display(), e))),
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("conf") {
+            continue;
+        }
+        let Ok(mut content) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let mut changed = false;
+        for domain in domains {
+            if let Some(updated) = apache::set_vhost_ssl(&content, domain, cert, key) {
+                content = updated;
+                changed = true;
+            }
+        }
+        if changed {
+            changeset.stage(&path, content);
+        }
+    }
+    Ok(())
+}
+
+/// Write the MTA-STS policy vhost for each `mta-sts.<domain>` in `domains`
+/// that does not have one yet: it is only served once its certificate
+/// exists.
+fn stage_policy_vhosts(changeset: &mut Changeset, domains: &[String], cert: &str, key: &str) -> Result<(), AcmeServiceError> {
+    for name in domains {
+        let Some(domain) = name.strip_prefix("mta-sts.") else { continue };
+        let path = policy_vhost_path(domain);
+        if path.exists() {
+            continue;
+        }
+        let vhost = policy_vhost(domain).map_err(|e| AcmeServiceError::Config(e.to_string()))?;
+        let vhost = apache::set_vhost_ssl(&vhost, name, cert, key).unwrap_or(vhost);
+        changeset.stage(path, vhost);
+    }
+    Ok(())
+}
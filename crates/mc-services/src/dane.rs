@@ -0,0 +1,209 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use mc_core::config::changeset::Changeset;
+use mc_core::config::history::ConfigChange;
+use mc_core::config::postfix::PostfixConfig;
+use mc_core::fs::atomic::atomic_write_secret;
+use mc_core::mail::dane::{self, DaneError, DaneKeys, TlsaRecord, NEXT_KEY_DIR};
+use mc_core::mail::dns_check::{CheckResult, CheckStatus};
+use mc_core::system::dns::{DnsError, DnsResolver, NativeResolver};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::{ConfigError, ConfigFileType, ConfigService, ConfigUpdate, DEFAULT_HEALTH_GRACE};
+
+#[derive(Debug, Error)]
+pub enum DaneServiceError {
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Config apply failed: {0}")]
+    Apply(#[from] ConfigError),
+    #[error("DANE error: {0}")]
+    Dane(#[from] DaneError),
+    #[error("DNS error: {0}")]
+    Dns(#[from] DnsError),
+    #[error("Write failed: {0}")]
+    Write(String),
+    #[error("DANE task failed: {0}")]
+    Task(String),
+}
+
+/// The TLSA records to publish for the mail server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaneRecords {
+    /// Postfix `myhostname`.
+    pub hostname: String,
+    /// Where the records go, `_25._tcp.<hostname>`.
+    pub name: String,
+    /// The live certificate's key.
+    pub current: TlsaRecord,
+    /// The key the next renewal switches to, once one is prepared.
+    pub next: Option<TlsaRecord>,
+}
+
+/// DANE for the mail server: TLSA records for the certificate Postfix
+/// presents, the next key to renew with, and outbound DANE.
+pub struct DaneService {
+    config: ConfigService,
+    key_dir: PathBuf,
+    /// Nameservers to query instead of those in /etc/resolv.conf.
+    nameservers: Option<Vec<SocketAddr>>,
+}
+
+impl DaneService {
+    pub fn new() -> Self {
+        Self::with_config_service(ConfigService::new())
+    }
+
+    pub fn with_config_service(config: ConfigService) -> Self {
+        Self {
+            config,
+            key_dir: PathBuf::from(NEXT_KEY_DIR),
+            nameservers: None,
+        }
+    }
+
+    /// Keep next keys under `key_dir` instead of [`NEXT_KEY_DIR`].
+    pub fn with_key_dir(config: ConfigService, key_dir: &Path) -> Self {
+        Self {
+            key_dir: key_dir.to_path_buf(),
+            ..Self::with_config_service(config)
+        }
+    }
+
+    /// Check against `nameservers` rather than the system's.
+    pub fn with_nameservers(mut self, nameservers: Vec<SocketAddr>) -> Self {
+        self.nameservers = Some(nameservers);
+        self
+    }
+
+    /// The records for the live certificate and any prepared next key.
+    pub async fn records(&self) -> Result<DaneRecords, DaneServiceError> {
+        let (hostname, keys) = load_keys(&self.key_dir)?;
+        Ok(records(hostname, &keys))
+    }
+
+    /// Make sure a next key is waiting, generating one if there is none or
+    /// the certificate already uses it, and return the records to publish
+    /// before the certificate is renewed with it.
+    pub async fn prepare_key_change(&self) -> Result<DaneRecords, DaneServiceError> {
+        let (hostname, keys) = load_keys(&self.key_dir)?;
+        if keys.next.is_some() {
+            return Ok(records(hostname, &keys));
+        }
+
+        // RSA key generation blocks
+        let pem = tokio::task::spawn_blocking(dane::generate_next_key)
+            .await
+            .map_err(|e| DaneServiceError::Task(e.to_string()))??;
+        let path = dane::next_key_path(&self.key_dir, &hostname);
+        std::fs::create_dir_all(&self.key_dir).map_err(|e| DaneServiceError::Write(e.to_string()))?;
+        atomic_write_secret(&path, &pem).map_err(|e| DaneServiceError::Write(e.to_string()))?;
+
+        let keys = DaneKeys::new(keys.current, Some(dane::spki_from_key_pem(&pem)?));
+        let records = records(hostname, &keys);
+        if let Some(next) = &records.next {
+            info!(
+                "Prepared next TLS key {}; publish TLSA {} at {} before renewing",
+                path.display(),
+                next,
+                records.name
+            );
+        }
+        Ok(records)
+    }
+
+    /// Compare the published TLSA records with the live certificate.
+    pub async fn check(&self) -> Result<CheckResult, DaneServiceError> {
+        let (hostname, keys) = load_keys(&self.key_dir)?;
+        let resolver = self.resolver()?;
+        // Lookups block
+        let check = tokio::task::spawn_blocking(move || dane::check_tlsa(&resolver, &hostname, &keys))
+            .await
+            .map_err(|e| DaneServiceError::Task(e.to_string()))?;
+        if check.status != CheckStatus::Pass {
+            warn!("TLSA check for {}: {}", check.record, check.detail);
+        }
+        Ok(check)
+    }
+
+    /// Whether the next key's record is served, so the certificate may
+    /// switch to it. `false` when no key change is prepared.
+    pub async fn next_key_published(&self) -> Result<bool, DaneServiceError> {
+        let (hostname, keys) = load_keys(&self.key_dir)?;
+        if keys.next.is_none() {
+            return Ok(false);
+        }
+        let resolver = self.resolver()?;
+        // Lookups block
+        let published = tokio::task::spawn_blocking(move || resolver.lookup_tlsa(&dane::tlsa_name(&hostname)))
+            .await
+            .map_err(|e| DaneServiceError::Task(e.to_string()))??;
+        Ok(keys.next_published(&published))
+    }
+
+    /// Switch DANE for outbound mail on or off in main.cf.
+    pub async fn set_outbound_dane(
+        &self,
+        enabled: bool,
+        dry_run: bool,
+        change: &ConfigChange,
+    ) -> Result<ConfigUpdate, DaneServiceError> {
+        let main_cf = ConfigFileType::PostfixMain.path();
+        let mut postfix = read_main_cf()?;
+        let mut changeset = Changeset::new();
+        if postfix.set_outbound_dane(enabled) {
+            changeset.stage(main_cf, postfix.to_string());
+        }
+
+        if dry_run {
+            return Ok(self.config.preview_changeset(&changeset, true, change).await?);
+        }
+        let update = self
+            .config
+            .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+            .await?;
+        info!("Outbound DANE {}", if enabled { "enabled" } else { "disabled" });
+        Ok(update)
+    }
+
+    fn resolver(&self) -> Result<NativeResolver, DaneServiceError> {
+        Ok(match &self.nameservers {
+            Some(nameservers) => NativeResolver::with_nameservers(nameservers.clone()),
+            None => NativeResolver::from_system_conf()?,
+        })
+    }
+}
+
+fn records(hostname: String, keys: &DaneKeys) -> DaneRecords {
+    DaneRecords {
+        name: dane::tlsa_name(&hostname),
+        hostname,
+        current: keys.current_record(),
+        next: keys.next_record(),
+    }
+}
+
+/// Postfix's `myhostname` and the keys of its certificate, with the next
+/// key kept under `key_dir`.
+pub(crate) fn load_keys(key_dir: &Path) -> Result<(String, DaneKeys), DaneServiceError> {
+    let postfix = read_main_cf()?;
+    let hostname = postfix
+        .myhostname()
+        .map(str::to_string)
+        .ok_or_else(|| DaneServiceError::Config("myhostname is not set".to_string()))?;
+    let cert = postfix
+        .get_expanded("smtpd_tls_cert_file")
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| DaneServiceError::Config("smtpd_tls_cert_file is not set".to_string()))?;
+    let keys = DaneKeys::load(Path::new(&cert), &dane::next_key_path(key_dir, &hostname))?;
+    Ok((hostname, keys))
+}
+
+fn read_main_cf() -> Result<PostfixConfig, DaneServiceError> {
+    let main_cf = ConfigFileType::PostfixMain.path();
+    let content = std::fs::read_to_string(main_cf)
+        .map_err(|e| DaneServiceError::Config(format!("{}: {}", main_cf, e)))?;
+    PostfixConfig::parse(&content).map_err(|e| DaneServiceError::Config(e.to_string()))
+}
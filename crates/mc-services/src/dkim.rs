@@ -1,8 +1,20 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use mc_core::config::changeset::Changeset;
+use mc_core::config::history::ConfigChange;
 use mc_core::config::opendkim::OpendkimConfig;
+use mc_core::config::postfix::PostfixConfig;
 use mc_core::mail::dkim;
-use mc_core::security::input;
+use mc_core::mail::dkim_rotation::{
+    self, Rotation, RotationPolicy, RotationStep, RotationStore, ROTATION_STATE_PATH,
+};
+use mc_core::security::{idn, input};
+use mc_core::system::dns::TxtResolver;
 use thiserror::Error;
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::config::{ConfigError, ConfigFileType, ConfigService, ConfigUpdate, DEFAULT_HEALTH_GRACE};
 
 #[derive(Debug, Error)]
 pub enum DkimServiceError {
@@ -12,64 +24,314 @@ pub enum DkimServiceError {
     Dkim(#[from] dkim::DkimError),
     #[error("Config error: {0}")]
     Config(String),
+    #[error("Config apply failed: {0}")]
+    Apply(#[from] ConfigError),
+    #[error("Rotation error: {0}")]
+    Rotation(#[from] dkim_rotation::RotationError),
+}
+
+/// Result of [`DkimService::generate_dkim`].
+#[derive(Debug, Clone)]
+pub struct DkimGeneration {
+    /// The new key; `None` in a dry run, where no key is generated.
+    pub key: Option<dkim::DkimKeyInfo>,
+    pub update: ConfigUpdate,
 }
 
-pub struct DkimService;
+/// One rotation's progress after [`DkimService::advance_rotations`].
+#[derive(Debug, Clone)]
+pub struct RotationProgress {
+    pub rotation: Rotation,
+    pub step: RotationStep,
+}
+
+/// Result of [`DkimService::run_scheduled_rotations`].
+#[derive(Debug, Clone, Default)]
+pub struct ScheduledRotations {
+    /// Rotations that were already under way.
+    pub advanced: Vec<RotationProgress>,
+    /// Rotations started because their domain was due.
+    pub started: Vec<Rotation>,
+    /// Domains that were due but could not be started, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+pub struct DkimService {
+    config: ConfigService,
+    rotation_state: PathBuf,
+}
 
 impl DkimService {
     pub fn new() -> Self {
-        Self
+        Self::with_config_service(ConfigService::new())
+    }
+
+    /// Apply config changes through `config`, e.g. one with an audit logger.
+    pub fn with_config_service(config: ConfigService) -> Self {
+        Self {
+            config,
+            rotation_state: PathBuf::from(ROTATION_STATE_PATH),
+        }
     }
 
-    /// Generate DKIM keys for a domain and update OpenDKIM config
+    /// Keep rotation state somewhere other than the default path.
+    pub fn with_rotation_state(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rotation_state = path.into();
+        self
+    }
+
+    /// Generate DKIM keys for a domain and update OpenDKIM config.
+    ///
+    /// A domain that already signs with a key of another type signs with
+    /// both, e.g. RSA for older receivers and Ed25519 for those that
+    /// support it.
+    ///
+    /// With `dry_run` no key is generated and nothing is written; the
+    /// result shows the OpenDKIM and Postfix edits the key would need.
     pub async fn generate_dkim(
         &self,
         domain: &str,
         selector: &str,
-    ) -> Result<dkim::DkimKeyInfo, DkimServiceError> {
-        // Validate domain
-        input::validate_domain(domain)
-            .map_err(|e| DkimServiceError::Validation(e.to_string()))?;
+        algorithm: dkim::DkimAlgorithm,
+        dry_run: bool,
+        change: &ConfigChange,
+    ) -> Result<DkimGeneration, DkimServiceError> {
+        // Validate domain; key files and DNS records use the punycode form
+        let domain = idn::normalize_domain(domain)
+            .map_err(|e| DkimServiceError::Validation(e.to_string()))?
+            .ascii;
+        let domain = domain.as_str();
         input::validate_path_component(selector)
             .map_err(|e| DkimServiceError::Validation(e.to_string()))?;
 
-        // Generate key
-        let key_info = dkim::generate_dkim_key(domain, selector)?;
-
-        // Update OpenDKIM config files
         let mut config = OpendkimConfig::load()
             .map_err(|e| DkimServiceError::Config(e.to_string()))?;
-        config.add_domain(domain, selector)
-            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
-        config.save()
-            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        // A domain's first key; otherwise a key of another type that signs
+        // alongside the existing one
+        if config.selectors(domain).is_empty() {
+            config.add_domain_with_algorithm(domain, selector, algorithm)
+        } else {
+            config.add_signing_key(domain, selector, algorithm)
+        }
+        .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        let changeset = self.changeset(&config)?;
+
+        if dry_run {
+            let update = self.config.preview_changeset(&changeset, true, change).await?;
+            return Ok(DkimGeneration { key: None, update });
+        }
+
+        // Generate key where the key table will point
+        let key_info = dkim::generate_selector_key(&config.key_dir(domain), domain, selector, algorithm)?;
+
+        // Update OpenDKIM config files
+        let update = self
+            .config
+            .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+            .await?;
 
         info!("Generated DKIM for domain: {} selector: {}", domain, selector);
-        Ok(key_info)
+        Ok(DkimGeneration {
+            key: Some(key_info),
+            update,
+        })
     }
 
     /// List all DKIM keys
     pub async fn list_keys(&self) -> Result<Vec<dkim::DkimKeyInfo>, DkimServiceError> {
-        Ok(dkim::list_dkim_domains()?)
+        let config = OpendkimConfig::load()
+            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        Ok(dkim::list_dkim_keys(Path::new(&config.key_base_dir))?)
     }
 
     /// Delete DKIM key and update config
-    pub async fn delete_key(&self, domain: &str) -> Result<(), DkimServiceError> {
-        input::validate_domain(domain)
-            .map_err(|e| DkimServiceError::Validation(e.to_string()))?;
+    pub async fn delete_key(
+        &self,
+        domain: &str,
+        change: &ConfigChange,
+    ) -> Result<(), DkimServiceError> {
+        let domain = idn::normalize_domain(domain)
+            .map_err(|e| DkimServiceError::Validation(e.to_string()))?
+            .ascii;
+        let domain = domain.as_str();
 
         // Remove from config
         let mut config = OpendkimConfig::load()
             .map_err(|e| DkimServiceError::Config(e.to_string()))?;
         config.remove_domain(domain)
             .map_err(|e| DkimServiceError::Config(e.to_string()))?;
-        config.save()
-            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        let changeset = self.changeset(&config)?;
+        self.config
+            .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+            .await?;
 
-        // Delete key files
-        dkim::delete_dkim_key(domain)?;
+        // Delete key files, every selector included
+        dkim::delete_key_dir(&config.key_dir(domain))?;
 
         info!("Deleted DKIM key for domain: {}", domain);
         Ok(())
     }
+
+    /// Start rotating a domain's key of `algorithm`'s type to a new
+    /// date-based selector.
+    ///
+    /// The new key is added to the key table while the current selector
+    /// keeps signing. The returned rotation carries the TXT record to
+    /// publish; [`advance_rotations`](Self::advance_rotations) switches
+    /// signing over once DNS serves it.
+    pub async fn start_rotation(
+        &self,
+        domain: &str,
+        algorithm: dkim::DkimAlgorithm,
+        change: &ConfigChange,
+    ) -> Result<Rotation, DkimServiceError> {
+        let domain = idn::normalize_domain(domain)
+            .map_err(|e| DkimServiceError::Validation(e.to_string()))?
+            .ascii;
+        let domain = domain.as_str();
+
+        let mut store = RotationStore::load(&self.rotation_state)?;
+        if store.active(domain, algorithm).is_some() {
+            return Err(dkim_rotation::RotationError::InProgress(format!(
+                "{} ({})",
+                domain,
+                algorithm.key_type()
+            ))
+            .into());
+        }
+        let mut config = OpendkimConfig::load()
+            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        let old_selector = config
+            .signing_selector(domain, algorithm)
+            .ok_or_else(|| {
+                dkim_rotation::RotationError::NotSigning(format!("{} ({})", domain, algorithm.key_type()))
+            })?
+            .to_string();
+
+        let now = Utc::now();
+        let selector = dkim_rotation::next_selector(now, &config.selectors(domain));
+        config.add_selector(domain, &selector, algorithm)
+            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        let changeset = self.changeset(&config)?;
+
+        let key_dir = config.key_dir(domain);
+        let key = dkim::generate_selector_key(&key_dir, domain, &selector, algorithm)?;
+        if let Err(e) = self
+            .config
+            .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+            .await
+        {
+            let _ = dkim::delete_selector_key(&key_dir, &selector);
+            return Err(e.into());
+        }
+
+        let rotation = Rotation::start(
+            domain,
+            &old_selector,
+            &selector,
+            algorithm,
+            &dkim::txt_record_value(&key.dns_record),
+            now,
+        );
+        store.begin(rotation.clone())?;
+        store.save()?;
+
+        info!("Started DKIM rotation for {}: {} -> {}", domain, old_selector, selector);
+        Ok(rotation)
+    }
+
+    /// Every recorded rotation, finished ones included.
+    pub async fn list_rotations(&self) -> Result<Vec<Rotation>, DkimServiceError> {
+        Ok(RotationStore::load(&self.rotation_state)?.rotations().to_vec())
+    }
+
+    /// Move every active rotation on: switch signing where DNS serves the
+    /// new key, and retire old selectors whose grace period has passed.
+    /// The OpenDKIM tables are written once for all rotations.
+    pub async fn advance_rotations(
+        &self,
+        resolver: &dyn TxtResolver,
+        policy: &RotationPolicy,
+        change: &ConfigChange,
+    ) -> Result<Vec<RotationProgress>, DkimServiceError> {
+        let mut store = RotationStore::load(&self.rotation_state)?;
+        let mut config = OpendkimConfig::load()
+            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        let before = store.rotations().to_vec();
+
+        let now = Utc::now();
+        let mut progress = Vec::new();
+        for rotation in store.active_mut() {
+            let step = rotation.advance(&mut config, resolver, policy.grace, now)?;
+            progress.push(RotationProgress {
+                rotation: rotation.clone(),
+                step,
+            });
+        }
+
+        if progress.iter().any(|p| p.step.changes_config()) {
+            let changeset = self.changeset(&config)?;
+            self.config
+                .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+                .await?;
+        }
+        if store.rotations() != before.as_slice() {
+            store.save()?;
+        }
+
+        // Only once OpenDKIM no longer references them
+        for p in progress.iter().filter(|p| p.step == RotationStep::Retired) {
+            let rotation = &p.rotation;
+            if let Err(e) = dkim::delete_selector_key(&config.key_dir(&rotation.domain), &rotation.old_selector) {
+                warn!("Failed to delete retired DKIM key {}: {}", rotation.old_selector, e);
+            }
+        }
+        Ok(progress)
+    }
+
+    /// The periodic rotation job: advance rotations under way, then start
+    /// one for every signing key due under `policy`, e.g. yearly.
+    pub async fn run_scheduled_rotations(
+        &self,
+        resolver: &dyn TxtResolver,
+        policy: &RotationPolicy,
+        change: &ConfigChange,
+    ) -> Result<ScheduledRotations, DkimServiceError> {
+        let advanced = self.advance_rotations(resolver, policy, change).await?;
+
+        let store = RotationStore::load(&self.rotation_state)?;
+        let config = OpendkimConfig::load()
+            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        let mut result = ScheduledRotations {
+            advanced,
+            ..Default::default()
+        };
+        for entry in store.due(&config, policy, Utc::now()) {
+            match self.start_rotation(&entry.domain, entry.algorithm, change).await {
+                Ok(rotation) => result.started.push(rotation),
+                Err(e) => {
+                    warn!("Scheduled DKIM rotation for {} failed: {}", entry.domain, e);
+                    result.failed.push((entry.domain, e.to_string()));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Stage the OpenDKIM files together with the Postfix milter hookup,
+    /// so both are reloaded together and rolled back if either fails.
+    fn changeset(&self, config: &OpendkimConfig) -> Result<Changeset, DkimServiceError> {
+        let mut changeset = Changeset::new();
+        config.stage(&mut changeset);
+
+        let main_cf = ConfigFileType::PostfixMain.path();
+        let content = std::fs::read_to_string(main_cf)
+            .map_err(|_| ConfigError::NotFound(main_cf.to_string()))?;
+        let mut postfix = PostfixConfig::parse(&content)
+            .map_err(|e| DkimServiceError::Config(e.to_string()))?;
+        if postfix.add_milter(&config.postfix_milter()) {
+            changeset.stage(main_cf, postfix.to_string());
+        }
+        Ok(changeset)
+    }
 }
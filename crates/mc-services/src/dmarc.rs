@@ -0,0 +1,269 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, Utc};
+use mc_core::mail::dmarc_report::{self, AggregateReport, DmarcReportError};
+use mc_core::mail::report_mailbox::{ReportMailboxError, ReportMaildir};
+use mc_core::mail::sieve::MAIL_HOME_BASE;
+use mc_core::security::idn;
+use mc_db::models::{DmarcDailySummary, DmarcReport, DmarcReportRecord, DmarcSourceSummary};
+use mc_db::pool::DbError;
+use mc_db::queries;
+use sqlx::MySqlPool;
+use thiserror::Error;
+use tracing::{info, warn};
+
+#[derive(Debug, Error)]
+pub enum DmarcServiceError {
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Report error: {0}")]
+    Report(#[from] DmarcReportError),
+    #[error("Mailbox error: {0}")]
+    Mailbox(#[from] ReportMailboxError),
+    #[error("Database error: {0}")]
+    Database(#[from] DbError),
+    #[error("Ingest task failed: {0}")]
+    Task(String),
+}
+
+/// What one pass over a report mailbox found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestSummary {
+    /// Unread messages read.
+    pub messages: u32,
+    /// Reports stored.
+    pub reports: u32,
+    /// Reports already stored by an earlier pass.
+    pub duplicates: u32,
+    /// Messages holding no readable report.
+    pub rejected: u32,
+    /// Reports that could not be stored; their messages stay unread.
+    pub failed: u32,
+}
+
+// Column widths of `dmarc_reports` and `dmarc_report_records`.
+const NAME_LEN: usize = 255;
+const KEYWORD_LEN: usize = 16;
+const IP_LEN: usize = 45;
+
+/// DMARC aggregate reports: ingestion from the `rua=` mailbox and the
+/// alignment views built from them.
+pub struct DmarcService {
+    dashboard_pool: MySqlPool,
+    home_base: PathBuf,
+}
+
+impl DmarcService {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self {
+            dashboard_pool: pool,
+            home_base: PathBuf::from(MAIL_HOME_BASE),
+        }
+    }
+
+    /// Read mailboxes under `home_base` instead of [`MAIL_HOME_BASE`].
+    pub fn with_home_base(pool: MySqlPool, home_base: &Path) -> Self {
+        Self {
+            dashboard_pool: pool,
+            home_base: home_base.to_path_buf(),
+        }
+    }
+
+    /// Store the reports in every unread message of `mailbox`, then mark
+    /// the messages seen.
+    ///
+    /// A message is left unread when storing any of its reports fails, so
+    /// the next pass picks it up again; the pass goes on with the other
+    /// messages.
+    pub async fn ingest_mailbox(&self, mailbox: &str) -> Result<IngestSummary, DmarcServiceError> {
+        let maildir = ReportMaildir::for_address(&self.home_base, mailbox)?;
+
+        // Maildir reads and decompression block
+        let read_dir = maildir.clone();
+        let messages = tokio::task::spawn_blocking(move || {
+            read_dir.unread().map(|paths| {
+                paths
+                    .into_iter()
+                    .map(|path| {
+                        let reports = std::fs::read(&path)
+                            .map_err(DmarcReportError::from)
+                            .and_then(|raw| dmarc_report::reports_from_message(&raw));
+                        (path, reports)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .await
+        .map_err(|e| DmarcServiceError::Task(e.to_string()))??;
+
+        let mut summary = IngestSummary::default();
+        for (path, reports) in messages {
+            summary.messages += 1;
+            let mut stored = true;
+            match reports {
+                Ok(reports) => {
+                    for report in &reports {
+                        match self.save_report(report).await {
+                            Ok(true) => summary.reports += 1,
+                            Ok(false) => summary.duplicates += 1,
+                            Err(e) => {
+                                warn!(
+                                    "Failed to store DMARC report {} from {} in {}: {}",
+                                    report.report_id,
+                                    report.org_name,
+                                    path.display(),
+                                    e
+                                );
+                                summary.failed += 1;
+                                stored = false;
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("No DMARC report in {}: {}", path.display(), e);
+                    summary.rejected += 1;
+                }
+            }
+            if stored {
+                maildir.mark_seen(&path)?;
+            }
+        }
+
+        info!(
+            "Ingested DMARC reports from {}: {} messages, {} reports, {} duplicates, {} rejected, {} failed",
+            mailbox,
+            summary.messages,
+            summary.reports,
+            summary.duplicates,
+            summary.rejected,
+            summary.failed
+        );
+        Ok(summary)
+    }
+
+    /// Store one report; `false` when it was already stored.
+    ///
+    /// Text longer than its column is cut to fit rather than failing the
+    /// insert.
+    async fn save_report(&self, report: &AggregateReport) -> Result<bool, DmarcServiceError> {
+        let row = DmarcReport {
+            id: 0,
+            org_name: fit(&report.org_name, NAME_LEN),
+            report_id: fit(&report.report_id, NAME_LEN),
+            domain: fit(&report.policy.domain, NAME_LEN),
+            policy: fit(&report.policy.policy, KEYWORD_LEN),
+            date_begin: report.begin,
+            date_end: report.end,
+            received_at: Utc::now(),
+        };
+        let records: Vec<DmarcReportRecord> = report
+            .records
+            .iter()
+            .map(|r| DmarcReportRecord {
+                source_ip: fit(&r.source_ip.to_string(), IP_LEN),
+                header_from: fit(&r.header_from, NAME_LEN),
+                message_count: r.count as i64,
+                disposition: fit(&r.disposition, KEYWORD_LEN),
+                dkim_aligned: r.dkim_aligned,
+                spf_aligned: r.spf_aligned,
+                dkim_domain: r.dkim_domain().map(|d| fit(d, NAME_LEN)),
+                spf_domain: r.spf_domain().map(|d| fit(d, NAME_LEN)),
+            })
+            .collect();
+
+        match queries::save_dmarc_report(&self.dashboard_pool, &row, &records).await {
+            Ok(_) => {
+                if report.failed() > 0 {
+                    warn!(
+                        "DMARC report {} from {}: {} of {} messages for {} failed alignment",
+                        report.report_id,
+                        report.org_name,
+                        report.failed(),
+                        report.total(),
+                        report.policy.domain
+                    );
+                }
+                Ok(true)
+            }
+            Err(DbError::Duplicate(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn list_reports(
+        &self,
+        domain: Option<&str>,
+        limit: i32,
+    ) -> Result<Vec<DmarcReport>, DmarcServiceError> {
+        let domain = domain.map(normalize_domain).transpose()?;
+        Ok(queries::list_dmarc_reports(&self.dashboard_pool, domain.as_deref(), limit).await?)
+    }
+
+    /// Daily pass and failure counts over the last `days` days, per domain.
+    pub async fn daily_summary(
+        &self,
+        domain: Option<&str>,
+        days: u32,
+    ) -> Result<Vec<DmarcDailySummary>, DmarcServiceError> {
+        let domain = domain.map(normalize_domain).transpose()?;
+        let since = Utc::now() - Duration::days(days as i64);
+        Ok(queries::dmarc_daily_summary(&self.dashboard_pool, domain.as_deref(), since).await?)
+    }
+
+    /// Source addresses seen over the last `days` days, most failures
+    /// first; `failing_only` hides sources whose mail always aligned.
+    pub async fn sources(
+        &self,
+        domain: Option<&str>,
+        days: u32,
+        failing_only: bool,
+    ) -> Result<Vec<DmarcSourceSummary>, DmarcServiceError> {
+        let domain = domain.map(normalize_domain).transpose()?;
+        let since = Utc::now() - Duration::days(days as i64);
+        Ok(queries::dmarc_source_summary(&self.dashboard_pool, domain.as_deref(), since, failing_only).await?)
+    }
+
+    /// Drop reports older than `days` days.
+    pub async fn prune(&self, days: u32) -> Result<u64, DmarcServiceError> {
+        let before = Utc::now() - Duration::days(days as i64);
+        let pruned = queries::prune_dmarc_reports(&self.dashboard_pool, before).await?;
+        info!("Pruned {} DMARC reports older than {} days", pruned, days);
+        Ok(pruned)
+    }
+}
+
+fn normalize_domain(domain: &str) -> Result<String, DmarcServiceError> {
+    idn::normalize_domain(domain)
+        .map(|d| d.ascii)
+        .map_err(|e| DmarcServiceError::Validation(e.to_string()))
+}
+
+/// `value` cut to at most `max` characters, the unit MySQL sizes
+/// `VARCHAR` columns in.
+fn fit(value: &str, max: usize) -> String {
+    match value.char_indices().nth(max) {
+        Some((end, _)) => {
+            warn!("Truncating DMARC report field to {} characters: {}", max, value);
+            value[..end].to_string()
+        }
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_keeps_short_values() {
+        assert_eq!(fit("reject", KEYWORD_LEN), "reject");
+        assert_eq!(fit("", KEYWORD_LEN), "");
+    }
+
+    #[test]
+    fn test_fit_truncates_on_char_boundary() {
+        assert_eq!(fit(&"a".repeat(300), NAME_LEN).len(), NAME_LEN);
+        assert_eq!(fit("ééé", 2), "éé");
+    }
+}
@@ -0,0 +1,160 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use mc_core::config::opendkim::OpendkimConfig;
+use mc_core::config::postfix::PostfixConfig;
+use mc_core::mail::dane::NEXT_KEY_DIR;
+use mc_core::mail::dkim;
+use mc_core::mail::dns_check::{self, DomainScorecard, MailServer};
+use mc_core::mail::dns_records::{DmarcRecord, MailZone};
+use mc_core::mail::mta_sts::MTA_STS_ROOT;
+use mc_core::security::idn;
+use mc_core::system::dns::{DnsError, DnsResolver, NativeResolver};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::ConfigFileType;
+use crate::{dane, mta_sts};
+
+#[derive(Debug, Error)]
+pub enum DnsServiceError {
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("DNS error: {0}")]
+    Dns(#[from] DnsError),
+    #[error("Verification task failed: {0}")]
+    Task(String),
+}
+
+pub struct DnsService {
+    /// Nameservers to query instead of those in /etc/resolv.conf.
+    nameservers: Option<Vec<SocketAddr>>,
+}
+
+impl DnsService {
+    pub fn new() -> Self {
+        Self { nameservers: None }
+    }
+
+    /// Query `nameservers` rather than the system's, e.g. a public
+    /// resolver to see what the rest of the world sees.
+    pub fn with_nameservers(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers: Some(nameservers),
+        }
+    }
+
+    /// Check the published mail records of `domains`, or of every domain
+    /// with a DKIM key when `domains` is empty.
+    ///
+    /// Records are checked against Postfix's `myhostname` and `server_ips`;
+    /// when no addresses are given, those `myhostname` resolves to are used.
+    pub async fn verify_domains(
+        &self,
+        domains: &[String],
+        server_ips: Vec<IpAddr>,
+    ) -> Result<Vec<DomainScorecard>, DnsServiceError> {
+        let hostname = myhostname()?;
+        let dkim = OpendkimConfig::load()
+            .map_err(|e| DnsServiceError::Config(e.to_string()))?;
+
+        let mut domains = domains
+            .iter()
+            .map(|d| idn::normalize_domain(d).map(|d| d.ascii))
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| DnsServiceError::Validation(e.to_string()))?;
+        if domains.is_empty() {
+            domains = dkim.key_table.iter().map(|e| e.domain.clone()).collect();
+            domains.sort();
+            domains.dedup();
+        }
+
+        let resolver = match &self.nameservers {
+            Some(nameservers) => NativeResolver::with_nameservers(nameservers.clone()),
+            None => NativeResolver::from_system_conf()?,
+        };
+        // Lookups block
+        tokio::task::spawn_blocking(move || {
+            let ips = if server_ips.is_empty() {
+                resolver.lookup_ip(&hostname)?
+            } else {
+                server_ips
+            };
+            let server = MailServer { hostname, ips };
+            let scorecards = dns_check::verify_domains(&resolver, &server, &dkim, &domains);
+            for card in &scorecards {
+                let (passed, total) = card.score();
+                info!("DNS check for {}: {}/{} passed", card.domain, passed, total);
+            }
+            Ok(scorecards)
+        })
+        .await
+        .map_err(|e| DnsServiceError::Task(e.to_string()))?
+    }
+
+    /// The records to publish for `domain`: MX, SPF, DKIM, DMARC,
+    /// MTA-STS, TLS-RPT, DANE and client autoconfiguration.
+    ///
+    /// `server_ips` go into SPF alongside `mx`. The DMARC policy defaults
+    /// to quarantine, with aggregate reports to postmaster unless
+    /// `dmarc_reports` names other addresses.
+    pub async fn zone_records(
+        &self,
+        domain: &str,
+        server_ips: Vec<IpAddr>,
+        dmarc_policy: Option<&str>,
+        dmarc_reports: Vec<String>,
+    ) -> Result<MailZone, DnsServiceError> {
+        let domain = idn::normalize_domain(domain)
+            .map_err(|e| DnsServiceError::Validation(e.to_string()))?
+            .ascii;
+        let mut zone = MailZone::new(&domain, &myhostname()?);
+        zone.ips = server_ips;
+        // Announce the published policy, so the id matches what is served
+        match mta_sts::load_policy(Path::new(MTA_STS_ROOT), &domain) {
+            Ok(Some(policy)) => zone.mta_sts_id = policy.id(),
+            Ok(None) => {}
+            Err(e) => warn!("Ignoring MTA-STS policy for {}: {}", domain, e),
+        }
+        // Includes the next key's record while a key change is prepared
+        match dane::load_keys(Path::new(NEXT_KEY_DIR)) {
+            Ok((_, keys)) => zone.tlsa = keys.records(),
+            Err(e) => warn!("No TLSA records for {}: {}", domain, e),
+        }
+
+        if let Some(policy) = dmarc_policy {
+            zone.dmarc.policy = policy.to_ascii_lowercase();
+        }
+        if !dmarc_reports.is_empty() {
+            zone.dmarc.aggregate_reports = dmarc_reports;
+        }
+        DmarcRecord::parse(&zone.dmarc.to_string()).map_err(DnsServiceError::Validation)?;
+
+        let config = OpendkimConfig::load()
+            .map_err(|e| DnsServiceError::Config(e.to_string()))?;
+        for entry in config.key_table.iter().filter(|e| e.domain == domain) {
+            let txt = config.key_path(&domain, &entry.selector).with_extension("txt");
+            match std::fs::read_to_string(&txt) {
+                Ok(record) => zone
+                    .dkim_keys
+                    .push((entry.selector.clone(), dkim::txt_record_value(&record))),
+                Err(e) => warn!("Skipping DKIM selector {}: {}: {}", entry.selector, txt.display(), e),
+            }
+        }
+        Ok(zone)
+    }
+}
+
+/// Postfix's `myhostname`, the name the records point at.
+pub(crate) fn myhostname() -> Result<String, DnsServiceError> {
+    let main_cf = ConfigFileType::PostfixMain.path();
+    let content = std::fs::read_to_string(main_cf)
+        .map_err(|e| DnsServiceError::Config(format!("{}: {}", main_cf, e)))?;
+    PostfixConfig::parse(&content)
+        .map_err(|e| DnsServiceError::Config(e.to_string()))?
+        .myhostname()
+        .map(str::to_string)
+        .ok_or_else(|| DnsServiceError::Config("myhostname is not set".to_string()))
+}
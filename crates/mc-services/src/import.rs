@@ -0,0 +1,180 @@
+use std::path::Path;
+
+use mc_core::config::history::ConfigChange;
+use mc_core::config::import::{self, ImportPaths, ImportedStack};
+use mc_core::security::credentials::{self, CredentialStore};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::{ConfigError, ConfigService, DEFAULT_HEALTH_GRACE};
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("Credential error: {0}")]
+    Credential(String),
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
+}
+
+/// The outcome of an import scan.
+pub struct ImportResult {
+    pub stack: ImportedStack,
+    /// Config files taken as the baseline drift is measured from.
+    pub adopted: Vec<String>,
+    /// Credential names moved into the credential store.
+    pub stored_credentials: Vec<String>,
+    /// Warnings from rewriting the files that held those passwords.
+    pub warnings: Vec<String>,
+}
+
+pub struct ImportService {
+    paths: ImportPaths,
+    config: ConfigService,
+    /// `None` opens the default store when credentials are moved.
+    credentials: Option<CredentialStore>,
+}
+
+impl ImportService {
+    pub fn new() -> Self {
+        Self::with_paths(ImportPaths::new())
+    }
+
+    pub fn with_paths(paths: ImportPaths) -> Self {
+        Self {
+            paths,
+            config: ConfigService::new(),
+            credentials: None,
+        }
+    }
+
+    /// Record adopted files and apply the credential move with `config`.
+    pub fn with_config_service(mut self, config: ConfigService) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Move imported passwords into `store`.
+    pub fn with_credential_store(mut self, store: CredentialStore) -> Self {
+        self.credentials = Some(store);
+        self
+    }
+
+    /// Read the live mail stack into the config models and adopt the files
+    /// it read as the managed baseline drift is measured from. MySQL
+    /// passwords are redacted from every recorded revision.
+    ///
+    /// With `store_credentials`, the MySQL passwords found in the map files
+    /// are moved to the credential store: the maps are rewritten to read
+    /// them from an option file, applied like any other changeset (checked,
+    /// reloaded, and restored if the services do not come back).
+    pub async fn import_server(
+        &self,
+        store_credentials: bool,
+        change: &ConfigChange,
+    ) -> Result<ImportResult, ImportError> {
+        let stack = import::scan(&self.paths);
+        info!(
+            "Import scan found {} DKIM keys, {} credentials, {} unmapped items",
+            stack.dkim_keys.len(),
+            stack.credentials.len(),
+            stack.unmapped.len()
+        );
+
+        let mut adopted = Vec::new();
+        for (path, content) in &stack.files {
+            match self.config.adopt(path, content, change).await {
+                Ok(Some(_)) => adopted.push(path.display().to_string()),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to adopt {}: {}", path.display(), e),
+            }
+        }
+
+        let (stored_credentials, warnings) = if store_credentials {
+            let opened;
+            let store = match &self.credentials {
+                Some(store) => store,
+                None => {
+                    opened = CredentialStore::new(Path::new(credentials::KEY_PATH))
+                        .map_err(|e| ImportError::Credential(e.to_string()))?;
+                    &opened
+                }
+            };
+            let (stored, changeset) = stack
+                .move_credentials(store, &self.paths)
+                .map_err(|e| ImportError::Credential(e.to_string()))?;
+            let update = self
+                .config
+                .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+                .await?;
+            info!("Moved imported credentials to the store: {}", stored.join(", "));
+            (stored, update.warnings)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        Ok(ImportResult {
+            stack,
+            adopted,
+            stored_credentials,
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_core::config::history::ConfigHistory;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path.trim_start_matches('/'));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_keeps_passwords_out_of_history() {
+        let server = tempfile::tempdir().unwrap();
+        let root = server.path();
+        write(
+            root,
+            "/etc/postfix/main.cf",
+            "myhostname = mail.example.com\n\
+             mydomain = example.com\n\
+             virtual_alias_maps = proxy:mysql:/etc/postfix/mysql-virtual-alias-maps.cf\n",
+        );
+        write(
+            root,
+            "/etc/postfix/mysql-virtual-alias-maps.cf",
+            "user = mailuser\npassword = hunter2\nhosts = 127.0.0.1\ndbname = mailserver\nquery = SELECT 1\n",
+        );
+        let state = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::with_root(state.path().join("history"));
+        let store = CredentialStore::with_credentials_dir(
+            &state.path().join("key.txt"),
+            &state.path().join("credentials"),
+        )
+        .unwrap();
+        let service = ImportService::with_paths(ImportPaths::with_root(root))
+            .with_config_service(ConfigService::with_history(history.clone()))
+            .with_credential_store(store);
+
+        let result = service
+            .import_server(true, &ConfigChange::new("admin", "Adopt server"))
+            .await
+            .unwrap();
+        assert_eq!(result.stored_credentials, vec!["mysql-mailuser"]);
+        let map = std::fs::read_to_string(root.join("etc/postfix/mysql-virtual-alias-maps.cf")).unwrap();
+        assert!(!map.contains("hunter2"));
+
+        let files = history.files().unwrap();
+        assert!(files.iter().any(|path| path.ends_with("mysql-mailuser.cnf")));
+        for path in files {
+            for summary in history.list(&path).unwrap() {
+                let revision = history.get(&path, summary.id).unwrap();
+                assert!(!revision.content.contains("hunter2"), "{} revision {}", path.display(), summary.id);
+                assert!(!revision.diff.contains("hunter2"), "{} revision {}", path.display(), summary.id);
+            }
+        }
+    }
+}
@@ -1,11 +1,17 @@
+use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{info, error};
 
+use mc_core::config::history::ConfigChange;
+use mc_core::config::validate;
 use mc_core::install::orchestrator::{
     InstallConfig, InstallOrchestrator, StepState, StepStatus,
 };
+use mc_core::security::audit::{AuditAction, AuditEvent, AuditLogger};
+
+use crate::config::{ConfigService, ConfigUpdate};
 
 #[derive(Debug, Error)]
 pub enum InstallError {
@@ -50,11 +56,25 @@ impl InstallProgress {
     }
 }
 
-pub struct InstallService;
+pub struct InstallService {
+    config: ConfigService,
+    audit: Option<Arc<dyn AuditLogger>>,
+}
 
 impl InstallService {
     pub fn new() -> Self {
-        Self
+        Self::with_config_service(ConfigService::new())
+    }
+
+    /// Validate step previews with `config`.
+    pub fn with_config_service(config: ConfigService) -> Self {
+        Self { config, audit: None }
+    }
+
+    /// Log step previews to `logger`.
+    pub fn with_audit_logger(mut self, logger: Arc<dyn AuditLogger>) -> Self {
+        self.audit = Some(logger);
+        self
     }
 
     /// Start a full installation, returning a stream of progress updates.
@@ -148,6 +168,52 @@ impl InstallService {
         Ok(ReceiverStream::new(rx))
     }
 
+    /// Dry run of a single step: the config files it would write, as
+    /// diffs against the live files, and what validating them found.
+    /// Nothing is written.
+    pub async fn preview_step(
+        &self,
+        config: &InstallConfig,
+        step_name: &str,
+        actor: &str,
+    ) -> Result<ConfigUpdate, InstallError> {
+        let orchestrator = InstallOrchestrator::new(config.clone());
+        let index = orchestrator
+            .get_steps()
+            .iter()
+            .position(|s| s.name == step_name)
+            .ok_or_else(|| InstallError::General(format!("Unknown step: {}", step_name)))?;
+
+        let step_failed = |message: String| InstallError::StepFailed {
+            step: step_name.to_string(),
+            message,
+        };
+        let result = match orchestrator.step_changeset(index) {
+            Ok(changeset) => {
+                let change = ConfigChange::new(actor, format!("Install step {}", step_name));
+                self.config
+                    .preview_changeset(&changeset, true, &change)
+                    .await
+                    .map_err(|e| step_failed(e.to_string()))
+            }
+            Err(e) => Err(step_failed(e.to_string())),
+        };
+        if let Some(logger) = &self.audit {
+            let event = match &result {
+                Ok(update) if validate::has_errors(&update.diagnostics) => AuditEvent::failure(
+                    AuditAction::InstallStep,
+                    actor,
+                    step_name,
+                    "generated config does not validate",
+                ),
+                Ok(_) => AuditEvent::success(AuditAction::InstallStep, actor, step_name),
+                Err(e) => AuditEvent::failure(AuditAction::InstallStep, actor, step_name, e),
+            };
+            logger.log_event(&event.preview());
+        }
+        result
+    }
+
     /// Get the current state of all installation steps.
     ///
     /// Returns a snapshot of step states (useful for UI to render the wizard
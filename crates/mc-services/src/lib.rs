@@ -8,3 +8,10 @@ pub mod install;
 pub mod webmail;
 pub mod backup;
 pub mod permissions;
+pub mod sieve;
+pub mod import;
+pub mod dns;
+pub mod dane;
+pub mod acme;
+pub mod dmarc;
+pub mod mta_sts;
@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, Utc};
+use mc_core::acme;
+use mc_core::config::apache;
+use mc_core::config::changeset::Changeset;
+use mc_core::config::history::ConfigChange;
+use mc_core::mail::mta_sts::{self, MtaStsError, MtaStsMode, MtaStsPolicy, DEFAULT_MAX_AGE, MTA_STS_ROOT};
+use mc_core::mail::report_mailbox::{ReportMailboxError, ReportMaildir};
+use mc_core::mail::sieve::MAIL_HOME_BASE;
+use mc_core::mail::tls_report::{self, OrganizationSummary, TlsReportError};
+use mc_core::security::idn;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::{ConfigError, ConfigFileType, ConfigService, ConfigUpdate, DEFAULT_HEALTH_GRACE};
+use crate::dns::{myhostname, DnsServiceError};
+
+#[derive(Debug, Error)]
+pub enum MtaStsServiceError {
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Policy error: {0}")]
+    Policy(#[from] MtaStsError),
+    #[error("Config apply failed: {0}")]
+    Config(#[from] ConfigError),
+    #[error("{0}")]
+    Hostname(#[from] DnsServiceError),
+    #[error("Report error: {0}")]
+    Report(#[from] TlsReportError),
+    #[error("Mailbox error: {0}")]
+    Mailbox(#[from] ReportMailboxError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Report task failed: {0}")]
+    Task(String),
+}
+
+/// MTA-STS policies and the TLS-RPT reports senders return about them.
+///
+/// Policies are written through a changeset, so they get the same
+/// history, audit and dry-run preview as the config files.
+pub struct MtaStsService {
+    config: ConfigService,
+    policy_root: PathBuf,
+    home_base: PathBuf,
+}
+
+impl MtaStsService {
+    pub fn new() -> Self {
+        Self::with_config_service(ConfigService::new())
+    }
+
+    pub fn with_config_service(config: ConfigService) -> Self {
+        Self {
+            config,
+            policy_root: PathBuf::from(MTA_STS_ROOT),
+            home_base: PathBuf::from(MAIL_HOME_BASE),
+        }
+    }
+
+    /// Keep policies under `policy_root` and read report mailboxes under
+    /// `home_base`.
+    pub fn with_paths(config: ConfigService, policy_root: &Path, home_base: &Path) -> Self {
+        Self {
+            config,
+            policy_root: policy_root.to_path_buf(),
+            home_base: home_base.to_path_buf(),
+        }
+    }
+
+    /// The published policy of `domain`, if it has one.
+    pub async fn get_policy(&self, domain: &str) -> Result<Option<MtaStsPolicy>, MtaStsServiceError> {
+        let domain = normalize_domain(domain)?;
+        load_policy(&self.policy_root, &domain)
+    }
+
+    /// Publish a policy for `domain` listing Postfix's `myhostname` as the
+    /// MX. `max_age` defaults to a week.
+    ///
+    /// The `mta-sts.<domain>` vhost that serves it is written once that
+    /// name has a certificate; until then the update carries a warning to
+    /// issue one (a vhost certificate through the ACME service, which then
+    /// writes the vhost). The `_mta-sts` record must then be republished
+    /// with the policy's new id.
+    pub async fn set_policy(
+        &self,
+        domain: &str,
+        mode: MtaStsMode,
+        max_age: Option<u32>,
+        dry_run: bool,
+        change: &ConfigChange,
+    ) -> Result<(MtaStsPolicy, ConfigUpdate), MtaStsServiceError> {
+        let domain = normalize_domain(domain)?;
+        let policy = MtaStsPolicy::new(
+            mode,
+            max_age.unwrap_or(DEFAULT_MAX_AGE),
+            vec![myhostname()?],
+        )?;
+
+        let policy_path = mta_sts::policy_path(&self.policy_root, &domain);
+        let mut changeset = Changeset::new();
+        changeset.stage_with_mode(&policy_path, policy.to_text(), 0o644);
+
+        let host = mta_sts::policy_host(&domain);
+        let mut warnings = Vec::new();
+        if Path::new(&acme::store::cert_path(&host)).exists() {
+            changeset.stage(policy_vhost_path(&domain), policy_vhost(&domain)?);
+        } else {
+            warnings.push(format!(
+                "{} has no certificate yet; the policy is served once one is issued for it",
+                host
+            ));
+        }
+
+        let mut update = if dry_run {
+            self.config.preview_changeset(&changeset, true, change).await?
+        } else {
+            if let Some(dir) = policy_path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let update = self
+                .config
+                .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+                .await?;
+            info!(
+                "Published MTA-STS policy for {}: mode {}, id {}",
+                domain,
+                policy.mode,
+                policy.id()
+            );
+            update
+        };
+        update.warnings.extend(warnings);
+        Ok((policy, update))
+    }
+
+    /// TLS failures per sending organization, from the reports delivered
+    /// to `mailbox` over the last `days` days. Messages are read, never
+    /// marked or moved.
+    pub async fn tls_report_summary(
+        &self,
+        mailbox: &str,
+        days: u32,
+    ) -> Result<Vec<OrganizationSummary>, MtaStsServiceError> {
+        let maildir = ReportMaildir::for_address(&self.home_base, mailbox)?;
+        let since = Utc::now() - Duration::days(days as i64);
+
+        // Maildir reads and decompression block
+        tokio::task::spawn_blocking(move || {
+            let mut reports = Vec::new();
+            for path in maildir.messages()? {
+                let parsed = std::fs::read(&path)
+                    .map_err(TlsReportError::from)
+                    .and_then(|raw| tls_report::reports_from_message(&raw));
+                match parsed {
+                    Ok(found) => reports.extend(
+                        found
+                            .into_iter()
+                            .filter(|r| r.date_range.end_datetime >= since),
+                    ),
+                    Err(e) => warn!("No TLS report in {}: {}", path.display(), e),
+                }
+            }
+            Ok(tls_report::summarize(&reports))
+        })
+        .await
+        .map_err(|e| MtaStsServiceError::Task(e.to_string()))?
+    }
+}
+
+/// Where the `mta-sts.<domain>` vhost is written.
+pub(crate) fn policy_vhost_path(domain: &str) -> PathBuf {
+    Path::new(ConfigFileType::ApacheVhost.path()).join(format!("{}.conf", mta_sts::policy_host(domain)))
+}
+
+/// The `mta-sts.<domain>` vhost, pointed at the default certificate store.
+pub(crate) fn policy_vhost(domain: &str) -> Result<String, MtaStsServiceError> {
+    apache::generate_mta_sts_vhost(domain, &format!("postmaster@{}", domain))
+        .map_err(|e| MtaStsServiceError::Validation(e.to_string()))
+}
+
+/// The policy stored for `domain` under `root`, if there is one.
+pub(crate) fn load_policy(root: &Path, domain: &str) -> Result<Option<MtaStsPolicy>, MtaStsServiceError> {
+    match std::fs::read_to_string(mta_sts::policy_path(root, domain)) {
+        Ok(text) => Ok(Some(MtaStsPolicy::parse(&text)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn normalize_domain(domain: &str) -> Result<String, MtaStsServiceError> {
+    idn::normalize_domain(domain)
+        .map(|d| d.ascii)
+        .map_err(|e| MtaStsServiceError::Validation(e.to_string()))
+}
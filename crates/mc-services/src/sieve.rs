@@ -0,0 +1,97 @@
+use mc_core::mail::sieve::{self, SieveScriptInfo, SieveStore, VacationSettings};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Debug, Error)]
+pub enum SieveServiceError {
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Sieve error: {0}")]
+    Sieve(#[from] sieve::SieveError),
+}
+
+/// Per-user Sieve filters and the vacation responder.
+///
+/// Every script is compiled with `sievec` before it is written, so a broken
+/// upload never replaces a working filter.
+pub struct SieveService {
+    store: SieveStore,
+}
+
+impl SieveService {
+    pub fn new() -> Self {
+        Self {
+            store: SieveStore::new(),
+        }
+    }
+
+    pub fn with_store(store: SieveStore) -> Self {
+        Self { store }
+    }
+
+    pub async fn list_scripts(&self, email: &str) -> Result<Vec<SieveScriptInfo>, SieveServiceError> {
+        Ok(self.store.list_scripts(email)?)
+    }
+
+    pub async fn get_script(&self, email: &str, name: &str) -> Result<String, SieveServiceError> {
+        Ok(self.store.get_script(email, name)?)
+    }
+
+    /// Compile and store a script, optionally making it the active one.
+    pub async fn upload_script(
+        &self,
+        email: &str,
+        name: &str,
+        content: &str,
+        activate: bool,
+    ) -> Result<(), SieveServiceError> {
+        sieve::validate_script_name(name)?;
+        sieve::validate_script(content)?;
+        self.store.put_script(email, name, content)?;
+        if activate {
+            self.store.activate(email, name)?;
+        }
+        info!("Uploaded Sieve script {} for {} (active: {})", name, email, activate);
+        Ok(())
+    }
+
+    pub async fn activate_script(&self, email: &str, name: &str) -> Result<(), SieveServiceError> {
+        Ok(self.store.activate(email, name)?)
+    }
+
+    pub async fn deactivate(&self, email: &str) -> Result<(), SieveServiceError> {
+        Ok(self.store.deactivate(email)?)
+    }
+
+    pub async fn delete_script(&self, email: &str, name: &str) -> Result<(), SieveServiceError> {
+        Ok(self.store.delete_script(email, name)?)
+    }
+
+    /// Turn on (or update) the vacation responder.
+    pub async fn set_vacation(
+        &self,
+        email: &str,
+        settings: &VacationSettings,
+    ) -> Result<(), SieveServiceError> {
+        if settings.addresses.iter().any(|a| a.eq_ignore_ascii_case(email)) {
+            return Err(SieveServiceError::Validation(
+                "addresses should list aliases, not the mailbox itself".to_string(),
+            ));
+        }
+        let script = settings.to_sieve()?;
+        sieve::validate_script(&script)?;
+        self.store.put_vacation(email, settings, &script)?;
+        Ok(())
+    }
+
+    pub async fn get_vacation(
+        &self,
+        email: &str,
+    ) -> Result<Option<VacationSettings>, SieveServiceError> {
+        Ok(self.store.get_vacation(email)?)
+    }
+
+    pub async fn disable_vacation(&self, email: &str) -> Result<(), SieveServiceError> {
+        Ok(self.store.remove_vacation(email)?)
+    }
+}
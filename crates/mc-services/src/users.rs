@@ -1,10 +1,18 @@
+use mc_core::config::postfix::PostfixConfig;
+use mc_core::mail::acl::{self, AclError, MailboxRights};
 use mc_core::mail::password;
-use mc_core::security::input;
+use mc_core::security::idn;
+use mc_core::security::password_policy::{
+    PasswordContext, PasswordPolicyEngine, PasswordRejected, PASSWORD_POLICY_PATH,
+};
 use mc_db::pool::DbError;
 use mc_db::queries;
 use sqlx::MySqlPool;
+use std::path::Path;
 use thiserror::Error;
-use tracing::info;
+
+use crate::config::ConfigFileType;
+use tracing::{info, warn};
 
 #[derive(Debug, Error)]
 pub enum UserError {
@@ -14,33 +22,63 @@ pub enum UserError {
     Database(#[from] DbError),
     #[error("Password error: {0}")]
     Password(String),
+    #[error("{0}")]
+    PasswordPolicy(#[from] PasswordRejected),
     #[error("Not found: {0}")]
     NotFound(String),
+    #[error("ACL error: {0}")]
+    Acl(#[from] AclError),
 }
 
 pub struct UserService {
     pool: MySqlPool,
+    /// Mirrors Postfix `smtputf8_enable`; gates UTF-8 local parts.
+    smtputf8_enabled: bool,
+    password_policy: PasswordPolicyEngine,
 }
 
 impl UserService {
     pub fn new(pool: MySqlPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            smtputf8_enabled: live_smtputf8_enabled(),
+            password_policy: password_policy_from(Path::new(PASSWORD_POLICY_PATH)),
+        }
+    }
+
+    /// Replace the password policy [`Self::new`] read from
+    /// [`PASSWORD_POLICY_PATH`], e.g. after the file was edited.
+    pub fn set_password_policy(&mut self, policy: PasswordPolicyEngine) {
+        self.password_policy = policy;
+    }
+
+    /// Allow or refuse addresses with UTF-8 local parts. [`Self::new`]
+    /// reads this from main.cf; call it after changing `smtputf8_enable`.
+    pub fn set_smtputf8_enabled(&mut self, enabled: bool) {
+        self.smtputf8_enabled = enabled;
     }
 
     // --- Domains ---
 
     pub async fn create_domain(&self, name: &str) -> Result<i64, UserError> {
-        // Validate domain name strictly
-        input::validate_domain(name)
+        // Normalize to the punycode form; the mail DB only ever holds ASCII
+        let domain = idn::normalize_domain(name)
             .map_err(|e| UserError::Validation(e.to_string()))?;
+        for warning in &domain.warnings {
+            warn!("Domain {} ({}): {}", domain.unicode, domain.ascii, warning);
+        }
 
-        let id = queries::create_domain(&self.pool, name).await?;
-        info!("Created domain: {} (id: {})", name, id);
+        let id = queries::create_domain(&self.pool, &domain.ascii).await?;
+        info!("Created domain: {} (id: {})", domain.ascii, id);
         Ok(id)
     }
 
     pub async fn list_domains(&self) -> Result<Vec<mc_db::models::VirtualDomain>, UserError> {
-        Ok(queries::list_domains(&self.pool).await?)
+        let mut domains = queries::list_domains(&self.pool).await?;
+        for domain in &mut domains {
+            domain.display_name = idn::to_unicode(&domain.name);
+        }
+        Ok(domains)
     }
 
     pub async fn delete_domain(&self, id: i64) -> Result<(), UserError> {
@@ -57,32 +95,39 @@ impl UserService {
         email: &str,
         plaintext_password: &str,
     ) -> Result<i64, UserError> {
-        // Validate email
-        input::validate_email(email)
-            .map_err(|e| UserError::Validation(e.to_string()))?;
+        // Validate email and normalize its domain to punycode
+        let email = idn::normalize_email(email, self.smtputf8_enabled)
+            .map_err(|e| UserError::Validation(e.to_string()))?
+            .address();
 
-        // Validate password strength
-        input::validate_password(plaintext_password)
-            .map_err(|e| UserError::Validation(e.to_string()))?;
+        // Check the password against the policy for this domain
+        self.password_policy.check(
+            plaintext_password,
+            &PasswordContext {
+                email: Some(&email),
+                previous_hashes: &[],
+            },
+        )?;
 
         // Hash password using SHA512-CRYPT (Dovecot compatible)
         let hash = password::hash_password(plaintext_password)
             .map_err(|e| UserError::Password(e.to_string()))?;
 
-        let id = queries::create_user(&self.pool, domain_id, email, &hash).await?;
+        let id = queries::create_user(&self.pool, domain_id, &email, &hash).await?;
+        self.record_password_history(id, &email, &hash).await?;
         info!("Created user: {} (id: {})", email, id);
         Ok(id)
     }
 
     pub async fn list_users(&self) -> Result<Vec<mc_db::models::VirtualUser>, UserError> {
-        Ok(queries::list_users(&self.pool).await?)
+        Ok(with_display_email(queries::list_users(&self.pool).await?))
     }
 
     pub async fn list_users_by_domain(
         &self,
         domain_id: i64,
     ) -> Result<Vec<mc_db::models::VirtualUser>, UserError> {
-        Ok(queries::list_users_by_domain(&self.pool, domain_id).await?)
+        Ok(with_display_email(queries::list_users_by_domain(&self.pool, domain_id).await?))
     }
 
     pub async fn change_password(
@@ -90,18 +135,54 @@ impl UserService {
         user_id: i64,
         new_password: &str,
     ) -> Result<(), UserError> {
-        // Validate password strength
-        input::validate_password(new_password)
-            .map_err(|e| UserError::Validation(e.to_string()))?;
+        let user = queries::get_user(&self.pool, user_id).await?;
+        let policy = self.password_policy.policy_for(Some(&user.email));
+
+        let mut previous_hashes = if policy.history_depth > 0 {
+            queries::list_password_history(&self.pool, user_id, policy.history_depth as i64).await?
+        } else {
+            Vec::new()
+        };
+        // History already starts with the current hash, except for accounts
+        // created before it was tracked; those still cannot "change" to the
+        // same password.
+        if previous_hashes.first() != Some(&user.password) {
+            previous_hashes.insert(0, user.password);
+        }
+
+        self.password_policy.check(
+            new_password,
+            &PasswordContext {
+                email: Some(&user.email),
+                previous_hashes: &previous_hashes,
+            },
+        )?;
 
         let hash = password::hash_password(new_password)
             .map_err(|e| UserError::Password(e.to_string()))?;
 
         queries::update_user_password(&self.pool, user_id, &hash).await?;
+        self.record_password_history(user_id, &user.email, &hash).await?;
         info!("Changed password for user id: {}", user_id);
         Ok(())
     }
 
+    /// Append a hash to the user's history and trim it to the policy depth.
+    async fn record_password_history(
+        &self,
+        user_id: i64,
+        email: &str,
+        hash: &str,
+    ) -> Result<(), UserError> {
+        let depth = self.password_policy.policy_for(Some(email)).history_depth;
+        if depth == 0 {
+            return Ok(());
+        }
+        queries::add_password_history(&self.pool, user_id, hash).await?;
+        queries::prune_password_history(&self.pool, user_id, depth as i64).await?;
+        Ok(())
+    }
+
     pub async fn delete_user(&self, id: i64) -> Result<(), UserError> {
         queries::delete_user(&self.pool, id).await?;
         info!("Deleted user id: {}", id);
@@ -117,12 +198,14 @@ impl UserService {
         destination: &str,
     ) -> Result<i64, UserError> {
         // Validate both email addresses
-        input::validate_email(source)
-            .map_err(|e| UserError::Validation(format!("Source: {}", e)))?;
-        input::validate_email(destination)
-            .map_err(|e| UserError::Validation(format!("Destination: {}", e)))?;
+        let source = idn::normalize_email(source, self.smtputf8_enabled)
+            .map_err(|e| UserError::Validation(format!("Source: {}", e)))?
+            .address();
+        let destination = idn::normalize_email(destination, self.smtputf8_enabled)
+            .map_err(|e| UserError::Validation(format!("Destination: {}", e)))?
+            .address();
 
-        let id = queries::create_alias(&self.pool, domain_id, source, destination).await?;
+        let id = queries::create_alias(&self.pool, domain_id, &source, &destination).await?;
         info!("Created alias: {} -> {} (id: {})", source, destination, id);
         Ok(id)
     }
@@ -143,4 +226,187 @@ impl UserService {
         info!("Deleted alias id: {}", id);
         Ok(())
     }
+
+    // --- Send-as grants ---
+
+    /// Allow a user to send as `sender` (typically a shared mailbox address).
+    /// A user's own address and aliases pointing at them need no grant.
+    pub async fn grant_send_as(&self, user_id: i64, sender: &str) -> Result<i64, UserError> {
+        let sender = idn::normalize_email(sender, self.smtputf8_enabled)
+            .map_err(|e| UserError::Validation(format!("Sender: {}", e)))?
+            .address();
+
+        let user = queries::get_user(&self.pool, user_id).await?;
+        if user.email.eq_ignore_ascii_case(&sender) {
+            return Err(UserError::Validation(format!(
+                "{} can already send as their own address",
+                user.email
+            )));
+        }
+
+        let id = queries::create_sender_grant(&self.pool, user_id, &sender).await?;
+        info!("Granted send-as: {} -> {} (id: {})", user.email, sender, id);
+        Ok(id)
+    }
+
+    pub async fn list_send_as_grants(&self) -> Result<Vec<mc_db::models::SenderGrant>, UserError> {
+        Ok(queries::list_sender_grants(&self.pool).await?)
+    }
+
+    pub async fn list_send_as_grants_by_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<mc_db::models::SenderGrant>, UserError> {
+        Ok(queries::list_sender_grants_by_user(&self.pool, user_id).await?)
+    }
+
+    pub async fn revoke_send_as(&self, grant_id: i64) -> Result<(), UserError> {
+        queries::delete_sender_grant(&self.pool, grant_id).await?;
+        info!("Revoked send-as grant id: {}", grant_id);
+        Ok(())
+    }
+
+    // --- Shared mailboxes ---
+
+    /// Grant `grantee_id` rights on one of `owner_id`'s mailboxes (`INBOX`
+    /// for the whole account). Re-granting replaces the previous rights.
+    pub async fn grant_mailbox_access(
+        &self,
+        owner_id: i64,
+        mailbox: &str,
+        grantee_id: i64,
+        rights: &MailboxRights,
+    ) -> Result<i64, UserError> {
+        acl::validate_mailbox_name(mailbox)?;
+        if owner_id == grantee_id {
+            return Err(UserError::Validation(
+                "A user already has full access to their own mailboxes".to_string(),
+            ));
+        }
+
+        let owner = queries::get_user(&self.pool, owner_id).await?;
+        let grantee = queries::get_user(&self.pool, grantee_id).await?;
+        let previous = queries::find_mailbox_acl(&self.pool, owner_id, mailbox, grantee_id).await?;
+
+        let rights_str = rights.to_string();
+        let id = queries::upsert_mailbox_acl(&self.pool, owner_id, mailbox, grantee_id, &rights_str)
+            .await?;
+
+        if let Err(e) = acl::apply_acl(&owner.email, mailbox, &grantee.email, rights) {
+            // Keep the DB in step with what Dovecot actually enforces
+            let restored = match previous {
+                Some(prev) => queries::upsert_mailbox_acl(
+                    &self.pool,
+                    owner_id,
+                    mailbox,
+                    grantee_id,
+                    &prev.rights,
+                )
+                .await
+                .map(|_| ()),
+                None => queries::delete_mailbox_acl(&self.pool, id).await,
+            };
+            if let Err(db_err) = restored {
+                warn!("Failed to roll back mailbox ACL id {}: {}", id, db_err);
+            }
+            return Err(e.into());
+        }
+
+        info!(
+            "Granted {} on {}:{} to {} (id: {})",
+            rights_str, owner.email, mailbox, grantee.email, id
+        );
+        Ok(id)
+    }
+
+    pub async fn revoke_mailbox_access(&self, acl_id: i64) -> Result<(), UserError> {
+        let grant = queries::get_mailbox_acl(&self.pool, acl_id).await?;
+        let owner = queries::get_user(&self.pool, grant.owner_id).await?;
+        let grantee = queries::get_user(&self.pool, grant.grantee_id).await?;
+
+        acl::remove_acl(&owner.email, &grant.mailbox, &grantee.email)?;
+        queries::delete_mailbox_acl(&self.pool, acl_id).await?;
+        info!(
+            "Revoked access to {}:{} for {} (id: {})",
+            owner.email, grant.mailbox, grantee.email, acl_id
+        );
+        Ok(())
+    }
+
+    /// Grants on mailboxes owned by `owner_id`.
+    pub async fn list_mailbox_access(
+        &self,
+        owner_id: i64,
+    ) -> Result<Vec<mc_db::models::MailboxAcl>, UserError> {
+        Ok(queries::list_mailbox_acls_by_owner(&self.pool, owner_id).await?)
+    }
+
+    /// Grants held by `grantee_id` on other users' mailboxes.
+    pub async fn list_shared_with(
+        &self,
+        grantee_id: i64,
+    ) -> Result<Vec<mc_db::models::MailboxAcl>, UserError> {
+        Ok(queries::list_mailbox_acls_by_grantee(&self.pool, grantee_id).await?)
+    }
+}
+
+fn with_display_email(mut users: Vec<mc_db::models::VirtualUser>) -> Vec<mc_db::models::VirtualUser> {
+    for user in &mut users {
+        user.display_email = idn::email_to_unicode(&user.email);
+    }
+    users
+}
+
+/// Whether the live main.cf has SMTPUTF8 on. An unreadable main.cf counts
+/// as off, which only rejects UTF-8 local parts.
+/// The password policy in `path`, or the built-in default if there is
+/// none or it cannot be loaded.
+fn password_policy_from(path: &Path) -> PasswordPolicyEngine {
+    if !path.exists() {
+        return PasswordPolicyEngine::default();
+    }
+    PasswordPolicyEngine::load(path).unwrap_or_else(|e| {
+        warn!("Using the default password policy: {}", e);
+        PasswordPolicyEngine::default()
+    })
+}
+
+fn live_smtputf8_enabled() -> bool {
+    std::fs::read_to_string(ConfigFileType::PostfixMain.path())
+        .ok()
+        .and_then(|content| PostfixConfig::parse(&content).ok())
+        .is_some_and(|config| config.smtputf8_enabled())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loaded_policy_applies_domain_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password-policy.json");
+        std::fs::write(
+            &path,
+            r#"{"domains": {"example.com": {"min_length": 20}}}"#,
+        )
+        .unwrap();
+        let policy = password_policy_from(&path);
+
+        let check = |email| {
+            policy.check(
+                "Tr0ub4dor&Horse!",
+                &PasswordContext {
+                    email: Some(email),
+                    previous_hashes: &[],
+                },
+            )
+        };
+        assert!(check("alice@example.com").is_err());
+        assert!(check("alice@example.org").is_ok());
+
+        // An unreadable file leaves the default in place
+        std::fs::write(&path, "{").unwrap();
+        assert_eq!(password_policy_from(&path).config(), PasswordPolicyEngine::default().config());
+    }
 }
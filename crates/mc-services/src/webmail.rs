@@ -1,10 +1,16 @@
-use mc_core::config::roundcube::RoundcubeConfig;
+use mc_core::config::roundcube::{self, RoundcubeConfig};
 use mc_core::config::apache;
+use mc_core::config::changeset::Changeset;
+use mc_core::config::history::ConfigChange;
 use mc_core::security::input;
-use mc_core::security::credentials;
+use mc_core::security::credentials::{self, CredentialError, CredentialStore};
+use std::path::Path;
+use std::process::{Command, Stdio};
 use thiserror::Error;
 use tracing::info;
 
+use crate::config::{ConfigError, ConfigService, ConfigUpdate, DEFAULT_HEALTH_GRACE};
+
 use rand::Rng;
 
 #[derive(Debug, Error)]
@@ -15,6 +21,8 @@ pub enum WebmailError {
     Setup(String),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Config apply failed: {0}")]
+    Config(#[from] ConfigError),
 }
 
 #[derive(Debug, Clone)]
@@ -32,18 +40,35 @@ pub struct WebmailSetupResult {
     pub webmail_url: String,
     pub db_name: String,
     pub dns_instructions: Vec<String>,
+    /// The Roundcube and Apache config written, or to be written in a
+    /// dry run.
+    pub update: ConfigUpdate,
 }
 
-pub struct WebmailService;
+pub struct WebmailService {
+    config: ConfigService,
+}
 
 impl WebmailService {
     pub fn new() -> Self {
-        Self
+        Self::with_config_service(ConfigService::new())
     }
 
+    pub fn with_config_service(config: ConfigService) -> Self {
+        Self { config }
+    }
+
+    /// Write the Roundcube config and webmail vhost for a site.
+    ///
+    /// The site's database and MySQL user are created first, with the
+    /// login kept in the credential store so a rerun reuses it.
+    /// With `dry_run` nothing is written and the result holds the diffs,
+    /// with the database password and session key masked.
     pub async fn setup_webmail(
         &self,
         config: WebmailSetupConfig,
+        dry_run: bool,
+        change: &ConfigChange,
     ) -> Result<WebmailSetupResult, WebmailError> {
         // Validate all inputs
         input::validate_path_component(&config.site_name)
@@ -60,11 +85,38 @@ impl WebmailService {
 
         info!("Setting up webmail for: {}", cey_domain);
 
-        // Generate credentials in memory (never written to disk as plaintext)
-        let _db_user = hex::encode(&rand::random::<[u8; 8]>());
-        let _db_pass = hex::encode(&rand::random::<[u8; 16]>());
-        let _session_key = hex::encode(&rand::random::<[u8; 24]>());
+        // A dry run touches neither the credential store nor MySQL; the
+        // password is masked in its diff anyway
         let db_name = format!("ceymail_{}", config.site_name);
+        let (db_user, db_pass) = if dry_run {
+            new_login()
+        } else {
+            let login = database_login(&config.site_name)?;
+            provision_database(&db_name, &login.0, &login.1)?;
+            login
+        };
+
+        let mut roundcube_cfg = RoundcubeConfig::generate_default(&full_domain, &config.admin_email);
+        roundcube_cfg.db_name = db_name.clone();
+        roundcube_cfg.db_user = db_user;
+        roundcube_cfg.db_password = db_pass;
+        let config_php = roundcube::generate_config_inc_php(&roundcube_cfg)
+            .map_err(|e| WebmailError::Setup(e.to_string()))?;
+        let vhost = apache::generate_webmail_vhost(&full_domain, &config.site_name, &config.admin_email)
+            .map_err(|e| WebmailError::Setup(e.to_string()))?;
+
+        let mut changeset = Changeset::new();
+        changeset
+            .stage_with_mode("/etc/roundcube/config.inc.php", config_php, 0o640)
+            .stage(format!("/etc/apache2/sites-available/webmail.{}.conf", full_domain), vhost);
+
+        let update = if dry_run {
+            self.config.preview_changeset(&changeset, true, change).await?
+        } else {
+            self.config
+                .apply_changeset(&changeset, change, DEFAULT_HEALTH_GRACE)
+                .await?
+        };
 
         Ok(WebmailSetupResult {
             webmail_url: format!("https://{}", cey_domain),
@@ -73,6 +125,78 @@ impl WebmailService {
                 format!("Add A record: ceymail.{} -> your server IP", full_domain),
                 format!("Add CNAME record: www.{} -> {}", full_domain, full_domain),
             ],
+            update,
         })
     }
 }
+
+/// The Roundcube database login for a site, as `(user, password)`: the
+/// one stored by an earlier setup, or a new one, which is stored.
+fn database_login(site_name: &str) -> Result<(String, String), WebmailError> {
+    let setup_error = |e: CredentialError| WebmailError::Setup(e.to_string());
+    let name = format!("webmail-{}", site_name);
+    let store = CredentialStore::new(Path::new(credentials::KEY_PATH)).map_err(setup_error)?;
+    match store.retrieve(&name) {
+        Ok(login) => {
+            // Stored as `user:password`; both are hex
+            if let Some((user, pass)) = login.split_once(':') {
+                return Ok((user.to_string(), pass.to_string()));
+            }
+        }
+        Err(CredentialError::NotFound(_)) => {}
+        Err(e) => return Err(setup_error(e)),
+    }
+
+    let (user, pass) = new_login();
+    store
+        .store(&name, &format!("{}:{}", user, pass))
+        .map_err(setup_error)?;
+    Ok((user, pass))
+}
+
+fn new_login() -> (String, String) {
+    (
+        format!("rc_{}", hex::encode(rand::random::<[u8; 8]>())),
+        CredentialStore::generate_db_password(),
+    )
+}
+
+/// Create the site's database and a MySQL user that can only reach it,
+/// resetting the user's password if it already exists.
+fn provision_database(db_name: &str, db_user: &str, db_pass: &str) -> Result<(), WebmailError> {
+    // SAFETY: db_name is `ceymail_` plus a validated path component
+    // ([A-Za-z0-9._-]), and the user and password are hex from
+    // database_login, so none can break out of their quotes.
+    let sql = format!(
+        "CREATE DATABASE IF NOT EXISTS `{db}` CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci; \
+         CREATE USER IF NOT EXISTS '{user}'@'localhost' IDENTIFIED BY '{pass}'; \
+         ALTER USER '{user}'@'localhost' IDENTIFIED BY '{pass}'; \
+         GRANT SELECT, INSERT, UPDATE, DELETE, CREATE, ALTER, DROP, INDEX ON `{db}`.* TO '{user}'@'localhost'; \
+         FLUSH PRIVILEGES;",
+        db = db_name,
+        user = db_user,
+        pass = db_pass,
+    );
+
+    // Pass SQL via stdin to keep the password out of the process list
+    use std::io::Write;
+    let mut child = Command::new("mysql")
+        .args(["-u", "root"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| WebmailError::Setup(format!("mysql command failed: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(sql.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(WebmailError::Setup(format!(
+            "Failed to create the Roundcube database: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    info!("Provisioned Roundcube database {}", db_name);
+    Ok(())
+}